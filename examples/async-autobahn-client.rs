@@ -64,6 +64,14 @@ fn main() {
 								Some(OwnedMessage::Pong(_)) => {
 									Box::new(future::ok(Loop::Continue(stream)))
 								}
+								Some(OwnedMessage::Reserved(opcode, _)) => {
+									println!("Received unsupported reserved opcode: {}", opcode);
+									Box::new(
+										stream
+											.send(OwnedMessage::Close(None))
+											.map(|_| Loop::Break(())),
+									)
+								}
 								None => Box::new(future::ok(Loop::Break(()))),
 							}
 						})
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use websocket::dataframe::DataFrame;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DataFrame::decode_frame(data, false);
+});
@@ -62,6 +62,15 @@ pub use tokio_tls::TlsStream;
 /// This is simply a `Stream` and `Sink` of `OwnedMessage`s.
 /// See the docs for `Stream` and `Sink` to learn more about how to use
 /// these futures.
+///
+/// `MessageCodec` already works at the message level, not the dataframe
+/// level: fragmented messages are reassembled internally and each item
+/// this yields is a complete `OwnedMessage`. Control frames (`Ping`,
+/// `Pong`, `Close`) come through the stream like any other message rather
+/// than being intercepted - this crate never auto-pongs, on the async
+/// client any more than the sync one (see `sync::Client::recv_event`), so
+/// a `Ping` is always yours to answer, typically by sending back an
+/// `OwnedMessage::Pong` with the same payload.
 pub type Client<S> = Framed<S, MessageCodec<OwnedMessage>>;
 
 /// A future which will evaluate to a `Client` and a set of hyper `Headers`.
@@ -5,13 +5,18 @@ use header::{Origin, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSo
 use hyper::header::{Header, HeaderFormat, Headers};
 use hyper::version::HttpVersion;
 use std::borrow::Cow;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
 pub use url::{ParseError, Url};
 
 #[cfg(any(feature = "sync", feature = "async"))]
 mod common_imports {
 	pub use header::WebSocketAccept;
 	pub use hyper::buffer::BufReader;
-	pub use hyper::header::{Connection, ConnectionOption, Host, Protocol, ProtocolName, Upgrade};
+	pub use hyper::header::{
+		Connection, ConnectionOption, Host, Protocol, ProtocolName, Upgrade, UserAgent,
+	};
 	pub use hyper::http::h1::parse_response;
 	pub use hyper::http::h1::Incoming;
 	pub use hyper::http::RawStatus;
@@ -28,8 +33,16 @@ mod common_imports {
 #[cfg(any(feature = "sync", feature = "async"))]
 use self::common_imports::*;
 
+#[cfg(feature = "sync")]
+use super::socks::{self, Socks5Auth};
 #[cfg(feature = "sync")]
 use super::sync::Client;
+#[cfg(feature = "sync")]
+use socket2::Socket;
+#[cfg(feature = "sync")]
+use std::sync::mpsc;
+#[cfg(feature = "sync")]
+use std::thread;
 
 #[cfg(feature = "sync-ssl")]
 use stream::sync::NetworkStream;
@@ -102,15 +115,44 @@ use self::async_imports::*;
 /// This crate's openssl dependency is optional (and included by default).
 /// One can use `connect_secure` to connect to an SSL service, or simply `connect`
 /// to choose either SSL or not based on the protocol (`ws://` or `wss://`).
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct ClientBuilder<'u> {
 	url: Cow<'u, Url>,
 	version: HttpVersion,
 	headers: Headers,
 	version_set: bool,
 	key_set: bool,
+	sni_hostname: Option<String>,
+	danger_accept_invalid_certs: bool,
+	socket_buffer_sizes: Option<(usize, usize)>,
+	read_buffer_capacity: Option<usize>,
+	resolver: Option<Resolver>,
+	happy_eyeballs: bool,
+}
+
+impl<'u> ::std::fmt::Debug for ClientBuilder<'u> {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		f.debug_struct("ClientBuilder")
+			.field("url", &self.url)
+			.field("version", &self.version)
+			.field("headers", &self.headers)
+			.field("version_set", &self.version_set)
+			.field("key_set", &self.key_set)
+			.field("sni_hostname", &self.sni_hostname)
+			.field("danger_accept_invalid_certs", &self.danger_accept_invalid_certs)
+			.field("socket_buffer_sizes", &self.socket_buffer_sizes)
+			.field("read_buffer_capacity", &self.read_buffer_capacity)
+			.field("resolver", &self.resolver.is_some())
+			.field("happy_eyeballs", &self.happy_eyeballs)
+			.finish()
+	}
 }
 
+/// A custom DNS resolver set with `ClientBuilder::resolve_with`: given the
+/// URL's hostname and port, returns the `SocketAddr`s to try connecting to,
+/// in order.
+type Resolver = Arc<Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync>;
+
 impl<'u> ClientBuilder<'u> {
 	/// Create a client builder from an already parsed Url,
 	/// because there is no need to parse this will never error.
@@ -156,6 +198,12 @@ impl<'u> ClientBuilder<'u> {
 			version_set: false,
 			key_set: false,
 			headers: Headers::new(),
+			sni_hostname: None,
+			danger_accept_invalid_certs: false,
+			socket_buffer_sizes: None,
+			read_buffer_capacity: None,
+			resolver: None,
+			happy_eyeballs: false,
 		}
 	}
 
@@ -332,6 +380,196 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
+	/// Overrides the `Host` header that would otherwise be derived from the
+	/// URL passed to `new`/`from_url`. Useful when connecting to a bare IP
+	/// address but presenting a virtual host name to the server, e.g. behind
+	/// a load balancer that routes on `Host`. The port from the URL is kept
+	/// unless it is also overridden.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// # use websocket::header::Host;
+	/// let builder = ClientBuilder::new("ws://203.0.113.5:8080/chat").unwrap()
+	///     .host("chat.example.com");
+	///
+	/// let host = builder.get_header::<Host>().unwrap();
+	/// assert_eq!(host.hostname, "chat.example.com");
+	/// assert_eq!(host.port, Some(8080));
+	/// ```
+	pub fn host<H>(mut self, host: H) -> Self
+	where
+		H: Into<String>,
+	{
+		self.headers.set(Host {
+			hostname: host.into(),
+			port: self.url.port(),
+		});
+		self
+	}
+
+	/// Remove the currently set `Host` override, going back to deriving it
+	/// from the URL.
+	pub fn clear_host(mut self) -> Self {
+		self.headers.remove::<Host>();
+		self
+	}
+
+	/// Overrides the server name used for SNI (and certificate
+	/// verification) during the TLS handshake, independent of the host
+	/// `connect`/`connect_secure` actually dial. Useful when connecting by
+	/// IP address but needing the peer to present (and be verified against)
+	/// a particular hostname's certificate. Defaults to the URL's host.
+	///
+	/// A mismatch between this name and the server's certificate surfaces
+	/// as a `WebSocketError::TlsError` from `connect`/`connect_secure`,
+	/// same as any other certificate verification failure.
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	pub fn sni_hostname<H>(mut self, hostname: H) -> Self
+	where
+		H: Into<String>,
+	{
+		self.sni_hostname = Some(hostname.into());
+		self
+	}
+
+	/// Remove the currently set SNI hostname override, going back to
+	/// deriving it from the URL.
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	pub fn clear_sni_hostname(mut self) -> Self {
+		self.sni_hostname = None;
+		self
+	}
+
+	/// **Danger:** disables TLS certificate verification for connections
+	/// made by this builder, when `true` is passed. This makes the
+	/// connection vulnerable to man-in-the-middle attacks and should only
+	/// ever be used against a known-bad, known-self-signed peer during
+	/// local development or testing. Defaults to `false` (full
+	/// verification).
+	///
+	/// This only takes effect when the connector used to establish the
+	/// `wss://` connection is the one built internally by this crate; an
+	/// explicit `TlsConnector` passed to `connect_secure`/`async_connect_secure`
+	/// is used as-is and is not affected by this setting.
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+		self.danger_accept_invalid_certs = accept_invalid_certs;
+		self
+	}
+
+	/// Set `SO_SNDBUF`/`SO_RCVBUF` on the socket this builder connects, for
+	/// high-throughput streaming clients that want bigger kernel buffers
+	/// than the OS default. Applied once the TCP connection succeeds, before
+	/// any TLS handshake or websocket upgrade. The kernel is free to clamp
+	/// or double whatever is asked for (as Linux does); a value it rejects
+	/// outright is tolerated too, rather than failing the connection over
+	/// it. Defaults to `None`, leaving the OS defaults in place.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://supersecret.l33t").unwrap()
+	///     .socket_buffer_sizes(256 * 1024, 256 * 1024);
+	/// ```
+	pub fn socket_buffer_sizes(mut self, send: usize, recv: usize) -> Self {
+		self.socket_buffer_sizes = Some((send, recv));
+		self
+	}
+
+	/// Sets the size, in bytes, of the buffer this builder's `connect*`
+	/// methods use to read from the stream before parsing it into
+	/// handshake response bytes and websocket frames. A larger buffer
+	/// means fewer reads for a high-throughput connection; a smaller one
+	/// saves memory when holding many mostly-idle connections. Defaults to
+	/// `None`, which leaves `hyper`'s own default (4KB) in place. Unlike
+	/// `socket_buffer_sizes`, this is purely an application-level buffer
+	/// and has no effect on the kernel's socket buffers.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://supersecret.l33t").unwrap()
+	///     .read_buffer_capacity(64 * 1024);
+	/// ```
+	pub fn read_buffer_capacity(mut self, capacity: usize) -> Self {
+		self.read_buffer_capacity = Some(capacity);
+		self
+	}
+
+	/// Overrides DNS resolution for this builder's `TcpStream` connection:
+	/// given the URL's hostname and port, `resolve` returns the
+	/// `SocketAddr`s to try connecting to, in order - the first one that
+	/// accepts a connection wins, just like the standard resolver's
+	/// `ToSocketAddrs` does. Useful for pinning a specific IP or
+	/// integrating a service discovery system without editing
+	/// `/etc/hosts`. Defaults to the standard resolver if never called.
+	///
+	/// Only affects `connect`/`connect_insecure`/`connect_secure`
+	/// (the `sync`/`sync-ssl` client); the async client has no TCP
+	/// connection step of its own to hook into.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// # use std::net::SocketAddr;
+	/// let builder = ClientBuilder::new("ws://myapp.com").unwrap()
+	///     .resolve_with(|_host, port| Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))]));
+	/// ```
+	pub fn resolve_with<F>(mut self, resolve: F) -> Self
+	where
+		F: Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+	{
+		self.resolver = Some(Arc::new(resolve));
+		self
+	}
+
+	/// Connects to every address this builder's DNS resolution turns up
+	/// (e.g. both the A and AAAA records of a dual-stack host) in parallel,
+	/// using whichever connection succeeds first - a rough approximation of
+	/// "Happy Eyeballs" (RFC 8305). This avoids paying a full connect
+	/// timeout for a broken address family before falling through to a
+	/// working one. Defaults to `false`, which tries each address in order
+	/// and only moves to the next once the previous one fails, the same
+	/// policy `TcpStream::connect` and `connect_to_any` use.
+	///
+	/// Only affects `connect`/`connect_insecure`/`connect_secure` (the
+	/// `sync`/`sync-ssl` client); the async client resolves to a single
+	/// address and has no fan-out connect step of its own.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://dual-stack.example.com").unwrap()
+	///     .happy_eyeballs(true);
+	/// ```
+	pub fn happy_eyeballs(mut self, enable: bool) -> Self {
+		self.happy_eyeballs = enable;
+		self
+	}
+
+	/// Sets the `User-Agent` header of the handshake. This crate sends no
+	/// `User-Agent` by default; some servers filter requests on it, so set
+	/// one here if that applies to you.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// # use websocket::header::UserAgent;
+	/// let builder = ClientBuilder::new("ws://moz.illest").unwrap()
+	///     .user_agent("my-cool-client/1.0");
+	///
+	/// let agent = builder.get_header::<UserAgent>().unwrap();
+	/// assert_eq!(agent.0, "my-cool-client/1.0");
+	/// ```
+	pub fn user_agent<A>(mut self, user_agent: A) -> Self
+	where
+		A: Into<String>,
+	{
+		self.headers.set(UserAgent(user_agent.into()));
+		self
+	}
+
+	/// Remove the `User-Agent` header from the handshake.
+	pub fn clear_user_agent(mut self) -> Self {
+		self.headers.remove::<UserAgent>();
+		self
+	}
+
 	/// This is a catch all to add random headers to your handshake,
 	/// the process here is more manual.
 	///
@@ -442,11 +680,56 @@ impl<'u> ClientBuilder<'u> {
 		self.connect_on(ssl_stream)
 	}
 
+	/// Connects to the websocket server through a SOCKS5 proxy.
+	///
+	/// `proxy` is the address of the SOCKS5 proxy itself; the host and port
+	/// to `CONNECT` to are taken from this builder's URL, same as every
+	/// other `connect*` method. Pass `auth` if the proxy requires
+	/// username/password authentication, `None` otherwise. Both IPv6 and
+	/// domain-name targets are sent to the proxy for it to resolve, rather
+	/// than being resolved locally.
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// use websocket::client::Socks5Auth;
+	///
+	/// let client = ClientBuilder::new("ws://example.com/socket").unwrap()
+	///     .socks5_proxy("127.0.0.1:1080", Some(&Socks5Auth::new("alice", "hunter2")))
+	///     .unwrap();
+	/// ```
+	#[cfg(feature = "sync")]
+	pub fn socks5_proxy<A>(
+		&mut self,
+		proxy: A,
+		auth: Option<&Socks5Auth>,
+	) -> WebSocketResult<Client<TcpStream>>
+	where
+		A: ToSocketAddrs,
+	{
+		let host_port = self.extract_host_port(None)?;
+		let target_host = match host_port.host {
+			::url::Host::Domain(domain) => domain.to_owned(),
+			::url::Host::Ipv4(ip) => ip.to_string(),
+			::url::Host::Ipv6(ip) => ip.to_string(),
+		};
+
+		let stream = socks::connect(proxy, &target_host, host_port.port, auth)?;
+
+		self.connect_on(stream)
+	}
+
 	/// Connects to a websocket server on any stream you would like.
 	/// Possible streams:
 	///  - Unix Sockets
 	///  - Logging Middle-ware
 	///  - SSH
+	///  - A TCP/TLS connection you already established yourself, e.g. through
+	///    a SOCKS proxy or other custom dialing logic
+	///
+	/// This only performs the WebSocket handshake over `stream`; it never
+	/// touches the network to set `stream` up. The builder's URL is still
+	/// used to fill in the `Host` header and the request path, even though
+	/// it plays no part in choosing where to connect.
 	///
 	/// ```rust
 	/// # use websocket::ClientBuilder;
@@ -480,15 +763,25 @@ impl<'u> ClientBuilder<'u> {
 		let resource = self.build_request();
 		let data = format!("GET {} {}\r\n{}\r\n", resource, self.version, self.headers);
 		stream.write_all(data.as_bytes())?;
+		let protocol_version = self
+			.headers
+			.get::<WebSocketVersion>()
+			.cloned()
+			.unwrap_or(WebSocketVersion::WebSocket13);
 
 		// wait for a response
-		let mut reader = BufReader::new(stream);
+		let mut reader = match self.read_buffer_capacity {
+			Some(capacity) => BufReader::with_capacity(stream, capacity),
+			None => BufReader::new(stream),
+		};
 		let response = parse_response(&mut reader)?;
 
 		// validate
 		self.validate(&response)?;
 
-		Ok(Client::unchecked(reader, response.headers, true, false))
+		let mut client = Client::unchecked(reader, response.headers, true, false);
+		client.protocol_version = protocol_version;
+		Ok(client)
 	}
 
 	/// Connect to a websocket server asynchronously.
@@ -549,6 +842,12 @@ impl<'u> ClientBuilder<'u> {
 			headers: self.headers,
 			version_set: self.version_set,
 			key_set: self.key_set,
+			sni_hostname: self.sni_hostname,
+			danger_accept_invalid_certs: self.danger_accept_invalid_certs,
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			read_buffer_capacity: self.read_buffer_capacity,
+			resolver: self.resolver,
+			happy_eyeballs: self.happy_eyeballs,
 		};
 
 		// check if we should connect over ssl or not
@@ -634,6 +933,12 @@ impl<'u> ClientBuilder<'u> {
 			headers: self.headers,
 			version_set: self.version_set,
 			key_set: self.key_set,
+			sni_hostname: self.sni_hostname,
+			danger_accept_invalid_certs: self.danger_accept_invalid_certs,
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			read_buffer_capacity: self.read_buffer_capacity,
+			resolver: self.resolver,
+			happy_eyeballs: self.happy_eyeballs,
 		};
 
 		// put it all together
@@ -685,6 +990,12 @@ impl<'u> ClientBuilder<'u> {
 			headers: self.headers,
 			version_set: self.version_set,
 			key_set: self.key_set,
+			sni_hostname: self.sni_hostname,
+			danger_accept_invalid_certs: self.danger_accept_invalid_certs,
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			read_buffer_capacity: self.read_buffer_capacity,
+			resolver: self.resolver,
+			happy_eyeballs: self.happy_eyeballs,
 		};
 
 		let future = tcp_stream.and_then(move |stream| builder.async_connect_on(stream));
@@ -745,6 +1056,12 @@ impl<'u> ClientBuilder<'u> {
 			headers: self.headers,
 			version_set: self.version_set,
 			key_set: self.key_set,
+			sni_hostname: self.sni_hostname,
+			danger_accept_invalid_certs: self.danger_accept_invalid_certs,
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			read_buffer_capacity: self.read_buffer_capacity,
+			resolver: self.resolver,
+			happy_eyeballs: self.happy_eyeballs,
 		};
 		let resource = builder.build_request();
 		let framed = ::codec::http::HttpClientCodec.framed(stream);
@@ -808,12 +1125,15 @@ impl<'u> ClientBuilder<'u> {
 
 	#[cfg(any(feature = "sync", feature = "async"))]
 	fn build_request(&mut self) -> String {
-		// enter host if available (unix sockets don't have hosts)
-		if let Some(host) = self.url.host_str() {
-			self.headers.set(Host {
-				hostname: host.to_string(),
-				port: self.url.port(),
-			});
+		// enter host if available (unix sockets don't have hosts), unless
+		// the caller already overrode it with `ClientBuilder::host`
+		if self.headers.get::<Host>().is_none() {
+			if let Some(host) = self.url.host_str() {
+				self.headers.set(Host {
+					hostname: host.to_string(),
+					port: self.url.port(),
+				});
+			}
 		}
 
 		self.headers
@@ -843,9 +1163,10 @@ impl<'u> ClientBuilder<'u> {
 		let status = StatusCode::from_u16(response.subject.0);
 
 		if status != StatusCode::SwitchingProtocols {
-			return Err(WebSocketError::ResponseError(
-				"Status code must be Switching Protocols",
-			));
+			return Err(WebSocketError::HandshakeFailed {
+				status,
+				headers: response.headers.clone(),
+			});
 		}
 
 		let key = self
@@ -884,6 +1205,23 @@ impl<'u> ClientBuilder<'u> {
 			));
 		}
 
+		if let Some(accepted) = response.headers.get::<WebSocketExtensions>() {
+			let offered = self
+				.headers
+				.get::<WebSocketExtensions>()
+				.map(|e| e.0.as_slice())
+				.unwrap_or(&[]);
+			if accepted
+				.0
+				.iter()
+				.any(|a| !offered.iter().any(|o| o.name == a.name))
+			{
+				return Err(WebSocketError::ResponseError(
+					"Sec-WebSocket-Extensions accepted an extension that was not offered",
+				));
+			}
+		}
+
 		Ok(())
 	}
 
@@ -911,7 +1249,20 @@ impl<'u> ClientBuilder<'u> {
 
 	#[cfg(feature = "sync")]
 	fn establish_tcp(&mut self, secure: Option<bool>) -> WebSocketResult<TcpStream> {
-		Ok(TcpStream::connect(self.extract_host_port(secure)?)?)
+		let host_port = self.extract_host_port(secure)?;
+		let addrs: Vec<SocketAddr> = match self.resolver {
+			Some(ref resolve) => resolve(&host_port.host.to_string(), host_port.port)?,
+			None => host_port.to_socket_addrs()?.collect(),
+		};
+		let stream = if self.happy_eyeballs {
+			connect_happy_eyeballs(&addrs)?
+		} else {
+			connect_to_any(&addrs)?
+		};
+		if let Some((send, recv)) = self.socket_buffer_sizes {
+			set_socket_buffer_sizes(&stream, send, recv);
+		}
+		Ok(stream)
 	}
 
 	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
@@ -919,17 +1270,24 @@ impl<'u> ClientBuilder<'u> {
 		&self,
 		connector: Option<TlsConnector>,
 	) -> WebSocketResult<(&str, TlsConnector)> {
-		let host = match self.url.host_str() {
-			Some(h) => h,
-			None => {
-				return Err(WebSocketError::WebSocketUrlError(
-					WSUrlErrorKind::NoHostName,
-				));
-			}
+		let host = match self.sni_hostname.as_ref() {
+			Some(h) => h.as_str(),
+			None => match self.url.host_str() {
+				Some(h) => h,
+				None => {
+					return Err(WebSocketError::WebSocketUrlError(
+						WSUrlErrorKind::NoHostName,
+					));
+				}
+			},
 		};
 		let connector = match connector {
 			Some(c) => c,
-			None => TlsConnector::builder().build()?,
+			None => {
+				TlsConnector::builder()
+					.danger_accept_invalid_certs(self.danger_accept_invalid_certs)
+					.build()?
+			}
 		};
 		Ok((host, connector))
 	}
@@ -946,6 +1304,76 @@ impl<'u> ClientBuilder<'u> {
 	}
 }
 
+/// Applies `SO_SNDBUF`/`SO_RCVBUF` to `stream`, ignoring any error - the OS
+/// is free to clamp, double, or reject whatever is asked for, and none of
+/// that should fail the connection. Works through a duplicated handle so
+/// `stream` keeps ownership of the original; `SO_SNDBUF`/`SO_RCVBUF` are
+/// properties of the underlying socket, shared across the duplicate. See
+/// `ClientBuilder::socket_buffer_sizes`.
+#[cfg(feature = "sync")]
+fn set_socket_buffer_sizes(stream: &TcpStream, send: usize, recv: usize) {
+	if let Ok(dup) = stream.try_clone() {
+		let socket = Socket::from(dup);
+		let _ = socket.set_send_buffer_size(send);
+		let _ = socket.set_recv_buffer_size(recv);
+	}
+}
+
+/// Tries connecting to each of `addrs` in order, succeeding on the first
+/// one that accepts a connection - the same policy `TcpStream::connect`
+/// uses for a multi-address `ToSocketAddrs`. See `ClientBuilder::resolve_with`.
+#[cfg(feature = "sync")]
+fn connect_to_any(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+	let mut last_err = None;
+	for addr in addrs {
+		match TcpStream::connect(addr) {
+			Ok(stream) => return Ok(stream),
+			Err(e) => last_err = Some(e),
+		}
+	}
+	Err(last_err.unwrap_or_else(|| {
+		io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+	}))
+}
+
+/// Connects to every one of `addrs` in parallel on its own thread,
+/// returning the first one to succeed - a rough approximation of "Happy
+/// Eyeballs" (RFC 8305). The other threads' connections, if and when they
+/// succeed, are simply dropped. See `ClientBuilder::happy_eyeballs`.
+#[cfg(feature = "sync")]
+fn connect_happy_eyeballs(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+	if addrs.is_empty() {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"could not resolve to any addresses",
+		));
+	}
+	if addrs.len() == 1 {
+		return TcpStream::connect(addrs[0]);
+	}
+
+	let (tx, rx) = mpsc::channel();
+	for &addr in addrs {
+		let tx = tx.clone();
+		thread::spawn(move || {
+			let _ = tx.send(TcpStream::connect(addr));
+		});
+	}
+	drop(tx);
+
+	let mut last_err = None;
+	for _ in 0..addrs.len() {
+		match rx.recv() {
+			Ok(Ok(stream)) => return Ok(stream),
+			Ok(Err(e)) => last_err = Some(e),
+			Err(_) => break,
+		}
+	}
+	Err(last_err.unwrap_or_else(|| {
+		io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+	}))
+}
+
 mod tests {
 	#[test]
 	fn build_client_with_protocols() {
@@ -970,4 +1398,217 @@ mod tests {
 		assert!(protos.contains(&"electric".to_string()));
 		assert!(!protos.contains(&"rust-websocket".to_string()));
 	}
+
+	#[test]
+	fn read_buffer_capacity_controls_the_bufreader_allocated_for_connect_on() {
+		use super::*;
+		use stream::ReadWritePair;
+		use std::io::Cursor;
+
+		let accept = b"HTTP/1.1 101 Switching Protocols\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+\r\n";
+
+		let client = ClientBuilder::new("wss://test.ws")
+			.unwrap()
+			.key(b"the sample nonce".clone())
+			.read_buffer_capacity(64 * 1024)
+			.connect_on(ReadWritePair(Cursor::new(&accept[..]), Cursor::new(Vec::new())))
+			.unwrap();
+
+		let (_, parts) = client.into_stream();
+		let (buf, _, _) = parts.unwrap();
+		assert_eq!(buf.len(), 64 * 1024);
+	}
+
+	#[test]
+	fn socket_buffer_sizes_are_applied_to_the_connected_stream() {
+		use super::*;
+		use std::net::TcpListener;
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let accepting = thread::spawn(move || listener.accept().unwrap());
+
+		let url = format!("ws://{}/", addr);
+		let mut builder = ClientBuilder::new(&url)
+			.unwrap()
+			.socket_buffer_sizes(256 * 1024, 256 * 1024);
+
+		let tcp_stream = builder.establish_tcp(Some(false)).unwrap();
+		let _ = accepting.join().unwrap();
+
+		let socket = Socket::from(tcp_stream.try_clone().unwrap());
+		// The OS is free to clamp/double what was asked for, so just check
+		// it moved off of whatever the (much smaller) default was.
+		assert!(socket.send_buffer_size().unwrap() >= 256 * 1024);
+		assert!(socket.recv_buffer_size().unwrap() >= 256 * 1024);
+	}
+
+	#[test]
+	fn resolve_with_overrides_the_addresses_connected_to() {
+		use super::*;
+		use std::net::TcpListener;
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let accepting = thread::spawn(move || listener.accept().unwrap());
+
+		// the URL names a host that does not exist; the custom resolver
+		// redirects the connection to the real listener instead.
+		let mut builder = ClientBuilder::new("ws://nonexistent.invalid/")
+			.unwrap()
+			.resolve_with(move |_host, _port| Ok(vec![addr]));
+
+		builder.establish_tcp(Some(false)).unwrap();
+		let _ = accepting.join().unwrap();
+	}
+
+	#[test]
+	fn resolve_with_surfaces_the_resolver_error() {
+		use super::*;
+
+		let mut builder = ClientBuilder::new("ws://nonexistent.invalid/")
+			.unwrap()
+			.resolve_with(|_host, _port| {
+				Err(io::Error::new(io::ErrorKind::NotFound, "no such service"))
+			});
+
+		match builder.establish_tcp(Some(false)) {
+			Err(WebSocketError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+			other => panic!("expected an IoError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn happy_eyeballs_connects_to_the_only_address_that_accepts() {
+		use super::*;
+		use std::net::TcpListener;
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let accepting = thread::spawn(move || listener.accept().unwrap());
+
+		// one address nothing is listening on, and the real listener; happy
+		// eyeballs races both and should come back with the real one.
+		let dead_addr = SocketAddr::from(([127, 0, 0, 1], 1));
+		let mut builder = ClientBuilder::new("ws://nonexistent.invalid/")
+			.unwrap()
+			.happy_eyeballs(true)
+			.resolve_with(move |_host, _port| Ok(vec![dead_addr, addr]));
+
+		builder.establish_tcp(Some(false)).unwrap();
+		let _ = accepting.join().unwrap();
+	}
+
+	#[test]
+	fn happy_eyeballs_surfaces_an_error_when_every_address_refuses() {
+		use super::*;
+
+		let dead_addrs = vec![
+			SocketAddr::from(([127, 0, 0, 1], 1)),
+			SocketAddr::from(([127, 0, 0, 1], 2)),
+		];
+		let mut builder = ClientBuilder::new("ws://nonexistent.invalid/")
+			.unwrap()
+			.happy_eyeballs(true)
+			.resolve_with(move |_host, _port| Ok(dead_addrs.clone()));
+
+		match builder.establish_tcp(Some(false)) {
+			Err(WebSocketError::IoError(_)) => {}
+			other => panic!("expected an IoError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn sni_hostname_overrides_url_host_and_can_be_cleared() {
+		use super::*;
+
+		let builder = ClientBuilder::new("wss://127.0.0.1:8080/hello").unwrap();
+		let (host, _) = builder.extract_host_ssl_conn(None).unwrap();
+		assert_eq!(host, "127.0.0.1");
+
+		let builder = builder.sni_hostname("example.com");
+		let (host, _) = builder.extract_host_ssl_conn(None).unwrap();
+		assert_eq!(host, "example.com");
+
+		let builder = builder.clear_sni_hostname();
+		let (host, _) = builder.extract_host_ssl_conn(None).unwrap();
+		assert_eq!(host, "127.0.0.1");
+	}
+
+	#[test]
+	fn connect_on_surfaces_the_status_of_a_rejected_handshake() {
+		use super::*;
+		use stream::ReadWritePair;
+		use std::io::Cursor;
+
+		let response = b"HTTP/1.1 403 Forbidden\r\nX-Deny-Reason: no soup for you\r\n\r\n";
+		let input = Cursor::new(&response[..]);
+		let output = Cursor::new(Vec::new());
+
+		let result = ClientBuilder::new("ws://example.org")
+			.unwrap()
+			.connect_on(ReadWritePair(input, output));
+
+		match result {
+			Err(WebSocketError::HandshakeFailed { status, headers }) => {
+				assert_eq!(status, StatusCode::Forbidden);
+				assert_eq!(
+					headers.get_raw("X-Deny-Reason").unwrap()[0],
+					b"no soup for you"
+				);
+			}
+			other => panic!("expected HandshakeFailed, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn connect_on_rejects_an_extension_the_server_accepted_but_was_never_offered() {
+		use super::*;
+		use header::extensions::Extension;
+		use stream::ReadWritePair;
+		use std::io::Cursor;
+		use std::str::FromStr;
+
+		// "the sample nonce" (the well-known RFC6455 handshake example) and
+		// its matching Sec-WebSocket-Accept value.
+		let key = WebSocketKey::from_str("dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+		let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+Sec-WebSocket-Extensions: permessage-deflate\r\n\r\n";
+		let input = Cursor::new(&response[..]);
+		let output = Cursor::new(Vec::new());
+
+		let result = ClientBuilder::new("ws://example.org")
+			.unwrap()
+			.key(key.0)
+			.add_extension(Extension {
+				name: "crypt-omemo".to_string(),
+				params: vec![],
+			})
+			.connect_on(ReadWritePair(input, output));
+
+		match result {
+			Err(WebSocketError::ResponseError(_)) => {}
+			other => panic!("expected ResponseError, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn danger_accept_invalid_certs_still_builds_a_connector() {
+		use super::*;
+
+		let builder = ClientBuilder::new("wss://127.0.0.1:8080/hello")
+			.unwrap()
+			.danger_accept_invalid_certs(true);
+		assert!(builder.extract_host_ssl_conn(None).is_ok());
+	}
 }
@@ -12,12 +12,41 @@
 //!
 //! Many of the useful things from this module will be hoisted and re-exported under the
 //! `websocket::{sync, async}::client` module which will have all sync or all async things.
+//!
+//! There is deliberately no single trait spanning both halves (e.g. a
+//! `WsConnection` with `send`/`recv`): `sync::Client` is blocking and owns
+//! its stream, while the async `Client` (`client::async::Client`) is a bare
+//! type alias for a `Framed<S, MessageCodec>` - a `Stream`/`Sink` this crate
+//! doesn't wrap in a struct of its own, so there is nowhere to hang shared
+//! inherent or trait methods without either boxing futures everywhere or
+//! replacing that alias with a breaking redesign. What *is* shared is the
+//! message-construction and closing-handshake layer both halves build on -
+//! `OwnedMessage::close_because`/`echo_close` in `message` - so application
+//! code that wants to treat "build a Close" and "what do I send back for a
+//! Close I received" the same way across sync and async already can,
+//! without this crate picking blocking vs. futures-based method signatures
+//! for it.
 
 pub mod builder;
 pub use self::builder::{ClientBuilder, ParseError, Url};
 
+#[cfg(feature = "sync")]
+pub mod socks;
+#[cfg(feature = "sync")]
+pub use self::socks::Socks5Auth;
+
 #[cfg(feature = "async")]
 pub mod async;
 
 #[cfg(feature = "sync")]
 pub mod sync;
+
+#[cfg(feature = "sync")]
+pub mod reconnect;
+#[cfg(feature = "sync")]
+pub use self::reconnect::{Backoff, ReconnectingClient};
+
+#[cfg(feature = "sync")]
+pub mod rpc;
+#[cfg(feature = "sync")]
+pub use self::rpc::RpcClient;
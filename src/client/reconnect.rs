@@ -0,0 +1,181 @@
+//! A `ClientBuilder` wrapper that reconnects with backoff when a connection
+//! drops, for long-lived client apps that would rather keep retrying than
+//! treat every dropped connection as fatal.
+//!
+//! Re-sending in-flight application messages across a reconnect isn't
+//! something this crate can do on the caller's behalf - only the caller
+//! knows what, if anything, still needs to go out once the new handshake
+//! completes (a queued message, a resubscribe, re-authenticating, ...). Use
+//! `ReconnectingClient::on_connect` to replay whatever that is.
+
+use client::builder::ClientBuilder;
+use client::sync::Client;
+use rand::Rng;
+use result::{WebSocketError, WebSocketResult};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+/// Backoff parameters for `ReconnectingClient::connect`.
+///
+/// Delays grow exponentially from `initial` up to `max` (doubling by
+/// default), and each one is jittered to a random point in
+/// `[delay / 2, delay)` so that many clients reconnecting at once don't all
+/// retry in lockstep.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+	initial: Duration,
+	max: Duration,
+	multiplier: f64,
+	max_attempts: Option<u32>,
+}
+
+impl Backoff {
+	/// Starts at `initial`, doubling after every failed attempt up to
+	/// `max`, with no limit on the number of attempts.
+	pub fn new(initial: Duration, max: Duration) -> Self {
+		Backoff {
+			initial,
+			max,
+			multiplier: 2.0,
+			max_attempts: None,
+		}
+	}
+
+	/// Sets the factor delays grow by after each failed attempt. Default `2.0`.
+	pub fn multiplier(mut self, multiplier: f64) -> Self {
+		self.multiplier = multiplier;
+		self
+	}
+
+	/// Caps how many attempts `ReconnectingClient::connect` makes before
+	/// giving up and returning the last error. `None` (the default) means
+	/// retry forever.
+	pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+		self.max_attempts = Some(max_attempts);
+		self
+	}
+
+	fn delay_for_attempt(&self, attempt: u32) -> Duration {
+		let initial_ms = duration_millis(self.initial) as f64;
+		let max_ms = duration_millis(self.max) as f64;
+		let scaled = initial_ms * self.multiplier.powi(attempt as i32);
+		let capped = scaled.min(max_ms).max(0.0);
+		if capped < 1.0 {
+			return Duration::from_millis(0);
+		}
+		let jittered = Rng::gen_range(&mut ::rand::thread_rng(), capped / 2.0, capped);
+		Duration::from_millis(jittered as u64)
+	}
+}
+
+fn duration_millis(d: Duration) -> u64 {
+	d.as_secs() * 1000 + u64::from(d.subsec_nanos() / 1_000_000)
+}
+
+/// A `ClientBuilder` wrapper that reconnects with backoff when the
+/// connection drops. See the module documentation.
+pub struct ReconnectingClient<'u> {
+	builder: ClientBuilder<'u>,
+	backoff: Backoff,
+	on_connect: Option<Box<FnMut(&mut Client<TcpStream>) + Send>>,
+	on_disconnect: Option<Box<FnMut(&WebSocketError) + Send>>,
+}
+
+impl<'u> ReconnectingClient<'u> {
+	/// Wraps `builder`, retrying failed connection attempts according to
+	/// `backoff`.
+	pub fn new(builder: ClientBuilder<'u>, backoff: Backoff) -> Self {
+		ReconnectingClient {
+			builder,
+			backoff,
+			on_connect: None,
+			on_disconnect: None,
+		}
+	}
+
+	/// Sets a callback run every time `connect` establishes a new
+	/// connection (the first one and every reconnect), with the fresh
+	/// `Client`. This is the place to resubscribe, re-authenticate, or
+	/// flush any messages queued while disconnected.
+	pub fn on_connect<F>(mut self, callback: F) -> Self
+	where
+		F: FnMut(&mut Client<TcpStream>) + Send + 'static,
+	{
+		self.on_connect = Some(Box::new(callback));
+		self
+	}
+
+	/// Sets a callback run every time an attempt in `connect` fails, with
+	/// the error, before the next attempt's backoff delay.
+	pub fn on_disconnect<F>(mut self, callback: F) -> Self
+	where
+		F: FnMut(&WebSocketError) + Send + 'static,
+	{
+		self.on_disconnect = Some(Box::new(callback));
+		self
+	}
+
+	/// Connects, retrying on failure with the backoff given to `new` until
+	/// either an attempt succeeds or `Backoff::max_attempts` is reached (if
+	/// set), in which case the last error is returned.
+	pub fn connect(&mut self) -> WebSocketResult<Client<TcpStream>> {
+		let mut attempt = 0;
+		loop {
+			match self.builder.connect_insecure() {
+				Ok(mut client) => {
+					if let Some(ref mut on_connect) = self.on_connect {
+						on_connect(&mut client);
+					}
+					return Ok(client);
+				}
+				Err(err) => {
+					if let Some(ref mut on_disconnect) = self.on_disconnect {
+						on_disconnect(&err);
+					}
+					if let Some(max_attempts) = self.backoff.max_attempts {
+						if attempt + 1 >= max_attempts {
+							return Err(err);
+						}
+					}
+					thread::sleep(self.backoff.delay_for_attempt(attempt));
+					attempt += 1;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn backoff_delay_grows_and_is_capped_at_max() {
+		let backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(400));
+
+		// jitter puts each delay in [delay / 2, delay), so check the upper bound.
+		assert!(backoff.delay_for_attempt(0) < Duration::from_millis(100));
+		assert!(backoff.delay_for_attempt(1) < Duration::from_millis(200));
+		assert!(backoff.delay_for_attempt(5) < Duration::from_millis(400));
+	}
+
+	#[test]
+	fn connect_gives_up_after_max_attempts() {
+		let builder = ClientBuilder::new("ws://127.0.0.1:1").unwrap();
+		let backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(1)).max_attempts(3);
+		let mut reconnecting = ReconnectingClient::new(builder, backoff);
+
+		let attempts = ::std::sync::Arc::new(::std::sync::atomic::AtomicUsize::new(0));
+		let counted = attempts.clone();
+		reconnecting = ReconnectingClient {
+			on_disconnect: Some(Box::new(move |_err| {
+				counted.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+			})),
+			..reconnecting
+		};
+
+		assert!(reconnecting.connect().is_err());
+		assert_eq!(attempts.load(::std::sync::atomic::Ordering::SeqCst), 3);
+	}
+}
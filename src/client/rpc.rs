@@ -0,0 +1,308 @@
+//! A request/response correlation helper built on `Client::split`, for an
+//! application protocol that multiplexes many concurrent RPCs over one
+//! websocket connection - see `RpcClient`.
+
+use byteorder::{BigEndian, ByteOrder};
+use client::sync::Client;
+use message::OwnedMessage;
+use receiver::Reader;
+use result::{WebSocketError, WebSocketResult};
+use sender::SharedSender;
+#[cfg(test)]
+use sender::Writer;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use stream::sync::{Splittable, Stream};
+
+type Pending = Arc<Mutex<HashMap<u64, mpsc::Sender<WebSocketResult<Vec<u8>>>>>>;
+
+/// Correlates binary request/response pairs sent over a split `Client`, for
+/// a protocol where each message is an 8-byte big-endian call id followed
+/// by an opaque payload. A websocket message is already a complete,
+/// length-delimited unit, so that id prefix is the only framing this adds.
+///
+/// Splits the `Client` internally (see `Client::split`) and spawns one
+/// background thread that drains incoming messages and routes each
+/// response to whichever `call` is waiting on its id. `call` itself may be
+/// invoked from any number of threads at once, each with a distinct id, to
+/// have several requests in flight concurrently over the one connection -
+/// `RpcClient` deliberately doesn't allocate ids itself, since a caller
+/// coordinating several concurrent callers usually already has its own
+/// counter or generator to hand it one.
+///
+/// Dropping the `RpcClient` drops its `SharedSender` handle; the background
+/// thread keeps running until the connection closes or errors out (at
+/// which point it fails every still-pending `call` and exits), since the
+/// `Reader` half it owns has no way to be woken up out of a blocking read
+/// otherwise. Call `shutdown` first if you need the connection closed
+/// promptly instead.
+pub struct RpcClient<W>
+where
+	W: Write + Send + 'static,
+{
+	sender: SharedSender<W>,
+	pending: Pending,
+	reader: Option<thread::JoinHandle<()>>,
+}
+
+impl<W> RpcClient<W>
+where
+	W: Write + Send + 'static,
+{
+	/// Splits `client` and starts routing its incoming messages to whichever
+	/// `call` is waiting on the response's id.
+	pub fn new<S>(client: Client<S>) -> io::Result<Self>
+	where
+		S: Splittable<Writer = W> + Stream,
+		<S as Splittable>::Reader: Read + Send + 'static,
+	{
+		let (reader, writer) = client.split()?;
+		let sender = SharedSender::new(writer);
+		let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+
+		let reader_pending = pending.clone();
+		let reader = thread::spawn(move || read_loop(reader, reader_pending));
+
+		Ok(RpcClient {
+			sender,
+			pending,
+			reader: Some(reader),
+		})
+	}
+
+	/// Sends `request` tagged with `id` and blocks until either a response
+	/// tagged with the same `id` arrives, `timeout` elapses, or the
+	/// connection closes or errors out.
+	///
+	/// On timeout, `id` is forgotten (cancelled): a response that arrives
+	/// late for it is silently dropped by the background thread, unless a
+	/// newer call has registered the same id by then, in which case that
+	/// call receives the late response.
+	/// Giving up on a call this way does not close the underlying
+	/// connection - other calls already in flight are unaffected.
+	pub fn call(&self, id: u64, request: &[u8], timeout: Duration) -> WebSocketResult<Vec<u8>> {
+		let (tx, rx) = mpsc::channel();
+		self.pending.lock().unwrap().insert(id, tx);
+
+		let mut framed = Vec::with_capacity(8 + request.len());
+		framed.extend_from_slice(&[0u8; 8]);
+		BigEndian::write_u64(&mut framed[..8], id);
+		framed.extend_from_slice(request);
+
+		if let Err(e) = self.sender.send(&OwnedMessage::Binary(framed)) {
+			self.pending.lock().unwrap().remove(&id);
+			return Err(e);
+		}
+
+		match rx.recv_timeout(timeout) {
+			Ok(result) => result,
+			Err(_) => {
+				self.pending.lock().unwrap().remove(&id);
+				Err(WebSocketError::IoError(io::Error::new(
+					io::ErrorKind::TimedOut,
+					"no response for this call's id within the timeout",
+				)))
+			}
+		}
+	}
+
+	/// Cancels a `call` still in flight for `id`, if any, failing it
+	/// immediately with `WebSocketError::IoError(ErrorKind::Interrupted)`
+	/// instead of waiting out its timeout. Harmless to call for an id with
+	/// no call waiting on it (e.g. one that already completed).
+	pub fn cancel(&self, id: u64) {
+		if let Some(tx) = self.pending.lock().unwrap().remove(&id) {
+			let _ = tx.send(Err(WebSocketError::IoError(io::Error::new(
+				io::ErrorKind::Interrupted,
+				"call was cancelled",
+			))));
+		}
+	}
+
+	/// Sends a Close message over the connection; once the peer answers
+	/// with its own Close (or the connection errors out), the background
+	/// reader thread fails every still-pending `call` and exits. Blocks until
+	/// that thread has actually exited.
+	pub fn shutdown(&mut self) -> WebSocketResult<()> {
+		self.sender.send(&OwnedMessage::Close(None))?;
+		if let Some(reader) = self.reader.take() {
+			let _ = reader.join();
+		}
+		Ok(())
+	}
+}
+
+fn read_loop<R>(mut reader: Reader<R>, pending: Pending)
+where
+	R: Read,
+{
+	loop {
+		let message = match reader.recv_message() {
+			Ok(message) => message,
+			Err(err) => {
+				fail_all_pending(&pending, err);
+				return;
+			}
+		};
+
+		match message {
+			OwnedMessage::Binary(bytes) => {
+				if bytes.len() < 8 {
+					continue;
+				}
+				let id = BigEndian::read_u64(&bytes[..8]);
+				if let Some(tx) = pending.lock().unwrap().remove(&id) {
+					let _ = tx.send(Ok(bytes[8..].to_vec()));
+				}
+			}
+			OwnedMessage::Close(data) => {
+				fail_all_pending(
+					&pending,
+					WebSocketError::IoError(io::Error::new(
+						io::ErrorKind::ConnectionAborted,
+						format!("connection closed: {:?}", data),
+					)),
+				);
+				return;
+			}
+			// pings, pongs, and text frames carry no call id - not part of
+			// this protocol, so just skip them.
+			_ => {}
+		}
+	}
+}
+
+fn fail_all_pending(pending: &Pending, err: WebSocketError) {
+	let mut pending = pending.lock().unwrap();
+	for (_, tx) in pending.drain() {
+		let _ = tx.send(Err(clone_error(&err)));
+	}
+}
+
+/// `WebSocketError` isn't `Clone`; every still-pending call needs its own
+/// copy of the one error that ended the read loop, so this reduces it down
+/// to the same shape `io::Error`'s own `Clone`-less nature forces everyone
+/// else in `std` to work around: a fresh `IoError` carrying the original's
+/// message.
+fn clone_error(err: &WebSocketError) -> WebSocketError {
+	WebSocketError::IoError(io::Error::new(io::ErrorKind::Other, err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use client::builder::ClientBuilder;
+	use server::upgrade::sync::IntoWs;
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+
+	/// `Client<TcpStream>` holds a `frame_hook: Option<Box<FnMut(&DataFrame)>>`
+	/// with no `Send` bound, so a whole `Client` can never cross a thread
+	/// boundary, and completing the handshake on one side requires the
+	/// other side to be actively reading/writing concurrently rather than
+	/// sequentially. The accept side therefore has to run its whole
+	/// handshake - and split immediately after, since `Reader`/`Writer` are
+	/// `Send` where `Client` isn't - on its own thread, concurrently with
+	/// the client side connecting on this one.
+	fn connected_pair() -> (
+		Client<TcpStream>,
+		Reader<TcpStream>,
+		Writer<TcpStream>,
+	) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let accept_thread = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let server_client = stream.into_ws().unwrap().accept().unwrap();
+			server_client.split().unwrap()
+		});
+
+		let client = ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+
+		let (server_reader, server_writer) = accept_thread.join().unwrap();
+		(client, server_reader, server_writer)
+	}
+
+	/// Runs an echo server over an already-split pair on a background
+	/// thread, so it can run concurrently with the foreground `rpc.call()`s.
+	fn spawn_echo_server(mut reader: Reader<TcpStream>, mut writer: Writer<TcpStream>) {
+		thread::spawn(move || loop {
+			match reader.recv_message().unwrap() {
+				OwnedMessage::Binary(bytes) => {
+					writer.send_message(&OwnedMessage::Binary(bytes)).unwrap();
+				}
+				OwnedMessage::Close(_) => return,
+				_ => {}
+			}
+		});
+	}
+
+	#[test]
+	fn call_returns_the_echoed_payload_tagged_with_its_id() {
+		let (client, server_reader, server_writer) = connected_pair();
+		let rpc = RpcClient::new(client).unwrap();
+		spawn_echo_server(server_reader, server_writer);
+
+		let response = rpc.call(42, b"hello", Duration::from_secs(5)).unwrap();
+		assert_eq!(response, b"hello");
+	}
+
+	#[test]
+	fn concurrent_calls_each_get_their_own_response() {
+		let (client, server_reader, server_writer) = connected_pair();
+		let rpc = Arc::new(RpcClient::new(client).unwrap());
+		spawn_echo_server(server_reader, server_writer);
+
+		let callers: Vec<_> = (0..8)
+			.map(|id| {
+				let rpc = rpc.clone();
+				thread::spawn(move || {
+					let payload = format!("payload-{}", id).into_bytes();
+					let response = rpc.call(id, &payload, Duration::from_secs(5)).unwrap();
+					assert_eq!(response, payload);
+				})
+			})
+			.collect();
+
+		for caller in callers {
+			caller.join().unwrap();
+		}
+	}
+
+	#[test]
+	fn call_times_out_and_forgets_the_id_when_nothing_answers() {
+		let (client, _server_reader, _server_writer) = connected_pair();
+		let rpc = RpcClient::new(client).unwrap();
+
+		match rpc.call(1, b"hello", Duration::from_millis(50)) {
+			Err(WebSocketError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+			other => panic!("expected a TimedOut IoError, got {:?}", other),
+		}
+		assert!(rpc.pending.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn cancel_fails_a_call_still_in_flight() {
+		let (client, _server_reader, _server_writer) = connected_pair();
+		let rpc = Arc::new(RpcClient::new(client).unwrap());
+
+		let caller_rpc = rpc.clone();
+		let caller = thread::spawn(move || caller_rpc.call(7, b"hello", Duration::from_secs(5)));
+
+		// give the call a moment to register itself before cancelling it
+		thread::sleep(Duration::from_millis(50));
+		rpc.cancel(7);
+
+		match caller.join().unwrap() {
+			Err(WebSocketError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::Interrupted),
+			other => panic!("expected an Interrupted IoError, got {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,297 @@
+//! A minimal SOCKS5 client handshake (RFC 1928/1929), used by
+//! `ClientBuilder::socks5_proxy` to tunnel the WebSocket connection through
+//! a SOCKS5 proxy before performing the WS upgrade over it.
+
+use result::{WebSocketError, WebSocketResult};
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+
+/// Username/password credentials for SOCKS5's username/password
+/// authentication method (RFC 1929).
+#[derive(Clone, Debug)]
+pub struct Socks5Auth {
+	/// The username to present to the proxy
+	pub username: String,
+	/// The password to present to the proxy
+	pub password: String,
+}
+
+impl Socks5Auth {
+	/// Create a new set of SOCKS5 username/password credentials.
+	pub fn new<U, P>(username: U, password: P) -> Socks5Auth
+	where
+		U: Into<String>,
+		P: Into<String>,
+	{
+		Socks5Auth {
+			username: username.into(),
+			password: password.into(),
+		}
+	}
+}
+
+/// Connects to `proxy`, negotiates SOCKS5 with `auth` (or no authentication
+/// if `None`), and asks it to `CONNECT` to `host`:`port`. `host` may be an
+/// IPv4 address, an IPv6 address, or a domain name, all of which the proxy
+/// is asked to resolve itself. On success, returns the now-tunnelled
+/// `TcpStream`, ready for the WebSocket handshake to be performed directly
+/// over it.
+pub fn connect<A>(
+	proxy: A,
+	host: &str,
+	port: u16,
+	auth: Option<&Socks5Auth>,
+) -> WebSocketResult<TcpStream>
+where
+	A: ToSocketAddrs,
+{
+	let mut stream = TcpStream::connect(proxy)?;
+	negotiate_auth(&mut stream, auth)?;
+	request_connect(&mut stream, host, port)?;
+	Ok(stream)
+}
+
+fn negotiate_auth<S>(stream: &mut S, auth: Option<&Socks5Auth>) -> WebSocketResult<()>
+where
+	S: Read + Write,
+{
+	let methods: &[u8] = if auth.is_some() { &[0x00, 0x02] } else { &[0x00] };
+	let mut greeting = Vec::with_capacity(2 + methods.len());
+	greeting.push(0x05); // SOCKS version
+	greeting.push(methods.len() as u8);
+	greeting.extend_from_slice(methods);
+	stream.write_all(&greeting)?;
+
+	let mut reply = [0u8; 2];
+	stream.read_exact(&mut reply)?;
+	if reply[0] != 0x05 {
+		return Err(WebSocketError::ProtocolError(
+			"SOCKS5 proxy replied with an unexpected protocol version",
+		));
+	}
+
+	match (reply[1], auth) {
+		(0x00, _) => Ok(()),
+		(0x02, Some(auth)) => negotiate_user_pass(stream, auth),
+		(0x02, None) => Err(WebSocketError::ProtocolError(
+			"SOCKS5 proxy requires username/password authentication",
+		)),
+		(0xff, _) => Err(WebSocketError::ProtocolError(
+			"SOCKS5 proxy rejected all offered authentication methods",
+		)),
+		(_, _) => Err(WebSocketError::ProtocolError(
+			"SOCKS5 proxy selected an unsupported authentication method",
+		)),
+	}
+}
+
+fn negotiate_user_pass<S>(stream: &mut S, auth: &Socks5Auth) -> WebSocketResult<()>
+where
+	S: Read + Write,
+{
+	if auth.username.len() > 255 || auth.password.len() > 255 {
+		return Err(WebSocketError::RequestError(
+			"SOCKS5 username/password must each be at most 255 bytes",
+		));
+	}
+
+	let mut request = Vec::with_capacity(3 + auth.username.len() + auth.password.len());
+	request.push(0x01); // username/password subnegotiation version
+	request.push(auth.username.len() as u8);
+	request.extend_from_slice(auth.username.as_bytes());
+	request.push(auth.password.len() as u8);
+	request.extend_from_slice(auth.password.as_bytes());
+	stream.write_all(&request)?;
+
+	let mut reply = [0u8; 2];
+	stream.read_exact(&mut reply)?;
+	if reply[1] != 0x00 {
+		return Err(WebSocketError::RequestError(
+			"SOCKS5 proxy rejected the supplied username/password",
+		));
+	}
+	Ok(())
+}
+
+fn request_connect<S>(stream: &mut S, host: &str, port: u16) -> WebSocketResult<()>
+where
+	S: Read + Write,
+{
+	let mut request = vec![0x05, 0x01, 0x00]; // VER, CMD=CONNECT, RSV
+	if let Ok(ip) = host.parse::<IpAddr>() {
+		match ip {
+			IpAddr::V4(ip) => {
+				request.push(0x01);
+				request.extend_from_slice(&ip.octets());
+			}
+			IpAddr::V6(ip) => {
+				request.push(0x04);
+				request.extend_from_slice(&ip.octets());
+			}
+		}
+	} else {
+		if host.len() > 255 {
+			return Err(WebSocketError::RequestError(
+				"SOCKS5 domain names must be at most 255 bytes",
+			));
+		}
+		request.push(0x03);
+		request.push(host.len() as u8);
+		request.extend_from_slice(host.as_bytes());
+	}
+	request.push((port >> 8) as u8);
+	request.push((port & 0xff) as u8);
+	stream.write_all(&request)?;
+
+	let mut head = [0u8; 4];
+	stream.read_exact(&mut head)?;
+	if head[0] != 0x05 {
+		return Err(WebSocketError::ProtocolError(
+			"SOCKS5 proxy replied with an unexpected protocol version",
+		));
+	}
+	if head[1] != 0x00 {
+		return Err(socks_reply_error(head[1]));
+	}
+
+	// Skip over the bound address the proxy reports, whose length depends
+	// on its address type; we only care that the tunnel is up.
+	match head[3] {
+		0x01 => read_and_discard(stream, 4 + 2),
+		0x03 => {
+			let mut len = [0u8; 1];
+			stream.read_exact(&mut len)?;
+			read_and_discard(stream, len[0] as usize + 2)
+		}
+		0x04 => read_and_discard(stream, 16 + 2),
+		_ => Err(WebSocketError::ProtocolError(
+			"SOCKS5 proxy reported an unrecognised bound address type",
+		)),
+	}
+}
+
+fn read_and_discard<S>(stream: &mut S, len: usize) -> WebSocketResult<()>
+where
+	S: Read,
+{
+	let mut rest = vec![0u8; len];
+	stream.read_exact(&mut rest)?;
+	Ok(())
+}
+
+fn socks_reply_error(code: u8) -> WebSocketError {
+	WebSocketError::RequestError(match code {
+		0x01 => "SOCKS5 proxy: general failure",
+		0x02 => "SOCKS5 proxy: connection not allowed by ruleset",
+		0x03 => "SOCKS5 proxy: network unreachable",
+		0x04 => "SOCKS5 proxy: host unreachable",
+		0x05 => "SOCKS5 proxy: connection refused",
+		0x06 => "SOCKS5 proxy: TTL expired",
+		0x07 => "SOCKS5 proxy: command not supported",
+		0x08 => "SOCKS5 proxy: address type not supported",
+		_ => "SOCKS5 proxy: unknown error",
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	use stream::ReadWritePair;
+
+	fn stream(input: Vec<u8>) -> ReadWritePair<Cursor<Vec<u8>>, Vec<u8>> {
+		ReadWritePair(Cursor::new(input), Vec::new())
+	}
+
+	#[test]
+	fn connects_without_authentication() {
+		let mut stream = stream(vec![
+			0x05, 0x00, // greeting reply: version 5, no auth required
+			0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0, // connect reply, IPv4 bound addr
+		]);
+
+		negotiate_auth(&mut stream, None).unwrap();
+		request_connect(&mut stream, "93.184.216.34", 80).unwrap();
+
+		let sent = stream.1;
+		assert_eq!(&sent[..3], &[0x05, 0x01, 0x00]);
+		assert_eq!(&sent[3..], &[0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0, 80]);
+	}
+
+	#[test]
+	fn negotiates_username_password_authentication() {
+		let mut stream = stream(vec![
+			0x05, 0x02, // greeting reply: version 5, username/password required
+			0x01, 0x00, // subnegotiation reply: version 1, success
+		]);
+		let auth = Socks5Auth::new("alice", "wonderland");
+
+		negotiate_auth(&mut stream, Some(&auth)).unwrap();
+
+		assert_eq!(
+			stream.1,
+			vec![0x05, 0x02, 0x00, 0x02, 0x01, 5, b'a', b'l', b'i', b'c', b'e', 10, b'w', b'o', b'n',
+				b'd', b'e', b'r', b'l', b'a', b'n', b'd']
+		);
+	}
+
+	#[test]
+	fn rejects_bad_credentials() {
+		let mut stream = stream(vec![0x05, 0x02, 0x01, 0x01]);
+		let auth = Socks5Auth::new("alice", "wrong");
+
+		match negotiate_auth(&mut stream, Some(&auth)) {
+			Err(WebSocketError::RequestError(_)) => {}
+			other => panic!("expected a RequestError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn fails_when_proxy_has_no_acceptable_method() {
+		let mut stream = stream(vec![0x05, 0xff]);
+
+		match negotiate_auth(&mut stream, None) {
+			Err(WebSocketError::ProtocolError(_)) => {}
+			other => panic!("expected a ProtocolError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn encodes_domain_name_targets() {
+		let mut stream = stream(vec![0x05, 0x00, 0x00, 0x03, 9, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 80]);
+
+		request_connect(&mut stream, "example.com", 443).unwrap();
+
+		let mut expected = vec![0x05, 0x01, 0x00, 0x03, 11];
+		expected.extend_from_slice(b"example.com");
+		expected.push(1);
+		expected.push(0xbb);
+		assert_eq!(stream.1, expected);
+	}
+
+	#[test]
+	fn encodes_ipv6_targets() {
+		let mut stream = stream(vec![
+			0x05, 0x00, 0x00, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+		]);
+
+		request_connect(&mut stream, "::1", 8080).unwrap();
+
+		let mut expected = vec![0x05, 0x01, 0x00, 0x04];
+		expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+		expected.extend_from_slice(&[0x1f, 0x90]);
+		assert_eq!(stream.1, expected);
+	}
+
+	#[test]
+	fn maps_connect_refused_to_a_descriptive_error() {
+		// VER=5, REP=5 (connection refused), RSV=0, ATYP=1 (IPv4), then a
+		// dummy 4-byte address and 2-byte port.
+		let mut stream = stream(vec![0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+
+		match request_connect(&mut stream, "93.184.216.34", 80) {
+			Err(WebSocketError::RequestError(msg)) => assert!(msg.contains("refused")),
+			other => panic!("expected a descriptive RequestError, got {:?}", other),
+		}
+	}
+}
@@ -9,6 +9,13 @@
 //! encoding and decoding websocket messages (and dataframes if you want to go
 //! more low level) in the `ws` module.
 //! See it's module level documentation for more info.
+//!
+//! None of these codecs are tied to any particular reactor or stream type -
+//! they only implement `tokio::codec::{Decoder, Encoder}` (which is itself a
+//! re-export of the stream-agnostic `tokio_io::codec` traits), so `.framed()`
+//! works over anything implementing `tokio::io::{AsyncRead, AsyncWrite}`, not
+//! just a `TcpStream`. `ws::tests` exercises this with plain in-memory
+//! `Cursor`s via `stream::ReadWritePair`.
 
 pub mod http;
 pub mod ws;
@@ -320,7 +320,7 @@ mod tests {
 			OwnedMessage::Pong("boop".to_string().into_bytes()),
 			OwnedMessage::Close(None),
 			OwnedMessage::Close(Some(CloseData {
-				status_code: 64,
+				status_code: 4000,
 				reason: "because".to_string(),
 			})),
 		];
@@ -349,7 +349,7 @@ mod tests {
 			Message::ping("beep".to_string().into_bytes()),
 			Message::pong("boop".to_string().into_bytes()),
 			Message::close(),
-			Message::close_because(64, "because"),
+			Message::close_because(4000, "because"),
 		];
 
 		for message in messages.iter() {
@@ -401,6 +401,45 @@ mod tests {
 			.unwrap();
 	}
 
+	#[test]
+	fn message_codec_surfaces_ping_frames_without_auto_replying() {
+		let mut input = Vec::new();
+		Message::ping("are you there".to_string().into_bytes())
+			.serialize(&mut input, false)
+			.unwrap();
+
+		let f = MessageCodec::<Message>::new(Context::Client)
+			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])))
+			.into_future()
+			.map_err(|e| e.0)
+			.map(|(m, s)| {
+				assert_eq!(
+					m,
+					Some(OwnedMessage::Ping("are you there".to_string().into_bytes()))
+				);
+				// nothing should have been written back - this codec never
+				// auto-pongs, the caller decides how (and whether) to reply.
+				let output = s.into_parts().io.1;
+				assert!(output.into_inner().is_empty());
+			});
+
+		tokio::runtime::Builder::new()
+			.build()
+			.unwrap()
+			.block_on(f)
+			.unwrap();
+	}
+
+	#[test]
+	fn message_codec_rejects_sending_an_out_of_range_close_code() {
+		let mut bytes = Vec::new();
+		let err = Message::close_because(1006, "").serialize(&mut bytes, false);
+		match err {
+			Err(WebSocketError::InvalidCloseCode(1006)) => {}
+			other => panic!("expected InvalidCloseCode(1006), got {:?}", other),
+		}
+	}
+
 	#[test]
 	fn message_codec_server_send_receive() {
 		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
@@ -54,7 +54,8 @@ impl DataFrame {
 			header.flags.contains(dfh::RSV3),
 		];
 
-		let opcode = Opcode::new(header.opcode).expect("Invalid header opcode!");
+		let opcode = Opcode::new(header.opcode)
+			.ok_or(WebSocketError::DataFrameError("Invalid header opcode"))?;
 
 		let data = match header.mask {
 			Some(mask) => {
@@ -96,6 +97,90 @@ impl DataFrame {
 
 		DataFrame::read_dataframe_body(header, data, should_be_masked)
 	}
+
+	/// Decodes a single data frame from the front of `data`, returning the
+	/// frame along with how many bytes of `data` it consumed.
+	///
+	/// Unlike `read_dataframe`, this takes a plain slice rather than a
+	/// `Read`, and never allocates more than `data.len()` bytes for the
+	/// payload regardless of what length the header claims, so it is safe
+	/// to hand directly to a fuzzer: truncated or otherwise malformed input
+	/// is always reported as a `WebSocketError`, never a panic or an
+	/// attempt to allocate an attacker-chosen amount of memory. A truncated
+	/// header or payload fails with `WebSocketError::NoDataAvailable`,
+	/// distinguishing "not enough bytes yet" from a genuinely malformed
+	/// frame - see `receiver::Receiver::try_recv_message`, which relies on
+	/// that to know when to wait for more bytes instead of giving up.
+	pub fn decode_frame(data: &[u8], should_be_masked: bool) -> WebSocketResult<(Self, usize)> {
+		let mut cursor = io::Cursor::new(data);
+		let header = dfh::read_header(&mut cursor)?;
+
+		let header_len = cursor.position() as usize;
+		let payload_len = header.len as usize;
+		let available = data.len() - header_len;
+		if payload_len > available {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
+		}
+
+		let body = data[header_len..header_len + payload_len].to_vec();
+		let frame = DataFrame::read_dataframe_body(header, body, should_be_masked)?;
+		Ok((frame, header_len + payload_len))
+	}
+
+	/// Serializes this frame to its exact wire bytes, the encode-side
+	/// complement to `decode_frame`. Like
+	/// `ws::dataframe::DataFrame::write_to`, but masks with exactly the key
+	/// given rather than a freshly generated random one, so the output is
+	/// deterministic and can be hard-coded as an expected byte sequence in a
+	/// test, or handed to a transport that isn't a `Write` (`decode_frame`'s
+	/// counterpart there too). `None` produces an unmasked frame.
+	pub fn to_bytes(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+		let mut buf = Vec::new();
+
+		let mut flags = dfh::DataFrameFlags::empty();
+		if self.finished {
+			flags.insert(dfh::FIN);
+		}
+		if self.reserved[0] {
+			flags.insert(dfh::RSV1);
+		}
+		if self.reserved[1] {
+			flags.insert(dfh::RSV2);
+		}
+		if self.reserved[2] {
+			flags.insert(dfh::RSV3);
+		}
+
+		let payload_len = self.data.len();
+		let opcode = self.opcode as u8;
+
+		if payload_len <= 125 {
+			buf.push(flags.bits() | opcode);
+			buf.push((if mask.is_some() { 0x80 } else { 0x00 }) | payload_len as u8);
+			if let Some(key) = mask {
+				buf.extend_from_slice(&key);
+			}
+		} else {
+			let header = dfh::DataFrameHeader {
+				flags,
+				opcode,
+				mask,
+				len: payload_len as u64,
+			};
+			dfh::write_header(&mut buf, header).expect("writing a header into a Vec<u8> cannot fail");
+		}
+
+		let payload_start = buf.len();
+		buf.extend_from_slice(&self.data);
+
+		if let Some(key) = mask {
+			for (i, byte) in buf[payload_start..].iter_mut().enumerate() {
+				*byte ^= key[i % 4];
+			}
+		}
+
+		buf
+	}
 }
 
 impl DataFrameable for DataFrame {
@@ -268,6 +353,29 @@ mod tests {
 		assert_eq!(&obtained[..], &expected[..]);
 	}
 
+	#[test]
+	fn test_write_dataframe_masked() {
+		let dataframe = DataFrame {
+			finished: true,
+			reserved: [false; 3],
+			opcode: Opcode::Text,
+			data: b"hello".to_vec(),
+		};
+		let mut obtained = Vec::new();
+		dataframe.write_to(&mut obtained, true).unwrap();
+
+		// byte 0: FIN + opcode, byte 1: MASK bit + length, bytes 2-5: mask key
+		assert_eq!(obtained[0], 0x81);
+		assert_eq!(obtained[1], 0x80 | 5);
+		let key = [obtained[2], obtained[3], obtained[4], obtained[5]];
+		let unmasked: Vec<u8> = obtained[6..]
+			.iter()
+			.enumerate()
+			.map(|(i, &byte)| byte ^ key[i % 4])
+			.collect();
+		assert_eq!(unmasked, b"hello");
+	}
+
 	#[bench]
 	fn bench_write_dataframe(b: &mut Bencher) {
 		let data = b"The quick brown fox jumps over the lazy dog";
@@ -283,3 +391,96 @@ mod tests {
 		});
 	}
 }
+
+#[cfg(test)]
+mod decode_frame_tests {
+	use super::*;
+
+	#[test]
+	fn decodes_a_well_formed_frame_and_reports_bytes_consumed() {
+		let data = b"The quick brown fox jumps over the lazy dog";
+		let mut bytes = vec![0x81, 0x2B];
+		bytes.extend_from_slice(data);
+		bytes.extend_from_slice(b"trailing garbage");
+
+		let (frame, consumed) = DataFrame::decode_frame(&bytes, false).unwrap();
+		assert_eq!(consumed, 2 + data.len());
+		assert_eq!(frame.opcode, Opcode::Text);
+		assert_eq!(frame.data, data);
+	}
+
+	#[test]
+	fn reports_an_error_instead_of_panicking_on_truncated_input() {
+		let bytes = [0x81u8, 0x2B, 0x00, 0x01];
+		assert!(DataFrame::decode_frame(&bytes, false).is_err());
+	}
+
+	#[test]
+	fn reports_an_error_instead_of_panicking_on_a_huge_claimed_length() {
+		// Claims a 2^64-1 byte payload while supplying almost none of it;
+		// a naive decoder would try to allocate that many bytes up front.
+		let bytes = [0x81u8, 0x7F, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+		assert!(DataFrame::decode_frame(&bytes, false).is_err());
+	}
+
+	#[test]
+	fn reports_an_error_instead_of_panicking_on_empty_input() {
+		assert!(DataFrame::decode_frame(&[], false).is_err());
+	}
+
+	#[test]
+	fn unmasks_a_masked_frame_when_told_to_expect_one() {
+		let mut bytes = vec![0x81, 0x80 | 5];
+		let key = [1u8, 2u8, 3u8, 4u8];
+		bytes.extend_from_slice(&key);
+		bytes.extend(mask::mask_data(key, b"hello"));
+
+		let (frame, consumed) = DataFrame::decode_frame(&bytes, true).unwrap();
+		assert_eq!(consumed, bytes.len());
+		assert_eq!(frame.data, b"hello");
+	}
+
+	#[test]
+	fn rejects_a_masked_frame_when_expecting_an_unmasked_one() {
+		let mut bytes = vec![0x81, 0x80 | 5, 1, 2, 3, 4];
+		bytes.extend(mask::mask_data([1, 2, 3, 4], b"hello"));
+
+		match DataFrame::decode_frame(&bytes, false) {
+			Err(WebSocketError::DataFrameError(_)) => {}
+			other => panic!("expected DataFrameError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn to_bytes_matches_a_hand_built_unmasked_frame() {
+		let data = b"The quick brown fox jumps over the lazy dog";
+		let mut expected = vec![0x81, 0x2B];
+		expected.extend_from_slice(data);
+
+		let frame = DataFrame::new(true, Opcode::Text, data.to_vec());
+		assert_eq!(frame.to_bytes(None), expected);
+	}
+
+	#[test]
+	fn to_bytes_masks_with_exactly_the_key_given() {
+		let key = [1u8, 2, 3, 4];
+		let frame = DataFrame::new(true, Opcode::Text, b"hello".to_vec());
+
+		let mut expected = vec![0x81, 0x80 | 5];
+		expected.extend_from_slice(&key);
+		expected.extend(mask::mask_data(key, b"hello"));
+
+		assert_eq!(frame.to_bytes(Some(key)), expected);
+	}
+
+	#[test]
+	fn to_bytes_round_trips_through_decode_frame() {
+		let key = [9u8, 8, 7, 6];
+		let frame = DataFrame::new(false, Opcode::Binary, vec![0u8; 200]);
+
+		let bytes = frame.to_bytes(Some(key));
+		let (decoded, consumed) = DataFrame::decode_frame(&bytes, true).unwrap();
+		assert_eq!(consumed, bytes.len());
+		assert_eq!(decoded, frame);
+	}
+}
@@ -55,7 +55,10 @@ impl FromStr for Extension {
 			},
 			params: ext
 				.map(|x| {
-					let mut pair = x.splitn(1, '=').map(|x| x.trim().to_string());
+					// `splitn(2, ...)`: a param's value (if any) may itself
+					// contain `=`, e.g. a base64-encoded extension token -
+					// only the first `=` separates the name from the value.
+					let mut pair = x.splitn(2, '=').map(|x| x.trim().to_string());
 
 					Parameter {
 						name: pair.next().unwrap(),
@@ -31,6 +31,18 @@
 //! # Extending Rust-WebSocket
 //! The `ws` module contains the traits and functions used by Rust-WebSocket at a lower
 //! level. Their usage is explained in the module documentation.
+//!
+//! # TLS
+//! TLS support lives behind its own `sync-ssl`/`async-ssl` features (on by
+//! default alongside `sync`/`async`), each pulling in `native-tls` as well
+//! as the plain `sync`/`async` feature it secures. Building with just
+//! `sync` or `async` - e.g. `--no-default-features --features sync` for a
+//! plaintext-only service - compiles with no `native-tls` dependency at
+//! all: every TLS type (`TlsAcceptor`/`TlsStream`/`TlsConnector`) and the
+//! secure server/client paths built on them (`WsServer::bind_secure`,
+//! `ClientBuilder::connect_secure`, ...) are `#[cfg]`-gated out along with
+//! the `extern crate native_tls` that declares them, shrinking both the
+//! binary and its attack surface.
 extern crate base64;
 extern crate byteorder;
 #[cfg(feature = "async")]
@@ -41,7 +53,15 @@ extern crate hyper;
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 extern crate native_tls;
 extern crate rand;
+#[cfg(feature = "codec")]
+extern crate serde;
+#[cfg(feature = "codec")]
+extern crate serde_json;
+#[cfg(feature = "codec-bincode")]
+extern crate bincode;
 extern crate sha1;
+#[cfg(feature = "sync")]
+extern crate socket2;
 #[cfg(feature = "async")]
 extern crate tokio;
 #[cfg(feature = "async-ssl")]
@@ -85,6 +105,9 @@ pub mod client;
 pub mod server;
 pub mod stream;
 
+#[cfg(feature = "codec")]
+pub mod serde_codec;
+
 /// A collection of handy synchronous-only parts of the crate.
 #[cfg(feature = "sync")]
 pub mod sync {
@@ -109,6 +132,8 @@ pub mod sync {
 	/// A collection of handy synchronous-only parts of the `client` module.
 	pub mod client {
 		pub use client::builder::ClientBuilder;
+		pub use client::reconnect::{Backoff, ReconnectingClient};
+		pub use client::socks::Socks5Auth;
 		pub use client::sync::*;
 	}
 	pub use client::sync::Client;
@@ -151,8 +176,11 @@ pub mod async {
 }
 
 pub use self::client::builder::ClientBuilder;
+pub use self::message::CloseCode;
 pub use self::message::CloseData;
+pub use self::message::ControlOrMessage;
 pub use self::message::Message;
+pub use self::message::MessageKind;
 pub use self::message::OwnedMessage;
 
 pub use self::result::WebSocketError;
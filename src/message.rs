@@ -70,6 +70,28 @@ impl<'a> Message<'a> {
 		)
 	}
 
+	/// Create a new WebSocket message with text data, validating that
+	/// `data` is UTF-8 rather than assuming it like `text` does. Useful
+	/// when the payload came from somewhere that only claims to be text,
+	/// such as bytes read off the wire. Sending a text frame that isn't
+	/// valid UTF-8 is a protocol violation conformant peers will reject
+	/// with close code 1007, so prefer this over `text` whenever the
+	/// input isn't already a `String`/`&str`.
+	///
+	/// ```rust
+	/// # use websocket::Message;
+	/// assert!(Message::try_text(&b"hello"[..]).is_ok());
+	/// assert!(Message::try_text(&b"\xff\xfe"[..]).is_err());
+	/// ```
+	pub fn try_text<B>(data: B) -> Result<Self, ::std::str::Utf8Error>
+	where
+		B: IntoCowBytes<'a>,
+	{
+		let payload = data.into();
+		from_utf8(&payload)?;
+		Ok(Message::new(Type::Text, None, payload))
+	}
+
 	/// Create a new WebSocket message with binary data
 	pub fn binary<B>(data: B) -> Self
 	where
@@ -101,6 +123,17 @@ impl<'a> Message<'a> {
 		)
 	}
 
+	/// Like `close_because`, but for the common case of a standard status
+	/// code with no reason phrase - shorter than `close_because(code.as_u16(), "")`.
+	///
+	///```rust
+	///# use websocket::{CloseCode, Message};
+	///assert_eq!(Message::close_code(CloseCode::Normal).cd_status_code, Some(1000));
+	///```
+	pub fn close_code(code: CloseCode) -> Self {
+		Message::new(Type::Close, Some(code.as_u16()), Cow::Borrowed(&[0 as u8; 0]))
+	}
+
 	/// Create a ping WebSocket message, a pong is usually sent back
 	/// after sending this with the same data
 	pub fn ping<P>(data: P) -> Self
@@ -131,6 +164,46 @@ impl<'a> Message<'a> {
 			Err(())
 		}
 	}
+
+	/// Checks that this message obeys the control-frame constraints
+	/// `write_payload`/a `Sender` would otherwise only discover mid-write:
+	/// a `Ping`/`Pong`/`Close` payload is at most 125 bytes (RFC6455
+	/// section 5.5 - control frames can never be fragmented, and the
+	/// 7-bit length field a single frame's header can express for them
+	/// tops out there), and a `Close` carrying a status code uses one
+	/// `CloseCode::is_sendable` accepts.
+	///
+	/// Most callers build messages with `ping`/`pong`/`close_because`
+	/// and never need this; it earns its keep once a payload or close
+	/// code comes from untrusted input, where catching the problem here
+	/// gives a clean error instead of a failed write partway through a
+	/// message already on the wire.
+	///
+	/// ```rust
+	/// # use websocket::Message;
+	/// assert!(Message::ping(vec![0u8; 125]).validate().is_ok());
+	/// assert!(Message::ping(vec![0u8; 126]).validate().is_err());
+	/// ```
+	pub fn validate(&self) -> WebSocketResult<()> {
+		let is_control = match self.opcode {
+			Type::Close | Type::Ping | Type::Pong => true,
+			Type::Text | Type::Binary => false,
+		};
+
+		if is_control && self.payload.len() > 125 {
+			return Err(WebSocketError::DataFrameError(
+				"Control frame payloads must be 125 bytes or less",
+			));
+		}
+
+		if let Some(code) = self.cd_status_code {
+			if !CloseCode::from_u16(code).is_sendable() {
+				return Err(WebSocketError::InvalidCloseCode(code));
+			}
+		}
+
+		Ok(())
+	}
 }
 
 impl<'a> ws::dataframe::DataFrame for Message<'a> {
@@ -154,8 +227,11 @@ impl<'a> ws::dataframe::DataFrame for Message<'a> {
 	}
 
 	fn write_payload(&self, socket: &mut Write) -> WebSocketResult<()> {
-		if let Some(reason) = self.cd_status_code {
-			socket.write_u16::<BigEndian>(reason)?;
+		if let Some(code) = self.cd_status_code {
+			if !CloseCode::from_u16(code).is_sendable() {
+				return Err(WebSocketError::InvalidCloseCode(code));
+			}
+			socket.write_u16::<BigEndian>(code)?;
 		}
 		socket.write_all(&*self.payload)?;
 		Ok(())
@@ -185,6 +261,17 @@ impl<'a> ws::Message for Message<'a> {
 		self.frame_size(masked)
 	}
 
+	fn is_control(&self) -> bool {
+		match self.opcode {
+			Type::Close | Type::Ping | Type::Pong => true,
+			Type::Text | Type::Binary => false,
+		}
+	}
+
+	fn is_close(&self) -> bool {
+		self.opcode == Type::Close
+	}
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
 	where
@@ -230,6 +317,12 @@ impl<'a> ws::Message for Message<'a> {
 			Some(Opcode::Close) => {
 				if !data.is_empty() {
 					let status_code = (&data[..]).read_u16::<BigEndian>()?;
+					if !CloseCode::from_u16(status_code).is_sendable() {
+						// A conformant peer would never put this code on the
+						// wire; the caller should respond in kind with a
+						// `Close` carrying `CloseCode::ProtocolError` (1002).
+						return Err(WebSocketError::InvalidCloseCode(status_code));
+					}
 					let reason = bytes_to_string(&data[2..])?;
 					Message::close_because(status_code, reason)
 				} else {
@@ -268,6 +361,12 @@ pub enum OwnedMessage {
 	/// A pong message, sent in response to a Ping message, usually
 	/// containing the same data as the received ping message.
 	Pong(Vec<u8>),
+	/// A message using one of the opcodes RFC6455 section 11.8 leaves
+	/// undefined (3-7 for data, 11-15 for control), carrying the raw
+	/// opcode and reassembled payload. Only ever produced by
+	/// `Receiver::set_allow_reserved_opcodes(true)` - by default these are
+	/// rejected with a `ProtocolError` instead. See that method.
+	Reserved(u8, Vec<u8>),
 }
 
 impl OwnedMessage {
@@ -284,6 +383,46 @@ impl OwnedMessage {
 		}
 	}
 
+	/// Creates a `Close` message from a status code and reason, the owned
+	/// equivalent of `Message::close_because` - the constructor both
+	/// `sync::Client` and an async `Stream`/`Sink` user build their closing
+	/// frames with, so the status-code/reason wrapping only lives here.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///assert_eq!(
+	///    OwnedMessage::close_because(1009, "Message Too Big".to_owned()),
+	///    OwnedMessage::Close(Some(::websocket::CloseData::new(1009, "Message Too Big".to_owned()))),
+	///);
+	///```
+	pub fn close_because(code: u16, reason: String) -> Self {
+		OwnedMessage::Close(Some(CloseData::new(code, reason)))
+	}
+
+	/// If `self` is a `Close`, the `Close` a standard closing handshake
+	/// calls for sending right back (echoing the same status code and
+	/// reason), `None` otherwise. Factors out the "what do I send back"
+	/// half of the closing handshake so it isn't duplicated per transport:
+	/// `sync::Client` uses this for its `set_auto_close`/`echo_next`
+	/// behavior, and an async `Stream`/`Sink` user - who must drive the
+	/// handshake by hand, since the async `Client` is a bare `Framed` with
+	/// no receive loop of its own - can call it the same way.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///assert_eq!(
+	///    OwnedMessage::Close(None).echo_close(),
+	///    Some(OwnedMessage::Close(None)),
+	///);
+	///assert_eq!(OwnedMessage::Ping(vec![]).echo_close(), None);
+	///```
+	pub fn echo_close(&self) -> Option<Self> {
+		match *self {
+			OwnedMessage::Close(ref data) => Some(OwnedMessage::Close(data.clone())),
+			_ => None,
+		}
+	}
+
 	/// Checks if this message is a control message.
 	/// Control messages are either `Close`, `Ping`, or `Pong`.
 	///
@@ -298,6 +437,7 @@ impl OwnedMessage {
 			OwnedMessage::Close(_) => true,
 			OwnedMessage::Ping(_) => true,
 			OwnedMessage::Pong(_) => true,
+			OwnedMessage::Reserved(opcode, _) => opcode >= 8,
 			_ => false,
 		}
 	}
@@ -342,6 +482,221 @@ impl OwnedMessage {
 			_ => false,
 		}
 	}
+
+	/// Like `from_dataframes`, but instead of allocating a fresh `Vec<u8>`
+	/// to hold the reassembled payload, appends it onto the end of
+	/// `buf` and returns only which kind of message it was. Useful for a
+	/// long-running receiver that wants to reuse one buffer across many
+	/// messages rather than allocate one per message - see
+	/// `Client::recv_message_into`.
+	///
+	/// `Close` is the exception: its status code and reason are small and
+	/// fixed-size, so they come back in the returned `MessageKind` instead
+	/// of being appended to `buf`.
+	pub fn from_dataframes_into<D>(frames: Vec<D>, buf: &mut Vec<u8>) -> WebSocketResult<MessageKind>
+	where
+		D: DataFrameTrait,
+	{
+		let opcode = frames
+			.first()
+			.ok_or(WebSocketError::ProtocolError("No dataframes provided"))
+			.map(|d| d.opcode())?;
+		let opcode = Opcode::new(opcode);
+
+		if opcode == Some(Opcode::Close) {
+			let mut data = Vec::new();
+			for (i, dataframe) in frames.into_iter().enumerate() {
+				check_continuation(i, &dataframe)?;
+				data.append(&mut dataframe.take_payload());
+			}
+			return if !data.is_empty() {
+				let status_code = (&data[..]).read_u16::<BigEndian>()?;
+				if !CloseCode::from_u16(status_code).is_sendable() {
+					return Err(WebSocketError::InvalidCloseCode(status_code));
+				}
+				let reason = bytes_to_string(&data[2..])?;
+				Ok(MessageKind::Close(Some(CloseData::new(status_code, reason))))
+			} else {
+				Ok(MessageKind::Close(None))
+			};
+		}
+
+		let start = buf.len();
+		for (i, dataframe) in frames.into_iter().enumerate() {
+			if let Err(e) = check_continuation(i, &dataframe) {
+				buf.truncate(start);
+				return Err(e);
+			}
+			buf.append(&mut dataframe.take_payload());
+		}
+
+		match opcode {
+			Some(Opcode::Text) => {
+				if let Err(e) = from_utf8(&buf[start..]) {
+					buf.truncate(start);
+					return Err(e.into());
+				}
+				Ok(MessageKind::Text)
+			}
+			Some(Opcode::Binary) => Ok(MessageKind::Binary),
+			Some(Opcode::Ping) => Ok(MessageKind::Ping),
+			Some(Opcode::Pong) => Ok(MessageKind::Pong),
+			_ => {
+				buf.truncate(start);
+				Err(WebSocketError::ProtocolError("Unsupported opcode received"))
+			}
+		}
+	}
+
+	/// Like `from_dataframes`, but when `allow_reserved` is `true`, a
+	/// message using an opcode this crate has no dedicated variant for (3-7,
+	/// the undefined non-control opcodes, or 11-15, the undefined control
+	/// ones) comes back as `OwnedMessage::Reserved` instead of a
+	/// `ProtocolError`. `allow_reserved` is normally
+	/// `Receiver::allow_reserved_opcodes()` - see
+	/// `Receiver::set_allow_reserved_opcodes`.
+	pub fn from_dataframes_allow_reserved<D>(
+		frames: Vec<D>,
+		allow_reserved: bool,
+	) -> WebSocketResult<Self>
+	where
+		D: DataFrameTrait,
+	{
+		let opcode = frames
+			.first()
+			.ok_or(WebSocketError::ProtocolError("No dataframes provided"))
+			.map(|d| d.opcode())?;
+
+		let is_standard = matches!(
+			Opcode::new(opcode),
+			Some(Opcode::Text)
+				| Some(Opcode::Binary)
+				| Some(Opcode::Close)
+				| Some(Opcode::Ping)
+				| Some(Opcode::Pong)
+		);
+
+		if !allow_reserved || is_standard {
+			return <Self as ws::Message>::from_dataframes(frames);
+		}
+
+		let mut data = Vec::new();
+		for (i, dataframe) in frames.into_iter().enumerate() {
+			check_continuation(i, &dataframe)?;
+			data.append(&mut dataframe.take_payload());
+		}
+		Ok(OwnedMessage::Reserved(opcode, data))
+	}
+
+	/// Like `from_dataframes_into`, but with the same `allow_reserved`
+	/// relaxation as `from_dataframes_allow_reserved`: a reserved opcode
+	/// comes back as `MessageKind::Reserved` with its payload appended to
+	/// `buf`, instead of a `ProtocolError`.
+	pub fn from_dataframes_into_allow_reserved<D>(
+		frames: Vec<D>,
+		buf: &mut Vec<u8>,
+		allow_reserved: bool,
+	) -> WebSocketResult<MessageKind>
+	where
+		D: DataFrameTrait,
+	{
+		let opcode = frames
+			.first()
+			.ok_or(WebSocketError::ProtocolError("No dataframes provided"))
+			.map(|d| d.opcode())?;
+
+		let is_standard = matches!(
+			Opcode::new(opcode),
+			Some(Opcode::Text)
+				| Some(Opcode::Binary)
+				| Some(Opcode::Close)
+				| Some(Opcode::Ping)
+				| Some(Opcode::Pong)
+		);
+
+		if !allow_reserved || is_standard {
+			return Self::from_dataframes_into(frames, buf);
+		}
+
+		let start = buf.len();
+		for (i, dataframe) in frames.into_iter().enumerate() {
+			if let Err(e) = check_continuation(i, &dataframe) {
+				buf.truncate(start);
+				return Err(e);
+			}
+			buf.append(&mut dataframe.take_payload());
+		}
+		Ok(MessageKind::Reserved(opcode))
+	}
+}
+
+fn check_continuation<D>(i: usize, dataframe: &D) -> WebSocketResult<()>
+where
+	D: DataFrameTrait,
+{
+	if i > 0 && dataframe.opcode() != Opcode::Continuation as u8 {
+		return Err(WebSocketError::ProtocolError(
+			"Unexpected non-continuation data frame",
+		));
+	}
+	if *dataframe.reserved() != [false; 3] {
+		return Err(WebSocketError::ProtocolError(
+			"Unsupported reserved bits received",
+		));
+	}
+	Ok(())
+}
+
+/// Which kind of message `Client::recv_message_into` received. Mirrors
+/// `OwnedMessage`'s variants but without the payload, since the payload (if
+/// any) was written into the caller's buffer instead.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum MessageKind {
+	/// A `Text` message; its UTF-8 bytes were appended to the buffer.
+	Text,
+	/// A `Binary` message; its bytes were appended to the buffer.
+	Binary,
+	/// A ping message; its payload was appended to the buffer.
+	Ping,
+	/// A pong message; its payload was appended to the buffer.
+	Pong,
+	/// A message which indicates closure of the WebSocket connection.
+	/// Nothing is appended to the buffer for this variant.
+	Close(Option<CloseData>),
+	/// A message using a reserved/undefined opcode; its payload was
+	/// appended to the buffer. See `OwnedMessage::Reserved`.
+	Reserved(u8),
+}
+
+/// The result of `Client::recv_event`/`Reader::recv_event`: an `OwnedMessage`
+/// with its control variants (`Ping`, `Pong`, `Close`) split out into their
+/// own variants here, so callers can `match` on control frames directly
+/// instead of checking `OwnedMessage::is_control` and re-destructuring.
+/// `Data` carries the remaining `Text`/`Binary` messages unchanged.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum ControlOrMessage {
+	/// A `Text` or `Binary` data message. See `OwnedMessage::is_data`.
+	Data(OwnedMessage),
+	/// A ping message - should be responded to with a pong message. See
+	/// `OwnedMessage::Ping`.
+	Ping(Vec<u8>),
+	/// A pong message, sent in response to a Ping message. See
+	/// `OwnedMessage::Pong`.
+	Pong(Vec<u8>),
+	/// A message which indicates closure of the WebSocket connection. See
+	/// `OwnedMessage::Close`.
+	Close(Option<CloseData>),
+}
+
+impl From<OwnedMessage> for ControlOrMessage {
+	fn from(message: OwnedMessage) -> Self {
+		match message {
+			OwnedMessage::Ping(data) => ControlOrMessage::Ping(data),
+			OwnedMessage::Pong(data) => ControlOrMessage::Pong(data),
+			OwnedMessage::Close(data) => ControlOrMessage::Close(data),
+			other => ControlOrMessage::Data(other),
+		}
+	}
 }
 
 impl ws::Message for OwnedMessage {
@@ -355,6 +710,14 @@ impl ws::Message for OwnedMessage {
 		self.frame_size(masked)
 	}
 
+	fn is_control(&self) -> bool {
+		OwnedMessage::is_control(self)
+	}
+
+	fn is_close(&self) -> bool {
+		OwnedMessage::is_close(self)
+	}
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
 	where
@@ -372,13 +735,14 @@ impl ws::dataframe::DataFrame for OwnedMessage {
 
 	#[inline(always)]
 	fn opcode(&self) -> u8 {
-		(match *self {
-			OwnedMessage::Text(_) => Type::Text,
-			OwnedMessage::Binary(_) => Type::Binary,
-			OwnedMessage::Close(_) => Type::Close,
-			OwnedMessage::Ping(_) => Type::Ping,
-			OwnedMessage::Pong(_) => Type::Pong,
-		}) as u8
+		match *self {
+			OwnedMessage::Text(_) => Type::Text as u8,
+			OwnedMessage::Binary(_) => Type::Binary as u8,
+			OwnedMessage::Close(_) => Type::Close as u8,
+			OwnedMessage::Ping(_) => Type::Ping as u8,
+			OwnedMessage::Pong(_) => Type::Pong as u8,
+			OwnedMessage::Reserved(opcode, _) => opcode,
+		}
 	}
 
 	#[inline(always)]
@@ -392,6 +756,7 @@ impl ws::dataframe::DataFrame for OwnedMessage {
 			OwnedMessage::Binary(ref bin) => bin.len(),
 			OwnedMessage::Ping(ref data) => data.len(),
 			OwnedMessage::Pong(ref data) => data.len(),
+			OwnedMessage::Reserved(_, ref data) => data.len(),
 			OwnedMessage::Close(ref data) => match data {
 				&Some(ref c) => c.reason.len() + 2,
 				&None => 0,
@@ -405,8 +770,12 @@ impl ws::dataframe::DataFrame for OwnedMessage {
 			OwnedMessage::Binary(ref bin) => socket.write_all(bin.as_slice())?,
 			OwnedMessage::Ping(ref data) => socket.write_all(data.as_slice())?,
 			OwnedMessage::Pong(ref data) => socket.write_all(data.as_slice())?,
+			OwnedMessage::Reserved(_, ref data) => socket.write_all(data.as_slice())?,
 			OwnedMessage::Close(ref data) => match data {
 				&Some(ref c) => {
+					if !CloseCode::from_u16(c.status_code).is_sendable() {
+						return Err(WebSocketError::InvalidCloseCode(c.status_code));
+					}
 					socket.write_u16::<BigEndian>(c.status_code)?;
 					socket.write_all(c.reason.as_bytes())?
 				}
@@ -422,6 +791,7 @@ impl ws::dataframe::DataFrame for OwnedMessage {
 			OwnedMessage::Binary(bin) => bin,
 			OwnedMessage::Ping(data) => data,
 			OwnedMessage::Pong(data) => data,
+			OwnedMessage::Reserved(_, data) => data,
 			OwnedMessage::Close(data) => match data {
 				Some(c) => {
 					let mut buf = Vec::with_capacity(2 + c.reason.len());
@@ -468,6 +838,9 @@ impl<'m> From<OwnedMessage> for Message<'m> {
 			},
 			OwnedMessage::Ping(data) => Message::ping(data),
 			OwnedMessage::Pong(data) => Message::pong(data),
+			// `Message`/`Type` have no variant for a custom opcode, so the
+			// best we can do without losing the payload is send it as binary.
+			OwnedMessage::Reserved(_, data) => Message::binary(data),
 		}
 	}
 }
@@ -498,6 +871,132 @@ impl CloseData {
 		}
 		Ok(buf)
 	}
+
+	/// `status_code` as a `CloseCode`, recognizing the standard codes and
+	/// falling back to `CloseCode::Other` for anything else a peer sent -
+	/// including the reserved codes RFC6455 section 7.4.1 says must never
+	/// actually appear on the wire, since a non-conformant peer could still
+	/// send one.
+	///
+	///```rust
+	///# use websocket::{CloseCode, CloseData};
+	///let close = CloseData::new(1000, "bye".to_owned());
+	///assert_eq!(close.code(), CloseCode::Normal);
+	///```
+	pub fn code(&self) -> CloseCode {
+		CloseCode::from_u16(self.status_code)
+	}
+}
+
+/// A WebSocket close status code, per RFC6455 section 7.4.1. Sending one of
+/// these via `Message::close_code` instead of a raw `u16` through
+/// `close_because` reads better and rules out typos like `CloseCode::Normal`
+/// for `1001`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CloseCode {
+	/// 1000: normal closure, the purpose for the connection has been fulfilled
+	Normal,
+	/// 1001: an endpoint is "going away", such as a server shutting down
+	GoingAway,
+	/// 1002: an endpoint is terminating the connection due to a protocol error
+	ProtocolError,
+	/// 1003: an endpoint received a type of data it cannot accept
+	Unsupported,
+	/// 1007: an endpoint received data inconsistent with its message's type
+	/// (for example non-UTF8 data in a `Text` message)
+	InvalidPayload,
+	/// 1008: an endpoint received a message that violates its policy
+	PolicyViolation,
+	/// 1009: an endpoint received a message too large for it to process
+	TooLarge,
+	/// 1010: a client is terminating the connection because the server
+	/// didn't negotiate an extension it required
+	MandatoryExtension,
+	/// 1011: a server is terminating the connection because it encountered
+	/// an unexpected error
+	InternalError,
+	/// Any other code: the reserved 1004/1005/1006/1015 (RFC6455 says these
+	/// must never actually be sent, but `from_u16` doesn't assume a peer is
+	/// conformant), the 3000-3999 range registered for libraries and
+	/// frameworks, the 4000-4999 range reserved for private use, or
+	/// anything else entirely.
+	Other(u16),
+}
+
+impl CloseCode {
+	/// The raw status code this variant represents.
+	///
+	///```rust
+	///# use websocket::CloseCode;
+	///assert_eq!(CloseCode::InternalError.as_u16(), 1011);
+	///assert_eq!(CloseCode::Other(4000).as_u16(), 4000);
+	///```
+	pub fn as_u16(self) -> u16 {
+		match self {
+			CloseCode::Normal => 1000,
+			CloseCode::GoingAway => 1001,
+			CloseCode::ProtocolError => 1002,
+			CloseCode::Unsupported => 1003,
+			CloseCode::InvalidPayload => 1007,
+			CloseCode::PolicyViolation => 1008,
+			CloseCode::TooLarge => 1009,
+			CloseCode::MandatoryExtension => 1010,
+			CloseCode::InternalError => 1011,
+			CloseCode::Other(code) => code,
+		}
+	}
+
+	/// Recognizes the standard codes, falling back to `CloseCode::Other`
+	/// for anything else - this never fails, since any `u16` is at least a
+	/// valid `Other`.
+	///
+	///```rust
+	///# use websocket::CloseCode;
+	///assert_eq!(CloseCode::from_u16(1000), CloseCode::Normal);
+	///assert_eq!(CloseCode::from_u16(1006), CloseCode::Other(1006));
+	///```
+	pub fn from_u16(code: u16) -> Self {
+		match code {
+			1000 => CloseCode::Normal,
+			1001 => CloseCode::GoingAway,
+			1002 => CloseCode::ProtocolError,
+			1003 => CloseCode::Unsupported,
+			1007 => CloseCode::InvalidPayload,
+			1008 => CloseCode::PolicyViolation,
+			1009 => CloseCode::TooLarge,
+			1010 => CloseCode::MandatoryExtension,
+			1011 => CloseCode::InternalError,
+			other => CloseCode::Other(other),
+		}
+	}
+
+	/// Whether RFC6455 section 7.4 allows this code to actually be put on
+	/// the wire in a `Close` frame. All the named variants are always
+	/// sendable; an `Other` code is only sendable in the 3000-3999 range
+	/// (registered for libraries/frameworks) or 4000-4999 (reserved for
+	/// private use) - anything in 1000-2999 is either already one of the
+	/// named variants or not a code this crate knows to be defined, and
+	/// includes 1004, 1005, 1006 and 1015, which RFC6455 says must never
+	/// actually be sent since they only describe conditions observed
+	/// locally (e.g. 1006 means the connection dropped with no `Close`
+	/// frame at all). Anything below 1000 or above 4999 is never valid.
+	///
+	///```rust
+	///# use websocket::CloseCode;
+	///assert!(CloseCode::Normal.is_sendable());
+	///assert!(CloseCode::Other(3000).is_sendable());
+	///assert!(!CloseCode::Other(1006).is_sendable());
+	///assert!(!CloseCode::Other(5000).is_sendable());
+	///```
+	pub fn is_sendable(self) -> bool {
+		match self {
+			CloseCode::Other(code) => match code {
+				3000...4999 => true,
+				_ => false,
+			},
+			_ => true,
+		}
+	}
 }
 
 /// Trait representing the ability to convert
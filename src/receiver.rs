@@ -1,18 +1,25 @@
 //! The default implementation of a WebSocket Receiver.
 
+use std::io;
 use std::io::Read;
 use std::io::Result as IoResult;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use hyper::buffer::BufReader;
 
 use dataframe::{DataFrame, Opcode};
-use message::OwnedMessage;
+use message::{ControlOrMessage, OwnedMessage};
 use result::{WebSocketError, WebSocketResult};
 pub use stream::sync::Shutdown;
 use stream::sync::{AsTcpStream, Stream};
 use ws;
+use ws::dataframe::DataFrame as DataFrameable;
 use ws::receiver::Receiver as ReceiverTrait;
 use ws::receiver::{DataFrameIterator, MessageIterator};
+use ws::util::header as dfh;
+use ws::Message as MessageTrait;
 
 /// This reader bundles an existing stream with a parsing algorithm.
 /// It is used by the client in its `.split()` function as the reading component.
@@ -42,7 +49,24 @@ where
 
 	/// Reads a single message from this receiver.
 	pub fn recv_message(&mut self) -> WebSocketResult<OwnedMessage> {
-		self.receiver.recv_message(&mut self.stream)
+		self.recv_message_with_meta().map(|(message, _meta)| message)
+	}
+
+	/// Like `recv_message`, but alongside the message returns `MessageMeta`
+	/// describing how it was framed on the wire. See
+	/// `Receiver::recv_message_with_meta`.
+	pub fn recv_message_with_meta(&mut self) -> WebSocketResult<(OwnedMessage, MessageMeta)> {
+		self.receiver.recv_message_with_meta(&mut self.stream)
+	}
+
+	/// Like `recv_message`, but splits control frames (`Ping`, `Pong`,
+	/// `Close`) out into their own `ControlOrMessage` variants, so a single
+	/// `match` can handle each explicitly. See `Client::recv_event`, which
+	/// this mirrors; unlike `Client`, a plain `Reader` has no
+	/// `set_auto_close` to interact with, so this is a direct relabelling
+	/// of whatever `recv_message` returns.
+	pub fn recv_event(&mut self) -> WebSocketResult<ControlOrMessage> {
+		self.recv_message().map(ControlOrMessage::from)
 	}
 
 	/// An iterator over incoming messsages.
@@ -50,6 +74,98 @@ where
 	pub fn incoming_messages<'a>(&'a mut self) -> MessageIterator<'a, Receiver, BufReader<R>> {
 		self.receiver.incoming_messages(&mut self.stream)
 	}
+
+	/// Like `recv_message`, but if the next message is at least
+	/// `Receiver::set_stream_threshold` bytes, returns a `MessageReader` to
+	/// stream its payload instead of buffering it first. See
+	/// `StreamedMessage`.
+	pub fn recv_message_or_stream<'a>(&'a mut self) -> WebSocketResult<StreamedMessage<'a, BufReader<R>>> {
+		match self.receiver.recv_message_dataframes_or_stream(&mut self.stream)? {
+			StreamedDataFrames::Buffered(dataframes) => {
+				OwnedMessage::from_dataframes_allow_reserved(dataframes, self.receiver.allow_reserved_opcodes())
+					.map(StreamedMessage::Buffered)
+			}
+			StreamedDataFrames::Streaming(reader) => Ok(StreamedMessage::Streaming(reader)),
+		}
+	}
+
+	/// Sets the payload size, in bytes, at or above which
+	/// `recv_message_or_stream` streams a message's payload instead of
+	/// buffering it. See `Receiver::set_stream_threshold`.
+	pub fn set_stream_threshold(&mut self, threshold: Option<usize>) {
+		self.receiver.set_stream_threshold(threshold);
+	}
+
+	/// The threshold currently set by `set_stream_threshold`, or `None` if
+	/// unset.
+	pub fn stream_threshold(&self) -> Option<usize> {
+		self.receiver.stream_threshold()
+	}
+
+	/// Limits how long a fragmented message may take to complete. See
+	/// `Receiver::set_fragment_timeout`.
+	pub fn set_fragment_timeout(&mut self, timeout: Option<Duration>) {
+		self.receiver.set_fragment_timeout(timeout);
+	}
+
+	/// The limit currently set by `set_fragment_timeout`, or `None` if
+	/// unset.
+	pub fn fragment_timeout(&self) -> Option<Duration> {
+		self.receiver.fragment_timeout()
+	}
+
+	/// Limits how many control frames the peer may send per second. See
+	/// `Receiver::set_max_control_frame_rate`.
+	pub fn set_max_control_frame_rate(&mut self, per_sec: Option<u32>) {
+		self.receiver.set_max_control_frame_rate(per_sec);
+	}
+
+	/// The limit currently set by `set_max_control_frame_rate`, or `None`
+	/// if unset.
+	pub fn max_control_frame_rate(&self) -> Option<u32> {
+		self.receiver.max_control_frame_rate()
+	}
+
+	/// Attaches up to `cap` bytes of raw parse context to a failed
+	/// `try_recv_message`. See
+	/// `receiver::Receiver::set_capture_parse_error_bytes`.
+	pub fn set_capture_parse_error_bytes(&mut self, cap: Option<usize>) {
+		self.receiver.set_capture_parse_error_bytes(cap);
+	}
+
+	/// The cap currently set by `set_capture_parse_error_bytes`, or `None`
+	/// if unset.
+	pub fn capture_parse_error_bytes(&self) -> Option<usize> {
+		self.receiver.capture_parse_error_bytes()
+	}
+
+	/// See `receiver::Receiver::set_redact_captured_bytes`.
+	pub fn set_redact_captured_bytes(&mut self, redact: bool) {
+		self.receiver.set_redact_captured_bytes(redact);
+	}
+
+	/// The setting currently set by `set_redact_captured_bytes`.
+	pub fn redact_captured_bytes(&self) -> bool {
+		self.receiver.redact_captured_bytes()
+	}
+
+	/// Whether a received `Pong` is surfaced by `recv_message`/`recv_event`
+	/// instead of being silently consumed. See `Receiver::set_deliver_pongs`.
+	pub fn set_deliver_pongs(&mut self, deliver_pongs: bool) {
+		self.receiver.set_deliver_pongs(deliver_pongs);
+	}
+
+	/// The setting currently set by `set_deliver_pongs`.
+	pub fn deliver_pongs(&self) -> bool {
+		self.receiver.deliver_pongs()
+	}
+
+	/// Like `recv_message`, but for a `stream` already in non-blocking
+	/// mode: returns `Ok(None)` instead of blocking when no complete
+	/// message is available yet. See `Receiver::try_recv_message`.
+	pub fn try_recv_message(&mut self) -> WebSocketResult<Option<OwnedMessage>> {
+		self.receiver.try_recv_message(&mut self.stream)
+	}
 }
 
 impl<S> Reader<S>
@@ -69,66 +185,482 @@ where
 	}
 }
 
+/// A snapshot of an in-progress fragmented message, as reported by
+/// `Receiver::in_fragmented_message`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FragmentState {
+	/// The opcode of the message's first fragment, i.e. what kind of
+	/// `OwnedMessage` it will become once reassembly finishes.
+	pub opcode: Opcode,
+	/// Total payload bytes buffered across every fragment received so far,
+	/// not counting whichever fragment the next `recv_dataframe` call is
+	/// currently blocked reading.
+	pub bytes_so_far: usize,
+}
+
+/// Out-of-band framing info about a message, as reported alongside it by
+/// `Receiver::recv_message_with_meta`/`Reader::recv_message_with_meta`.
+/// Entirely derived from the data frames reassembly already produced - no
+/// extra reads or bookkeeping beyond what `recv_message` itself does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageMeta {
+	/// Whether the message's first data frame had RSV1 set - the bit
+	/// `permessage-deflate` (RFC7692) and similar extensions conventionally
+	/// use to flag a compressed payload. This crate doesn't implement any
+	/// such extension itself, and `OwnedMessage::from_dataframes` rejects
+	/// any frame with a reserved bit set before a message carrying one
+	/// could ever reach here - so today this is always `false`. The field
+	/// exists for callers layering their own extension handling in front
+	/// of this crate (see `ws::dataframe::DataFrame::reserved`), and so
+	/// this type doesn't need a breaking change once one is negotiated.
+	pub compressed: bool,
+	/// How many data frames the message was split across - 1 for an
+	/// unfragmented message.
+	pub fragments: usize,
+	/// Total bytes the message's frames took on the wire, headers
+	/// included - i.e. the sum of each frame's
+	/// `ws::dataframe::DataFrame::frame_size`.
+	pub wire_bytes: usize,
+}
+
+impl MessageMeta {
+	fn from_dataframes(dataframes: &[DataFrame], masked: bool) -> Self {
+		MessageMeta {
+			compressed: dataframes.first().map_or(false, |frame| frame.reserved()[0]),
+			fragments: dataframes.len(),
+			wire_bytes: dataframes.iter().map(|frame| frame.frame_size(masked)).sum(),
+		}
+	}
+}
+
 /// A Receiver that wraps a Reader and provides a default implementation using
 /// DataFrames and Messages.
 pub struct Receiver {
 	buffer: Vec<DataFrame>,
 	mask: bool,
+	received_close: Arc<AtomicBool>,
+	max_message_size: Option<usize>,
+	max_frame_size: Option<usize>,
+	allow_reserved_opcodes: bool,
+	stream_threshold: Option<usize>,
+	deliver_pongs: bool,
+	fragment_timeout: Option<Duration>,
+	/// When the message currently mid-reassembly (if any) must finish by,
+	/// per `fragment_timeout`. Set when a message's first fragment is
+	/// buffered, cleared once reassembly completes.
+	fragment_deadline: Option<Instant>,
+	/// Bytes already read off the stream by `try_recv_message` but not yet
+	/// enough to decode a complete data frame from. Kept here rather than
+	/// discarded so the next call picks up where the last one left off,
+	/// even if a `WouldBlock` landed mid frame header or mid payload.
+	partial: Vec<u8>,
+	max_control_frame_rate: Option<u32>,
+	/// The one-second window `max_control_frame_rate` is currently being
+	/// counted against, and how many control frames have landed in it so
+	/// far. Reset to a fresh window (rather than a sliding one) the first
+	/// time a control frame arrives after the previous window has elapsed.
+	control_frame_window: Option<(Instant, u32)>,
+	/// The cap set by `set_capture_parse_error_bytes`, or `None` (the
+	/// default) to leave frame parse failures as plain errors.
+	capture_parse_error_bytes: Option<usize>,
+	/// Whether `set_redact_captured_bytes` is set, suppressing the bytes
+	/// `capture_parse_error_bytes` would otherwise capture.
+	redact_captured_bytes: bool,
 }
 
 impl Receiver {
-	/// Create a new Receiver using the specified Reader.
+	/// Create a new `Receiver`. This builds just the framing layer - pass
+	/// `mask: true` if this side of the connection expects to receive
+	/// masked frames (i.e. it is the server, per RFC6455 section 5.1),
+	/// `false` if it is the client.
+	///
+	/// A `Receiver` has no stream of its own; pass a `&mut Read` (a
+	/// `TcpStream`, a `TlsStream` from another crate, a pipe, ...) to
+	/// `recv_message`/`recv_dataframe` each time you use it, or wrap it and
+	/// a stream together in a `Reader` for a self-contained handle.
 	pub fn new(mask: bool) -> Receiver {
 		Receiver {
 			buffer: Vec::new(),
 			mask,
+			received_close: Arc::new(AtomicBool::new(false)),
+			max_message_size: None,
+			max_frame_size: None,
+			allow_reserved_opcodes: false,
+			stream_threshold: None,
+			deliver_pongs: false,
+			fragment_timeout: None,
+			fragment_deadline: None,
+			partial: Vec::new(),
+			max_control_frame_rate: None,
+			control_frame_window: None,
+			capture_parse_error_bytes: None,
+			redact_captured_bytes: false,
 		}
 	}
-}
 
-impl ws::Receiver for Receiver {
-	type F = DataFrame;
+	/// Like `new`, but sharing `received_close` with a `sender::Sender`
+	/// (built with `sender::Sender::new_with_close_state`) so that once this
+	/// `Receiver` sees a `Close` frame, the paired `Sender` refuses to send
+	/// further data frames.
+	pub(crate) fn new_with_close_state(mask: bool, received_close: Arc<AtomicBool>) -> Receiver {
+		Receiver {
+			buffer: Vec::new(),
+			mask,
+			received_close,
+			max_message_size: None,
+			max_frame_size: None,
+			allow_reserved_opcodes: false,
+			stream_threshold: None,
+			deliver_pongs: false,
+			fragment_timeout: None,
+			fragment_deadline: None,
+			partial: Vec::new(),
+			max_control_frame_rate: None,
+			control_frame_window: None,
+			capture_parse_error_bytes: None,
+			redact_captured_bytes: false,
+		}
+	}
 
-	type M = OwnedMessage;
+	/// Limit how long a fragmented message may take to complete, counted
+	/// from when its first fragment is buffered to when its final (`fin`)
+	/// fragment arrives. The deadline is checked each time reassembly is
+	/// driven forward - before blocking on the next data frame in
+	/// `recv_message_dataframes`, and on every call into
+	/// `try_recv_message_dataframes` - so once it passes, the next such
+	/// check aborts the stalled reassembly with
+	/// `WebSocketError::FragmentTimeout` instead of continuing to hold its
+	/// fragments. `None` (the default) means no limit.
+	///
+	/// This targets a peer that opens a fragmented message and then goes
+	/// quiet, distinct from an idle connection timeout (a read timeout set
+	/// on the underlying stream) - combine the two for both a
+	/// per-connection and a per-message bound.
+	pub fn set_fragment_timeout(&mut self, timeout: Option<Duration>) {
+		self.fragment_timeout = timeout;
+	}
 
-	/// Reads a single data frame from the remote endpoint.
-	fn recv_dataframe<R>(&mut self, reader: &mut R) -> WebSocketResult<DataFrame>
+	/// The limit currently set by `set_fragment_timeout`, or `None` if
+	/// unset.
+	pub fn fragment_timeout(&self) -> Option<Duration> {
+		self.fragment_timeout
+	}
+
+	/// Limit how many `Close`/`Ping`/`Pong` control frames
+	/// `recv_message`/`try_recv_message` will accept from the peer within
+	/// a one-second window - a fixed window, restarted by the first control
+	/// frame that arrives after the previous one has elapsed, not a sliding
+	/// one. Once the count within the current
+	/// window crosses `per_sec`, the control frame that tripped it is
+	/// rejected with `WebSocketError::ControlFrameFlood` instead of being
+	/// delivered (or, for a swallowed `Pong`, silently consumed) as usual.
+	/// Guards against a peer flooding pings to force wasted CPU answering
+	/// them, distinct from `set_max_message_size`, which bounds data
+	/// messages rather than the control frames interleaved between them.
+	/// `None` (the default) means no limit.
+	pub fn set_max_control_frame_rate(&mut self, per_sec: Option<u32>) {
+		self.max_control_frame_rate = per_sec;
+	}
+
+	/// The limit currently set by `set_max_control_frame_rate`, or `None`
+	/// if unset.
+	pub fn max_control_frame_rate(&self) -> Option<u32> {
+		self.max_control_frame_rate
+	}
+
+	/// When a data frame fails to parse inside `try_recv_message`/
+	/// `try_recv_message_dataframes`, capture up to `cap` bytes from the
+	/// front of the buffer being parsed and attach them to the
+	/// `WebSocketError::FrameParseError` raised in its place - turning an
+	/// opaque protocol error into something that can be hex-dumped to see
+	/// exactly what a desynced or misbehaving peer actually sent. `None`
+	/// (the default) leaves such failures as the plain
+	/// `DataFrameError`/`ProtocolError`/`IoError` they'd otherwise be.
+	///
+	/// Only the non-blocking, buffered path captures anything:
+	/// `recv_dataframe`/`recv_message_dataframes` read straight from the
+	/// `Read` they're given with nothing retained on failure, so there is
+	/// no buffer left to capture from once a blocking read errors.
+	///
+	/// See `set_redact_captured_bytes` to keep this enabled for the
+	/// `FrameParseError` variant and its message without logging the raw
+	/// bytes themselves.
+	pub fn set_capture_parse_error_bytes(&mut self, cap: Option<usize>) {
+		self.capture_parse_error_bytes = cap;
+	}
+
+	/// The cap currently set by `set_capture_parse_error_bytes`, or `None`
+	/// if unset.
+	pub fn capture_parse_error_bytes(&self) -> Option<usize> {
+		self.capture_parse_error_bytes
+	}
+
+	/// When set, `set_capture_parse_error_bytes` still wraps parse
+	/// failures in `WebSocketError::FrameParseError`, but its `raw` field
+	/// is always empty - useful for deployments where the error's
+	/// existence is worth logging but the bytes themselves might carry
+	/// sensitive payload data. Defaults to `false`.
+	pub fn set_redact_captured_bytes(&mut self, redact: bool) {
+		self.redact_captured_bytes = redact;
+	}
+
+	/// The setting currently set by `set_redact_captured_bytes`.
+	pub fn redact_captured_bytes(&self) -> bool {
+		self.redact_captured_bytes
+	}
+
+	/// Limit how many bytes a fragmented message's payload may add up to
+	/// before `recv_message`/`recv_message_dataframes` gives up on it. Once
+	/// the running total crosses `limit`, reassembly aborts immediately
+	/// (rather than after the final fragment arrives) with
+	/// `WebSocketError::MessageTooLong`, so a peer cannot force the whole
+	/// oversized message to be buffered first. `None` (the default) means
+	/// no limit.
+	///
+	/// This is also this crate's answer to a decompression-ratio ("zip
+	/// bomb") cap: since it implements no `permessage-deflate`-style
+	/// extension (see `ws::dataframe::DataFrame::reserved`), nothing ever
+	/// inflates a frame's payload before it reaches here, so the size this
+	/// limit checks already *is* the final size of the message - there is
+	/// no separate compressed-vs-decompressed ratio to additionally bound.
+	pub fn set_max_message_size(&mut self, limit: Option<usize>) {
+		self.max_message_size = limit;
+	}
+
+	/// The limit currently set by `set_max_message_size`, or `None` if
+	/// unset. Callable at any point in the connection's lifetime, so a
+	/// server can read back the limit it set for a negotiated subprotocol.
+	pub fn max_message_size(&self) -> Option<usize> {
+		self.max_message_size
+	}
+
+	/// Limit how large a single data frame's payload may be, checked
+	/// against the length a frame's header declares before its payload is
+	/// read off the wire - unlike `set_max_message_size`, which only
+	/// notices a frame is too big after allocating space for it and
+	/// reading it in full. A peer that announces a huge length in a frame
+	/// header is rejected with `WebSocketError::MessageTooLong` immediately,
+	/// before this crate attempts whatever allocation that length would
+	/// otherwise call for.
+	///
+	/// `set_max_message_size` still matters on top of this: it catches a
+	/// fragmented message whose individual frames each stay under
+	/// `max_frame_size` but whose reassembled total does not. `None` (the
+	/// default) means no per-frame limit.
+	pub fn set_max_frame_size(&mut self, limit: Option<usize>) {
+		self.max_frame_size = limit;
+	}
+
+	/// The limit currently set by `set_max_frame_size`, or `None` if
+	/// unset.
+	pub fn max_frame_size(&self) -> Option<usize> {
+		self.max_frame_size
+	}
+
+	/// Sets the payload size, in bytes, at or above which
+	/// `recv_message_dataframes_or_stream` hands back a `MessageReader`
+	/// instead of a fully buffered message. `None` (the default) never
+	/// streams, always buffering in full like `recv_message_dataframes`.
+	pub fn set_stream_threshold(&mut self, threshold: Option<usize>) {
+		self.stream_threshold = threshold;
+	}
+
+	/// The threshold currently set by `set_stream_threshold`, or `None` if
+	/// unset.
+	pub fn stream_threshold(&self) -> Option<usize> {
+		self.stream_threshold
+	}
+
+	/// Whether a message using a reserved or non-control opcode (3-7, 11-15)
+	/// is accepted as `OwnedMessage::Reserved` instead of being rejected with
+	/// `WebSocketError::ProtocolError`. Off (`false`) by default, since no
+	/// extension negotiated by this crate assigns those opcodes a meaning -
+	/// turn it on only if the peer is known to use one for an
+	/// application-defined purpose.
+	pub fn set_allow_reserved_opcodes(&mut self, allow: bool) {
+		self.allow_reserved_opcodes = allow;
+	}
+
+	/// The setting currently set by `set_allow_reserved_opcodes`.
+	pub fn allow_reserved_opcodes(&self) -> bool {
+		self.allow_reserved_opcodes
+	}
+
+	/// Whether an unsolicited `Pong` (one that isn't immediately merged into
+	/// a larger reassembly, i.e. every `Pong` - control frames can't be
+	/// fragmented) is handed back from `recv_message`/`recv_message_dataframes`
+	/// at all. Off (`false`) by default: most callers have nothing to do
+	/// with a `Pong` they didn't explicitly solicit, so it's silently
+	/// consumed rather than forcing every caller to match and discard it.
+	/// Turn this on to measure round-trip latency against payloads sent
+	/// with `Client::send_ping`/`send_pong`.
+	///
+	/// Apart from `Client::recv_message_keepalive` (with keepalive on) and
+	/// `Client::echo_next`, this crate never auto-replies to a `Ping` with a
+	/// `Pong` regardless of this setting - that's left to the caller (see
+	/// `Client::recv_event`) - `deliver_pongs` only controls whether a
+	/// `Pong` *received* from the peer is surfaced or dropped.
+	pub fn set_deliver_pongs(&mut self, deliver_pongs: bool) {
+		self.deliver_pongs = deliver_pongs;
+	}
+
+	/// The setting currently set by `set_deliver_pongs`.
+	pub fn deliver_pongs(&self) -> bool {
+		self.deliver_pongs
+	}
+
+	/// Whether a `Close` frame has been received yet, per `received_close`.
+	/// Shared with the paired `sender::Sender` when built with
+	/// `new_with_close_state`, so it already reflects frames the `Sender`
+	/// has blocked on the strength of. See `Client::is_closed`.
+	pub(crate) fn received_close(&self) -> bool {
+		self.received_close.load(Ordering::SeqCst)
+	}
+
+	/// Whether a fragmented message is currently mid-reassembly, i.e.
+	/// `recv_message`/`recv_message_dataframes` has buffered at least one
+	/// fragment and is waiting on its `fin` fragment. Handy for diagnosing a
+	/// peer that starts a fragmented message and never finishes it. A
+	/// read-only look at the buffered state - never performs any IO.
+	pub fn in_fragmented_message(&self) -> Option<FragmentState> {
+		let first = self.buffer.first()?;
+		Some(FragmentState {
+			opcode: first.opcode,
+			bytes_so_far: self.buffer.iter().map(|frame| frame.data.len()).sum(),
+		})
+	}
+
+	/// Checks whether adding `extra` bytes to the fragments already buffered
+	/// would cross `max_message_size`, aborting before those bytes are kept
+	/// around if so.
+	fn check_message_size(&self, extra: usize) -> WebSocketResult<()> {
+		if let Some(limit) = self.max_message_size {
+			let seen = self.buffer.iter().map(|frame| frame.data.len()).sum::<usize>() + extra;
+			if seen > limit {
+				return Err(WebSocketError::MessageTooLong { limit, seen });
+			}
+		}
+		Ok(())
+	}
+
+	/// Checks a frame header's declared length against `max_frame_size`
+	/// before its payload is read (and thus before anything is allocated
+	/// for it).
+	fn check_frame_size(&self, declared_len: u64) -> WebSocketResult<()> {
+		if let Some(limit) = self.max_frame_size {
+			let seen = declared_len as usize;
+			if seen > limit {
+				return Err(WebSocketError::MessageTooLong { limit, seen });
+			}
+		}
+		Ok(())
+	}
+
+	/// Checks whether the message currently mid-reassembly, if any, has
+	/// outlived `fragment_timeout`.
+	fn check_fragment_timeout(&self) -> WebSocketResult<()> {
+		if let Some(deadline) = self.fragment_deadline {
+			if Instant::now() >= deadline {
+				return Err(WebSocketError::FragmentTimeout);
+			}
+		}
+		Ok(())
+	}
+
+	/// Counts one more `Close`/`Ping`/`Pong` control frame against
+	/// `max_control_frame_rate`'s current one-second window, starting a
+	/// fresh window if the previous one has already elapsed, and rejects it
+	/// if that crosses the configured limit.
+	fn check_control_frame_rate(&mut self) -> WebSocketResult<()> {
+		let limit = match self.max_control_frame_rate {
+			Some(limit) => limit,
+			None => return Ok(()),
+		};
+
+		let now = Instant::now();
+		let (start, count) = match self.control_frame_window {
+			Some((start, count)) if now.duration_since(start) < Duration::from_secs(1) => (start, count + 1),
+			_ => (now, 1),
+		};
+		self.control_frame_window = Some((start, count));
+
+		if count > limit {
+			return Err(WebSocketError::ControlFrameFlood);
+		}
+		Ok(())
+	}
+
+	/// Like `DataFrame::read_dataframe`, but checks the header's declared
+	/// length against `max_frame_size` before allocating space for its
+	/// payload, rather than only after reading it in full.
+	fn read_dataframe_checked<R>(&self, reader: &mut R) -> WebSocketResult<DataFrame>
 	where
 		R: Read,
 	{
-		DataFrame::read_dataframe(reader, self.mask)
+		let header = dfh::read_header(reader)?;
+		self.check_frame_size(header.len)?;
+
+		let mut data = Vec::with_capacity(header.len as usize);
+		let read = reader.take(header.len).read_to_end(&mut data)?;
+		if (read as u64) < header.len {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
+		}
+
+		DataFrame::read_dataframe_body(header, data, self.mask)
 	}
+}
 
-	/// Returns the data frames that constitute one message.
-	fn recv_message_dataframes<R>(&mut self, reader: &mut R) -> WebSocketResult<Vec<DataFrame>>
+impl Receiver {
+	/// Validates and buffers a message's first data frame, as
+	/// `recv_message_dataframes`/`recv_message_dataframes_or_stream` do
+	/// before falling into `finish_reassembly`.
+	fn push_first_dataframe(&mut self, first: DataFrame) -> WebSocketResult<()> {
+		if first.opcode == Opcode::Continuation {
+			return Err(WebSocketError::ProtocolError(
+				"Unexpected continuation data frame opcode",
+			));
+		}
+
+		if first.opcode as u8 >= 8 {
+			self.check_control_frame_rate()?;
+		}
+
+		if first.opcode == Opcode::Close {
+			self.received_close.store(true, Ordering::SeqCst);
+		}
+
+		self.check_message_size(first.data.len())?;
+		self.fragment_deadline = self.fragment_timeout.map(|timeout| Instant::now() + timeout);
+		self.buffer.push(first);
+		Ok(())
+	}
+
+	/// Reads data frames off `reader` until `self.buffer` holds a complete
+	/// message, starting from whatever is already buffered (which must
+	/// include at least the message's first frame).
+	fn finish_reassembly<R>(&mut self, reader: &mut R) -> WebSocketResult<Vec<DataFrame>>
 	where
 		R: Read,
 	{
-		let mut finished = if self.buffer.is_empty() {
-			let first = self.recv_dataframe(reader)?;
-
-			if first.opcode == Opcode::Continuation {
-				return Err(WebSocketError::ProtocolError(
-					"Unexpected continuation data frame opcode",
-				));
-			}
-
-			let finished = first.finished;
-			self.buffer.push(first);
-			finished
-		} else {
-			false
-		};
+		let mut finished = self.buffer.last().map_or(true, |frame| frame.finished);
 
 		while !finished {
+			self.check_fragment_timeout()?;
 			let next = self.recv_dataframe(reader)?;
 			finished = next.finished;
 
 			match next.opcode as u8 {
 				// Continuation opcode
-				0 => self.buffer.push(next),
+				0 => {
+					self.check_message_size(next.data.len())?;
+					self.buffer.push(next);
+				}
 				// Control frame
 				8...15 => {
+					self.check_control_frame_rate()?;
+					if next.opcode == Opcode::Close {
+						self.received_close.store(true, Ordering::SeqCst);
+					}
 					return Ok(vec![next]);
 				}
 				// Others
@@ -140,6 +672,1086 @@ impl ws::Receiver for Receiver {
 			}
 		}
 
+		self.fragment_deadline = None;
 		Ok(::std::mem::replace(&mut self.buffer, Vec::new()))
 	}
+
+	/// Like `recv_message_dataframes`, but if the next message is a single
+	/// unfragmented frame whose declared length is at least the threshold
+	/// set by `set_stream_threshold`, reports that instead of reading its
+	/// payload, so the caller can stream it with a `MessageReader` (made
+	/// from the result via `MessageReader::new`) rather than buffer it.
+	///
+	/// This stops short of actually building the `MessageReader` itself so
+	/// that the borrow of `reader` it would hold doesn't have to outlive
+	/// this call - callers with more `Read`ing or writing of their own to
+	/// do in the non-streaming case (`Client::recv_message_or_stream`, for
+	/// one) build the `MessageReader` only once they've committed to the
+	/// streaming branch. `Reader::recv_message_or_stream`, which has
+	/// nothing else to do either way, uses
+	/// `recv_message_dataframes_or_stream` below instead.
+	///
+	/// Fragmented messages are always buffered in full, since there is no
+	/// size hint for the whole message until its last fragment arrives -
+	/// only a message's lone frame (or its first fragment, which this can't
+	/// tell apart from a lone frame without reading ahead) is ever a
+	/// candidate to stream.
+	pub fn next_message_outcome<R>(&mut self, reader: &mut R) -> WebSocketResult<MessageOutcome>
+	where
+		R: Read,
+	{
+		if !self.buffer.is_empty() {
+			return self.finish_reassembly(reader).map(MessageOutcome::Buffered);
+		}
+
+		let header = dfh::read_header(reader)?;
+		let opcode = Opcode::new(header.opcode)
+			.ok_or(WebSocketError::DataFrameError("Invalid header opcode"))?;
+
+		let streamable = header.flags.contains(dfh::FIN)
+			&& (opcode == Opcode::Text || opcode == Opcode::Binary)
+			&& self
+				.stream_threshold
+				.map_or(false, |threshold| header.len as usize >= threshold);
+
+		if streamable {
+			self.check_frame_size(header.len)?;
+			self.check_message_size(header.len as usize)?;
+
+			if header.mask.is_some() != self.mask {
+				return Err(WebSocketError::DataFrameError(if self.mask {
+					"Expected masked data frame"
+				} else {
+					"Expected unmasked data frame"
+				}));
+			}
+
+			return Ok(MessageOutcome::Streamable {
+				opcode,
+				len: header.len,
+				mask: header.mask,
+			});
+		}
+
+		self.check_frame_size(header.len)?;
+		let mut data = Vec::with_capacity(header.len as usize);
+		let read = reader.take(header.len).read_to_end(&mut data)?;
+		if (read as u64) < header.len {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
+		}
+		let first = DataFrame::read_dataframe_body(header, data, self.mask)?;
+		self.push_first_dataframe(first)?;
+
+		self.finish_reassembly(reader).map(MessageOutcome::Buffered)
+	}
+
+	/// Like `next_message_outcome`, but builds the `MessageReader` itself
+	/// for the streaming case - convenient when, unlike
+	/// `Client::recv_message_or_stream`, the caller has nothing further to
+	/// do with `self` either way and so doesn't need `next_message_outcome`'s
+	/// two-step split to keep that borrow of `reader` short-lived.
+	pub fn recv_message_dataframes_or_stream<'r, R>(
+		&mut self,
+		reader: &'r mut R,
+	) -> WebSocketResult<StreamedDataFrames<'r, R>>
+	where
+		R: Read,
+	{
+		match self.next_message_outcome(reader)? {
+			MessageOutcome::Buffered(dataframes) => Ok(StreamedDataFrames::Buffered(dataframes)),
+			MessageOutcome::Streamable { opcode, len, mask } => {
+				Ok(StreamedDataFrames::Streaming(MessageReader::new(opcode, len, mask, reader)))
+			}
+		}
+	}
+
+	/// Reads whatever `reader` currently has available without blocking,
+	/// returning the data frames making up a complete message once one has
+	/// fully arrived, or `Ok(None)` if not enough has arrived yet.
+	/// `reader` must already be in non-blocking mode (e.g.
+	/// `TcpStream::set_nonblocking`) - a `WouldBlock` from it just means
+	/// "try again once more data arrives" here, not an error, and whatever
+	/// was read in the meantime stays buffered on `self` for the next call
+	/// rather than being lost, even if it lands mid frame header or mid
+	/// payload.
+	///
+	/// Like `recv_message_dataframes`, a lone `Pong` is dropped rather than
+	/// returned unless `deliver_pongs` is set, and reading continues for
+	/// the next message in the same call. See `try_recv_message`, which
+	/// reassembles the result into an `OwnedMessage` the way `recv_message`
+	/// does for `recv_message_dataframes`.
+	pub fn try_recv_message_dataframes<R>(
+		&mut self,
+		reader: &mut R,
+	) -> WebSocketResult<Option<Vec<DataFrame>>>
+	where
+		R: Read,
+	{
+		self.fill_partial(reader)?;
+
+		loop {
+			let (frame, consumed) = match DataFrame::decode_frame(&self.partial, self.mask) {
+				Ok(result) => result,
+				Err(WebSocketError::NoDataAvailable) => return Ok(None),
+				Err(err) => return Err(self.annotate_parse_error(err)),
+			};
+			self.partial.drain(..consumed);
+
+			if let Some(dataframes) = self.ingest_dataframe(frame)? {
+				if !self.deliver_pongs && dataframes.len() == 1 && dataframes[0].opcode == Opcode::Pong {
+					continue;
+				}
+				return Ok(Some(dataframes));
+			}
+		}
+	}
+
+	/// Like `recv_message`, but alongside the reassembled message returns
+	/// `MessageMeta` describing how it was framed on the wire - whether
+	/// RSV1 ("compressed") was set, how many data frames it was split
+	/// across, and the total bytes (headers included) all of them took on
+	/// the wire. Built from the same data frames `recv_message_dataframes`
+	/// already reassembles, so there's no extra read pass to get it.
+	///
+	/// Only `Receiver`/`Reader` expose this - `client::sync::Client`
+	/// doesn't, since its `recv_message` can hand back a queued `Ping`
+	/// answer or an already-buffered event rather than a message it just
+	/// read, for which "bytes this took on the wire" has no single
+	/// sensible answer.
+	pub fn recv_message_with_meta<R>(
+		&mut self,
+		reader: &mut R,
+	) -> WebSocketResult<(OwnedMessage, MessageMeta)>
+	where
+		R: Read,
+	{
+		let dataframes = self.recv_message_dataframes(reader)?;
+		let meta = MessageMeta::from_dataframes(&dataframes, self.mask);
+		OwnedMessage::from_dataframes_allow_reserved(dataframes, self.allow_reserved_opcodes)
+			.map(|message| (message, meta))
+	}
+
+	/// Like `recv_message`, but for `reader` already in non-blocking mode:
+	/// returns `Ok(None)` instead of blocking when no complete message is
+	/// available yet. See `try_recv_message_dataframes`.
+	pub fn try_recv_message<R>(&mut self, reader: &mut R) -> WebSocketResult<Option<OwnedMessage>>
+	where
+		R: Read,
+	{
+		match self.try_recv_message_dataframes(reader)? {
+			Some(dataframes) => {
+				OwnedMessage::from_dataframes_allow_reserved(dataframes, self.allow_reserved_opcodes).map(Some)
+			}
+			None => Ok(None),
+		}
+	}
+
+	/// Reads everything currently available from `reader` into
+	/// `self.partial`, stopping (without error) at the first `WouldBlock`
+	/// or a clean EOF - used by `try_recv_message`.
+	fn fill_partial<R>(&mut self, reader: &mut R) -> WebSocketResult<()>
+	where
+		R: Read,
+	{
+		let mut chunk = [0u8; 4096];
+		loop {
+			match reader.read(&mut chunk) {
+				Ok(0) => return Ok(()),
+				Ok(n) => self.partial.extend_from_slice(&chunk[..n]),
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+				Err(err) => return Err(err.into()),
+			}
+		}
+	}
+
+	/// Wraps `err` in `WebSocketError::FrameParseError` with up to
+	/// `capture_parse_error_bytes` bytes from the front of `self.partial`,
+	/// or returns it unchanged if capturing is disabled. See
+	/// `set_capture_parse_error_bytes`.
+	fn annotate_parse_error(&self, err: WebSocketError) -> WebSocketError {
+		let cap = match self.capture_parse_error_bytes {
+			Some(cap) => cap,
+			None => return err,
+		};
+
+		let raw = if self.redact_captured_bytes {
+			Vec::new()
+		} else {
+			self.partial[..cap.min(self.partial.len())].to_vec()
+		};
+
+		WebSocketError::FrameParseError {
+			description: err.to_string(),
+			raw,
+		}
+	}
+
+	/// Feeds one already-decoded data frame into the reassembly state that
+	/// `self.buffer` tracks, the same way the loop body of
+	/// `finish_reassembly` does for a blocking read - except driven one
+	/// already-available frame at a time, rather than by reading more from
+	/// a (possibly blocking) `Read` whenever the message isn't finished
+	/// yet. Returns the finished message's data frames once `frame`
+	/// completes one, or `None` if more fragments are still expected.
+	fn ingest_dataframe(&mut self, frame: DataFrame) -> WebSocketResult<Option<Vec<DataFrame>>> {
+		if self.buffer.is_empty() {
+			let finished = frame.finished;
+			self.push_first_dataframe(frame)?;
+			if finished {
+				self.fragment_deadline = None;
+				return Ok(Some(::std::mem::replace(&mut self.buffer, Vec::new())));
+			}
+			return Ok(None);
+		}
+
+		self.check_fragment_timeout()?;
+
+		match frame.opcode as u8 {
+			// Continuation opcode
+			0 => {
+				self.check_message_size(frame.data.len())?;
+				let finished = frame.finished;
+				self.buffer.push(frame);
+				if finished {
+					self.fragment_deadline = None;
+					Ok(Some(::std::mem::replace(&mut self.buffer, Vec::new())))
+				} else {
+					Ok(None)
+				}
+			}
+			// Control frame
+			8...15 => {
+				self.check_control_frame_rate()?;
+				if frame.opcode == Opcode::Close {
+					self.received_close.store(true, Ordering::SeqCst);
+				}
+				Ok(Some(vec![frame]))
+			}
+			// Others
+			_ => Err(WebSocketError::ProtocolError(
+				"Unexpected data frame opcode",
+			)),
+		}
+	}
+}
+
+/// What `Receiver::next_message_outcome` found waiting: either a complete
+/// message's data frames, or the header of one big enough to stream - see
+/// `Receiver::set_stream_threshold`.
+pub enum MessageOutcome {
+	/// A complete message's data frames, buffered in full - either it was
+	/// smaller than the stream threshold, or it arrived fragmented.
+	Buffered(Vec<DataFrame>),
+	/// A single, unfragmented frame at or above the stream threshold, not
+	/// yet read. Pass this to `MessageReader::new` along with the same
+	/// reader to stream its payload.
+	Streamable {
+		/// Whether the message is `Text` or `Binary`.
+		opcode: Opcode,
+		/// The payload length, in bytes, as declared by the frame header.
+		len: u64,
+		/// The frame's masking key, if it was masked (client frames always
+		/// are; server frames never are).
+		mask: Option<[u8; 4]>,
+	},
+}
+
+impl ws::Receiver for Receiver {
+	type F = DataFrame;
+
+	type M = OwnedMessage;
+
+	/// Reads a single data frame from the remote endpoint.
+	fn recv_dataframe<R>(&mut self, reader: &mut R) -> WebSocketResult<DataFrame>
+	where
+		R: Read,
+	{
+		self.read_dataframe_checked(reader)
+	}
+
+	/// Returns the data frames that constitute one message.
+	///
+	/// A `Pong` is never fragmented, so it always arrives as its own
+	/// single-frame "message" here; when `deliver_pongs` is off (the
+	/// default), such a frame is dropped and this reads on for the next
+	/// message instead of returning it. See `Receiver::set_deliver_pongs`.
+	fn recv_message_dataframes<R>(&mut self, reader: &mut R) -> WebSocketResult<Vec<DataFrame>>
+	where
+		R: Read,
+	{
+		loop {
+			if self.buffer.is_empty() {
+				let first = self.recv_dataframe(reader)?;
+				self.push_first_dataframe(first)?;
+			}
+
+			let dataframes = self.finish_reassembly(reader)?;
+
+			if !self.deliver_pongs && dataframes.len() == 1 && dataframes[0].opcode == Opcode::Pong {
+				continue;
+			}
+
+			return Ok(dataframes);
+		}
+	}
+}
+
+/// A size-hinted message from `Receiver::recv_message_dataframes_or_stream`:
+/// either a complete message, already reassembled from its data frames, or
+/// a handle streaming one too large to buffer. See `Receiver::set_stream_threshold`.
+pub enum StreamedDataFrames<'a, R>
+where
+	R: 'a + Read,
+{
+	/// A complete message's data frames, buffered in full - either it was
+	/// smaller than the stream threshold, or it arrived fragmented.
+	Buffered(Vec<DataFrame>),
+	/// A single data frame's payload, still on the wire. Read from this
+	/// like any other `Read` to pull it off the stream as it arrives.
+	Streaming(MessageReader<'a, R>),
+}
+
+/// A size-hinted message from `Reader::recv_message_or_stream`/
+/// `Client::recv_message_or_stream`: either a fully reassembled message, or
+/// a handle streaming one too large to buffer. See
+/// `Receiver::set_stream_threshold`.
+pub enum StreamedMessage<'a, R>
+where
+	R: 'a + Read,
+{
+	/// A complete message, already reassembled - either it was smaller than
+	/// the stream threshold, or it arrived fragmented.
+	Buffered(OwnedMessage),
+	/// A single message's payload, still on the wire. Read from this like
+	/// any other `Read` to pull it off the stream as it arrives.
+	Streaming(MessageReader<'a, R>),
+}
+
+/// Streams a single data frame's payload directly off the underlying
+/// reader, unmasking as it goes, rather than buffering it up front. Returned
+/// in place of a buffered message by
+/// `Receiver::recv_message_dataframes_or_stream` once a message's declared
+/// length crosses the threshold set by `Receiver::set_stream_threshold`.
+pub struct MessageReader<'a, R>
+where
+	R: 'a + Read,
+{
+	reader: &'a mut R,
+	/// Whether the message being streamed is `Text` or `Binary`.
+	pub opcode: Opcode,
+	remaining: u64,
+	mask: Option<[u8; 4]>,
+	mask_pos: usize,
+}
+
+impl<'a, R> MessageReader<'a, R>
+where
+	R: 'a + Read,
+{
+	/// Builds a `MessageReader` from the `MessageOutcome::Streamable` fields
+	/// reported by `Receiver::next_message_outcome`, and the same reader
+	/// that was passed to it.
+	pub(crate) fn new(opcode: Opcode, len: u64, mask: Option<[u8; 4]>, reader: &'a mut R) -> Self {
+		MessageReader {
+			reader,
+			opcode,
+			remaining: len,
+			mask,
+			mask_pos: 0,
+		}
+	}
+
+	/// How many payload bytes are still unread.
+	pub fn remaining(&self) -> u64 {
+		self.remaining
+	}
+}
+
+impl<'a, R> Read for MessageReader<'a, R>
+where
+	R: 'a + Read,
+{
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+		if self.remaining == 0 {
+			return Ok(0);
+		}
+
+		let want = ::std::cmp::min(buf.len() as u64, self.remaining) as usize;
+		let read = self.reader.read(&mut buf[..want])?;
+
+		if let Some(mask) = self.mask {
+			for byte in &mut buf[..read] {
+				*byte ^= mask[self.mask_pos % mask.len()];
+				self.mask_pos += 1;
+			}
+		}
+
+		self.remaining -= read as u64;
+		Ok(read)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dataframe::Opcode;
+	use ws::dataframe::DataFrame as DataFrameable;
+	use ws::receiver::Receiver as ReceiverTrait;
+
+	fn fragment(finished: bool, opcode: Opcode, data: &[u8]) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		DataFrame::new(finished, opcode, data.to_vec())
+			.write_to(&mut bytes, false)
+			.unwrap();
+		bytes
+	}
+
+	/// A reader that only hands out up to `allowance` bytes per `read` call
+	/// before refusing the rest with `WouldBlock`, to exercise
+	/// `try_recv_message` being driven across several calls the same way a
+	/// real non-blocking socket with a small receive buffer would be. See
+	/// `ChunkedStream` in `server::upgrade::incremental`, which does the
+	/// same thing for writes.
+	struct ChunkedReader {
+		unread: Vec<u8>,
+		allowance: usize,
+	}
+
+	impl ChunkedReader {
+		fn new(bytes: Vec<u8>, allowance: usize) -> Self {
+			ChunkedReader {
+				unread: bytes,
+				allowance,
+			}
+		}
+
+		fn allow_more(&mut self, n: usize) {
+			self.allowance += n;
+		}
+	}
+
+	impl io::Read for ChunkedReader {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			if self.unread.is_empty() {
+				return Ok(0);
+			}
+			if self.allowance == 0 {
+				return Err(io::Error::new(io::ErrorKind::WouldBlock, "no more data"));
+			}
+			let n = ::std::cmp::min(::std::cmp::min(self.allowance, buf.len()), self.unread.len());
+			let rest = self.unread.split_off(n);
+			buf[..n].copy_from_slice(&self.unread);
+			self.unread = rest;
+			self.allowance -= n;
+			Ok(n)
+		}
+	}
+
+	#[test]
+	fn aborts_mid_reassembly_once_the_limit_is_crossed() {
+		let mut bytes = fragment(false, Opcode::Text, b"hello ");
+		bytes.extend(fragment(true, Opcode::Continuation, b"world, this fragment is too long"));
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_message_size(Some(10));
+
+		let mut reader = &bytes[..];
+		match receiver.recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::MessageTooLong { limit, seen }) => {
+				assert_eq!(limit, 10);
+				// the first fragment alone ("hello ") is within the limit;
+				// only the second fragment should push it over.
+				assert!(seen > limit);
+			}
+			other => panic!("expected MessageTooLong, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn max_message_size_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.max_message_size(), None);
+
+		receiver.set_max_message_size(Some(10));
+		assert_eq!(receiver.max_message_size(), Some(10));
+	}
+
+	#[test]
+	fn max_frame_size_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.max_frame_size(), None);
+
+		receiver.set_max_frame_size(Some(10));
+		assert_eq!(receiver.max_frame_size(), Some(10));
+	}
+
+	#[test]
+	fn fragment_timeout_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.fragment_timeout(), None);
+
+		receiver.set_fragment_timeout(Some(Duration::from_millis(5)));
+		assert_eq!(receiver.fragment_timeout(), Some(Duration::from_millis(5)));
+	}
+
+	#[test]
+	fn aborts_mid_reassembly_once_the_fragment_timeout_elapses() {
+		let first = fragment(false, Opcode::Text, b"hello ");
+		let rest = fragment(true, Opcode::Continuation, b"world");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_fragment_timeout(Some(Duration::from_millis(5)));
+
+		// The first fragment alone is buffered, starting the deadline; no
+		// more data follows it yet, so this call fails trying to read past
+		// the end of `first`.
+		let mut reader = &first[..];
+		match receiver.recv_message_dataframes(&mut reader) {
+			Err(_) => {}
+			Ok(_) => panic!("expected an error reading past the end of the stream"),
+		}
+
+		::std::thread::sleep(Duration::from_millis(10));
+
+		let mut reader = &rest[..];
+		match receiver.recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::FragmentTimeout) => {}
+			other => panic!("expected FragmentTimeout, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn fragment_timeout_does_not_trigger_once_reassembly_completes_in_time() {
+		let mut bytes = fragment(false, Opcode::Text, b"hello ");
+		bytes.extend(fragment(true, Opcode::Continuation, b"world"));
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_fragment_timeout(Some(Duration::from_secs(60)));
+
+		let mut reader = &bytes[..];
+		let dataframes = receiver.recv_message_dataframes(&mut reader).unwrap();
+		assert_eq!(dataframes.len(), 2);
+	}
+
+	#[test]
+	fn fragment_timeout_is_checked_by_try_recv_message_dataframes_too() {
+		let first = fragment(false, Opcode::Text, b"hello ");
+		let rest = fragment(true, Opcode::Continuation, b"world");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_fragment_timeout(Some(Duration::from_millis(5)));
+
+		let mut reader = ChunkedReader::new(first, usize::max_value());
+		assert_eq!(receiver.try_recv_message_dataframes(&mut reader).unwrap(), None);
+
+		::std::thread::sleep(Duration::from_millis(10));
+
+		let mut reader = ChunkedReader::new(rest, usize::max_value());
+		match receiver.try_recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::FragmentTimeout) => {}
+			other => panic!("expected FragmentTimeout, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn max_control_frame_rate_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.max_control_frame_rate(), None);
+
+		receiver.set_max_control_frame_rate(Some(3));
+		assert_eq!(receiver.max_control_frame_rate(), Some(3));
+	}
+
+	#[test]
+	fn control_frame_rate_allows_up_to_the_configured_limit_within_a_second() {
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_control_frame_rate(Some(3));
+
+		for _ in 0..3 {
+			let bytes = fragment(true, Opcode::Ping, b"");
+			let mut reader = &bytes[..];
+			assert!(receiver.recv_message_dataframes(&mut reader).is_ok());
+		}
+	}
+
+	#[test]
+	fn control_frame_rate_rejects_the_frame_that_crosses_the_limit() {
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_control_frame_rate(Some(2));
+
+		for _ in 0..2 {
+			let bytes = fragment(true, Opcode::Ping, b"");
+			let mut reader = &bytes[..];
+			assert!(receiver.recv_message_dataframes(&mut reader).is_ok());
+		}
+
+		let bytes = fragment(true, Opcode::Ping, b"");
+		let mut reader = &bytes[..];
+		match receiver.recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::ControlFrameFlood) => {}
+			other => panic!("expected ControlFrameFlood, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn control_frame_rate_resets_once_the_window_elapses() {
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_control_frame_rate(Some(1));
+
+		let bytes = fragment(true, Opcode::Ping, b"");
+		let mut reader = &bytes[..];
+		assert!(receiver.recv_message_dataframes(&mut reader).is_ok());
+
+		::std::thread::sleep(Duration::from_millis(1050));
+
+		let bytes = fragment(true, Opcode::Ping, b"");
+		let mut reader = &bytes[..];
+		assert!(receiver.recv_message_dataframes(&mut reader).is_ok());
+	}
+
+	#[test]
+	fn unset_control_frame_rate_never_rejects_anything() {
+		let mut receiver = Receiver::new(false);
+
+		for _ in 0..50 {
+			let bytes = fragment(true, Opcode::Ping, b"");
+			let mut reader = &bytes[..];
+			assert!(receiver.recv_message_dataframes(&mut reader).is_ok());
+		}
+	}
+
+	#[test]
+	fn capture_parse_error_bytes_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.capture_parse_error_bytes(), None);
+
+		receiver.set_capture_parse_error_bytes(Some(16));
+		assert_eq!(receiver.capture_parse_error_bytes(), Some(16));
+	}
+
+	#[test]
+	fn redact_captured_bytes_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.redact_captured_bytes(), false);
+
+		receiver.set_redact_captured_bytes(true);
+		assert_eq!(receiver.redact_captured_bytes(), true);
+	}
+
+	#[test]
+	fn malformed_frame_is_a_plain_error_without_capturing_enabled() {
+		// FIN|Text, then a 16-bit extended length of 5 - invalid, since a
+		// length that small should have used the 1-byte form instead.
+		let bytes = vec![0x81, 0x7E, 0x00, 0x05];
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = &bytes[..];
+		match receiver.try_recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::DataFrameError(_)) => {}
+			other => panic!("expected a plain DataFrameError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn malformed_frame_captures_up_to_the_configured_cap() {
+		let bytes = vec![0x81, 0x7E, 0x00, 0x05];
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_capture_parse_error_bytes(Some(2));
+		let mut reader = &bytes[..];
+		match receiver.try_recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::FrameParseError { raw, .. }) => assert_eq!(raw, vec![0x81, 0x7E]),
+			other => panic!("expected FrameParseError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn malformed_frame_capture_respects_redaction() {
+		let bytes = vec![0x81, 0x7E, 0x00, 0x05];
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_capture_parse_error_bytes(Some(16));
+		receiver.set_redact_captured_bytes(true);
+		let mut reader = &bytes[..];
+		match receiver.try_recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::FrameParseError { raw, .. }) => assert!(raw.is_empty()),
+			other => panic!("expected FrameParseError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn recv_message_with_meta_reports_an_unfragmented_message() {
+		let bytes = fragment(true, Opcode::Text, b"hello");
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = &bytes[..];
+		let (message, meta) = receiver.recv_message_with_meta(&mut reader).unwrap();
+
+		assert_eq!(message, OwnedMessage::Text("hello".to_owned()));
+		assert_eq!(
+			meta,
+			MessageMeta {
+				compressed: false,
+				fragments: 1,
+				wire_bytes: bytes.len(),
+			}
+		);
+	}
+
+	#[test]
+	fn recv_message_with_meta_counts_every_fragment_and_their_total_wire_bytes() {
+		let mut bytes = fragment(false, Opcode::Text, b"hello ");
+		bytes.extend(fragment(true, Opcode::Continuation, b"world"));
+		let total_wire_bytes = bytes.len();
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = &bytes[..];
+		let (message, meta) = receiver.recv_message_with_meta(&mut reader).unwrap();
+
+		assert_eq!(message, OwnedMessage::Text("hello world".to_owned()));
+		assert_eq!(
+			meta,
+			MessageMeta {
+				compressed: false,
+				fragments: 2,
+				wire_bytes: total_wire_bytes,
+			}
+		);
+	}
+
+	#[test]
+	fn recv_message_discards_the_meta_recv_message_with_meta_would_return() {
+		let bytes = fragment(true, Opcode::Text, b"hello");
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = &bytes[..];
+		let message = ReceiverTrait::recv_message(&mut receiver, &mut reader).unwrap();
+
+		assert_eq!(message, OwnedMessage::Text("hello".to_owned()));
+	}
+
+	#[test]
+	fn allow_reserved_opcodes_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.allow_reserved_opcodes(), false);
+
+		receiver.set_allow_reserved_opcodes(true);
+		assert_eq!(receiver.allow_reserved_opcodes(), true);
+	}
+
+	#[test]
+	fn in_fragmented_message_is_none_before_any_fragment_arrives() {
+		let receiver = Receiver::new(false);
+		assert_eq!(receiver.in_fragmented_message(), None);
+	}
+
+	#[test]
+	fn in_fragmented_message_reports_opcode_and_bytes_so_far_mid_reassembly() {
+		// Only the first, non-final fragment is on the wire - the peer never
+		// sends the rest, so reassembly is left hanging with one fragment
+		// buffered.
+		let bytes = fragment(false, Opcode::Text, b"hello ");
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = &bytes[..];
+		match receiver.recv_message_dataframes(&mut reader) {
+			Err(_) => {}
+			Ok(_) => panic!("expected an error reading past the end of the stream"),
+		}
+
+		let state = receiver.in_fragmented_message().expect("a fragment is buffered");
+		assert_eq!(state.opcode, Opcode::Text);
+		assert_eq!(state.bytes_so_far, b"hello ".len());
+	}
+
+	#[test]
+	fn in_fragmented_message_is_none_once_a_message_finishes() {
+		let bytes = fragment(true, Opcode::Text, b"hello");
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = &bytes[..];
+		receiver.recv_message_dataframes(&mut reader).unwrap();
+
+		assert_eq!(receiver.in_fragmented_message(), None);
+	}
+
+	#[test]
+	fn allows_messages_within_the_limit() {
+		let bytes = fragment(true, Opcode::Text, b"hello");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_message_size(Some(10));
+
+		let mut reader = &bytes[..];
+		let dataframes = receiver.recv_message_dataframes(&mut reader).unwrap();
+		assert_eq!(dataframes[0].data, b"hello");
+	}
+
+	#[test]
+	fn a_single_frame_over_the_limit_is_rejected_before_its_payload_is_read() {
+		let bytes = fragment(true, Opcode::Text, b"hello world, this frame is too long");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_frame_size(Some(10));
+
+		let mut reader = &bytes[..];
+		match receiver.recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::MessageTooLong { limit, seen }) => {
+				assert_eq!(limit, 10);
+				assert_eq!(seen, b"hello world, this frame is too long".len());
+			}
+			other => panic!("expected MessageTooLong, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn a_continuation_frame_over_the_limit_is_rejected_even_under_the_message_cap() {
+		let mut bytes = fragment(false, Opcode::Text, b"hi");
+		bytes.extend(fragment(true, Opcode::Continuation, b"a fragment longer than the frame cap"));
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_message_size(Some(1024));
+		receiver.set_max_frame_size(Some(10));
+
+		let mut reader = &bytes[..];
+		match receiver.recv_message_dataframes(&mut reader) {
+			Err(WebSocketError::MessageTooLong { limit, seen }) => {
+				assert_eq!(limit, 10);
+				assert_eq!(seen, b"a fragment longer than the frame cap".len());
+			}
+			other => panic!("expected MessageTooLong, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn frames_within_the_limit_are_unaffected() {
+		let bytes = fragment(true, Opcode::Text, b"hello");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_max_frame_size(Some(10));
+
+		let mut reader = &bytes[..];
+		let dataframes = receiver.recv_message_dataframes(&mut reader).unwrap();
+		assert_eq!(dataframes[0].data, b"hello");
+	}
+
+	#[test]
+	fn stream_threshold_reads_back_what_was_set() {
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.stream_threshold(), None);
+
+		receiver.set_stream_threshold(Some(10));
+		assert_eq!(receiver.stream_threshold(), Some(10));
+	}
+
+	#[test]
+	fn below_threshold_messages_are_buffered() {
+		let bytes = fragment(true, Opcode::Text, b"hello");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_stream_threshold(Some(10));
+
+		let mut reader = &bytes[..];
+		match receiver.next_message_outcome(&mut reader).unwrap() {
+			MessageOutcome::Buffered(dataframes) => assert_eq!(dataframes[0].data, b"hello"),
+			MessageOutcome::Streamable { .. } => panic!("expected a buffered message"),
+		}
+	}
+
+	#[test]
+	fn at_or_above_threshold_unfragmented_messages_stream() {
+		let bytes = fragment(true, Opcode::Binary, b"hello world");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_stream_threshold(Some(5));
+
+		let mut reader = &bytes[..];
+		match receiver.next_message_outcome(&mut reader).unwrap() {
+			MessageOutcome::Streamable { opcode, len, mask } => {
+				assert_eq!(opcode, Opcode::Binary);
+				assert_eq!(len, b"hello world".len() as u64);
+				let mut message_reader = MessageReader::new(opcode, len, mask, &mut reader);
+				assert_eq!(message_reader.remaining(), len);
+
+				let mut payload = Vec::new();
+				message_reader.read_to_end(&mut payload).unwrap();
+				assert_eq!(payload, b"hello world");
+				assert_eq!(message_reader.remaining(), 0);
+			}
+			MessageOutcome::Buffered(_) => panic!("expected a streamable message"),
+		}
+	}
+
+	#[test]
+	fn fragmented_messages_always_buffer_regardless_of_threshold() {
+		let mut bytes = fragment(false, Opcode::Text, b"hello ");
+		bytes.extend(fragment(true, Opcode::Continuation, b"world"));
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_stream_threshold(Some(1));
+
+		let mut reader = &bytes[..];
+		match receiver.next_message_outcome(&mut reader).unwrap() {
+			MessageOutcome::Buffered(dataframes) => {
+				assert_eq!(dataframes[0].data, b"hello ");
+				assert_eq!(dataframes[1].data, b"world");
+			}
+			MessageOutcome::Streamable { .. } => panic!("expected a buffered message"),
+		}
+	}
+
+	#[test]
+	fn streaming_decision_still_enforces_the_message_size_limit() {
+		let bytes = fragment(true, Opcode::Binary, b"hello world");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_stream_threshold(Some(5));
+		receiver.set_max_message_size(Some(5));
+
+		let mut reader = &bytes[..];
+		match receiver.next_message_outcome(&mut reader) {
+			Err(WebSocketError::MessageTooLong { limit, seen }) => {
+				assert_eq!(limit, 5);
+				assert_eq!(seen, b"hello world".len());
+			}
+			other => panic!("expected MessageTooLong, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn streaming_decision_still_enforces_the_frame_size_limit() {
+		let bytes = fragment(true, Opcode::Binary, b"hello world");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_stream_threshold(Some(5));
+		receiver.set_max_frame_size(Some(5));
+
+		let mut reader = &bytes[..];
+		match receiver.next_message_outcome(&mut reader) {
+			Err(WebSocketError::MessageTooLong { limit, seen }) => {
+				assert_eq!(limit, 5);
+				assert_eq!(seen, b"hello world".len());
+			}
+			other => panic!("expected MessageTooLong, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn recv_message_dataframes_or_stream_buffers_below_threshold() {
+		let bytes = fragment(true, Opcode::Text, b"hello");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_stream_threshold(Some(10));
+
+		let mut reader = &bytes[..];
+		match receiver.recv_message_dataframes_or_stream(&mut reader).unwrap() {
+			StreamedDataFrames::Buffered(dataframes) => assert_eq!(dataframes[0].data, b"hello"),
+			StreamedDataFrames::Streaming(_) => panic!("expected a buffered message"),
+		}
+	}
+
+	#[test]
+	fn recv_message_dataframes_or_stream_streams_at_or_above_threshold() {
+		let bytes = fragment(true, Opcode::Text, b"hello world");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_stream_threshold(Some(5));
+
+		let mut reader = &bytes[..];
+		match receiver.recv_message_dataframes_or_stream(&mut reader).unwrap() {
+			StreamedDataFrames::Streaming(mut message_reader) => {
+				let mut payload = Vec::new();
+				message_reader.read_to_end(&mut payload).unwrap();
+				assert_eq!(payload, b"hello world");
+			}
+			StreamedDataFrames::Buffered(_) => panic!("expected a streamable message"),
+		}
+	}
+
+	#[test]
+	fn pongs_are_swallowed_by_default() {
+		let mut bytes = fragment(true, Opcode::Pong, b"pong payload");
+		bytes.extend(fragment(true, Opcode::Text, b"hello"));
+
+		let mut receiver = Receiver::new(false);
+		assert!(!receiver.deliver_pongs());
+
+		let mut reader = &bytes[..];
+		let dataframes = receiver.recv_message_dataframes(&mut reader).unwrap();
+		assert_eq!(dataframes[0].opcode, Opcode::Text);
+		assert_eq!(dataframes[0].data, b"hello");
+	}
+
+	#[test]
+	fn pongs_are_delivered_once_enabled() {
+		let bytes = fragment(true, Opcode::Pong, b"pong payload");
+
+		let mut receiver = Receiver::new(false);
+		receiver.set_deliver_pongs(true);
+		assert!(receiver.deliver_pongs());
+
+		let mut reader = &bytes[..];
+		let dataframes = receiver.recv_message_dataframes(&mut reader).unwrap();
+		assert_eq!(dataframes[0].opcode, Opcode::Pong);
+		assert_eq!(dataframes[0].data, b"pong payload");
+	}
+
+	#[test]
+	fn try_recv_message_returns_none_until_the_frame_fully_arrives() {
+		let bytes = fragment(true, Opcode::Text, b"hello world");
+		let mut reader = ChunkedReader::new(bytes, 4);
+
+		let mut receiver = Receiver::new(false);
+		assert_eq!(receiver.try_recv_message(&mut reader).unwrap(), None);
+
+		reader.allow_more(4);
+		assert_eq!(receiver.try_recv_message(&mut reader).unwrap(), None);
+
+		reader.allow_more(100);
+		let message = receiver.try_recv_message(&mut reader).unwrap().unwrap();
+		assert_eq!(message, OwnedMessage::Text("hello world".to_owned()));
+	}
+
+	#[test]
+	fn try_recv_message_keeps_bytes_read_too_early_for_the_next_call() {
+		// One full frame, followed by the first few bytes of a second one.
+		let mut bytes = fragment(true, Opcode::Text, b"hello");
+		bytes.extend(&fragment(true, Opcode::Text, b"world")[..3]);
+		let allowance = bytes.len();
+		let mut reader = ChunkedReader::new(bytes, allowance);
+
+		let mut receiver = Receiver::new(false);
+		let message = receiver.try_recv_message(&mut reader).unwrap().unwrap();
+		assert_eq!(message, OwnedMessage::Text("hello".to_owned()));
+
+		// Nothing more is available yet - the partial second frame stays
+		// buffered rather than being mistaken for a malformed one.
+		assert_eq!(receiver.try_recv_message(&mut reader).unwrap(), None);
+
+		reader.unread.extend(&fragment(true, Opcode::Text, b"world")[3..]);
+		reader.allow_more(100);
+		let message = receiver.try_recv_message(&mut reader).unwrap().unwrap();
+		assert_eq!(message, OwnedMessage::Text("world".to_owned()));
+	}
+
+	#[test]
+	fn try_recv_message_resumes_byte_by_byte_across_repeated_wouldblocks() {
+		// Drip-feeds the frame one byte at a time, so every call but the
+		// last hits `WouldBlock` partway through - first mid header, then
+		// mid payload - and confirms no byte already read is ever lost or
+		// re-read.
+		let bytes = fragment(true, Opcode::Text, b"hello world");
+		let mut reader = ChunkedReader::new(bytes.clone(), 0);
+
+		let mut receiver = Receiver::new(false);
+		for _ in 0..bytes.len() - 1 {
+			reader.allow_more(1);
+			assert_eq!(receiver.try_recv_message(&mut reader).unwrap(), None);
+		}
+
+		reader.allow_more(1);
+		let message = receiver.try_recv_message(&mut reader).unwrap().unwrap();
+		assert_eq!(message, OwnedMessage::Text("hello world".to_owned()));
+	}
 }
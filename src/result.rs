@@ -1,5 +1,7 @@
 //! The result type used within Rust-WebSocket
 
+use hyper::header::Headers;
+use hyper::status::StatusCode;
 use hyper::Error as HttpError;
 use server::upgrade::HyperIntoWsError;
 use std::convert::From;
@@ -38,12 +40,45 @@ pub enum WebSocketError {
 	RequestError(&'static str),
 	/// Invalid WebSocket response error
 	ResponseError(&'static str),
+	/// The server did not upgrade the connection, returning some other
+	/// status code instead of `101 Switching Protocols`. `status` and
+	/// `headers` are the server's response, letting the caller inspect
+	/// things like an auth challenge or error body header before giving up.
+	HandshakeFailed {
+		/// the HTTP status code the server responded with
+		status: StatusCode,
+		/// the HTTP headers the server responded with
+		headers: Headers,
+	},
 	/// Invalid WebSocket data frame error
 	DataFrameError(&'static str),
 	/// No data available
 	NoDataAvailable,
+	/// Tried to send a data frame after the peer's `Close` frame was
+	/// already received, or the OS reported the peer is gone while writing
+	/// one - a `BrokenPipe`/`ConnectionReset` from the underlying stream is
+	/// mapped to this variant rather than surfacing as a raw `IoError`,
+	/// since both mean the same thing to a caller: stop sending, the
+	/// connection is over.
+	ConnectionClosed,
+	/// A fragmented message's running total exceeded the configured
+	/// `max_message_size` before the final fragment arrived. `seen` is the
+	/// size (in bytes) that tripped the limit; `limit` is the configured
+	/// maximum.
+	MessageTooLong {
+		/// the configured maximum message size, in bytes
+		limit: usize,
+		/// the size, in bytes, that was reached when the limit was tripped
+		seen: usize,
+	},
 	/// An input/output error
 	IoError(io::Error),
+	/// A call bounded by a timeout or deadline ran out of time: a send via
+	/// `Client::send_message_timeout`, a `Client::ping_rtt` waiting for its
+	/// `Pong`, or a receive via `Client::recv_message_deadline`. After a
+	/// send times out, a partial write may already have reached the
+	/// stream, so the connection must be closed rather than reused.
+	Timeout,
 	/// An HTTP parsing error
 	HttpError(HttpError),
 	/// A URL parsing error
@@ -61,6 +96,66 @@ pub enum WebSocketError {
 	TlsHandshakeInterruption,
 	/// A UTF-8 error
 	Utf8Error(Utf8Error),
+	/// A (de)serialization failure raised by `Client::send_serde`/
+	/// `recv_serde` (see the `codec` feature), as opposed to the
+	/// transport-level variants above. Carries the underlying format's
+	/// error message, since `WebSocketError` can't depend on every format
+	/// crate's own error type.
+	#[cfg(feature = "codec")]
+	SerdeError(String),
+	/// Tried to send, or received, a `Close` frame carrying a status code
+	/// RFC6455 section 7.4 forbids on the wire - below 1000, above 4999, or
+	/// one of the reserved-but-unassigned codes in 1000-2999 (which includes
+	/// 1005/1006/1015, codes that only ever describe a *local* close
+	/// condition and must never actually be sent). See
+	/// `CloseCode::is_sendable`.
+	InvalidCloseCode(u16),
+	/// The peer closed the TCP connection (or otherwise hit EOF) before
+	/// ever sending a `Close` frame - an abrupt, protocol-violating
+	/// disconnect rather than a clean closing handshake. Distinct from
+	/// `OwnedMessage::Close`, which is what a proper closing handshake
+	/// produces. See `Client::recv_message`.
+	UnexpectedEof,
+	/// The negotiated `Sec-WebSocket-Protocol` value doesn't match any
+	/// variant of the `KnownProtocol` enum passed to `Client::protocol_as`.
+	/// Carries the raw negotiated protocol name.
+	UnknownProtocol(String),
+	/// A fragmented message's first fragment arrived more than the
+	/// configured `Receiver::set_fragment_timeout` ago, without the message
+	/// having finished reassembling yet. Guards against a peer opening a
+	/// fragmented message and then pausing indefinitely, tying up server
+	/// memory for that message's buffered fragments - distinct from an idle
+	/// connection timeout, since the peer may still be sending other
+	/// traffic.
+	FragmentTimeout,
+	/// The peer sent more `Close`/`Ping`/`Pong` control frames within one
+	/// second than the configured `Receiver::set_max_control_frame_rate`
+	/// allows. Guards against a peer flooding control frames to force
+	/// wasted CPU answering them, distinct from `MessageTooLong`/
+	/// `FragmentTimeout`, which bound data messages rather than the control
+	/// frames interleaved between them.
+	ControlFrameFlood,
+	/// A data frame failed to parse while `Receiver` had byte-level
+	/// diagnostics enabled (see `Receiver::set_capture_parse_error_bytes`).
+	/// `description` is the underlying parse failure's message; `raw` is
+	/// up to the configured cap of bytes from the front of the buffer
+	/// being parsed when it happened, turning an opaque protocol error
+	/// into something that can be hex-dumped to see exactly what a
+	/// desynced peer sent. Empty when the cap is 0 or
+	/// `Receiver::set_redact_captured_bytes` is set.
+	FrameParseError {
+		/// The underlying parse failure's message.
+		description: String,
+		/// Raw bytes captured for diagnostics, up to the configured cap.
+		raw: Vec<u8>,
+	},
+	/// The peer sent nothing at all - not even a `Pong` answering this
+	/// crate's own automatic keepalive `Ping` - for longer than the
+	/// `timeout` configured via `Client::set_keepalive`. Distinct from
+	/// `FragmentTimeout`, which only tracks a message mid-reassembly: this
+	/// tracks the whole connection going quiet. Only
+	/// `Client::recv_message_keepalive` checks this deadline and returns it.
+	KeepaliveTimeout,
 }
 
 impl fmt::Display for WebSocketError {
@@ -77,9 +172,15 @@ impl Error for WebSocketError {
 			WebSocketError::ProtocolError(_) => "WebSocket protocol error",
 			WebSocketError::RequestError(_) => "WebSocket request error",
 			WebSocketError::ResponseError(_) => "WebSocket response error",
+			WebSocketError::HandshakeFailed { .. } => {
+				"WebSocket handshake failed: server did not return 101 Switching Protocols"
+			}
 			WebSocketError::DataFrameError(_) => "WebSocket data frame error",
 			WebSocketError::NoDataAvailable => "No data available",
+			WebSocketError::ConnectionClosed => "Connection already closed by peer",
+			WebSocketError::MessageTooLong { .. } => "Message too long",
 			WebSocketError::IoError(_) => "I/O failure",
+			WebSocketError::Timeout => "Timed out before the operation completed",
 			WebSocketError::HttpError(_) => "HTTP failure",
 			WebSocketError::UrlError(_) => "URL failure",
 			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
@@ -90,10 +191,29 @@ impl Error for WebSocketError {
 			WebSocketError::TlsHandshakeInterruption => "TLS Handshake interrupted",
 			WebSocketError::Utf8Error(_) => "UTF-8 failure",
 			WebSocketError::WebSocketUrlError(_) => "WebSocket URL failure",
+			#[cfg(feature = "codec")]
+			WebSocketError::SerdeError(_) => "Failed to serialize or deserialize a message",
+			WebSocketError::InvalidCloseCode(_) => "Invalid WebSocket close status code",
+			WebSocketError::UnexpectedEof => {
+				"Connection closed without a WebSocket closing handshake"
+			}
+			WebSocketError::UnknownProtocol(_) => {
+				"Negotiated protocol does not match any known protocol"
+			}
+			WebSocketError::FragmentTimeout => {
+				"Fragmented message was not completed within the configured fragment timeout"
+			}
+			WebSocketError::ControlFrameFlood => {
+				"Too many control frames received within the configured rate limit"
+			}
+			WebSocketError::FrameParseError { .. } => "WebSocket data frame failed to parse",
+			WebSocketError::KeepaliveTimeout => {
+				"No data, including a keepalive Pong, was received from the peer within the configured timeout"
+			}
 		}
 	}
 
-	fn cause(&self) -> Option<&Error> {
+	fn source(&self) -> Option<&(Error + 'static)> {
 		match *self {
 			WebSocketError::IoError(ref error) => Some(error),
 			WebSocketError::HttpError(ref error) => Some(error),
@@ -109,10 +229,23 @@ impl Error for WebSocketError {
 
 impl From<io::Error> for WebSocketError {
 	fn from(err: io::Error) -> WebSocketError {
-		if err.kind() == io::ErrorKind::UnexpectedEof {
-			return WebSocketError::NoDataAvailable;
+		match err.kind() {
+			io::ErrorKind::UnexpectedEof => WebSocketError::NoDataAvailable,
+			// the peer is gone - whether the OS reported it as BrokenPipe (a
+			// write after the kernel already tore the connection down) or as
+			// ConnectionReset (on a read or a write), there's nothing left to
+			// send to.
+			// Rust's runtime already sets SIGPIPE to SIG_IGN on startup for
+			// any binary linking std (see `std::rt::init`), so a write to a
+			// closed socket reaches us as this `io::Error` rather than
+			// killing the process; no `SO_NOSIGPIPE`/`MSG_NOSIGNAL` socket
+			// option is needed on top of that, and this crate has no raw
+			// syscall layer to set one from even if it were.
+			io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset => {
+				WebSocketError::ConnectionClosed
+			}
+			_ => WebSocketError::IoError(err),
 		}
-		WebSocketError::IoError(err)
 	}
 }
 
@@ -178,10 +311,21 @@ impl From<HyperIntoWsError> for WebSocketError {
 			UnsupportedHttpVersion => ProtocolError("Unsupported request HTTP version"),
 			UnsupportedWebsocketVersion => ProtocolError("Unsupported WebSocket version"),
 			NoSecWsKeyHeader => ProtocolError("Missing Sec-WebSocket-Key header"),
+			InvalidSecWsKeyHeader => ProtocolError("Sec-WebSocket-Key must be valid base64 of 16 bytes"),
 			NoWsUpgradeHeader => ProtocolError("Invalid Upgrade WebSocket header"),
 			NoUpgradeHeader => ProtocolError("Missing Upgrade WebSocket header"),
 			NoWsConnectionHeader => ProtocolError("Invalid Connection WebSocket header"),
 			NoConnectionHeader => ProtocolError("Missing Connection WebSocket header"),
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			Tls(err) => TlsError(err),
+			HandshakeTooLarge { limit, seen } => MessageTooLong { limit, seen },
+			TooManyHeaders { limit, seen } => MessageTooLong { limit, seen },
+			PathNotAllowed { .. } => ProtocolError("Request path is not in the server's allowed_paths"),
+			Overloaded => ProtocolError("The server is at capacity and rejected this handshake"),
+			NoMatchingProtocol => {
+				ProtocolError("Request didn't offer any of the server's required_protocol(s)")
+			}
+			ShutDown => ProtocolError("The server was shut down via a ShutdownHandle"),
 		}
 	}
 }
@@ -214,3 +358,54 @@ impl Error for WSUrlErrorKind {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn source_is_some_for_wrapped_io_errors() {
+		let io_err = io::Error::new(io::ErrorKind::Other, "disk on fire");
+		let err = WebSocketError::IoError(io_err);
+
+		assert!(err.source().is_some());
+		assert_eq!(err.source().unwrap().to_string(), "disk on fire");
+	}
+
+	#[test]
+	fn source_is_none_for_variants_without_a_cause() {
+		let err = WebSocketError::NoDataAvailable;
+
+		assert!(err.source().is_none());
+	}
+
+	#[test]
+	fn broken_pipe_converts_to_connection_closed() {
+		let io_err = io::Error::new(io::ErrorKind::BrokenPipe, "pipe is broken");
+
+		match WebSocketError::from(io_err) {
+			WebSocketError::ConnectionClosed => {}
+			other => panic!("expected ConnectionClosed, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn connection_reset_converts_to_connection_closed() {
+		let io_err = io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by peer");
+
+		match WebSocketError::from(io_err) {
+			WebSocketError::ConnectionClosed => {}
+			other => panic!("expected ConnectionClosed, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn other_io_errors_remain_io_errors() {
+		let io_err = io::Error::new(io::ErrorKind::Other, "disk on fire");
+
+		match WebSocketError::from(io_err) {
+			WebSocketError::IoError(_) => {}
+			other => panic!("expected IoError, got {:?}", other),
+		}
+	}
+}
@@ -1,8 +1,16 @@
 //! The default implementation of a WebSocket Sender.
 
-use result::WebSocketResult;
+use dataframe::{DataFrame as OwnedDataFrame, Opcode};
+use result::{WebSocketError, WebSocketResult};
+use std::io;
 use std::io::Result as IoResult;
 use std::io::Write;
+use std::str;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use stream::sync::AsTcpStream;
 pub use stream::sync::Shutdown;
 use ws;
@@ -13,7 +21,10 @@ use ws::sender::Sender as SenderTrait;
 /// This is used in the client's `.split()` function as the writing component.
 ///
 /// It can also be useful to use a websocket connection without a handshake.
-pub struct Writer<W> {
+pub struct Writer<W>
+where
+	W: Write,
+{
 	/// The stream that websocket messages will be written to
 	pub stream: W,
 	/// The serializer that will be used to serialize the messages
@@ -40,40 +51,1267 @@ where
 	{
 		self.sender.send_message(&mut self.stream, message)
 	}
+
+	/// Sends an unsolicited Pong to the remote endpoint, for example as a
+	/// heartbeat.
+	pub fn send_pong<P>(&mut self, data: P) -> WebSocketResult<()>
+	where
+		P: Into<::std::vec::Vec<u8>>,
+	{
+		self.send_message(&::message::OwnedMessage::Pong(data.into()))
+	}
+
+	/// Sends a single data frame with an explicit `opcode` and `fin` bit,
+	/// bypassing the usual "serialize a whole `Message` at once" path. This
+	/// lets a caller (e.g. a conformance test) manually control fragmentation
+	/// by sending a series of frames itself, with `Opcode::Continuation` for
+	/// all but the first. Control frames (`opcode >= 8`) can never be
+	/// fragmented, so passing `fin: false` with one is a `DataFrameError`.
+	pub fn send_fragment(&mut self, opcode: Opcode, data: Vec<u8>, fin: bool) -> WebSocketResult<()> {
+		self.sender.send_fragment(&mut self.stream, opcode, data, fin)
+	}
+
+	/// Sends `chunks` as one fragmented message in a single buffered write.
+	/// See `Sender::send_fragmented`.
+	pub fn send_fragmented(&mut self, opcode: Opcode, chunks: &[&[u8]]) -> WebSocketResult<()> {
+		self.sender.send_fragmented(&mut self.stream, opcode, chunks)
+	}
+
+	/// Sends a text message built directly from a borrowed `&str`. See
+	/// `Sender::send_text`.
+	pub fn send_text(&mut self, data: &str) -> WebSocketResult<()> {
+		self.sender.send_text(&mut self.stream, data)
+	}
+
+	/// Sends every message in `messages`, in order, flushing the stream once
+	/// at the end rather than after each one. Stops at the first message
+	/// that fails to send and returns its zero-based index along with the
+	/// error, without attempting any later messages; if every message sends
+	/// but the final flush fails, the index is the total number of messages
+	/// sent.
+	pub fn send_all<M, I>(&mut self, messages: I) -> Result<(), (usize, WebSocketError)>
+	where
+		M: ws::Message,
+		I: IntoIterator<Item = M>,
+	{
+		let mut sent = 0;
+		for message in messages {
+			self
+				.sender
+				.send_message(&mut self.stream, &message)
+				.map_err(|e| (sent, e))?;
+			sent += 1;
+		}
+		self.stream.flush().map_err(|e| (sent, e.into()))
+	}
 }
 
 impl<S> Writer<S>
 where
 	S: AsTcpStream + Write,
 {
-	/// Closes the sender side of the connection, will cause all pending and future IO to
-	/// return immediately with an appropriate value.
-	pub fn shutdown(&self) -> IoResult<()> {
+	/// Flushes any buffered data, then closes the sender side of the
+	/// connection, will cause all pending and future IO to return
+	/// immediately with an appropriate value.
+	pub fn shutdown(&mut self) -> IoResult<()> {
+		self.stream.flush()?;
 		self.stream.as_tcp().shutdown(Shutdown::Write)
 	}
 
-	/// Shuts down both Sender and Receiver, will cause all pending and future IO to
-	/// return immediately with an appropriate value.
-	pub fn shutdown_all(&self) -> IoResult<()> {
+	/// Flushes any buffered data, then shuts down both Sender and Receiver,
+	/// will cause all pending and future IO to return immediately with an
+	/// appropriate value.
+	pub fn shutdown_all(&mut self) -> IoResult<()> {
+		self.stream.flush()?;
 		self.stream.as_tcp().shutdown(Shutdown::Both)
 	}
+
+	/// Splits off a lightweight handle for sending control frames (`Ping`,
+	/// `Pong`, `Close`) out of band from this `Writer`, by duplicating the
+	/// underlying `TcpStream`. This is safe to use concurrently with the
+	/// `Writer` it was split from, even while a fragmented message is
+	/// mid-flight on it: every frame, whether sent here or through
+	/// `send_message`/`send_dataframe`/`send_fragment`, is serialized into a
+	/// single buffer before being handed to one `write_all` call, so frames
+	/// from the two handles are interleaved whole and never torn. Their
+	/// relative order on the wire is not guaranteed.
+	pub fn control_sender(&self) -> IoResult<ControlSender> {
+		Ok(ControlSender {
+			stream: self.stream.as_tcp().try_clone()?,
+			sender: self.sender.clone(),
+		})
+	}
+}
+
+impl<W> Drop for Writer<W>
+where
+	W: Write,
+{
+	/// Makes a best-effort attempt to flush any data still sitting in a
+	/// buffered stream (e.g. `io::BufWriter`) before this `Writer` goes
+	/// away. A failed flush here has nowhere to report its error, so call
+	/// `.stream.flush()` (or `shutdown`/`shutdown_all`, which flush and
+	/// propagate the error) explicitly if you need to know it succeeded.
+	fn drop(&mut self) {
+		let _ = self.stream.flush();
+	}
+}
+
+/// A `Write` wrapper that batches writes into an in-memory buffer, only
+/// writing through to the underlying stream once `capacity` bytes have
+/// accumulated or `flush` is called. Wrap a `Writer`'s stream in one of
+/// these (`Writer { stream: BufferedWriter::new(tcp_stream, 8192), sender }`)
+/// to pair with `Writer::pending_bytes` and decide for yourself when to
+/// flush a backed-up slow peer, instead of letting every send go straight
+/// to the socket.
+pub struct BufferedWriter<W> {
+	inner: W,
+	buffer: Vec<u8>,
+	capacity: usize,
+}
+
+impl<W> BufferedWriter<W>
+where
+	W: Write,
+{
+	/// Wraps `inner`, writing its buffer through automatically once
+	/// `capacity` bytes have accumulated.
+	pub fn new(inner: W, capacity: usize) -> Self {
+		BufferedWriter {
+			inner,
+			buffer: Vec::new(),
+			capacity,
+		}
+	}
+}
+
+impl<W> Write for BufferedWriter<W>
+where
+	W: Write,
+{
+	fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+		self.buffer.extend_from_slice(buf);
+		if self.buffer.len() >= self.capacity {
+			self.flush()?;
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> IoResult<()> {
+		if !self.buffer.is_empty() {
+			self.inner.write_all(&self.buffer)?;
+			self.buffer.clear();
+		}
+		self.inner.flush()
+	}
+}
+
+impl<W> Writer<BufferedWriter<W>>
+where
+	W: Write,
+{
+	/// How many bytes are queued in the underlying `BufferedWriter` but not
+	/// yet written to the socket - `0` right after a `flush` (explicit, or
+	/// automatic once `capacity` is crossed). Useful for deciding when to
+	/// flush early, or for noticing a peer that's reading too slowly to
+	/// keep up with what's being sent to it.
+	///
+	/// Only available when this `Writer`'s stream is a `BufferedWriter` -
+	/// this crate does not buffer sends on its own, so a plain unbuffered
+	/// stream (the default) has nothing to report; every send to one
+	/// already goes straight to the socket.
+	pub fn pending_bytes(&self) -> usize {
+		self.stream.buffer.len()
+	}
+}
+
+/// A handle for sending control frames out of band from a `Writer`, obtained
+/// with `Writer::control_sender`. See that method for the interleaving
+/// guarantees.
+pub struct ControlSender {
+	stream: TcpStream,
+	sender: Sender,
+}
+
+impl ControlSender {
+	/// Sends a single control message (`Ping`, `Pong`, or `Close`).
+	/// Returns a `DataFrameError` if `message` is not a control message.
+	pub fn send_control<M>(&mut self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		if !message.is_control() {
+			return Err(WebSocketError::DataFrameError(
+				"send_control can only send control messages (Ping, Pong, Close)",
+			));
+		}
+		self.sender.send_message(&mut self.stream, message)
+	}
+}
+
+/// A thread-safe handle for sending on a `Writer` shared between threads,
+/// e.g. one connection fanned out to several worker threads that each want
+/// to push unsolicited messages.
+///
+/// `send_message`/`send_dataframe`/`send_fragment` each serialize into a
+/// single buffer before making one `write_all` call (see `Writer::
+/// control_sender`'s docs), so a single call through `SharedSender::send`
+/// is already atomic: the lock is held for exactly that one call, and two
+/// threads' frames can never interleave. A message fragmented across
+/// several `send_fragment` calls is not automatically atomic the same way,
+/// since nothing stops another thread's `send` from running between two of
+/// them - use `send_fragments` to send a whole fragmented message under one
+/// lock acquisition instead of calling `send_fragment` directly in a loop.
+pub struct SharedSender<W>(Arc<Mutex<Writer<W>>>)
+where
+	W: Write;
+
+impl<W> Clone for SharedSender<W>
+where
+	W: Write,
+{
+	fn clone(&self) -> Self {
+		SharedSender(self.0.clone())
+	}
+}
+
+impl<W> SharedSender<W>
+where
+	W: Write,
+{
+	/// Wraps `writer` so it can be shared between threads via `clone`.
+	pub fn new(writer: Writer<W>) -> Self {
+		SharedSender(Arc::new(Mutex::new(writer)))
+	}
+
+	/// Sends a single message, holding the lock for the one call that
+	/// serializes it. Safe to call from multiple threads at once.
+	pub fn send<M>(&self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		self.0.lock().unwrap().send_message(message)
+	}
+
+	/// Sends a whole fragmented message - `fragments` in the same
+	/// `(opcode, data, fin)` shape as `Writer::send_fragment`, with
+	/// `Opcode::Continuation` for all but the first - under a single lock
+	/// acquisition, so no other thread's frame can land in the middle of
+	/// it. Stops at the first fragment that fails to send.
+	pub fn send_fragments<I>(&self, fragments: I) -> WebSocketResult<()>
+	where
+		I: IntoIterator<Item = (Opcode, Vec<u8>, bool)>,
+	{
+		let mut writer = self.0.lock().unwrap();
+		for (opcode, data, fin) in fragments {
+			writer.send_fragment(opcode, data, fin)?;
+		}
+		Ok(())
+	}
+
+	/// Sends `chunks` as one fragmented message - see
+	/// `Sender::send_fragmented` - under a single lock acquisition and a
+	/// single `write_all`, so no other thread's frame can land in the
+	/// middle of it.
+	pub fn send_fragmented(&self, opcode: Opcode, chunks: &[&[u8]]) -> WebSocketResult<()> {
+		self.0.lock().unwrap().send_fragmented(opcode, chunks)
+	}
 }
 
 /// A Sender that wraps a Writer and provides a default implementation using
 /// DataFrames and Messages.
+#[derive(Clone)]
 pub struct Sender {
 	mask: bool,
+	received_close: Arc<AtomicBool>,
+	validate_text: bool,
+	force_masking: Option<bool>,
+	rate_limiter: Option<RateLimiter>,
+	/// Scratch space for `send_dataframe` to build a frame into before
+	/// handing it to the writer in one `write_all`. Kept around between
+	/// calls so repeated sends on the same `Sender` reuse its allocation
+	/// instead of starting from an empty `Vec` every time - see
+	/// `DataFrame::write_to_buf`.
+	scratch: Vec<u8>,
 }
 
 impl Sender {
-	/// Create a new WebSocketSender using the specified Writer.
+	/// Create a new `Sender`. This builds just the framing layer - pass
+	/// `mask: true` if this side of the connection must mask the frames it
+	/// sends (i.e. it is the client, per RFC6455 section 5.1), `false` if
+	/// it is the server.
+	///
+	/// A `Sender` has no stream of its own; pass a `&mut Write` (a
+	/// `TcpStream`, a `TlsStream` from another crate, a pipe, ...) to
+	/// `send_message`/`send_dataframe` each time you use it, or wrap it and
+	/// a stream together in a `Writer` for a self-contained handle.
 	pub fn new(mask: bool) -> Sender {
-		Sender { mask }
+		Sender {
+			mask,
+			received_close: Arc::new(AtomicBool::new(false)),
+			validate_text: true,
+			force_masking: None,
+			rate_limiter: None,
+			scratch: Vec::new(),
+		}
+	}
+
+	/// Like `new`, but sharing `received_close` with a
+	/// `::receiver::Receiver` (built with
+	/// `::receiver::Receiver::new_with_close_state`) so that once that
+	/// `Receiver` sees a `Close` frame, this `Sender` refuses to send
+	/// further data frames.
+	pub(crate) fn new_with_close_state(mask: bool, received_close: Arc<AtomicBool>) -> Sender {
+		Sender {
+			mask,
+			received_close,
+			validate_text: true,
+			force_masking: None,
+			rate_limiter: None,
+			scratch: Vec::new(),
+		}
+	}
+
+	/// Paces `send_message`/`send_dataframe`/`send_fragment` to at most
+	/// `bytes_per_sec` bytes (counting the whole frame on the wire, header
+	/// and mask included) per second, using a token bucket that can burst
+	/// up to one second's worth of budget. `None` (the default) sends as
+	/// fast as the underlying writer allows.
+	///
+	/// This guards against a buggy caller flooding a slow peer, e.g. a
+	/// server thread that fans the same broadcast out to many clients and
+	/// wants to keep any one of them from hogging bandwidth or scheduler
+	/// time. Once the budget is exhausted, sends block until it refills -
+	/// see `set_rate_limit_nonblocking` to get a `WouldBlock` error back
+	/// instead.
+	pub fn set_send_rate_limit(&mut self, bytes_per_sec: Option<u32>) {
+		let nonblocking = self
+			.rate_limiter
+			.as_ref()
+			.map(|limiter| limiter.nonblocking)
+			.unwrap_or(false);
+		self.rate_limiter = bytes_per_sec.map(|limit| RateLimiter::new(limit, nonblocking));
+	}
+
+	/// The limit currently set by `set_send_rate_limit`.
+	pub fn send_rate_limit(&self) -> Option<u32> {
+		self.rate_limiter.as_ref().map(|limiter| limiter.bytes_per_sec)
+	}
+
+	/// Whether an exhausted rate limit budget should block the caller
+	/// (`false`, the default) or fail the send immediately with an
+	/// `IoError` of kind `WouldBlock` (`true`). Has no effect until a limit
+	/// is set with `set_send_rate_limit`.
+	pub fn set_rate_limit_nonblocking(&mut self, nonblocking: bool) {
+		if let Some(limiter) = self.rate_limiter.as_mut() {
+			limiter.nonblocking = nonblocking;
+		}
+	}
+
+	/// Whether `send_fragment` checks a `Opcode::Text` fragment's payload
+	/// is valid UTF-8 before sending it, per RFC6455 section 5.6. Defaults
+	/// to `true`. `Message`/`OwnedMessage`'s own `Text` variants are always
+	/// valid UTF-8 by construction (they're backed by `String`), so this
+	/// only matters for raw `send_fragment` calls; turning it off trusts
+	/// the caller to send well-formed text and skips the scan, at the cost
+	/// of being able to send a non-conformant frame a strict peer may
+	/// reject or disconnect over.
+	pub fn set_validate_text(&mut self, validate: bool) {
+		self.validate_text = validate;
+	}
+
+	/// The value currently set by `set_validate_text`.
+	pub fn validate_text(&self) -> bool {
+		self.validate_text
+	}
+
+	/// Test-only escape hatch that overrides the role-based masking decision
+	/// `Sender::new`'s `mask` argument normally makes: `Some(true)`/`Some(false)`
+	/// forces every frame to be masked/unmasked regardless of role, `None`
+	/// (the default) restores the correct RFC6455 behavior.
+	///
+	/// This exists to write conformance tests for the *other* side of a
+	/// connection, e.g. a client that must reject masked frames from a
+	/// server. Using it outside tests sends frames that violate the
+	/// protocol and that a conformant peer is entitled to reject.
+	pub fn force_masking(&mut self, mask: Option<bool>) {
+		self.force_masking = mask;
+	}
+
+	/// Sends a single data frame with an explicit `opcode` and `fin` bit,
+	/// bypassing the usual "serialize a whole `Message` at once" path. This
+	/// lets a caller (e.g. a conformance test) manually control fragmentation
+	/// by sending a series of frames itself, with `Opcode::Continuation` for
+	/// all but the first. Control frames (`opcode >= 8`) can never be
+	/// fragmented, so passing `fin: false` with one is a `DataFrameError`.
+	///
+	/// If `opcode` is `Opcode::Text` and `validate_text` is set (the
+	/// default), `data` is checked for valid UTF-8 first, failing with a
+	/// `WebSocketError::Utf8Error` rather than putting an invalid frame on
+	/// the wire. See `set_validate_text`.
+	pub fn send_fragment<W>(
+		&mut self,
+		writer: &mut W,
+		opcode: Opcode,
+		data: Vec<u8>,
+		fin: bool,
+	) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		if !fin && opcode as u8 >= 8 {
+			return Err(WebSocketError::DataFrameError(
+				"Control frames cannot be fragmented",
+			));
+		}
+		if opcode == Opcode::Text && self.validate_text {
+			if let Err(e) = str::from_utf8(&data) {
+				return Err(e.into());
+			}
+		}
+		self.send_dataframe(writer, &OwnedDataFrame::new(fin, opcode, data))
+	}
+
+	/// Sends a single text message built directly from a borrowed `&str`,
+	/// skipping the `String` allocation `send_message` would otherwise need
+	/// to hand it an `OwnedMessage::Text`/`Message::text` - the frame header
+	/// and (for a masking client) the payload's masked copy land in
+	/// `self.scratch` instead, the same as `send_dataframe`.
+	pub fn send_text<W>(&mut self, writer: &mut W, data: &str) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		if self.received_close.load(Ordering::SeqCst) {
+			return Err(WebSocketError::ConnectionClosed);
+		}
+		let masked = self.is_masked();
+		let message = ::message::Message::text(data);
+		if let Some(limiter) = self.rate_limiter.as_mut() {
+			limiter.consume(message.frame_size(masked))?;
+		}
+		message.write_to_buf(&mut self.scratch, masked)?;
+		writer.write_all(&self.scratch)?;
+		Ok(())
+	}
+
+	/// Sends `chunks` as one fragmented message with `opcode`: the first
+	/// chunk goes out carrying `opcode`, every chunk after it as
+	/// `Opcode::Continuation`, and only the last chunk's frame has `FIN`
+	/// set. Unlike calling `send_fragment` once per chunk, every frame is
+	/// built into `self.scratch` first and handed to `writer` in a single
+	/// `write_all`, so nothing - another thread's `send` on a shared
+	/// `Writer`, a control frame sent through `Writer::control_sender` -
+	/// can land in the middle of this message on its way out.
+	///
+	/// `chunks` must not be empty. Control frames (`opcode as u8 >= 8`) can
+	/// never be fragmented, so passing one alongside more than one chunk is
+	/// a `DataFrameError`, matching `send_fragment`.
+	///
+	/// If `opcode` is `Opcode::Text` and `validate_text` is set (the
+	/// default), each chunk is checked for valid UTF-8 on its own; a
+	/// multi-byte character split across a chunk boundary fails validation
+	/// even though the reassembled text would be valid, so callers who need
+	/// to split text mid-character should turn `validate_text` off first.
+	pub fn send_fragmented<W>(
+		&mut self,
+		writer: &mut W,
+		opcode: Opcode,
+		chunks: &[&[u8]],
+	) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		if chunks.is_empty() {
+			return Err(WebSocketError::DataFrameError(
+				"send_fragmented requires at least one chunk",
+			));
+		}
+		if chunks.len() > 1 && opcode as u8 >= 8 {
+			return Err(WebSocketError::DataFrameError(
+				"Control frames cannot be fragmented",
+			));
+		}
+		if opcode == Opcode::Text && self.validate_text {
+			for chunk in chunks {
+				if let Err(e) = str::from_utf8(chunk) {
+					return Err(e.into());
+				}
+			}
+		}
+		if (opcode as u8) < 8 && self.received_close.load(Ordering::SeqCst) {
+			return Err(WebSocketError::ConnectionClosed);
+		}
+
+		let masked = self.is_masked();
+		let last = chunks.len() - 1;
+		self.scratch.clear();
+		let mut frame = Vec::new();
+		for (i, chunk) in chunks.iter().enumerate() {
+			let frame_opcode = if i == 0 { opcode } else { Opcode::Continuation };
+			let dataframe = OwnedDataFrame::new(i == last, frame_opcode, chunk.to_vec());
+			if let Some(limiter) = self.rate_limiter.as_mut() {
+				limiter.consume(dataframe.frame_size(masked))?;
+			}
+			dataframe.write_to_buf(&mut frame, masked)?;
+			self.scratch.extend_from_slice(&frame);
+		}
+		writer.write_all(&self.scratch)?;
+		Ok(())
 	}
 }
 
 impl ws::Sender for Sender {
 	fn is_masked(&self) -> bool {
-		self.mask
+		self.force_masking.unwrap_or(self.mask)
+	}
+
+	fn send_dataframe<D, W>(&mut self, writer: &mut W, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+		W: Write,
+	{
+		if dataframe.opcode() < 8 && self.received_close.load(Ordering::SeqCst) {
+			return Err(WebSocketError::ConnectionClosed);
+		}
+		let masked = self.is_masked();
+		if let Some(limiter) = self.rate_limiter.as_mut() {
+			limiter.consume(dataframe.frame_size(masked))?;
+		}
+		dataframe.write_to_buf(&mut self.scratch, masked)?;
+		writer.write_all(&self.scratch)?;
+		Ok(())
+	}
+
+	fn send_message<M, W>(&mut self, writer: &mut W, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+		W: Write,
+	{
+		if !message.is_control() && self.received_close.load(Ordering::SeqCst) {
+			return Err(WebSocketError::ConnectionClosed);
+		}
+		let masked = self.is_masked();
+		if let Some(limiter) = self.rate_limiter.as_mut() {
+			limiter.consume(message.message_size(masked))?;
+		}
+		message.serialize(writer, masked)?;
+		Ok(())
+	}
+}
+
+/// Token-bucket state behind `Sender::set_send_rate_limit`. Refills
+/// continuously (based on wall-clock time elapsed since the last send)
+/// rather than in fixed ticks, so a burst of small frames right after an
+/// idle period can use up to one second's worth of banked budget before
+/// it starts pacing.
+#[derive(Clone)]
+struct RateLimiter {
+	bytes_per_sec: u32,
+	tokens: u64,
+	last_refill: Instant,
+	nonblocking: bool,
+}
+
+impl RateLimiter {
+	fn new(bytes_per_sec: u32, nonblocking: bool) -> Self {
+		RateLimiter {
+			bytes_per_sec,
+			tokens: bytes_per_sec as u64,
+			last_refill: Instant::now(),
+			nonblocking,
+		}
+	}
+
+	fn refill(&mut self) {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.last_refill);
+		let elapsed_nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+		let added = elapsed_nanos.saturating_mul(self.bytes_per_sec as u64) / 1_000_000_000;
+		self.tokens = self.tokens.saturating_add(added).min(self.bytes_per_sec as u64);
+		self.last_refill = now;
+	}
+
+	/// Waits for (or, in nonblocking mode, checks for) `bytes` worth of
+	/// budget and spends it. A single send larger than the whole bucket is
+	/// allowed to drain it completely once it's full, rather than blocking
+	/// forever - nothing above `bytes_per_sec` could ever be paid off a
+	/// bucket capped at that same size.
+	fn consume(&mut self, bytes: usize) -> IoResult<()> {
+		let needed = bytes as u64;
+		loop {
+			self.refill();
+			let capacity = self.bytes_per_sec as u64;
+			if self.tokens >= needed || (needed >= capacity && self.tokens >= capacity) {
+				self.tokens = self.tokens.saturating_sub(needed);
+				return Ok(());
+			}
+			if self.nonblocking {
+				return Err(io::Error::new(
+					io::ErrorKind::WouldBlock,
+					"send rate limit exceeded",
+				));
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct FlushTracking {
+		flushed: ::std::rc::Rc<::std::cell::Cell<bool>>,
+	}
+
+	impl Write for FlushTracking {
+		fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> IoResult<()> {
+			self.flushed.set(true);
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn dropping_a_writer_flushes_the_underlying_stream() {
+		let flushed = ::std::rc::Rc::new(::std::cell::Cell::new(false));
+		let writer = Writer {
+			stream: FlushTracking {
+				flushed: flushed.clone(),
+			},
+			sender: Sender::new(false),
+		};
+
+		assert!(!flushed.get());
+		drop(writer);
+		assert!(flushed.get());
+	}
+
+	#[test]
+	fn pending_bytes_tracks_unflushed_writes_below_capacity() {
+		let mut writer = Writer {
+			stream: BufferedWriter::new(Vec::new(), 1024),
+			sender: Sender::new(false),
+		};
+
+		assert_eq!(writer.pending_bytes(), 0);
+		writer
+			.send_fragment(Opcode::Binary, vec![0u8; 10], true)
+			.unwrap();
+		assert!(writer.pending_bytes() > 0);
+		assert!(writer.stream.inner.is_empty());
+	}
+
+	#[test]
+	fn pending_bytes_resets_once_capacity_is_crossed() {
+		let mut writer = Writer {
+			stream: BufferedWriter::new(Vec::new(), 16),
+			sender: Sender::new(false),
+		};
+
+		writer
+			.send_fragment(Opcode::Binary, vec![0u8; 100], true)
+			.unwrap();
+
+		assert_eq!(writer.pending_bytes(), 0);
+		assert!(!writer.stream.inner.is_empty());
+	}
+
+	#[test]
+	fn pending_bytes_resets_on_an_explicit_flush() {
+		let mut writer = Writer {
+			stream: BufferedWriter::new(Vec::new(), 1024),
+			sender: Sender::new(false),
+		};
+
+		writer
+			.send_fragment(Opcode::Binary, vec![0u8; 10], true)
+			.unwrap();
+		assert!(writer.pending_bytes() > 0);
+
+		writer.stream.flush().unwrap();
+		assert_eq!(writer.pending_bytes(), 0);
+		assert!(!writer.stream.inner.is_empty());
+	}
+
+	#[test]
+	fn send_fragment_allows_manual_continuation_frames() {
+		let mut sender = Sender::new(false);
+		let mut bytes = Vec::new();
+
+		sender
+			.send_fragment(&mut bytes, Opcode::Text, b"hello ".to_vec(), false)
+			.unwrap();
+		sender
+			.send_fragment(&mut bytes, Opcode::Continuation, b"world".to_vec(), true)
+			.unwrap();
+
+		let first = OwnedDataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+		assert_eq!(first.opcode, Opcode::Text);
+		assert!(!first.finished);
+		assert_eq!(first.data, b"hello ");
+
+		let mut rest = &bytes[first.data.len() + 2..];
+		let second = OwnedDataFrame::read_dataframe(&mut rest, false).unwrap();
+		assert_eq!(second.opcode, Opcode::Continuation);
+		assert!(second.finished);
+		assert_eq!(second.data, b"world");
+	}
+
+	#[test]
+	fn send_fragmented_splits_chunks_into_a_single_buffered_write() {
+		struct CountingWrites {
+			buf: Vec<u8>,
+			writes: usize,
+		}
+		impl Write for CountingWrites {
+			fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+				self.buf.extend_from_slice(buf);
+				self.writes += 1;
+				Ok(buf.len())
+			}
+			fn flush(&mut self) -> IoResult<()> {
+				Ok(())
+			}
+		}
+
+		let mut sender = Sender::new(false);
+		let mut stream = CountingWrites {
+			buf: Vec::new(),
+			writes: 0,
+		};
+
+		sender
+			.send_fragmented(&mut stream, Opcode::Text, &[b"hello ", b"wor", b"ld"])
+			.unwrap();
+		assert_eq!(stream.writes, 1);
+
+		let bytes = stream.buf;
+		let first = OwnedDataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+		assert_eq!(first.opcode, Opcode::Text);
+		assert!(!first.finished);
+		assert_eq!(first.data, b"hello ");
+
+		let mut rest = &bytes[first.data.len() + 2..];
+		let second = OwnedDataFrame::read_dataframe(&mut rest, false).unwrap();
+		assert_eq!(second.opcode, Opcode::Continuation);
+		assert!(!second.finished);
+		assert_eq!(second.data, b"wor");
+
+		let third = OwnedDataFrame::read_dataframe(&mut rest, false).unwrap();
+		assert_eq!(third.opcode, Opcode::Continuation);
+		assert!(third.finished);
+		assert_eq!(third.data, b"ld");
+	}
+
+	#[test]
+	fn send_fragmented_rejects_an_empty_chunk_list() {
+		let mut sender = Sender::new(false);
+		let mut bytes = Vec::new();
+
+		match sender.send_fragmented(&mut bytes, Opcode::Text, &[]) {
+			Err(WebSocketError::DataFrameError(_)) => {}
+			other => panic!("expected DataFrameError, got {:?}", other),
+		}
+		assert!(bytes.is_empty());
+	}
+
+	#[test]
+	fn send_fragmented_rejects_a_multi_chunk_control_message() {
+		let mut sender = Sender::new(false);
+		let mut bytes = Vec::new();
+
+		match sender.send_fragmented(&mut bytes, Opcode::Ping, &[b"a", b"b"]) {
+			Err(WebSocketError::DataFrameError(_)) => {}
+			other => panic!("expected DataFrameError, got {:?}", other),
+		}
+		assert!(bytes.is_empty());
+	}
+
+	#[test]
+	fn send_fragmented_rejects_invalid_utf8_in_any_chunk() {
+		let mut sender = Sender::new(false);
+		let mut bytes = Vec::new();
+
+		match sender.send_fragmented(&mut bytes, Opcode::Text, &[b"ok", &[0xff, 0xfe]]) {
+			Err(WebSocketError::Utf8Error(_)) => {}
+			other => panic!("expected Utf8Error, got {:?}", other),
+		}
+		assert!(bytes.is_empty());
+	}
+
+	#[test]
+	fn shared_sender_send_fragmented_keeps_a_fragmented_message_whole() {
+		use std::net::{TcpListener, TcpStream};
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let stream = TcpStream::connect(addr).unwrap();
+		let mut peer = listener.accept().unwrap().0;
+
+		let shared = SharedSender::new(Writer {
+			stream,
+			sender: Sender::new(false),
+		});
+
+		let fragmenter = {
+			let shared = shared.clone();
+			thread::spawn(move || {
+				shared
+					.send_fragmented(Opcode::Text, &[b"hello ", b"world"])
+					.unwrap();
+			})
+		};
+		fragmenter.join().unwrap();
+		shared
+			.send(&::message::OwnedMessage::Text("done".to_string()))
+			.unwrap();
+
+		let first = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(first.opcode, Opcode::Text);
+		assert!(!first.finished);
+		assert_eq!(first.data, b"hello ");
+
+		let second = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(second.opcode, Opcode::Continuation);
+		assert!(second.finished);
+		assert_eq!(second.data, b"world");
+
+		let third = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(third.opcode, Opcode::Text);
+		assert_eq!(third.data, b"done");
+	}
+
+	#[test]
+	fn force_masking_overrides_the_role_based_default() {
+		let mut sender = Sender::new(false);
+		assert!(!SenderTrait::is_masked(&sender));
+
+		sender.force_masking(Some(true));
+		assert!(SenderTrait::is_masked(&sender));
+
+		sender.force_masking(Some(false));
+		assert!(!SenderTrait::is_masked(&sender));
+
+		sender.force_masking(None);
+		assert!(!SenderTrait::is_masked(&sender));
+	}
+
+	#[test]
+	fn send_rate_limit_is_unset_by_default() {
+		let sender = Sender::new(false);
+		assert_eq!(sender.send_rate_limit(), None);
+	}
+
+	#[test]
+	fn send_rate_limit_blocks_until_the_budget_refills() {
+		let mut sender = Sender::new(false);
+		sender.set_send_rate_limit(Some(100));
+		let mut bytes = Vec::new();
+
+		// Spend the whole 100-byte bucket on the first send.
+		sender
+			.send_fragment(&mut bytes, Opcode::Binary, vec![0u8; 100], true)
+			.unwrap();
+
+		// Nothing has refilled yet, so this one has to wait for it.
+		let started = Instant::now();
+		sender
+			.send_fragment(&mut bytes, Opcode::Binary, vec![0u8; 50], true)
+			.unwrap();
+		assert!(started.elapsed() >= Duration::from_millis(400));
+	}
+
+	#[test]
+	fn send_rate_limit_nonblocking_fails_fast_once_exhausted() {
+		let mut sender = Sender::new(false);
+		sender.set_send_rate_limit(Some(100));
+		sender.set_rate_limit_nonblocking(true);
+		let mut bytes = Vec::new();
+
+		sender
+			.send_fragment(&mut bytes, Opcode::Binary, vec![0u8; 100], true)
+			.unwrap();
+
+		match sender.send_fragment(&mut bytes, Opcode::Binary, vec![0u8; 1], true) {
+			Err(WebSocketError::IoError(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+			other => panic!("expected a WouldBlock IoError, got {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn send_fragment_rejects_invalid_utf8_text_by_default() {
+		let mut sender = Sender::new(false);
+		assert!(sender.validate_text());
+		let mut bytes = Vec::new();
+
+		match sender.send_fragment(&mut bytes, Opcode::Text, vec![0xff, 0xfe], true) {
+			Err(WebSocketError::Utf8Error(_)) => {}
+			other => panic!("expected Utf8Error, got {:?}", other),
+		}
+		assert!(bytes.is_empty());
+	}
+
+	#[test]
+	fn send_fragment_allows_invalid_utf8_text_once_validation_is_disabled() {
+		let mut sender = Sender::new(false);
+		sender.set_validate_text(false);
+		let mut bytes = Vec::new();
+
+		sender
+			.send_fragment(&mut bytes, Opcode::Text, vec![0xff, 0xfe], true)
+			.unwrap();
+
+		let frame = OwnedDataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+		assert_eq!(frame.opcode, Opcode::Text);
+		assert_eq!(frame.data, vec![0xff, 0xfe]);
+	}
+
+	#[test]
+	fn send_text_writes_a_text_frame_from_a_borrowed_str() {
+		let mut sender = Sender::new(false);
+		let mut bytes = Vec::new();
+
+		sender.send_text(&mut bytes, "hello").unwrap();
+
+		let frame = OwnedDataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+		assert_eq!(frame.opcode, Opcode::Text);
+		assert!(frame.finished);
+		assert_eq!(frame.data, b"hello");
+	}
+
+	#[test]
+	fn send_text_masks_for_a_client_sender() {
+		let mut sender = Sender::new(true);
+		let mut bytes = Vec::new();
+
+		sender.send_text(&mut bytes, "hello").unwrap();
+
+		let frame = OwnedDataFrame::read_dataframe(&mut &bytes[..], true).unwrap();
+		assert_eq!(frame.opcode, Opcode::Text);
+		assert_eq!(frame.data, b"hello");
+	}
+
+	#[test]
+	fn send_text_refuses_once_a_close_has_been_received() {
+		let received_close = Arc::new(AtomicBool::new(true));
+		let mut sender = Sender::new_with_close_state(false, received_close);
+		let mut bytes = Vec::new();
+
+		match sender.send_text(&mut bytes, "hello") {
+			Err(WebSocketError::ConnectionClosed) => {}
+			other => panic!("expected ConnectionClosed, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn send_fragment_rejects_unfinished_control_frames() {
+		let mut sender = Sender::new(false);
+		let mut bytes = Vec::new();
+
+		match sender.send_fragment(&mut bytes, Opcode::Ping, b"hi".to_vec(), false) {
+			Err(WebSocketError::DataFrameError(_)) => {}
+			other => panic!("expected DataFrameError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn control_sender_writes_alongside_a_mid_flight_fragment() {
+		use std::net::{TcpListener, TcpStream};
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let stream = TcpStream::connect(addr).unwrap();
+		let (mut peer, _) = listener.accept().unwrap();
+
+		let mut writer = Writer {
+			stream,
+			sender: Sender::new(false),
+		};
+		let mut control = writer.control_sender().unwrap();
+
+		writer
+			.send_fragment(Opcode::Text, b"hello ".to_vec(), false)
+			.unwrap();
+		control
+			.send_control(&::message::OwnedMessage::Ping(b"ping".to_vec()))
+			.unwrap();
+		writer
+			.send_fragment(Opcode::Continuation, b"world".to_vec(), true)
+			.unwrap();
+
+		let first = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(first.opcode, Opcode::Text);
+		assert_eq!(first.data, b"hello ");
+
+		let second = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(second.opcode, Opcode::Ping);
+		assert_eq!(second.data, b"ping");
+
+		let third = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(third.opcode, Opcode::Continuation);
+		assert_eq!(third.data, b"world");
+	}
+
+	#[test]
+	fn shared_sender_send_is_usable_from_multiple_threads() {
+		use std::net::{TcpListener, TcpStream};
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let stream = TcpStream::connect(addr).unwrap();
+		let mut peer = listener.accept().unwrap().0;
+
+		let shared = SharedSender::new(Writer {
+			stream,
+			sender: Sender::new(false),
+		});
+
+		let threads: Vec<_> = (0..4)
+			.map(|i| {
+				let shared = shared.clone();
+				thread::spawn(move || {
+					shared
+						.send(&::message::OwnedMessage::Text(i.to_string()))
+						.unwrap();
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+
+		let mut seen = Vec::new();
+		for _ in 0..4 {
+			let frame = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+			assert_eq!(frame.opcode, Opcode::Text);
+			seen.push(String::from_utf8(frame.data).unwrap());
+		}
+		seen.sort();
+		assert_eq!(seen, vec!["0", "1", "2", "3"]);
+	}
+
+	#[test]
+	fn shared_sender_send_fragments_keeps_a_fragmented_message_whole() {
+		use std::net::{TcpListener, TcpStream};
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let stream = TcpStream::connect(addr).unwrap();
+		let mut peer = listener.accept().unwrap().0;
+
+		let shared = SharedSender::new(Writer {
+			stream,
+			sender: Sender::new(false),
+		});
+
+		let fragmenter = {
+			let shared = shared.clone();
+			thread::spawn(move || {
+				shared
+					.send_fragments(vec![
+						(Opcode::Text, b"hello ".to_vec(), false),
+						(Opcode::Continuation, b"world".to_vec(), true),
+					])
+					.unwrap();
+			})
+		};
+		fragmenter.join().unwrap();
+		shared
+			.send(&::message::OwnedMessage::Text("done".to_string()))
+			.unwrap();
+
+		let first = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(first.opcode, Opcode::Text);
+		assert!(!first.finished);
+		assert_eq!(first.data, b"hello ");
+
+		let second = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(second.opcode, Opcode::Continuation);
+		assert!(second.finished);
+		assert_eq!(second.data, b"world");
+
+		let third = OwnedDataFrame::read_dataframe(&mut peer, false).unwrap();
+		assert_eq!(third.opcode, Opcode::Text);
+		assert_eq!(third.data, b"done");
+	}
+
+	#[test]
+	fn control_sender_rejects_non_control_messages() {
+		use std::net::{TcpListener, TcpStream};
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let stream = TcpStream::connect(addr).unwrap();
+		let _peer = listener.accept().unwrap();
+
+		let writer = Writer {
+			stream,
+			sender: Sender::new(false),
+		};
+		let mut control = writer.control_sender().unwrap();
+
+		match control.send_control(&::message::OwnedMessage::Text("hi".to_string())) {
+			Err(WebSocketError::DataFrameError(_)) => {}
+			other => panic!("expected DataFrameError, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn send_all_sends_every_message_and_flushes_once_at_the_end() {
+		let flushed = ::std::rc::Rc::new(::std::cell::Cell::new(0));
+		struct CountingFlush {
+			buf: Vec<u8>,
+			flushes: ::std::rc::Rc<::std::cell::Cell<usize>>,
+		}
+		impl Write for CountingFlush {
+			fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+				self.buf.extend_from_slice(buf);
+				Ok(buf.len())
+			}
+			fn flush(&mut self) -> IoResult<()> {
+				self.flushes.set(self.flushes.get() + 1);
+				Ok(())
+			}
+		}
+
+		let mut writer = Writer {
+			stream: CountingFlush {
+				buf: Vec::new(),
+				flushes: flushed.clone(),
+			},
+			sender: Sender::new(false),
+		};
+
+		let messages = vec![
+			::message::OwnedMessage::Text("hello".to_string()),
+			::message::OwnedMessage::Text("world".to_string()),
+		];
+		writer.send_all(messages).unwrap();
+
+		// the flush from `send_all` plus the one `Drop` adds on top
+		let bytes = writer.stream.buf.clone();
+		drop(writer);
+		assert_eq!(flushed.get(), 2);
+
+		let first = OwnedDataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+		assert_eq!(first.data, b"hello");
+		let mut rest = &bytes[first.data.len() + 2..];
+		let second = OwnedDataFrame::read_dataframe(&mut rest, false).unwrap();
+		assert_eq!(second.data, b"world");
+	}
+
+	#[test]
+	fn send_all_stops_at_the_first_failure_and_reports_its_index() {
+		struct FailsOnSecondWrite {
+			writes: usize,
+		}
+		impl Write for FailsOnSecondWrite {
+			fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+				self.writes += 1;
+				if self.writes == 2 {
+					return Err(::std::io::Error::new(::std::io::ErrorKind::Other, "disk on fire"));
+				}
+				Ok(buf.len())
+			}
+			fn flush(&mut self) -> IoResult<()> {
+				Ok(())
+			}
+		}
+
+		let mut writer = Writer {
+			stream: FailsOnSecondWrite { writes: 0 },
+			sender: Sender::new(false),
+		};
+
+		let messages = vec![
+			::message::OwnedMessage::Text("first".to_string()),
+			::message::OwnedMessage::Text("second".to_string()),
+			::message::OwnedMessage::Text("never sent".to_string()),
+		];
+
+		match writer.send_all(messages) {
+			Err((1, WebSocketError::IoError(_))) => {}
+			other => panic!("expected failure at index 1, got {:?}", other.map(|_| ())),
+		}
+	}
+}
+
+/// Measures the fan-out savings `ws::sender::Sender::send_dataframe`/
+/// `send_message`'s by-reference signatures already give for free: sending
+/// the same message to many connections without rebuilding it per
+/// connection, versus a baseline that does rebuild it each time.
+///
+/// `bench_send_dataframe_small_payload`/`bench_send_message_small_payload`
+/// below instead measure the single-connection hot path for a small (16
+/// byte) payload - the case `Sender::scratch` and `DataFrame::write_to_buf`'s
+/// inlined header and in-place masking are aimed at. This crate has no
+/// criterion dependency and nothing else in it benchmarks that way; nightly
+/// `#[bench]` is the established convention here (see also
+/// `ws::util::header`'s benches), so these follow it rather than adding one.
+#[cfg(all(feature = "nightly", test))]
+mod benches {
+	use super::*;
+	use test::Bencher;
+
+	const FAN_OUT: usize = 64;
+	const SMALL_PAYLOAD_LEN: usize = 16;
+
+	#[bench]
+	fn bench_send_message_reused_across_many_connections(b: &mut Bencher) {
+		let message = ::message::OwnedMessage::Binary(vec![0u8; 4096]);
+		let mut sender = Sender::new(true);
+		let mut connections: Vec<Vec<u8>> = (0..FAN_OUT).map(|_| Vec::new()).collect();
+
+		b.iter(|| {
+			for connection in &mut connections {
+				connection.clear();
+				sender.send_message(connection, &message).unwrap();
+			}
+		});
+	}
+
+	#[bench]
+	fn bench_send_message_rebuilt_per_connection(b: &mut Bencher) {
+		let mut sender = Sender::new(true);
+		let mut connections: Vec<Vec<u8>> = (0..FAN_OUT).map(|_| Vec::new()).collect();
+
+		b.iter(|| {
+			for connection in &mut connections {
+				connection.clear();
+				let message = ::message::OwnedMessage::Binary(vec![0u8; 4096]);
+				sender.send_message(connection, &message).unwrap();
+			}
+		});
+	}
+
+	#[bench]
+	fn bench_send_dataframe_small_payload(b: &mut Bencher) {
+		let dataframe = OwnedDataFrame::new(true, Opcode::Binary, vec![0u8; SMALL_PAYLOAD_LEN]);
+		let mut sender = Sender::new(true);
+		let mut connection = Vec::new();
+
+		b.iter(|| {
+			connection.clear();
+			sender.send_dataframe(&mut connection, &dataframe).unwrap();
+		});
+	}
+
+	#[bench]
+	fn bench_send_message_small_payload(b: &mut Bencher) {
+		let message = ::message::OwnedMessage::Binary(vec![0u8; SMALL_PAYLOAD_LEN]);
+		let mut sender = Sender::new(true);
+		let mut connection = Vec::new();
+
+		b.iter(|| {
+			connection.clear();
+			sender.send_message(&mut connection, &message).unwrap();
+		});
 	}
 }
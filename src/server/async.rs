@@ -1,12 +1,18 @@
 //! The asynchronous implementation of a websocket server.
 use bytes::BytesMut;
-use futures::{Future, Stream};
+use futures::stream::poll_fn;
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll, Stream};
+use hyper::header::Server as ServerHeader;
+use hyper::status::StatusCode;
 use server::upgrade::async::{IntoWs, Upgrade};
 use server::InvalidConnection;
-use server::{NoTlsAcceptor, WsServer};
+use server::{HandshakeLimiter, NoTlsAcceptor, ServerStats, WsServer};
 use std::io;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::net::{TcpListener, TcpStream};
 pub use tokio::reactor::Handle;
 
@@ -37,6 +43,23 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: NoTlsAcceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			on_accept: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			socket_config: None,
 		})
 	}
 
@@ -50,6 +73,10 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// (https://github.com/cyderize/rust-websocket/blob/master/examples/async-server.rs)
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TcpStream> {
+		let connection_ids = self.connection_ids.clone();
+		let server_header = self.server_header.clone();
+		let switching_protocols_reason = self.switching_protocols_reason.clone();
+		let overload_response = self.overload_response;
 		let future = self
 			.listener
 			.incoming()
@@ -59,20 +86,144 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 				parsed: None,
 				buffer: None,
 				error: e.into(),
+				peer_addr: None,
 			})
-			.and_then(|(stream, a)| {
+			.and_then(move |(stream, a)| {
+				let connection_id = connection_ids.fetch_add(1, Ordering::SeqCst);
+				let server_header = server_header.clone();
+				let switching_protocols_reason = switching_protocols_reason.clone();
 				stream
 					.into_ws()
-					.map_err(|(stream, req, buf, err)| InvalidConnection {
+					.map_err(move |(stream, req, buf, err)| InvalidConnection {
 						stream: Some(stream),
 						parsed: req,
 						buffer: Some(buf),
 						error: err,
+						peer_addr: Some(a),
+					})
+					.map(move |mut u| {
+						u.connection_id = connection_id;
+						if let Some(ref server_header) = server_header {
+							u.headers.set(ServerHeader(server_header.clone()));
+						}
+						u.switching_protocols_reason = switching_protocols_reason.clone();
+						u.overload_response = overload_response;
+						(u, a)
 					})
-					.map(move |u| (u, a))
 			});
 		Box::new(future)
 	}
+
+	/// Caps how many connections `incoming()` will hand out at once to
+	/// `limit`, returning a `ConnectionPermit` alongside each one that frees
+	/// its slot when dropped. Call `incoming` on the returned
+	/// `BoundedServer` instead of on `self` to get the bounded stream.
+	pub fn with_max_connections(self, limit: usize) -> BoundedServer<NoTlsAcceptor> {
+		BoundedServer {
+			server: self,
+			limiter: Arc::new(ConnectionLimiter::new(limit)),
+		}
+	}
+}
+
+/// Non-blocking cap on how many connections `BoundedIncoming` will hand out
+/// at once, built by `WsServer::with_max_connections`. Unlike
+/// `WsServer::max_pending_handshakes` (which only bounds handshakes in
+/// flight, and blocks a thread to do it) this counts connections the caller
+/// is still holding open, and parks the polling task rather than blocking -
+/// the same backpressure a bounded `Stream::buffer_unordered` gives a fixed
+/// pool of in-flight futures, but driven by `ConnectionPermit` drops instead
+/// of future completions.
+struct ConnectionLimiter {
+	active: AtomicUsize,
+	limit: usize,
+	parked: Mutex<Vec<Task>>,
+}
+
+impl ConnectionLimiter {
+	fn new(limit: usize) -> Self {
+		ConnectionLimiter {
+			active: AtomicUsize::new(0),
+			limit,
+			parked: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Non-blocking: returns a permit if a slot is free. Otherwise parks
+	/// the current task (woken by the next `release`) and returns `None`.
+	fn try_acquire(self: &Arc<Self>) -> Option<ConnectionPermit> {
+		loop {
+			let active = self.active.load(Ordering::SeqCst);
+			if active >= self.limit {
+				self.parked.lock().unwrap().push(task::current());
+				return None;
+			}
+			if self.active.compare_and_swap(active, active + 1, Ordering::SeqCst) == active {
+				return Some(ConnectionPermit {
+					limiter: self.clone(),
+				});
+			}
+		}
+	}
+
+	fn release(&self) {
+		self.active.fetch_sub(1, Ordering::SeqCst);
+		if let Some(task) = self.parked.lock().unwrap().pop() {
+			task.notify();
+		}
+	}
+}
+
+/// Counts towards the cap set by `WsServer::with_max_connections` for as
+/// long as it's alive; dropping it (e.g. when the connection it was issued
+/// for is done) frees the slot for another connection.
+pub struct ConnectionPermit {
+	limiter: Arc<ConnectionLimiter>,
+}
+
+impl Drop for ConnectionPermit {
+	fn drop(&mut self) {
+		self.limiter.release();
+	}
+}
+
+/// Like `Incoming`, but each item also carries a `ConnectionPermit` - see
+/// `WsServer::with_max_connections`.
+pub type BoundedIncoming<S> = Box<
+	Stream<Item = (Upgrade<S>, SocketAddr, ConnectionPermit), Error = InvalidConnection<S, BytesMut>>
+		+ Send,
+>;
+
+/// An async server configured by `WsServer::with_max_connections`; call
+/// `incoming` to get the bounded stream of connections.
+pub struct BoundedServer<S>
+where
+	S: ::server::OptionalTlsAcceptor,
+{
+	server: WsServer<S, TcpListener>,
+	limiter: Arc<ConnectionLimiter>,
+}
+
+impl BoundedServer<NoTlsAcceptor> {
+	/// Like `WsServer::incoming`, but stops yielding new connections once
+	/// `limit` many `ConnectionPermit`s (see `WsServer::with_max_connections`)
+	/// are outstanding, resuming as soon as one is dropped.
+	pub fn incoming(self) -> BoundedIncoming<TcpStream> {
+		let limiter = self.limiter;
+		let mut inner = self.server.incoming();
+		let stream = poll_fn(move || -> Poll<Option<(Upgrade<TcpStream>, SocketAddr, ConnectionPermit)>, InvalidConnection<TcpStream, BytesMut>> {
+			let permit = match limiter.try_acquire() {
+				Some(permit) => permit,
+				None => return Ok(Async::NotReady),
+			};
+			match inner.poll()? {
+				Async::Ready(Some((upgrade, addr))) => Ok(Async::Ready(Some((upgrade, addr, permit)))),
+				Async::Ready(None) => Ok(Async::Ready(None)),
+				Async::NotReady => Ok(Async::NotReady),
+			}
+		});
+		Box::new(stream)
+	}
 }
 
 /// Asynchronous methods for creating an async SSL server and accepting incoming connections.
@@ -93,6 +244,23 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: acceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			on_accept: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			socket_config: None,
 		})
 	}
 
@@ -107,6 +275,10 @@ impl WsServer<TlsAcceptor, TcpListener> {
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TlsStream<TcpStream>> {
 		let acceptor = TlsAcceptorExt::from(self.ssl_acceptor);
+		let connection_ids = self.connection_ids.clone();
+		let server_header = self.server_header.clone();
+		let switching_protocols_reason = self.switching_protocols_reason.clone();
+		let overload_response = self.overload_response;
 		let future = self
 			.listener
 			.incoming()
@@ -116,31 +288,45 @@ impl WsServer<TlsAcceptor, TcpListener> {
 				parsed: None,
 				buffer: None,
 				error: e.into(),
+				peer_addr: None,
 			})
 			.and_then(move |(stream, a)| {
 				acceptor
 					.accept(stream)
-					.map_err(|e| {
+					.map_err(move |e| {
 						InvalidConnection {
 							stream: None,
 							parsed: None,
 							buffer: None,
 							// TODO: better error types
 							error: io::Error::new(io::ErrorKind::Other, e).into(),
+							peer_addr: Some(a),
 						}
 					})
 					.map(move |s| (s, a))
 			})
-			.and_then(|(stream, a)| {
+			.and_then(move |(stream, a)| {
+				let connection_id = connection_ids.fetch_add(1, Ordering::SeqCst);
+				let server_header = server_header.clone();
+				let switching_protocols_reason = switching_protocols_reason.clone();
 				stream
 					.into_ws()
-					.map_err(|(stream, req, buf, err)| InvalidConnection {
+					.map_err(move |(stream, req, buf, err)| InvalidConnection {
 						stream: Some(stream),
 						parsed: req,
 						buffer: Some(buf),
 						error: err,
+						peer_addr: Some(a),
+					})
+					.map(move |mut u| {
+						u.connection_id = connection_id;
+						if let Some(ref server_header) = server_header {
+							u.headers.set(ServerHeader(server_header.clone()));
+						}
+						u.switching_protocols_reason = switching_protocols_reason.clone();
+						u.overload_response = overload_response;
+						(u, a)
 					})
-					.map(move |u| (u, a))
 			});
 		Box::new(future)
 	}
@@ -1,8 +1,19 @@
 //! Provides an implementation of a WebSocket server
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
-use native_tls::TlsAcceptor;
+use native_tls::{HandshakeError, TlsAcceptor, TlsStream};
 
-use self::upgrade::{HyperIntoWsError, Request};
+use self::upgrade::{HyperIntoWsError, Request, WsUpgrade};
+use hyper::header::Server as ServerHeader;
+use hyper::status::StatusCode;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+#[cfg(feature = "sync-ssl")]
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+#[cfg(feature = "sync-ssl")]
+use std::thread;
+use std::time::{Duration, Instant};
 use stream::Stream;
 
 pub mod upgrade;
@@ -16,14 +27,382 @@ pub mod sync;
 /// Marker struct for a struct not being secure
 #[derive(Clone)]
 pub struct NoTlsAcceptor;
-/// Trait that is implemented over NoSslAcceptor and SslAcceptor that
-/// serves as a generic bound to make a struct with.
-/// Used in the Server to specify impls based on whether the server
-/// is running over SSL or not.
-pub trait OptionalTlsAcceptor {}
-impl OptionalTlsAcceptor for NoTlsAcceptor {}
+
+/// Implemented by `NoTlsAcceptor`, `native_tls::TlsAcceptor`, and any other
+/// acceptor a caller wants to plug into `WsServer` - for example a wrapper
+/// around a TLS library other than `native-tls`. Used as a generic bound on
+/// `WsServer` to specify impls based on whether, and how, the server accepts
+/// TLS.
+///
+/// `WsServer::accept_tls` (the synchronous TLS half of `accept`) calls
+/// `accept` below, so implementing this trait for your own acceptor type is
+/// all it takes for `WsServer<YourAcceptor, TcpListener>::accept` to work,
+/// with `Client<YourAcceptor::Output>` coming out the other end instead of
+/// `Client<TlsStream<TcpStream>>`.
+pub trait OptionalTlsAcceptor {
+	/// The stream type this acceptor turns a freshly-accepted `TcpStream`
+	/// into. For `NoTlsAcceptor` this is the `TcpStream` itself; for a TLS
+	/// acceptor it is whatever encrypted stream type that library produces.
+	type Output: Stream;
+
+	/// Performs whatever handshake this acceptor needs over `stream`,
+	/// producing `Output`. A `HyperIntoWsError::Io` error of kind
+	/// `WouldBlock` signals a transient failure (e.g. a non-blocking TLS
+	/// handshake that hasn't completed yet) rather than a fatal one.
+	fn accept(&self, stream: TcpStream) -> Result<Self::Output, HyperIntoWsError>;
+}
+
+impl OptionalTlsAcceptor for NoTlsAcceptor {
+	type Output = TcpStream;
+
+	fn accept(&self, stream: TcpStream) -> Result<TcpStream, HyperIntoWsError> {
+		Ok(stream)
+	}
+}
+
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
-impl OptionalTlsAcceptor for TlsAcceptor {}
+impl OptionalTlsAcceptor for TlsAcceptor {
+	type Output = TlsStream<TcpStream>;
+
+	fn accept(&self, stream: TcpStream) -> Result<TlsStream<TcpStream>, HyperIntoWsError> {
+		match TlsAcceptor::accept(self, stream) {
+			Ok(stream) => Ok(stream),
+			Err(HandshakeError::Failure(err)) => Err(HyperIntoWsError::Tls(err)),
+			Err(HandshakeError::WouldBlock(_)) => {
+				Err(io::Error::new(io::ErrorKind::WouldBlock, "TLS handshake would block").into())
+			}
+		}
+	}
+}
+
+/// A `TlsAcceptor` chosen per-connection from the ClientHello's SNI
+/// `server_name`, for multi-tenant hosting where each tenant's certificate
+/// is resolved some other way (e.g. a database lookup) rather than being
+/// fixed at server construction time. Build with `SniTlsAcceptor::new`.
+///
+/// `select` is called with the SNI hostname, if one was present and could be
+/// parsed out (see `peek_sni`); falls back to the `default` acceptor
+/// whenever `select` returns `None`, including when there was no SNI
+/// extension to read in the first place.
+#[cfg(feature = "sync-ssl")]
+pub struct SniTlsAcceptor<F> {
+	default: TlsAcceptor,
+	select: F,
+}
+
+#[cfg(feature = "sync-ssl")]
+impl<F> SniTlsAcceptor<F>
+where
+	F: Fn(Option<&str>) -> Option<TlsAcceptor>,
+{
+	/// `default` is used whenever `select` returns `None`.
+	pub fn new(default: TlsAcceptor, select: F) -> Self {
+		SniTlsAcceptor { default, select }
+	}
+}
+
+#[cfg(feature = "sync-ssl")]
+impl<F> OptionalTlsAcceptor for SniTlsAcceptor<F>
+where
+	F: Fn(Option<&str>) -> Option<TlsAcceptor>,
+{
+	type Output = TlsStream<TcpStream>;
+
+	fn accept(&self, stream: TcpStream) -> Result<TlsStream<TcpStream>, HyperIntoWsError> {
+		let hostname = peek_sni(&stream)?;
+		let acceptor = (self.select)(hostname.as_ref().map(String::as_str)).unwrap_or_else(|| self.default.clone());
+		<TlsAcceptor as OptionalTlsAcceptor>::accept(&acceptor, stream)
+	}
+}
+
+/// How large a ClientHello `peek_sni` will grow its peek buffer to before
+/// giving up on a connection that keeps claiming to need more room than any
+/// real ClientHello plausibly would.
+#[cfg(feature = "sync-ssl")]
+const MAX_CLIENT_HELLO_BYTES: usize = 16 * 1024;
+
+/// How long `peek_sni` waits between peeks while a ClientHello is still
+/// arriving, and how many times it will wait before giving up.
+#[cfg(feature = "sync-ssl")]
+const CLIENT_HELLO_RETRY_DELAY: Duration = Duration::from_millis(5);
+#[cfg(feature = "sync-ssl")]
+const MAX_CLIENT_HELLO_RETRIES: usize = 50;
+
+/// Reads (without consuming, via `TcpStream::peek`) the SNI `server_name`
+/// sitting in the ClientHello arriving on `stream`, if there is one. For
+/// routing and per-tenant TLS cert selection (see `SniTlsAcceptor`) that want
+/// to decide something before the handshake itself runs, which still sees
+/// the full ClientHello afterwards since nothing here is consumed from the
+/// socket.
+///
+/// A ClientHello that arrives split across several TCP segments is handled
+/// by peeking again, with a short delay, until the whole thing has arrived
+/// or `MAX_CLIENT_HELLO_RETRIES` is exceeded.
+///
+/// Returns `Ok(None)` - not an error - for anything that isn't a complete,
+/// well-formed ClientHello carrying an SNI extension: a non-TLS client, a
+/// ClientHello without SNI, or one that never fully arrives.
+#[cfg(feature = "sync-ssl")]
+pub fn peek_sni(stream: &TcpStream) -> io::Result<Option<String>> {
+	let mut buf = vec![0u8; 4096];
+	let mut retries = 0;
+
+	loop {
+		let n = stream.peek(&mut buf)?;
+		match parse_client_hello_sni(&buf[..n]) {
+			SniParse::Found(name) => return Ok(Some(name)),
+			SniParse::NotFound => return Ok(None),
+			SniParse::Incomplete => {
+				if n == buf.len() && buf.len() < MAX_CLIENT_HELLO_BYTES {
+					// Our buffer may be hiding more already-arrived bytes;
+					// grow it and peek again right away.
+					buf.resize(buf.len() * 2, 0);
+					continue;
+				}
+				if retries >= MAX_CLIENT_HELLO_RETRIES {
+					return Ok(None);
+				}
+				retries += 1;
+				thread::sleep(CLIENT_HELLO_RETRY_DELAY);
+			}
+		}
+	}
+}
+
+/// The result of trying to parse a ClientHello's SNI `server_name` out of
+/// however many bytes of it have arrived so far.
+#[cfg(feature = "sync-ssl")]
+#[derive(Debug, PartialEq, Eq)]
+enum SniParse {
+	/// A complete ClientHello carrying an SNI `server_name`.
+	Found(String),
+	/// A complete ClientHello, or a non-TLS client, with nothing to find.
+	NotFound,
+	/// Not enough of the ClientHello has arrived yet to tell either way.
+	Incomplete,
+}
+
+/// Parses the SNI `server_name` out of a raw TLS handshake record, per
+/// RFC5246 section 7.4.1.2 (ClientHello) and RFC6066 section 3 (the
+/// `server_name` extension). Returns `SniParse::Incomplete` rather than
+/// erroring if `record` doesn't yet hold everything it claims to - the
+/// caller only uses this as a best-effort hint for picking a certificate or
+/// route - the actual handshake is what validates the ClientHello for real.
+#[cfg(feature = "sync-ssl")]
+fn parse_client_hello_sni(record: &[u8]) -> SniParse {
+	use self::SniParse::*;
+
+	// Record header: content type (0x16 = handshake), version, length.
+	if record.len() < 5 {
+		return Incomplete;
+	}
+	if record[0] != 0x16 {
+		return NotFound;
+	}
+	let record_len = ((record[3] as usize) << 8) | record[4] as usize;
+	let body = &record[5..];
+	if body.len() < record_len {
+		return Incomplete;
+	}
+
+	// Handshake header: msg type (0x01 = ClientHello), length.
+	if body.len() < 4 {
+		return Incomplete;
+	}
+	if body[0] != 0x01 {
+		return NotFound;
+	}
+	let hello_len = ((body[1] as usize) << 16) | ((body[2] as usize) << 8) | body[3] as usize;
+	let hello = match body.get(4..4 + hello_len) {
+		Some(hello) => hello,
+		None => return Incomplete,
+	};
+
+	match parse_client_hello_body(hello) {
+		Some(name) => Found(name),
+		None => NotFound,
+	}
+}
+
+/// Parses everything after a ClientHello's own length prefix. Once `hello`
+/// has been sliced to `hello_len`, every length it claims internally is
+/// either genuinely malformed or, since it's already complete, simply
+/// missing an SNI extension - both are reported as `None`, since a split
+/// packet can't be the cause at this point.
+#[cfg(feature = "sync-ssl")]
+fn parse_client_hello_body(hello: &[u8]) -> Option<String> {
+	// client_version (2) + random (32) + session_id (1-length-prefixed).
+	let mut pos = 2 + 32;
+	let session_id_len = *hello.get(pos)? as usize;
+	pos += 1 + session_id_len;
+
+	// cipher_suites (2-length-prefixed).
+	let cipher_suites_len = u16_at(hello, pos)? as usize;
+	pos += 2 + cipher_suites_len;
+
+	// compression_methods (1-length-prefixed).
+	let compression_methods_len = *hello.get(pos)? as usize;
+	pos += 1 + compression_methods_len;
+
+	// extensions (2-length-prefixed), optional per RFC5246.
+	let extensions_len = u16_at(hello, pos)? as usize;
+	pos += 2;
+	let mut extensions = hello.get(pos..pos + extensions_len)?;
+
+	while extensions.len() >= 4 {
+		let extension_type = u16_at(extensions, 0)?;
+		let extension_len = u16_at(extensions, 2)? as usize;
+		let extension_data = extensions.get(4..4 + extension_len)?;
+
+		// server_name extension, RFC6066 section 3.
+		if extension_type == 0x0000 {
+			let list_len = u16_at(extension_data, 0)? as usize;
+			let mut names = extension_data.get(2..2 + list_len)?;
+			while names.len() >= 3 {
+				let name_type = names[0];
+				let name_len = u16_at(names, 1)? as usize;
+				let name = names.get(3..3 + name_len)?;
+				// host_name, the only type RFC6066 defines.
+				if name_type == 0x00 {
+					return str::from_utf8(name).ok().map(str::to_owned);
+				}
+				names = &names[3 + name_len..];
+			}
+			return None;
+		}
+
+		extensions = &extensions[4 + extension_len..];
+	}
+
+	None
+}
+
+#[cfg(feature = "sync-ssl")]
+fn u16_at(bytes: &[u8], pos: usize) -> Option<u16> {
+	let pair = bytes.get(pos..pos + 2)?;
+	Some(((pair[0] as u16) << 8) | pair[1] as u16)
+}
+
+/// Why `WsServer::accept` rejected a connection, as tracked by
+/// `ServerStats`. `RejectReason::classify` is how `accept_tcp`/`accept_tls`
+/// turn a `HyperIntoWsError` into one of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RejectReason {
+	/// The handshake request was malformed or asked for something this
+	/// server doesn't support (bad method, missing/invalid headers, an
+	/// unsupported `Sec-WebSocket-Version`, oversized headers, ...).
+	BadHandshake,
+	/// The TLS handshake itself failed.
+	Tls,
+	/// An I/O error reading the socket, including a refusal while
+	/// `WsServer::drain`ing.
+	Io,
+	/// The request's path wasn't in `WsServer::allowed_paths`.
+	NotFound,
+	/// The server was overloaded and rejected the handshake via
+	/// `WsUpgrade::reject_unavailable`.
+	Overloaded,
+}
+
+impl RejectReason {
+	pub(crate) fn classify(error: &HyperIntoWsError) -> Self {
+		match *error {
+			HyperIntoWsError::Io(_) => RejectReason::Io,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			HyperIntoWsError::Tls(_) => RejectReason::Tls,
+			HyperIntoWsError::PathNotAllowed { .. } => RejectReason::NotFound,
+			HyperIntoWsError::Overloaded => RejectReason::Overloaded,
+			_ => RejectReason::BadHandshake,
+		}
+	}
+}
+
+/// Server-level counts of connections `WsServer::accept` rejected, broken
+/// down by `RejectReason`, complementing whatever per-connection metrics
+/// you already track with a server-wide view for capacity planning. Cheap
+/// and always-on: each rejection increments one `AtomicUsize`. Shared
+/// across `try_clone`d servers the same way `connection_ids` is, so worker
+/// threads accepting off cloned listeners all contribute to the same
+/// totals. See `WsServer::stats`.
+#[derive(Default)]
+pub struct ServerStats {
+	bad_handshake: AtomicUsize,
+	tls: AtomicUsize,
+	io: AtomicUsize,
+	not_found: AtomicUsize,
+	overloaded: AtomicUsize,
+}
+
+impl ServerStats {
+	pub(crate) fn record(&self, reason: RejectReason) {
+		let counter = match reason {
+			RejectReason::BadHandshake => &self.bad_handshake,
+			RejectReason::Tls => &self.tls,
+			RejectReason::Io => &self.io,
+			RejectReason::NotFound => &self.not_found,
+			RejectReason::Overloaded => &self.overloaded,
+		};
+		counter.fetch_add(1, Ordering::SeqCst);
+	}
+
+	/// Connections rejected for a malformed or unsupported handshake
+	/// request (bad method, missing/invalid headers, an unsupported
+	/// `Sec-WebSocket-Version`, oversized headers, ...).
+	pub fn bad_handshake(&self) -> usize {
+		self.bad_handshake.load(Ordering::SeqCst)
+	}
+
+	/// Connections rejected because the TLS handshake itself failed.
+	pub fn tls_failure(&self) -> usize {
+		self.tls.load(Ordering::SeqCst)
+	}
+
+	/// Connections rejected by an I/O error reading the socket, including a
+	/// refusal while `WsServer::drain`ing.
+	pub fn io_error(&self) -> usize {
+		self.io.load(Ordering::SeqCst)
+	}
+
+	/// Connections rejected because their path wasn't in
+	/// `WsServer::allowed_paths`.
+	pub fn not_found(&self) -> usize {
+		self.not_found.load(Ordering::SeqCst)
+	}
+
+	/// Connections rejected because the server was overloaded. See
+	/// `WsServer::overload_response`.
+	pub fn overloaded(&self) -> usize {
+		self.overloaded.load(Ordering::SeqCst)
+	}
+
+	/// The sum of every reason above.
+	pub fn total(&self) -> usize {
+		self.bad_handshake() + self.tls_failure() + self.io_error() + self.not_found() + self.overloaded()
+	}
+}
+
+/// Metadata about a handshake `accept` just completed, passed to the
+/// callback registered with `WsServer::on_accept`.
+///
+/// This fires once the handshake *request* has been read and validated
+/// (the same moment `accept`/`accept_tls` returns `Ok`), not once the
+/// `101` response has actually been written back - the synchronous server
+/// only writes that later, when the caller calls `WsUpgrade::accept`, and
+/// no event marks that moment. There's
+/// deliberately no matching close event: this library hands the
+/// accepted connection back to the caller rather than keeping it (see
+/// `DrainHandle`'s docs), so it has no way to know when, or whether, the
+/// caller is done with it.
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+	/// The id assigned to this connection. See `WsUpgrade::connection_id`.
+	pub connection_id: u64,
+	/// The client's address, if the underlying TCP stream had one to give.
+	pub peer_addr: Option<SocketAddr>,
+	/// How long the handshake took, from the moment the TCP connection was
+	/// accepted to the moment the request was validated.
+	pub handshake_duration: Duration,
+}
 
 /// When a sever tries to accept a connection many things can go wrong.
 ///
@@ -46,6 +425,24 @@ where
 	pub buffer: Option<B>,
 	/// the cause of the failed websocket connection setup
 	pub error: HyperIntoWsError,
+	/// the client's address, captured from the raw TCP accept before the TLS
+	/// handshake (if any) was attempted, so it's available even when `stream`
+	/// above is `None`. `None` if the connection never got that far (e.g. the
+	/// server was draining, or `listener.accept()` itself failed).
+	pub peer_addr: Option<SocketAddr>,
+}
+
+impl<S, B> InvalidConnection<S, B>
+where
+	S: Stream,
+{
+	/// Shorthand for `self.error.is_transient()`: whether an accept loop
+	/// should pause and retry this connection rather than treat it as
+	/// fatal (for example `EMFILE`/`ENFILE`, or a spurious `WouldBlock`/
+	/// `Interrupted`).
+	pub fn is_transient(&self) -> bool {
+		self.error.is_transient()
+	}
 }
 
 /// Represents a WebSocket server which can work with either normal
@@ -93,4 +490,668 @@ where
 	listener: L,
 	/// The SSL acceptor given to the server
 	pub ssl_acceptor: S,
+	/// The `Sec-WebSocket-Version` values this server will accept from a client.
+	/// Defaults to `[13]`, the only version defined by RFC6455. See
+	/// `WsServer::accepted_versions`.
+	pub(crate) accepted_versions: Vec<u8>,
+	/// Flag flipped by `WsServer::drain` to stop accepting new connections.
+	pub(crate) draining: Arc<AtomicBool>,
+	/// Whether `TCP_NODELAY` is set on every accepted stream. See
+	/// `WsServer::tcp_nodelay`.
+	pub(crate) tcp_nodelay: bool,
+	/// `TCP_USER_TIMEOUT` applied to every accepted stream, or `None` to
+	/// leave the kernel default in place. See
+	/// `WsServer::tcp_user_timeout`.
+	pub(crate) tcp_user_timeout: Option<Duration>,
+	/// Read timeout applied to an accepted stream for just the handshake
+	/// phase (the TLS handshake, if any, and the HTTP upgrade read), or
+	/// `None` (the default) to let it block indefinitely. Cleared again
+	/// once the handshake succeeds, so it has no effect on later message
+	/// traffic. See `WsServer::set_accept_timeout`.
+	pub(crate) accept_timeout: Option<Duration>,
+	/// Source of the monotonically increasing ids handed out to accepted
+	/// connections, shared across clones of this server (e.g. `try_clone`'s
+	/// worker threads) so ids stay unique. See `WsUpgrade::connection_id`.
+	pub(crate) connection_ids: Arc<AtomicU64>,
+	/// The `Server` header value set on every handshake response (both
+	/// accepted and rejected), or `None` to omit it. See
+	/// `WsServer::server_header`.
+	pub(crate) server_header: Option<String>,
+	/// Counts of rejected connections by reason, shared across clones of
+	/// this server the same way `connection_ids` is. See `WsServer::stats`.
+	pub(crate) stats: Arc<ServerStats>,
+	/// `SO_SNDBUF`/`SO_RCVBUF` sizes applied to every accepted stream, or
+	/// `None` to leave the OS defaults in place. See
+	/// `WsServer::socket_buffer_sizes`.
+	pub(crate) socket_buffer_sizes: Option<(usize, usize)>,
+	/// Bounds how many handshakes (the `into_ws` phase of
+	/// `WsServer::accept_tls`) can be in flight at once, shared across
+	/// clones of this server the same way `connection_ids` is. See
+	/// `WsServer::max_pending_handshakes`.
+	pub(crate) max_pending_handshakes: Arc<HandshakeLimiter>,
+	/// Overrides the reason phrase on every accepted handshake's `101`
+	/// response, or `None` to use hyper's canonical `Switching Protocols`.
+	/// Never changes the status code itself. See
+	/// `WsServer::switching_protocols_reason`.
+	pub(crate) switching_protocols_reason: Option<String>,
+	/// Paths this server accepts handshakes on, or `None` (the default) to
+	/// accept any path. See `WsServer::allowed_paths`.
+	pub(crate) allowed_paths: Option<Vec<String>>,
+	/// Subprotocols at least one of which a handshake must offer via
+	/// `Sec-WebSocket-Protocol`, or `None` (the default) to leave
+	/// subprotocol selection entirely to the application. Enforced by the
+	/// sync server's `accept`/`accept_tls` only - like `allowed_paths`,
+	/// there's no equivalent check in the async `incoming()` path. See
+	/// `WsServer::require_protocol`.
+	pub(crate) required_protocols: Option<Vec<String>>,
+	/// Run against every handshake request right after it's parsed, before
+	/// this crate validates it, or left `None` (the default, an implicit
+	/// identity rewrite) to validate the request exactly as the client sent
+	/// it. See `WsServer::rewrite_request`.
+	pub(crate) rewrite_request: Option<Arc<dyn Fn(&mut Request) + Send + Sync>>,
+	/// Called with a `ConnectionEvent` every time `accept` completes a
+	/// handshake, or left `None` (the default) to skip the bookkeeping this
+	/// costs entirely. Shared across clones of this server the same way
+	/// `stats` is - `try_clone` clones the `Arc`, so every worker calls the
+	/// same callback; `into_async` does not carry it over. See
+	/// `WsServer::on_accept`.
+	pub(crate) on_accept: Option<Arc<dyn Fn(ConnectionEvent) + Send + Sync>>,
+	/// Status and optional `Retry-After` seconds `WsUpgrade::reject_unavailable`
+	/// sends. Defaults to `(StatusCode::ServiceUnavailable, None)`. See
+	/// `WsServer::overload_response`.
+	pub(crate) overload_response: (StatusCode, Option<u32>),
+	/// Run against every raw accepted `TcpStream`, right after `accept_tcp`'s
+	/// own `tcp_nodelay`/`socket_buffer_sizes`/`tcp_user_timeout` and before
+	/// `accept_tls` starts the TLS handshake (if any) or the handshake read,
+	/// or left `None` (the default) to apply no extra configuration. See
+	/// `WsServer::set_socket_config`.
+	pub(crate) socket_config: Option<Arc<dyn Fn(&TcpStream) -> io::Result<()> + Send + Sync>>,
+}
+
+impl<S, L> WsServer<S, L>
+where
+	S: OptionalTlsAcceptor,
+{
+	/// Hands out the next connection id, wrapping back to `0` once `u64`
+	/// overflows rather than panicking.
+	pub(crate) fn next_connection_id(&self) -> u64 {
+		self.connection_ids.fetch_add(1, Ordering::SeqCst)
+	}
+
+	/// Counts of connections `accept` has rejected so far, broken down by
+	/// reason. See `ServerStats`.
+	pub fn stats(&self) -> &ServerStats {
+		&self.stats
+	}
+
+	/// Caps how many handshakes this server will read and parse at once,
+	/// independent of `accept`'s total connection count. Once `limit`
+	/// in-flight handshakes are pending, `accept_tls` blocks new ones until
+	/// an existing one completes or fails, bounding the resources a batch of
+	/// slow or stalled clients (e.g. a slowloris-style attack) can tie up in
+	/// the handshake phase. `None` removes the cap, which is also the
+	/// default. Shared across clones of this server the same way `stats` is.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.max_pending_handshakes(Some(128));
+	/// ```
+	pub fn max_pending_handshakes(&mut self, limit: Option<usize>) -> &mut Self {
+		self.max_pending_handshakes.set_limit(limit);
+		self
+	}
+
+	/// Registers a callback invoked with a `ConnectionEvent` every time
+	/// `accept` completes a handshake. `None` (the default) skips the
+	/// bookkeeping this costs entirely, so instrumentation is opt-in and
+	/// free when unused.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.on_accept(|event| {
+	/// 	println!("accepted connection {}", event.connection_id);
+	/// });
+	/// ```
+	pub fn on_accept<F>(&mut self, callback: F) -> &mut Self
+	where
+		F: Fn(ConnectionEvent) + Send + Sync + 'static,
+	{
+		self.on_accept = Some(Arc::new(callback));
+		self
+	}
+
+	/// Registers a closure run against every raw `TcpStream` this server
+	/// accepts, right after `accept_tcp`'s own `tcp_nodelay`/
+	/// `socket_buffer_sizes`/`tcp_user_timeout` and before `accept_tls`
+	/// starts the TLS handshake (if any) or reads the handshake request -
+	/// for socket options those built-in knobs don't cover, such as
+	/// `TcpStream::set_keepalive`. `None` (the default) applies no extra
+	/// configuration. An `Err` returned by the closure fails the connection
+	/// with that error, the same as a failed `tcp_nodelay`/handshake read
+	/// would - it surfaces as an `InvalidConnection` from `accept_tcp`, and
+	/// the raw stream is not passed on to `accept_tls`.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	/// use std::time::Duration;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.set_socket_config(|stream| stream.set_nodelay(true));
+	/// ```
+	pub fn set_socket_config<F>(&mut self, config: F) -> &mut Self
+	where
+		F: Fn(&TcpStream) -> io::Result<()> + Send + Sync + 'static,
+	{
+		self.socket_config = Some(Arc::new(config));
+		self
+	}
+
+	/// Stamps `self.server_header`, if any, onto an in-progress handshake so
+	/// it's sent on both the eventual accept (`101 Switching Protocols`) and
+	/// reject response, since both read from `upgrade.headers`.
+	pub(crate) fn apply_server_header<C, B>(&self, upgrade: &mut WsUpgrade<C, B>)
+	where
+		C: Stream,
+	{
+		if let Some(ref server_header) = self.server_header {
+			upgrade.headers.set(ServerHeader(server_header.clone()));
+		}
+	}
+
+	/// Stamps `self.switching_protocols_reason`, if any, onto an
+	/// in-progress handshake so `accept` uses it as the reason phrase on
+	/// the eventual `101` response, in place of hyper's canonical one.
+	pub(crate) fn apply_switching_protocols_reason<C, B>(&self, upgrade: &mut WsUpgrade<C, B>)
+	where
+		C: Stream,
+	{
+		upgrade.switching_protocols_reason = self.switching_protocols_reason.clone();
+	}
+
+	/// Stamps `self.overload_response` onto an in-progress handshake so a
+	/// later `WsUpgrade::reject_unavailable` call sends this server's
+	/// configured status code and `Retry-After`.
+	pub(crate) fn apply_overload_response<C, B>(&self, upgrade: &mut WsUpgrade<C, B>)
+	where
+		C: Stream,
+	{
+		upgrade.overload_response = self.overload_response;
+	}
+}
+
+/// A handle returned by `WsServer::drain`, used to track whether the drain
+/// deadline has passed and, if needed, to cancel the drain.
+///
+/// This library does not track how many connections are still alive for
+/// you; the deadline is just a timer. Pair it with your own live-connection
+/// count (for example an `Arc<AtomicUsize>` incremented on `accept` and
+/// decremented when each connection's handler thread finishes) so you can
+/// stop waiting as soon as the count hits zero, *or* the deadline passes,
+/// whichever comes first.
+#[derive(Clone)]
+pub struct DrainHandle {
+	deadline: Instant,
+	draining: Arc<AtomicBool>,
+}
+
+impl DrainHandle {
+	pub(crate) fn new(deadline: Duration, draining: Arc<AtomicBool>) -> Self {
+		DrainHandle {
+			deadline: Instant::now() + deadline,
+			draining,
+		}
+	}
+
+	/// Whether the deadline passed by to `WsServer::drain` has elapsed.
+	pub fn expired(&self) -> bool {
+		Instant::now() >= self.deadline
+	}
+
+	/// Stop draining and let the server accept new connections again.
+	pub fn cancel(&self) {
+		self.draining.store(false, Ordering::SeqCst);
+	}
+}
+
+/// A non-owning handle returned by `WsServer::weak_handle`, for cooperative
+/// shutdown from a supervisor that should not keep the server alive on its
+/// own. Unlike `DrainHandle`, which holds a strong reference and is itself
+/// part of keeping the server draining, a `WeakHandle` holds only a `Weak`
+/// reference; once every `WsServer` clone has been dropped, `shutdown` and
+/// `is_alive` simply report that and do nothing.
+#[derive(Clone)]
+pub struct WeakHandle {
+	draining: Weak<AtomicBool>,
+}
+
+impl WeakHandle {
+	pub(crate) fn new(draining: Weak<AtomicBool>) -> Self {
+		WeakHandle { draining }
+	}
+
+	/// Whether any `WsServer` this handle was created from is still alive.
+	pub fn is_alive(&self) -> bool {
+		self.draining.upgrade().is_some()
+	}
+
+	/// Put the server into the draining state it would reach via
+	/// `WsServer::drain`, so it stops accepting new connections. A no-op if
+	/// the server has already been dropped.
+	pub fn shutdown(&self) {
+		if let Some(draining) = self.draining.upgrade() {
+			draining.store(true, Ordering::SeqCst);
+		}
+	}
+}
+
+/// Returned by `WsServer::shutdown_handle`: unlike `DrainHandle`/`WeakHandle`,
+/// calling `shutdown` also unblocks a thread already parked inside a
+/// blocking `accept` call, rather than only taking effect on the next one.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+	draining: Arc<AtomicBool>,
+	addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+	pub(crate) fn new(draining: Arc<AtomicBool>, addr: SocketAddr) -> Self {
+		ShutdownHandle { draining, addr }
+	}
+
+	/// Stop the server's accept loop, including a thread currently blocked
+	/// inside `accept`. Sets the same draining flag `WsServer::drain` does,
+	/// then opens (and immediately drops) a connection to the server's own
+	/// listening address, waking up any `accept` call already parked in the
+	/// kernel. That call then fails with `HyperIntoWsError::ShutDown`, and
+	/// every one after it with the `Io` error of kind `ConnectionRefused`
+	/// that `WsServer::drain` describes, instead of completing a handshake or
+	/// blocking indefinitely.
+	///
+	/// The wakeup connection is a best-effort nudge: if it fails to connect
+	/// (the listener backlog is full, say), the draining flag is still set,
+	/// so a thread not currently blocked in `accept` stops on its next call
+	/// regardless. A thread already blocked may have to wait for some other
+	/// connection attempt to arrive instead.
+	pub fn shutdown(&self) -> io::Result<()> {
+		self.draining.store(true, Ordering::SeqCst);
+		TcpStream::connect(self.addr).map(|_| ())
+	}
+}
+
+/// A cloneable live-connection counter, for pairing with `WsServer::drain`
+/// (see `WsServer::shutdown_graceful`). This library hands accepted
+/// connections back to the caller rather than keeping them, so it has no
+/// way to count or close them on its own (see `DrainHandle`'s docs); call
+/// `track` yourself once per accepted connection and keep the returned
+/// `ConnectionToken` alive for as long as that connection is.
+#[derive(Clone, Default)]
+pub struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionGuard {
+	/// Creates a guard starting at zero live connections.
+	pub fn new() -> Self {
+		ConnectionGuard(Arc::new(AtomicUsize::new(0)))
+	}
+
+	/// Registers one live connection, returning a token that un-registers
+	/// it again when dropped.
+	pub fn track(&self) -> ConnectionToken {
+		self.0.fetch_add(1, Ordering::SeqCst);
+		ConnectionToken(self.0.clone())
+	}
+
+	/// How many `ConnectionToken`s handed out by `track` haven't been
+	/// dropped yet.
+	pub fn active(&self) -> usize {
+		self.0.load(Ordering::SeqCst)
+	}
+}
+
+/// Returned by `ConnectionGuard::track`; decrements the guard's count when
+/// dropped, so simply letting a connection's handler end (or go out of
+/// scope) is enough to un-register it.
+pub struct ConnectionToken(Arc<AtomicUsize>);
+
+impl Drop for ConnectionToken {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Returned by `WsServer::run`: a background accept loop already wired up
+/// with a `ConnectionGuard`, for the common case of `serve` without giving
+/// up the calling thread.
+///
+/// This is nothing `serve`, `drain`/`shutdown_graceful` and
+/// `ConnectionGuard` couldn't do wired together by hand - `run` just saves
+/// doing that wiring yourself. Reach for `serve` directly if you need
+/// something this doesn't cover (rejecting some handshakes before they
+/// reach `handler`, accepting on the calling thread too, ...).
+pub struct RunHandle {
+	pub(crate) connections: ConnectionGuard,
+	pub(crate) workers: ::std::thread::JoinHandle<()>,
+}
+
+impl RunHandle {
+	/// How many connections `run` has accepted and not yet finished
+	/// handling - the same `ConnectionGuard` count `WsServer::shutdown_graceful`
+	/// polls, so pass `run_handle.connections()` there once you call
+	/// `WsServer::drain` (or just drop straight into `WsServer::shutdown_graceful`,
+	/// which drains for you).
+	pub fn connections(&self) -> ConnectionGuard {
+		self.connections.clone()
+	}
+
+	/// Block until every worker thread `run` started has exited. That only
+	/// happens once the server is draining and every in-flight `handler`
+	/// call has returned, so call this after `WsServer::drain` or
+	/// `WsServer::shutdown_graceful`, not before.
+	///
+	/// Like `serve`'s own accept loop, a worker currently blocked inside the
+	/// listener's `accept` won't notice the server is draining until its
+	/// next connection attempt wakes it back up - on an otherwise idle
+	/// listener this can leave `join` waiting past the drain deadline, for
+	/// as long as it takes another connection attempt (successful or not)
+	/// to arrive.
+	pub fn join(self) {
+		let _ = self.workers.join();
+	}
+}
+
+/// A blocking counting semaphore bounding how many handshakes (the
+/// `into_ws` phase of `WsServer::accept_tls`) can be read and parsed at
+/// once, distinct from - and usually much smaller than - the total number
+/// of established connections. This bounds the threads/memory a
+/// slowloris-style attack can tie up in the handshake phase, where a client
+/// can stall indefinitely before this crate has anything of its own to
+/// reject it with. See `WsServer::max_pending_handshakes`.
+pub(crate) struct HandshakeLimiter {
+	limit: AtomicUsize,
+	pending: Mutex<usize>,
+	freed: Condvar,
+}
+
+impl HandshakeLimiter {
+	/// Starts out unlimited; see `set_limit`.
+	pub(crate) fn new() -> Self {
+		HandshakeLimiter {
+			limit: AtomicUsize::new(usize::max_value()),
+			pending: Mutex::new(0),
+			freed: Condvar::new(),
+		}
+	}
+
+	/// Like `new`, but applies `limit` straight away instead of starting
+	/// out unlimited.
+	pub(crate) fn with_limit(limit: Option<usize>) -> Self {
+		let limiter = HandshakeLimiter::new();
+		limiter.set_limit(limit);
+		limiter
+	}
+
+	/// Changes the cap, waking any handshake currently waiting on `acquire`
+	/// in case the new limit lets it through. `None` means unlimited.
+	pub(crate) fn set_limit(&self, limit: Option<usize>) {
+		self.limit
+			.store(limit.unwrap_or_else(usize::max_value), Ordering::SeqCst);
+		self.freed.notify_all();
+	}
+
+	/// Blocks until a handshake slot is free, then reserves it. Drop the
+	/// returned permit (typically by letting it go out of scope right after
+	/// `into_ws` resolves, whether it succeeded or failed) to free the slot
+	/// again.
+	pub(crate) fn acquire(&self) -> HandshakePermit<'_> {
+		let mut pending = self.pending.lock().unwrap();
+		while *pending >= self.limit.load(Ordering::SeqCst) {
+			pending = self.freed.wait(pending).unwrap();
+		}
+		*pending += 1;
+		HandshakePermit(self)
+	}
+}
+
+/// Returned by `HandshakeLimiter::acquire`; frees the reserved slot when
+/// dropped.
+pub(crate) struct HandshakePermit<'a>(&'a HandshakeLimiter);
+
+impl<'a> Drop for HandshakePermit<'a> {
+	fn drop(&mut self) {
+		*self.0.pending.lock().unwrap() -= 1;
+		self.0.freed.notify_one();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use server::upgrade::sync::IntoWs;
+	use stream::MockStream;
+
+	const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+	fn server_with_header(server_header: Option<&str>) -> WsServer<NoTlsAcceptor, ()> {
+		WsServer {
+			listener: (),
+			ssl_acceptor: NoTlsAcceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: server_header.map(Into::into),
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			on_accept: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			socket_config: None,
+		}
+	}
+
+	#[test]
+	fn apply_server_header_sets_the_header_used_by_both_accept_and_reject() {
+		use hyper::header::Server as ServerHeader;
+
+		let server = server_with_header(Some("my-app/1.0"));
+
+		// Both `internal_accept` and `internal_reject` build their response
+		// from `upgrade.headers`, so checking it here covers both paths.
+		let mut upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		server.apply_server_header(&mut upgrade);
+		assert_eq!(
+			upgrade.headers.get::<ServerHeader>(),
+			Some(&ServerHeader("my-app/1.0".to_string()))
+		);
+
+		let rejected = upgrade.reject().unwrap();
+		let response = String::from_utf8(rejected.output).unwrap();
+		assert!(response.contains("Server: my-app/1.0"));
+	}
+
+	#[test]
+	fn apply_server_header_does_nothing_when_unset() {
+		let server = server_with_header(None);
+
+		let mut upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		server.apply_server_header(&mut upgrade);
+		let stream = upgrade.reject().unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		assert!(!response.contains("Server:"));
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	fn client_hello(sni: Option<&str>) -> Vec<u8> {
+		let mut extensions = Vec::new();
+		if let Some(host) = sni {
+			let mut server_name_list = vec![0x00]; // name type: host_name
+			server_name_list.extend_from_slice(&(host.len() as u16).to_be_bytes());
+			server_name_list.extend_from_slice(host.as_bytes());
+
+			let mut ext_data = (server_name_list.len() as u16).to_be_bytes().to_vec();
+			ext_data.extend_from_slice(&server_name_list);
+
+			extensions.extend_from_slice(&[0x00, 0x00]); // extension type: server_name
+			extensions.extend_from_slice(&(ext_data.len() as u16).to_be_bytes());
+			extensions.extend_from_slice(&ext_data);
+		}
+
+		let mut hello = vec![0x03, 0x03]; // client_version: TLS 1.2
+		hello.extend_from_slice(&[0u8; 32]); // random
+		hello.push(0); // session_id_len
+		hello.extend_from_slice(&[0x00, 0x02]); // cipher_suites_len
+		hello.extend_from_slice(&[0x00, 0x2f]); // one cipher suite
+		hello.push(1); // compression_methods_len
+		hello.push(0); // compression_method: null
+		hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+		hello.extend_from_slice(&extensions);
+
+		let mut body = vec![0x01]; // msg type: ClientHello
+		let hello_len = hello.len() as u32;
+		body.extend_from_slice(&[(hello_len >> 16) as u8, (hello_len >> 8) as u8, hello_len as u8]);
+		body.extend_from_slice(&hello);
+
+		let mut record = vec![0x16, 0x03, 0x01]; // content type: handshake, record version
+		record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+		record.extend_from_slice(&body);
+		record
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn parse_client_hello_sni_finds_the_server_name() {
+		let record = client_hello(Some("example.com"));
+		assert_eq!(
+			parse_client_hello_sni(&record),
+			SniParse::Found("example.com".to_string())
+		);
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn parse_client_hello_sni_is_not_found_without_an_sni_extension() {
+		let record = client_hello(None);
+		assert_eq!(parse_client_hello_sni(&record), SniParse::NotFound);
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn parse_client_hello_sni_is_not_found_for_non_handshake_records() {
+		assert_eq!(
+			parse_client_hello_sni(b"GET / HTTP/1.1\r\n"),
+			SniParse::NotFound
+		);
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn parse_client_hello_sni_is_incomplete_for_a_truncated_record() {
+		let record = client_hello(Some("example.com"));
+		assert_eq!(
+			parse_client_hello_sni(&record[..record.len() - 5]),
+			SniParse::Incomplete
+		);
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn parse_client_hello_sni_is_incomplete_for_a_bare_record_header() {
+		assert_eq!(parse_client_hello_sni(&[0x16, 0x03, 0x01]), SniParse::Incomplete);
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn peek_sni_finds_the_server_name_over_a_real_socket() {
+		use std::io::Write;
+		use std::net::{TcpListener, TcpStream as StdTcpStream};
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let record = client_hello(Some("example.com"));
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(&record).unwrap();
+
+		let (server, _) = listener.accept().unwrap();
+		assert_eq!(peek_sni(&server).unwrap(), Some("example.com".to_string()));
+
+		// Nothing was consumed from the socket - the real handshake still
+		// sees the whole ClientHello.
+		let mut buf = vec![0u8; record.len()];
+		server.peek(&mut buf).unwrap();
+		assert_eq!(buf, record);
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn peek_sni_waits_out_a_client_hello_split_across_several_writes() {
+		use std::io::Write;
+		use std::net::{TcpListener, TcpStream as StdTcpStream};
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let record = client_hello(Some("example.com"));
+		let mut client = StdTcpStream::connect(addr).unwrap();
+
+		let split_at = record.len() - 5;
+		client.write_all(&record[..split_at]).unwrap();
+
+		let sender = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(20));
+			client.write_all(&record[split_at..]).unwrap();
+		});
+
+		let (server, _) = listener.accept().unwrap();
+		assert_eq!(peek_sni(&server).unwrap(), Some("example.com".to_string()));
+
+		sender.join().unwrap();
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn peek_sni_is_none_for_a_non_tls_client() {
+		use std::io::Write;
+		use std::net::{TcpListener, TcpStream as StdTcpStream};
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+		let (server, _) = listener.accept().unwrap();
+		assert_eq!(peek_sni(&server).unwrap(), None);
+	}
+
+	#[test]
+	fn connection_guard_counts_tokens_and_untracks_on_drop() {
+		let guard = ConnectionGuard::new();
+		assert_eq!(guard.active(), 0);
+
+		let a = guard.track();
+		let b = guard.track();
+		assert_eq!(guard.active(), 2);
+
+		drop(a);
+		assert_eq!(guard.active(), 1);
+
+		drop(b);
+		assert_eq!(guard.active(), 0);
+	}
 }
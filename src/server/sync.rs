@@ -1,12 +1,26 @@
 //! Provides an implementation of a WebSocket server
 #[cfg(feature = "sync-ssl")]
 use native_tls::{TlsAcceptor, TlsStream};
-use server::upgrade::sync::{Buffer, IntoWs, Upgrade};
+use client::sync::Client;
+use server::upgrade::sync::{into_ws_with_versions, Buffer, Upgrade};
 pub use server::upgrade::{HyperIntoWsError, Request};
-use server::{InvalidConnection, NoTlsAcceptor, OptionalTlsAcceptor, WsServer};
+use server::{
+	ConnectionEvent, ConnectionGuard, ConnectionToken, DrainHandle, HandshakeLimiter,
+	InvalidConnection, NoTlsAcceptor, OptionalTlsAcceptor, RejectReason, RunHandle, ServerStats,
+	ShutdownHandle, WeakHandle, WsServer,
+};
+#[cfg(feature = "sync-ssl")]
+use server::SniTlsAcceptor;
+use hyper::status::StatusCode;
+use socket2::{Domain, Socket, Type};
 use std::convert::Into;
 use std::io;
 use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "async")]
 use server::async;
@@ -28,6 +42,262 @@ pub type AcceptResult<S> = Result<Upgrade<S>, InvalidConnection<S, Buffer>>;
 /// a WebSocketClient, so if needed, an alternative server implementation can be used.
 pub type Server<S> = WsServer<S, TcpListener>;
 
+/// The settings that make up a `WsServer`, without a bound listener.
+///
+/// `WsServer` ties its configuration to a single listening socket, so
+/// spinning up several listeners that share the same timeouts, limits and
+/// TLS acceptor means repeating all that setup. Build a `ServerConfig` once
+/// and call `bind` as many times as needed instead.
+///
+/// ```rust,no_run
+/// use websocket::sync::server::ServerConfig;
+///
+/// let config = ServerConfig::new().accepted_versions(&[13]);
+///
+/// let a = config.clone().bind("127.0.0.1:8080").unwrap();
+/// let b = config.bind("127.0.0.1:8081").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ServerConfig<S>
+where
+	S: OptionalTlsAcceptor,
+{
+	ssl_acceptor: S,
+	accepted_versions: Vec<u8>,
+	tcp_nodelay: bool,
+	tcp_user_timeout: Option<Duration>,
+	accept_timeout: Option<Duration>,
+	server_header: Option<String>,
+	socket_buffer_sizes: Option<(usize, usize)>,
+	max_pending_handshakes: Option<usize>,
+	switching_protocols_reason: Option<String>,
+	allowed_paths: Option<Vec<String>>,
+	required_protocols: Option<Vec<String>>,
+	rewrite_request: Option<Arc<dyn Fn(&mut Request) + Send + Sync>>,
+	overload_response: (StatusCode, Option<u32>),
+}
+
+impl ServerConfig<NoTlsAcceptor> {
+	/// Create a new, non-secure server configuration. Defaults to accepting
+	/// only `Sec-WebSocket-Version: 13`.
+	pub fn new() -> Self {
+		ServerConfig {
+			ssl_acceptor: NoTlsAcceptor,
+			accepted_versions: vec![13],
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			server_header: None,
+			socket_buffer_sizes: None,
+			max_pending_handshakes: None,
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+		}
+	}
+
+	/// Bind this configuration to a socket address, producing a `Server`
+	/// ready to `accept` connections.
+	pub fn bind<A: ToSocketAddrs>(self, addr: A) -> io::Result<Server<NoTlsAcceptor>> {
+		Ok(Server {
+			listener: TcpListener::bind(&addr)?,
+			ssl_acceptor: self.ssl_acceptor,
+			accepted_versions: self.accepted_versions,
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: self.tcp_nodelay,
+			tcp_user_timeout: self.tcp_user_timeout,
+			accept_timeout: self.accept_timeout,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: self.server_header,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::with_limit(self.max_pending_handshakes)),
+			switching_protocols_reason: self.switching_protocols_reason,
+			allowed_paths: self.allowed_paths,
+			required_protocols: self.required_protocols,
+			rewrite_request: self.rewrite_request,
+			overload_response: self.overload_response,
+			on_accept: None,
+			socket_config: None,
+		})
+	}
+}
+
+impl Default for ServerConfig<NoTlsAcceptor> {
+	fn default() -> Self {
+		ServerConfig::new()
+	}
+}
+
+#[cfg(feature = "sync-ssl")]
+impl ServerConfig<TlsAcceptor> {
+	/// Create a new secure server configuration from a `TlsAcceptor`.
+	/// Defaults to accepting only `Sec-WebSocket-Version: 13`.
+	pub fn with_tls(acceptor: TlsAcceptor) -> Self {
+		ServerConfig {
+			ssl_acceptor: acceptor,
+			accepted_versions: vec![13],
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			server_header: None,
+			socket_buffer_sizes: None,
+			max_pending_handshakes: None,
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+		}
+	}
+
+	/// Bind this configuration to a socket address, producing a `Server`
+	/// ready to `accept` secure connections.
+	pub fn bind<A: ToSocketAddrs>(self, addr: A) -> io::Result<Server<TlsAcceptor>> {
+		Ok(Server {
+			listener: TcpListener::bind(&addr)?,
+			ssl_acceptor: self.ssl_acceptor,
+			accepted_versions: self.accepted_versions,
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: self.tcp_nodelay,
+			tcp_user_timeout: self.tcp_user_timeout,
+			accept_timeout: self.accept_timeout,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: self.server_header,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::with_limit(self.max_pending_handshakes)),
+			switching_protocols_reason: self.switching_protocols_reason,
+			allowed_paths: self.allowed_paths,
+			required_protocols: self.required_protocols,
+			rewrite_request: self.rewrite_request,
+			overload_response: self.overload_response,
+			on_accept: None,
+			socket_config: None,
+		})
+	}
+}
+
+impl<S> ServerConfig<S>
+where
+	S: OptionalTlsAcceptor,
+{
+	/// Configure the set of `Sec-WebSocket-Version` values servers bound
+	/// from this configuration will accept. See `WsServer::accepted_versions`.
+	pub fn accepted_versions(mut self, versions: &[u8]) -> Self {
+		self.accepted_versions = versions.to_vec();
+		self
+	}
+
+	/// Whether `TCP_NODELAY` (disabling Nagle's algorithm) is set on every
+	/// stream accepted by servers bound from this configuration. See
+	/// `WsServer::tcp_nodelay`.
+	pub fn tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+		self.tcp_nodelay = tcp_nodelay;
+		self
+	}
+
+	/// Set `TCP_USER_TIMEOUT` on every stream accepted by servers bound
+	/// from this configuration, or `None` to leave the kernel default in
+	/// place. See `WsServer::tcp_user_timeout`.
+	pub fn tcp_user_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.tcp_user_timeout = timeout;
+		self
+	}
+
+	/// Read timeout applied to every accepted stream for just the handshake
+	/// phase, or `None` (the default) to let it block indefinitely. See
+	/// `WsServer::set_accept_timeout`.
+	pub fn accept_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.accept_timeout = timeout;
+		self
+	}
+
+	/// Set the `Server` header value sent on every handshake response (both
+	/// accepted and rejected) by servers bound from this configuration, or
+	/// `None` to omit it. See `WsServer::server_header`.
+	pub fn server_header<H: Into<String>>(mut self, server_header: Option<H>) -> Self {
+		self.server_header = server_header.map(Into::into);
+		self
+	}
+
+	/// Set `SO_SNDBUF`/`SO_RCVBUF` on every stream accepted by servers bound
+	/// from this configuration. See `WsServer::socket_buffer_sizes`.
+	pub fn socket_buffer_sizes(mut self, send: usize, recv: usize) -> Self {
+		self.socket_buffer_sizes = Some((send, recv));
+		self
+	}
+
+	/// Cap how many handshakes servers bound from this configuration will
+	/// read and parse at once, or `None` for no cap (the default). See
+	/// `WsServer::max_pending_handshakes`.
+	pub fn max_pending_handshakes(mut self, limit: Option<usize>) -> Self {
+		self.max_pending_handshakes = limit;
+		self
+	}
+
+	/// Override the reason phrase sent on every accepted handshake's
+	/// `101 Switching Protocols` response by servers bound from this
+	/// configuration, or `None` to use hyper's canonical phrase (the
+	/// default). See `WsServer::switching_protocols_reason`.
+	pub fn switching_protocols_reason<H: Into<String>>(mut self, reason: Option<H>) -> Self {
+		self.switching_protocols_reason = reason.map(Into::into);
+		self
+	}
+
+	/// Restrict the paths servers bound from this configuration will accept
+	/// handshakes on, or `None` for no restriction (the default). See
+	/// `WsServer::allowed_paths`.
+	pub fn allowed_paths(mut self, paths: &[&str]) -> Self {
+		self.allowed_paths = Some(paths.iter().map(|p| p.to_string()).collect());
+		self
+	}
+
+	/// Require that every handshake accepted by servers bound from this
+	/// configuration offer `protocol` in its `Sec-WebSocket-Protocol`
+	/// header, auto-rejecting (`400 Bad Request`, no protocol header) any
+	/// handshake that doesn't and auto-selecting (echoing) `protocol` back
+	/// on one that does. Call this more than once to require one of
+	/// several protocols - any single match is enough. `None` (the
+	/// default) leaves subprotocol selection entirely to the application,
+	/// via `WsUpgrade::use_protocol`. See `WsServer::require_protocol`.
+	pub fn require_protocol<P: Into<String>>(mut self, protocol: P) -> Self {
+		self
+			.required_protocols
+			.get_or_insert_with(Vec::new)
+			.push(protocol.into());
+		self
+	}
+
+	/// Run `rewrite` against every handshake request servers bound from this
+	/// configuration receive, right after it's parsed but before this crate
+	/// validates it - an escape hatch for interop with a misbehaving client,
+	/// e.g. one that omits a required header such as `Connection: Upgrade`.
+	/// `None` (the default) validates the request exactly as the client sent
+	/// it. See `WsServer::rewrite_request`.
+	pub fn rewrite_request<F>(mut self, rewrite: F) -> Self
+	where
+		F: Fn(&mut Request) + Send + Sync + 'static,
+	{
+		self.rewrite_request = Some(Arc::new(rewrite));
+		self
+	}
+
+	/// The status code and optional `Retry-After` seconds that
+	/// `WsUpgrade::reject_unavailable` sends on servers bound from this
+	/// configuration, for turning an application-level overload check (a
+	/// connection cap, a rate limiter, ...) into a clean HTTP response
+	/// instead of an `InvalidConnection` with nothing written to the
+	/// stream. Defaults to `(StatusCode::ServiceUnavailable, None)`. See
+	/// `WsServer::overload_response`.
+	pub fn overload_response(mut self, status: StatusCode, retry_after_secs: Option<u32>) -> Self {
+		self.overload_response = (status, retry_after_secs);
+		self
+	}
+}
+
 /// Synchronous methods for creating a server and accepting incoming connections.
 impl<S> WsServer<S, TcpListener>
 where
@@ -38,6 +308,329 @@ where
 		self.listener.local_addr()
 	}
 
+	/// Configure the set of `Sec-WebSocket-Version` values this server will
+	/// accept from a client. Defaults to `[13]`, the only version defined by
+	/// RFC6455.
+	///
+	/// When a client asks for a version that is not in this list, `accept`
+	/// responds with `426 Upgrade Required` and a `Sec-WebSocket-Version`
+	/// header advertising the versions given here, as required by RFC6455
+	/// section 4.4.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.accepted_versions(&[13]);
+	/// ```
+	pub fn accepted_versions(&mut self, versions: &[u8]) -> &mut Self {
+		self.accepted_versions = versions.to_vec();
+		self
+	}
+
+	/// Set `TCP_NODELAY` (disabling Nagle's algorithm) on every stream this
+	/// server accepts from now on, including handshake traffic, so small
+	/// frames aren't delayed waiting to be coalesced. Defaults to `false`,
+	/// leaving the OS default in place. Useful for latency-sensitive
+	/// services where you'd otherwise call `TcpStream::set_nodelay` on every
+	/// connection by hand.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.tcp_nodelay(true);
+	/// ```
+	pub fn tcp_nodelay(&mut self, tcp_nodelay: bool) -> &mut Self {
+		self.tcp_nodelay = tcp_nodelay;
+		self
+	}
+
+	/// Set `TCP_USER_TIMEOUT` on every stream this server accepts from now
+	/// on: the maximum time transmitted data may go unacknowledged before
+	/// the kernel gives up on the connection and fails pending/future
+	/// writes, which catches an unreachable peer (power loss, a yanked
+	/// cable, ...) far sooner than TCP's own retransmission timeout would.
+	/// `None` (the default) leaves the kernel default in place. Linux-only;
+	/// a no-op elsewhere, since there is no equivalent socket option.
+	///
+	/// ```rust,no_run
+	/// use std::time::Duration;
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.tcp_user_timeout(Some(Duration::from_secs(30)));
+	/// ```
+	pub fn tcp_user_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+		self.tcp_user_timeout = timeout;
+		self
+	}
+
+	/// Read timeout applied to an accepted stream for just the handshake
+	/// phase (the TLS handshake, if any, and the HTTP upgrade read) that
+	/// `accept_tls` runs, so a client that opens a TCP connection but never
+	/// finishes it can't tie up an accepting thread forever. `None` (the
+	/// default) leaves it blocking indefinitely. Cleared again once a
+	/// handshake succeeds, so it has no effect on the accepted `Client`'s
+	/// own message traffic.
+	///
+	/// Named with a `set_` prefix - unlike this server's other knobs - to
+	/// avoid colliding with `WsServer::accept_timeout`, the unrelated
+	/// per-call timeout on `accept` itself.
+	///
+	/// ```rust,no_run
+	/// use std::time::Duration;
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.set_accept_timeout(Some(Duration::from_secs(10)));
+	/// ```
+	pub fn set_accept_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+		self.accept_timeout = timeout;
+		self
+	}
+
+	/// Set the `Server` header value this server sends on every handshake
+	/// response from now on, both accepted (`101 Switching Protocols`) and
+	/// rejected ones, or `None` to omit it. Defaults to `None`, leaving out
+	/// the header entirely, letting deployments hide or rebrand what this
+	/// crate would otherwise reveal about itself.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.server_header(Some("my-app/1.0"));
+	/// ```
+	pub fn server_header<H: Into<String>>(&mut self, server_header: Option<H>) -> &mut Self {
+		self.server_header = server_header.map(Into::into);
+		self
+	}
+
+	/// Override the reason phrase this server sends on every accepted
+	/// handshake's `101 Switching Protocols` response from now on, or
+	/// `None` to use hyper's canonical phrase. Defaults to `None`. The
+	/// status code itself is never affected - this only changes the text
+	/// after it on the response line.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.switching_protocols_reason(Some("Upgraded"));
+	/// ```
+	pub fn switching_protocols_reason<H: Into<String>>(&mut self, reason: Option<H>) -> &mut Self {
+		self.switching_protocols_reason = reason.map(Into::into);
+		self
+	}
+
+	/// Restrict the paths this server accepts handshakes on from now on, or
+	/// `None` to accept any path. Defaults to `None`. A request for a path
+	/// not in this list is rejected with `404 Not Found` rather than this
+	/// crate's usual `400 Bad Request`, and counted under
+	/// `ServerStats::not_found`.
+	///
+	/// Matching is against `WsUpgrade::path` - the request path with any
+	/// query string stripped - so `allowed_paths(&["/chat"])` also accepts
+	/// `/chat?room=42`.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.allowed_paths(&["/chat", "/admin"]);
+	/// ```
+	pub fn allowed_paths(&mut self, paths: &[&str]) -> &mut Self {
+		self.allowed_paths = Some(paths.iter().map(|p| p.to_string()).collect());
+		self
+	}
+
+	/// Require that every handshake this server accepts from now on offer
+	/// `protocol` in its `Sec-WebSocket-Protocol` header, auto-rejecting
+	/// (`400 Bad Request`, no protocol header) any handshake that doesn't
+	/// and auto-selecting (echoing) `protocol` back on one that does. Call
+	/// this more than once to require one of several protocols - any
+	/// single match is enough. Defaults to `None`, leaving subprotocol
+	/// selection entirely to the application via `WsUpgrade::use_protocol`.
+	///
+	/// A handshake that offers no `Sec-WebSocket-Protocol` at all is
+	/// treated the same as one offering protocols that don't match: with
+	/// no required protocol configured it's accepted and no protocol
+	/// header is sent back (this server never imposes a protocol the
+	/// client didn't ask for), but once a required protocol is configured,
+	/// offering none is exactly as unacceptable as offering the wrong one.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.require_protocol("json.v1");
+	/// ```
+	pub fn require_protocol<P: Into<String>>(&mut self, protocol: P) -> &mut Self {
+		self
+			.required_protocols
+			.get_or_insert_with(Vec::new)
+			.push(protocol.into());
+		self
+	}
+
+	/// Run `rewrite` against every handshake request this server receives
+	/// from now on, right after it's parsed but before this crate validates
+	/// it - an escape hatch for interop with a misbehaving client, e.g. one
+	/// that omits a required header such as `Connection: Upgrade`. Defaults
+	/// to `None`, validating the request exactly as the client sent it.
+	///
+	/// ```rust,no_run
+	/// # extern crate unicase;
+	/// use websocket::sync::Server;
+	/// use websocket::header::{Connection, ConnectionOption};
+	/// use unicase::UniCase;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.rewrite_request(|request| {
+	/// 	if !request.headers.has::<Connection>() {
+	/// 		request
+	/// 			.headers
+	/// 			.set(Connection(vec![ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_owned()))]));
+	/// 	}
+	/// });
+	/// ```
+	pub fn rewrite_request<F>(&mut self, rewrite: F) -> &mut Self
+	where
+		F: Fn(&mut Request) + Send + Sync + 'static,
+	{
+		self.rewrite_request = Some(Arc::new(rewrite));
+		self
+	}
+
+	/// The status code and optional `Retry-After` seconds that
+	/// `WsUpgrade::reject_unavailable` sends from now on, for turning an
+	/// application-level overload check (a connection cap, a rate limiter,
+	/// ...) into a clean HTTP response instead of an `InvalidConnection`
+	/// with nothing written to the stream. Defaults to
+	/// `(StatusCode::ServiceUnavailable, None)`.
+	///
+	/// ```rust
+	/// # extern crate hyper;
+	/// # extern crate websocket;
+	/// use websocket::sync::Server;
+	/// use hyper::status::StatusCode;
+	///
+	/// # fn main() {
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.overload_response(StatusCode::ServiceUnavailable, Some(30));
+	/// # }
+	/// ```
+	pub fn overload_response(&mut self, status: StatusCode, retry_after_secs: Option<u32>) -> &mut Self {
+		self.overload_response = (status, retry_after_secs);
+		self
+	}
+
+	/// Set `SO_SNDBUF`/`SO_RCVBUF` on every stream this server accepts from
+	/// now on, for high-throughput connections that want bigger kernel
+	/// buffers than the OS default. The kernel is free to clamp or double
+	/// whatever is asked for (as Linux does); a value it rejects outright is
+	/// tolerated too - `accept_tcp` ignores the error rather than failing
+	/// the connection over it. Defaults to `None`, leaving the OS defaults
+	/// in place.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.socket_buffer_sizes(256 * 1024, 256 * 1024);
+	/// ```
+	pub fn socket_buffer_sizes(&mut self, send: usize, recv: usize) -> &mut Self {
+		self.socket_buffer_sizes = Some((send, recv));
+		self
+	}
+
+	/// Stop accepting new connections for a rolling deploy, while letting
+	/// already-accepted connections keep running until they close on their
+	/// own or `deadline` passes.
+	///
+	/// From the moment this is called, `accept` (and therefore the server's
+	/// `Iterator` impl) returns an `Io` error of kind `ConnectionRefused`
+	/// for every new connection instead of completing the handshake (a call
+	/// already blocked inside `accept` when this is called fails with
+	/// `HyperIntoWsError::ShutDown` instead, once a connection wakes it). The
+	/// returned `DrainHandle` lets you poll whether `deadline` has elapsed,
+	/// and `DrainHandle::cancel` resumes normal accepting if the rollout is
+	/// aborted. This crate does not track how many connections are still
+	/// open; combine the deadline with your own live-connection counter to
+	/// know when it is safe to stop the process.
+	pub fn drain(&self, deadline: Duration) -> DrainHandle {
+		self.draining.store(true, Ordering::SeqCst);
+		DrainHandle::new(deadline, self.draining.clone())
+	}
+
+	/// Whether this server is currently draining (see `WsServer::drain`).
+	pub fn is_draining(&self) -> bool {
+		self.draining.load(Ordering::SeqCst)
+	}
+
+	/// A non-owning handle that a supervisor can hold onto without keeping
+	/// this server alive: `WeakHandle::shutdown` puts the server into the
+	/// same draining state as `drain`, but is a no-op once every `WsServer`
+	/// this handle was created from has been dropped, and `WeakHandle::is_alive`
+	/// lets the supervisor check that before bothering to call it.
+	///
+	/// ```rust,no_run
+	/// use websocket::sync::Server;
+	///
+	/// let server = Server::bind("127.0.0.1:0").unwrap();
+	/// let handle = server.weak_handle();
+	/// assert!(handle.is_alive());
+	/// handle.shutdown();
+	/// assert!(server.is_draining());
+	/// ```
+	pub fn weak_handle(&self) -> WeakHandle {
+		WeakHandle::new(Arc::downgrade(&self.draining))
+	}
+
+	/// Like `drain`, but also able to interrupt a thread already blocked
+	/// inside `accept`: `ShutdownHandle::shutdown` sets the same draining
+	/// flag, then connects to this server's own listening address to wake
+	/// up a pending `accept` call, which fails with
+	/// `HyperIntoWsError::ShutDown` instead of blocking past the shutdown
+	/// signal. Use this (rather than `drain`/`weak_handle`) for a server run
+	/// on its own accept loop (e.g. `serve`, or a hand-rolled `for upgrade in
+	/// &mut server`) that needs to react to something like `SIGINT` without
+	/// waiting on another connection attempt to notice.
+	///
+	/// ```no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// let handle = server.shutdown_handle().unwrap();
+	///
+	/// // ... on another thread, once it's time to stop ...
+	/// handle.shutdown().unwrap();
+	/// ```
+	pub fn shutdown_handle(&self) -> io::Result<ShutdownHandle> {
+		let addr = self.listener.local_addr()?;
+		Ok(ShutdownHandle::new(self.draining.clone(), addr))
+	}
+
+	/// Ties `drain` together with a `ConnectionGuard` you've been handing a
+	/// `ConnectionToken` to on every accepted connection: stops accepting
+	/// new connections, then blocks until `guard` reports no live
+	/// connections or `timeout` elapses, whichever comes first.
+	///
+	/// Returns how many connections `guard` still reported as active when
+	/// this gave up, so you can log it. As with `drain`, this crate doesn't
+	/// own your `Client`s, so a non-zero return means those connections are
+	/// still running and need closing through whatever channel you are
+	/// already using to manage them (a shutdown flag, dropping their
+	/// socket, ...) - this call will not do that for you.
+	pub fn shutdown_graceful(&self, guard: &ConnectionGuard, timeout: Duration) -> usize {
+		let handle = self.drain(timeout);
+		while guard.active() > 0 && !handle.expired() {
+			thread::sleep(Duration::from_millis(10));
+		}
+		guard.active()
+	}
+
 	/// Changes whether the Server is in nonblocking mode.
 	/// NOTE: It is strongly encouraged to use the `websocket::async` module instead
 	/// of this. It provides high level APIs for creating asynchronous servers.
@@ -84,111 +677,735 @@ where
 	/// This will only work if the stream used for this server `S` already implements
 	/// `AsyncRead + AsyncWrite`. Useful if you would like some blocking things to happen
 	/// at the start of your server.
-	#[cfg(feature = "async")]
-	pub fn into_async(self, handle: &Handle) -> io::Result<async::Server<S>> {
-		Ok(WsServer {
-			listener: AsyncTcpListener::from_std(self.listener, handle)?,
-			ssl_acceptor: self.ssl_acceptor,
-		})
-	}
-}
-
-/// Synchronous methods for creating an SSL server and accepting incoming connections.
-#[cfg(feature = "sync-ssl")]
-impl WsServer<TlsAcceptor, TcpListener> {
-	/// Bind this Server to this socket, utilising the given SslContext
 	///
-	/// # Secure Servers
+	/// This carries `ssl_acceptor` (and every other setting except the
+	/// `on_accept` and `set_socket_config` callbacks, which start unset on the
+	/// async server) straight over,
+	/// so a server built with `bind_secure` keeps accepting TLS connections
+	/// once it's async - the resulting `async::Server<TlsAcceptor>`'s
+	/// `incoming` already runs every accepted socket through the same
+	/// `TlsAcceptor` via `tokio-tls` before the websocket handshake, exactly
+	/// like `Server<TlsAcceptor>::incoming` does on the synchronous side.
+	///
 	/// ```no_run
+	/// extern crate tokio;
 	/// extern crate websocket;
 	/// extern crate native_tls;
 	/// # fn main() {
-	/// use std::thread;
 	/// use std::io::Read;
 	/// use std::fs::File;
-	/// use websocket::Message;
+	/// use websocket::async::futures::{Future, Stream};
 	/// use websocket::sync::Server;
 	/// use native_tls::{Identity, TlsAcceptor};
 	///
-	/// // In this example we retrieve our keypair and certificate chain from a PKCS #12 archive,
-	/// // but but they can also be retrieved from, for example, individual PEM- or DER-formatted
-	/// // files. See the documentation for the `PKey` and `X509` types for more details.
 	/// let mut file = File::open("identity.pfx").unwrap();
 	/// let mut pkcs12 = vec![];
 	/// file.read_to_end(&mut pkcs12).unwrap();
 	/// let pkcs12 = Identity::from_pkcs12(&pkcs12, "hacktheplanet").unwrap();
-	///
 	/// let acceptor = TlsAcceptor::builder(pkcs12).build().unwrap();
 	///
+	/// let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+	/// let executor = runtime.executor();
 	/// let server = Server::bind_secure("127.0.0.1:1234", acceptor).unwrap();
+	/// let server = server.into_async(&runtime.reactor()).unwrap();
 	///
-	/// for connection in server.filter_map(Result::ok) {
-	///     // Spawn a new thread for each connection.
-	///     thread::spawn(move || {
-	/// 		    let mut client = connection.accept().unwrap();
-	///
-	/// 		    let message = Message::text("Hello, client!");
-	/// 		    let _ = client.send_message(&message);
-	///
-	/// 		    // ...
-	///     });
-	/// }
+	/// let handshake_executor = executor.clone();
+	/// let wss_connections = server.incoming().for_each(move |(upgrade, _addr)| {
+	///     let accepted = upgrade.accept().map(|_client| ()).map_err(|_| ());
+	///     handshake_executor.spawn(accepted);
+	///     Ok(())
+	/// });
+	/// executor.spawn(wss_connections.map_err(|_| ()));
 	/// # }
 	/// ```
-	pub fn bind_secure<A>(addr: A, acceptor: TlsAcceptor) -> io::Result<Self>
-	where
-		A: ToSocketAddrs,
-	{
-		Ok(Server {
-			listener: TcpListener::bind(&addr)?,
-			ssl_acceptor: acceptor,
+	#[cfg(feature = "async")]
+	pub fn into_async(self, handle: &Handle) -> io::Result<async::Server<S>> {
+		Ok(WsServer {
+			listener: AsyncTcpListener::from_std(self.listener, handle)?,
+			ssl_acceptor: self.ssl_acceptor,
+			accepted_versions: self.accepted_versions,
+			draining: self.draining,
+			tcp_nodelay: self.tcp_nodelay,
+			tcp_user_timeout: self.tcp_user_timeout,
+			accept_timeout: self.accept_timeout,
+			connection_ids: self.connection_ids,
+			server_header: self.server_header,
+			stats: self.stats,
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			max_pending_handshakes: self.max_pending_handshakes,
+			switching_protocols_reason: self.switching_protocols_reason,
+			allowed_paths: self.allowed_paths,
+			required_protocols: self.required_protocols,
+			rewrite_request: self.rewrite_request,
+			overload_response: self.overload_response,
+			on_accept: None,
+			socket_config: None,
 		})
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
-	pub fn accept(&mut self) -> AcceptResult<TlsStream<TcpStream>> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
-			Err(e) => {
-				return Err(InvalidConnection {
+	pub fn accept(&mut self) -> AcceptResult<S::Output> {
+		let stream = self.accept_tcp()?;
+		self.accept_tls(stream)
+	}
+
+	/// Just the TCP half of `accept`: wait for and accept an incoming TCP
+	/// connection, without running `ssl_acceptor`'s handshake.
+	///
+	/// Pairing this with `accept_tls` lets you move the (comparatively
+	/// expensive) TLS handshake off the accept loop, for example onto a
+	/// thread pool, while still reusing this server's acceptor. The returned
+	/// `TcpStream` already carries its own peer address (`peer_addr()`), so
+	/// there's nothing extra to thread through to whichever thread calls
+	/// `accept_tls` later. In a non-blocking server (`set_nonblocking`),
+	/// `accept_tcp` surfaces the same `WouldBlock` error on its own that
+	/// `accept` does, without ever touching `ssl_acceptor` or the handshake.
+	pub fn accept_tcp(&mut self) -> Result<TcpStream, InvalidConnection<S::Output, Buffer>> {
+		if self.is_draining() {
+			let error = HyperIntoWsError::Io(io::Error::new(
+				io::ErrorKind::ConnectionRefused,
+				"server is draining, not accepting new connections",
+			));
+			self.stats.record(RejectReason::classify(&error));
+			return Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error,
+				peer_addr: None,
+			});
+		}
+
+		let stream = self.listener.accept().map(|s| s.0).map_err(|e| {
+			let error = HyperIntoWsError::Io(e);
+			self.stats.record(RejectReason::classify(&error));
+			InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error,
+				peer_addr: None,
+			}
+		})?;
+
+		// `shutdown_handle`'s wakeup connection (or a real connection that
+		// raced it) lands here: this `accept` call was already blocked
+		// inside the kernel by the time draining started, so the check
+		// above let it through. Catching it here, rather than leaving it to
+		// fail the handshake read a few lines later, gives callers a
+		// distinguishable reason it stopped rather than a generic I/O error.
+		if self.is_draining() {
+			let error = HyperIntoWsError::ShutDown;
+			self.stats.record(RejectReason::classify(&error));
+			return Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error,
+				peer_addr: stream.peer_addr().ok(),
+			});
+		}
+
+		if self.tcp_nodelay {
+			stream.set_nodelay(true).map_err(|e| {
+				let error = HyperIntoWsError::Io(e);
+				self.stats.record(RejectReason::classify(&error));
+				let peer_addr = stream.peer_addr().ok();
+				InvalidConnection {
 					stream: None,
 					parsed: None,
 					buffer: None,
-					error: HyperIntoWsError::Io(e),
-				});
-			}
+					error,
+					peer_addr,
+				}
+			})?;
+		}
+
+		if let Some((send, recv)) = self.socket_buffer_sizes {
+			set_socket_buffer_sizes(&stream, send, recv);
+		}
+
+		if self.tcp_user_timeout.is_some() {
+			let _ = ::stream::sync::set_tcp_user_timeout(&stream, self.tcp_user_timeout);
+		}
+
+		if let Some(ref config) = self.socket_config {
+			config(&stream).map_err(|e| {
+				let error = HyperIntoWsError::Io(e);
+				self.stats.record(RejectReason::classify(&error));
+				let peer_addr = stream.peer_addr().ok();
+				InvalidConnection {
+					stream: None,
+					parsed: None,
+					buffer: None,
+					error,
+					peer_addr,
+				}
+			})?;
+		}
+
+		Ok(stream)
+	}
+
+	/// Just the non-TCP half of `accept`: given a TCP stream (typically from
+	/// `accept_tcp`), run this server's `ssl_acceptor` over it (a no-op for
+	/// `NoTlsAcceptor`) and then read and validate the websocket handshake.
+	///
+	/// `&self` (not `&mut self`) is deliberate: unlike `accept_tcp`, this
+	/// doesn't touch the listener, so it's fine to call from a worker thread
+	/// while the accept loop keeps calling `accept_tcp` on `&mut self`
+	/// elsewhere - that's the whole point of splitting the two.
+	pub fn accept_tls(&self, stream: TcpStream) -> AcceptResult<S::Output> {
+		let started = Instant::now();
+		let peer_addr = stream.peer_addr().ok();
+
+		// `set_read_timeout` is a socket option, so it survives `stream`
+		// being wrapped (by `ssl_acceptor`) or read from through a
+		// different handle - keep a duplicate around purely to clear it
+		// again once the handshake succeeds, without needing `S::Output`
+		// to offer any way back to the raw `TcpStream`.
+		let clear_accept_timeout = if self.accept_timeout.is_some() {
+			let _ = stream.set_read_timeout(self.accept_timeout);
+			stream.try_clone().ok()
+		} else {
+			None
 		};
 
 		let stream = match self.ssl_acceptor.accept(stream) {
 			Ok(s) => s,
-			Err(err) => {
+			Err(e) => {
+				self.stats.record(RejectReason::classify(&e));
 				return Err(InvalidConnection {
 					stream: None,
 					parsed: None,
 					buffer: None,
-					error: io::Error::new(io::ErrorKind::Other, err).into(),
+					error: e,
+					peer_addr,
 				});
 			}
 		};
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
-			Err((s, r, b, e)) => Err(InvalidConnection {
-				stream: Some(s),
-				parsed: r,
-				buffer: b,
-				error: e,
-			}),
-		}
-	}
-}
+		let _permit = self.max_pending_handshakes.acquire();
+		let rewrite_request = self
+			.rewrite_request
+			.as_ref()
+			.map(|f| f.as_ref() as &(dyn Fn(&mut Request) + Send + Sync));
+		match into_ws_with_versions(
+			stream,
+			&self.accepted_versions,
+			self.next_connection_id(),
+			rewrite_request,
+		) {
+			Ok(mut u) => {
+				if let Some(ref raw) = clear_accept_timeout {
+					let _ = raw.set_read_timeout(None);
+				}
 
-#[cfg(feature = "sync-ssl")]
-impl Iterator for WsServer<TlsAcceptor, TcpListener> {
-	type Item = AcceptResult<TlsStream<TcpStream>>;
+				self.apply_server_header(&mut u);
+				self.apply_switching_protocols_reason(&mut u);
+				self.apply_overload_response(&mut u);
 
-	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-		Some(self.accept())
+				if let Some(ref allowed_paths) = self.allowed_paths {
+					let path = u.path();
+					if !allowed_paths.iter().any(|allowed| allowed == &path) {
+						let error = HyperIntoWsError::PathNotAllowed { path };
+						self.stats.record(RejectReason::classify(&error));
+						return match u.reject_not_found() {
+							Ok(s) => Err(InvalidConnection {
+								stream: Some(s),
+								parsed: None,
+								buffer: None,
+								error,
+								peer_addr,
+							}),
+							Err((s, io_err)) => Err(InvalidConnection {
+								stream: Some(s),
+								parsed: None,
+								buffer: None,
+								error: HyperIntoWsError::Io(io_err),
+								peer_addr,
+							}),
+						};
+					}
+				}
+
+				if let Some(ref required_protocols) = self.required_protocols {
+					let offered = u.protocols();
+					match required_protocols.iter().find(|req| offered.contains(req)) {
+						Some(matched) => u = u.use_protocol(matched.clone()),
+						None => {
+							let error = HyperIntoWsError::NoMatchingProtocol;
+							self.stats.record(RejectReason::classify(&error));
+							return match u.reject() {
+								Ok(s) => Err(InvalidConnection {
+									stream: Some(s),
+									parsed: None,
+									buffer: None,
+									error,
+									peer_addr,
+								}),
+								Err((s, io_err)) => Err(InvalidConnection {
+									stream: Some(s),
+									parsed: None,
+									buffer: None,
+									error: HyperIntoWsError::Io(io_err),
+									peer_addr,
+								}),
+							};
+						}
+					}
+				}
+
+				if let Some(ref on_accept) = self.on_accept {
+					on_accept(ConnectionEvent {
+						connection_id: u.connection_id(),
+						peer_addr,
+						handshake_duration: started.elapsed(),
+					});
+				}
+				Ok(u)
+			}
+			Err((s, r, b, e)) => {
+				self.stats.record(RejectReason::classify(&e));
+				Err(InvalidConnection {
+					stream: Some(s),
+					parsed: r,
+					buffer: b,
+					error: e,
+					peer_addr,
+				})
+			}
+		}
+	}
+
+	/// Wait for and accept an incoming WebSocket connection like `accept`,
+	/// but give up once `timeout` elapses without one arriving rather than
+	/// blocking forever. On expiry this surfaces the same `WouldBlock`
+	/// flavoured `InvalidConnection` that a `set_nonblocking(true)` server
+	/// would produce when nothing is pending - check
+	/// `InvalidConnection::is_transient` to tell that apart from a fatal
+	/// accept failure.
+	///
+	/// `SO_RCVTIMEO` isn't honoured by `accept(2)` on every platform, so
+	/// this instead puts the listener in nonblocking mode and polls it in
+	/// short bursts until either a connection shows up or `timeout`
+	/// elapses, putting it back to blocking mode before returning either
+	/// way. This is friendlier than doing the same thing yourself with
+	/// `set_nonblocking` and a tight spin loop for a single-threaded
+	/// server that wants to interleave accepting connections with other
+	/// periodic work.
+	///
+	/// ```no_run
+	/// use websocket::sync::Server;
+	/// use std::time::Duration;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// match server.accept_timeout(Duration::from_millis(100)) {
+	/// 	Ok(wsupgrade) => {
+	/// 		// Do something with the established connection.
+	/// 	}
+	/// 	Err(ref invalid) if invalid.is_transient() => {
+	/// 		// Nobody tried to connect within the timeout, move on.
+	/// 	}
+	/// 	Err(_) => {
+	/// 		// Some other, fatal accept error.
+	/// 	}
+	/// }
+	/// ```
+	pub fn accept_timeout(&mut self, timeout: Duration) -> AcceptResult<S::Output> {
+		const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+		let deadline = Instant::now() + timeout;
+		if self.listener.set_nonblocking(true).is_err() {
+			return self.accept();
+		}
+
+		loop {
+			let result = self.accept();
+			match result {
+				Err(ref err) if err.is_transient() && Instant::now() < deadline => {
+					thread::sleep(POLL_INTERVAL);
+				}
+				_ => {
+					let _ = self.listener.set_nonblocking(false);
+					return result;
+				}
+			}
+		}
+	}
+
+	/// Like iterating over `&self` directly, but caps how many accepted
+	/// connections can be outstanding at once. Each item bundles an
+	/// `AcceptResult` with a `ConnectionToken`; hold onto the token for as
+	/// long as the connection is alive (typically by moving it into the
+	/// handler thread) and `next` won't hand out more than `max` of them
+	/// before one gets dropped.
+	///
+	/// `next` blocks the calling thread once `max` tokens are outstanding,
+	/// rather than shedding the new connection - pair with
+	/// `WsServer::overload_response`/`WsUpgrade::reject_unavailable` first
+	/// if you'd rather respond and move on than wait for a slot to free up.
+	///
+	/// ```no_run
+	/// use std::thread;
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// for (result, token) in server.incoming_limited(50) {
+	/// 	let upgrade = match result {
+	/// 		Ok(upgrade) => upgrade,
+	/// 		Err(_) => continue,
+	/// 	};
+	/// 	thread::spawn(move || {
+	/// 		let _token = token;
+	/// 		let _client = upgrade.accept();
+	/// 		// ... handle the connection; the slot frees up once this
+	/// 		// thread (and `_token` with it) is dropped.
+	/// 	});
+	/// }
+	/// ```
+	pub fn incoming_limited(&mut self, max: usize) -> LimitedIncoming<S> {
+		LimitedIncoming {
+			server: self,
+			guard: ConnectionGuard::new(),
+			max,
+		}
+	}
+}
+
+/// An iterator over the connections being accepted by a `WsServer`,
+/// capping how many may be outstanding at once. See
+/// `WsServer::incoming_limited`.
+pub struct LimitedIncoming<'s, S>
+where
+	S: OptionalTlsAcceptor,
+{
+	server: &'s mut WsServer<S, TcpListener>,
+	guard: ConnectionGuard,
+	max: usize,
+}
+
+impl<'s, S> Iterator for LimitedIncoming<'s, S>
+where
+	S: OptionalTlsAcceptor,
+{
+	type Item = (AcceptResult<S::Output>, ConnectionToken);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+		while self.guard.active() >= self.max {
+			thread::sleep(POLL_INTERVAL);
+		}
+
+		let token = self.guard.track();
+		Some((self.server.accept(), token))
+	}
+}
+
+impl<S> Iterator for WsServer<S, TcpListener>
+where
+	S: OptionalTlsAcceptor,
+{
+	type Item = AcceptResult<S::Output>;
+
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		Some(self.accept())
+	}
+}
+
+/// Synchronous methods for creating an SSL server and accepting incoming connections.
+#[cfg(feature = "sync-ssl")]
+impl WsServer<TlsAcceptor, TcpListener> {
+	/// Bind this Server to this socket, utilising the given SslContext
+	///
+	/// # Secure Servers
+	/// ```no_run
+	/// extern crate websocket;
+	/// extern crate native_tls;
+	/// # fn main() {
+	/// use std::thread;
+	/// use std::io::Read;
+	/// use std::fs::File;
+	/// use websocket::Message;
+	/// use websocket::sync::Server;
+	/// use native_tls::{Identity, TlsAcceptor};
+	///
+	/// // In this example we retrieve our keypair and certificate chain from a PKCS #12 archive,
+	/// // but but they can also be retrieved from, for example, individual PEM- or DER-formatted
+	/// // files. See the documentation for the `PKey` and `X509` types for more details.
+	/// let mut file = File::open("identity.pfx").unwrap();
+	/// let mut pkcs12 = vec![];
+	/// file.read_to_end(&mut pkcs12).unwrap();
+	/// let pkcs12 = Identity::from_pkcs12(&pkcs12, "hacktheplanet").unwrap();
+	///
+	/// let acceptor = TlsAcceptor::builder(pkcs12).build().unwrap();
+	///
+	/// let server = Server::bind_secure("127.0.0.1:1234", acceptor).unwrap();
+	///
+	/// for connection in server.filter_map(Result::ok) {
+	///     // Spawn a new thread for each connection.
+	///     thread::spawn(move || {
+	/// 		    let mut client = connection.accept().unwrap();
+	///
+	/// 		    let message = Message::text("Hello, client!");
+	/// 		    let _ = client.send_message(&message);
+	///
+	/// 		    // ...
+	///     });
+	/// }
+	/// # }
+	/// ```
+	pub fn bind_secure<A>(addr: A, acceptor: TlsAcceptor) -> io::Result<Self>
+	where
+		A: ToSocketAddrs,
+	{
+		Ok(Server {
+			listener: TcpListener::bind(&addr)?,
+			ssl_acceptor: acceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			on_accept: None,
+			socket_config: None,
+		})
+	}
+
+	/// Like `bind_secure`, but around an already-bound, already-listening
+	/// `std::net::TcpListener` instead of having this method bind one - see
+	/// `from_listener` (the non-secure equivalent) for why you'd want that.
+	pub fn from_listener_secure(listener: TcpListener, acceptor: TlsAcceptor) -> io::Result<Self> {
+		Ok(Server {
+			listener,
+			ssl_acceptor: acceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			on_accept: None,
+			socket_config: None,
+		})
+	}
+
+	/// Create a new independently owned handle to the underlying socket,
+	/// sharing the same `TlsAcceptor` - cloning it is cheap, it's just a
+	/// handle to the underlying SSL context. See `WsServer::try_clone` (the
+	/// non-secure equivalent) for the work-sharing pattern this enables:
+	/// several threads each accepting off their own clone of the same
+	/// listener, load-balanced by the kernel. `serve` already does this for
+	/// you.
+	pub fn try_clone(&self) -> io::Result<Self> {
+		let inner = self.listener.try_clone()?;
+		Ok(Server {
+			listener: inner,
+			ssl_acceptor: self.ssl_acceptor.clone(),
+			accepted_versions: self.accepted_versions.clone(),
+			draining: self.draining.clone(),
+			tcp_nodelay: self.tcp_nodelay,
+			tcp_user_timeout: self.tcp_user_timeout,
+			accept_timeout: self.accept_timeout,
+			connection_ids: self.connection_ids.clone(),
+			server_header: self.server_header.clone(),
+			stats: self.stats.clone(),
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			max_pending_handshakes: self.max_pending_handshakes.clone(),
+			switching_protocols_reason: self.switching_protocols_reason.clone(),
+			allowed_paths: self.allowed_paths.clone(),
+			required_protocols: self.required_protocols.clone(),
+			rewrite_request: self.rewrite_request.clone(),
+			overload_response: self.overload_response,
+			on_accept: self.on_accept.clone(),
+			socket_config: self.socket_config.clone(),
+		})
+	}
+
+	/// A batteries-included accept loop: runs `pool_size` worker threads,
+	/// each repeatedly accepting a connection (performing the TLS handshake
+	/// via the shared `TlsAcceptor` in the process) and passing it to
+	/// `handler`. Errors returned by `accept` (other than those caused by
+	/// `drain` shutting the loop down) are passed to `on_error` instead of
+	/// stopping the loop. A panic inside `handler` is caught so it takes
+	/// down neither the worker thread nor the rest of the pool.
+	///
+	/// This call blocks, using the calling thread as one of the workers,
+	/// until every worker has stopped (which currently only happens once
+	/// the server starts `drain`-ing). See `WsServer::serve` (the
+	/// non-secure equivalent) for more.
+	///
+	/// If cloning the server for an extra worker fails (e.g. the process is
+	/// out of file descriptors), that worker is simply not spawned and the
+	/// failure is reported to `on_error` as an `Io` error with no stream,
+	/// rather than panicking the thread setting up the pool - the same
+	/// `on_error` used for any other accept failure.
+	pub fn serve<H, E>(mut self, pool_size: usize, handler: H, on_error: E)
+	where
+		H: Fn(Upgrade<TlsStream<TcpStream>>) + Send + Sync + 'static,
+		E: Fn(InvalidConnection<TlsStream<TcpStream>, Buffer>) + Send + Sync + 'static,
+	{
+		let handler = Arc::new(handler);
+		let on_error = Arc::new(on_error);
+
+		let workers: Vec<_> = (1..pool_size)
+			.filter_map(|_| {
+				let mut server = match self.try_clone() {
+					Ok(server) => server,
+					Err(err) => {
+						on_error(InvalidConnection {
+							stream: None,
+							parsed: None,
+							buffer: None,
+							error: err.into(),
+							peer_addr: None,
+						});
+						return None;
+					}
+				};
+				let handler = handler.clone();
+				let on_error = on_error.clone();
+				Some(thread::spawn(move || {
+					secure_accept_loop(&mut server, &*handler, &*on_error)
+				}))
+			})
+			.collect();
+
+		secure_accept_loop(&mut self, &*handler, &*on_error);
+
+		for worker in workers {
+			let _ = worker.join();
+		}
+	}
+
+	/// `serve`, minus the blocking: runs the same pool of worker threads in
+	/// the background and returns immediately with a `RunHandle`, tracking
+	/// each connection `handler` is given through the `RunHandle`'s
+	/// `ConnectionGuard`.
+	///
+	/// `handler` is given the already-accepted `Client` rather than
+	/// `serve`'s `Upgrade` - `run` calls `WsUpgrade::accept` on its behalf,
+	/// silently dropping any connection whose handshake fails to finish
+	/// rather than passing it to `on_error` (which, as with `serve`, only
+	/// sees failures from `accept`-ing off the listener itself).
+	///
+	/// Stop the returned loop with `WsServer::drain` or
+	/// `WsServer::shutdown_graceful`, called on `self` - `run` leaves `self`
+	/// untouched precisely so you still have it for that.
+	pub fn run<H, E>(&self, pool_size: usize, handler: H, on_error: E) -> io::Result<RunHandle>
+	where
+		H: Fn(Client<TlsStream<TcpStream>>) + Send + Sync + 'static,
+		E: Fn(InvalidConnection<TlsStream<TcpStream>, Buffer>) + Send + Sync + 'static,
+	{
+		let server = self.try_clone()?;
+		let connections = ConnectionGuard::new();
+		let tracked = connections.clone();
+
+		let workers = thread::spawn(move || {
+			server.serve(
+				pool_size,
+				move |upgrade| {
+					let token = tracked.track();
+					if let Ok(client) = upgrade.accept() {
+						handler(client);
+					}
+					drop(token);
+				},
+				on_error,
+			);
+		});
+
+		Ok(RunHandle { connections, workers })
+	}
+}
+
+#[cfg(feature = "sync-ssl")]
+impl<F> WsServer<SniTlsAcceptor<F>, TcpListener>
+where
+	F: Fn(Option<&str>) -> Option<TlsAcceptor>,
+{
+	/// Like `bind_secure`, but picks the `TlsAcceptor` per-connection from
+	/// the SNI hostname in the ClientHello (see `SniTlsAcceptor`,
+	/// `peek_sni`) instead of using one certificate for the whole listener -
+	/// for virtual-hosting several WebSocket domains behind a single port.
+	///
+	/// `select` is tried first; `default` is used whenever it returns
+	/// `None`, including for clients that send no SNI extension at all.
+	/// Unlike the bare `SniTlsAcceptor::accept` this wraps, there's no way
+	/// to make an unresolved host fail the connection outright short of
+	/// having `select` itself fall back to something that will reject the
+	/// handshake - `default` always wins that has-no-opinion case, the same
+	/// as every other use of `SniTlsAcceptor` in this crate.
+	///
+	/// ```no_run
+	/// extern crate websocket;
+	/// extern crate native_tls;
+	/// # fn main() {
+	/// use std::io::Read;
+	/// use std::fs::File;
+	/// use websocket::sync::Server;
+	/// use native_tls::{Identity, TlsAcceptor};
+	///
+	/// fn cert_for(_host: &str) -> Option<TlsAcceptor> { None }
+	///
+	/// let mut file = File::open("identity.pfx").unwrap();
+	/// let mut pkcs12 = vec![];
+	/// file.read_to_end(&mut pkcs12).unwrap();
+	/// let pkcs12 = Identity::from_pkcs12(&pkcs12, "hacktheplanet").unwrap();
+	/// let default = TlsAcceptor::builder(pkcs12).build().unwrap();
+	///
+	/// let server = Server::bind_secure_with_resolver(
+	/// 	"127.0.0.1:8443",
+	/// 	default,
+	/// 	|hostname| hostname.and_then(cert_for),
+	/// ).unwrap();
+	/// # }
+	/// ```
+	pub fn bind_secure_with_resolver<A>(addr: A, default: TlsAcceptor, select: F) -> io::Result<Self>
+	where
+		A: ToSocketAddrs,
+	{
+		Ok(Server {
+			listener: TcpListener::bind(&addr)?,
+			ssl_acceptor: SniTlsAcceptor::new(default, select),
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			on_accept: None,
+			socket_config: None,
+		})
 	}
 }
 
@@ -223,32 +1440,146 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::bind(&addr)?,
 			ssl_acceptor: NoTlsAcceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			on_accept: None,
+			socket_config: None,
 		})
 	}
 
-	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
-	pub fn accept(&mut self) -> AcceptResult<TcpStream> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
-			Err(e) => {
-				return Err(InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					error: e.into(),
-				});
-			}
-		};
+	/// Build a server around an already-bound, already-listening
+	/// `std::net::TcpListener` instead of having `bind` create one. For a
+	/// listener that didn't come from this process binding a socket itself -
+	/// one handed over via systemd socket activation, or reconstructed from
+	/// a raw fd with `FromRawFd` - so this crate never gets the chance to
+	/// call `TcpListener::bind`. `accept`/iteration behave identically to a
+	/// `bind`-created server either way. See `from_socket` for the
+	/// `socket2`-based equivalent when you need options `bind` doesn't
+	/// expose but still want this crate to do the binding.
+	pub fn from_listener(listener: TcpListener) -> io::Result<Self> {
+		Ok(Server {
+			listener,
+			ssl_acceptor: NoTlsAcceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			on_accept: None,
+			socket_config: None,
+		})
+	}
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
-			Err((s, r, b, e)) => Err(InvalidConnection {
-				stream: Some(s),
-				parsed: r,
-				buffer: b,
-				error: e,
-			}),
-		}
+	/// Build a server around an already-configured `socket2::Socket`,
+	/// putting it into the listening state and wrapping it exactly as
+	/// `bind` would.
+	///
+	/// This is for the socket options `bind` doesn't have a method for
+	/// (`SO_REUSEPORT`, a custom `SO_RCVBUF`, binding to a specific
+	/// interface, ...): configure the options you need on the socket
+	/// yourself, then hand it here instead of this crate growing a method
+	/// per option. `socket` must already be bound to the address it should
+	/// listen on, and must be a TCP socket of the domain (IPv4/IPv6) you
+	/// intend to use.
+	///
+	/// ```no_run
+	/// extern crate socket2;
+	/// extern crate websocket;
+	/// # fn main() {
+	/// use socket2::{Domain, Socket, Type};
+	/// use websocket::sync::Server;
+	///
+	/// let socket = Socket::new(Domain::ipv4(), Type::stream(), None).unwrap();
+	/// socket.set_reuse_address(true).unwrap();
+	/// socket.bind(&"127.0.0.1:1234".parse::<::std::net::SocketAddr>().unwrap().into()).unwrap();
+	///
+	/// let server = Server::from_socket(socket).unwrap();
+	/// # }
+	/// ```
+	pub fn from_socket(socket: Socket) -> io::Result<Self> {
+		socket.listen(128)?;
+		Ok(Server {
+			listener: socket.into_tcp_listener(),
+			ssl_acceptor: NoTlsAcceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			on_accept: None,
+			socket_config: None,
+		})
+	}
+
+	/// Bind to `addr` with `SO_REUSEPORT` set, so that several processes (or
+	/// several `WsServer`s in this one process) can each bind the *same*
+	/// address and have the kernel load-balance incoming connections across
+	/// all of them, instead of the usual one-listener-per-port restriction.
+	/// Useful for a multi-process server architecture that wants to scale
+	/// accepts across cores without a shared accept loop.
+	///
+	/// `SO_REUSEADDR` is also set, matching what most `SO_REUSEPORT` setups
+	/// expect.
+	///
+	/// Only available on platforms `socket2` supports `SO_REUSEPORT` on
+	/// (Unix, except Solaris/illumos). For anything else `from_socket` lets
+	/// you configure the options yourself.
+	///
+	/// ```no_run
+	/// use websocket::sync::Server;
+	///
+	/// // Both servers below can accept connections on the same address; the
+	/// // kernel hands each new connection to one of them.
+	/// let a = Server::bind_reuseport("127.0.0.1:1234").unwrap();
+	/// let b = Server::bind_reuseport("127.0.0.1:1234").unwrap();
+	/// # let _ = (a, b);
+	/// ```
+	#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+	pub fn bind_reuseport<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		let addr = addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind to"))?;
+		let domain = if addr.is_ipv4() { Domain::ipv4() } else { Domain::ipv6() };
+
+		let socket = Socket::new(domain, Type::stream(), None)?;
+		socket.set_reuse_address(true)?;
+		socket.set_reuse_port(true)?;
+		socket.bind(&addr.into())?;
+		Self::from_socket(socket)
 	}
 
 	/// Create a new independently owned handle to the underlying socket.
@@ -257,43 +1588,1470 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: inner,
 			ssl_acceptor: self.ssl_acceptor.clone(),
+			accepted_versions: self.accepted_versions.clone(),
+			draining: self.draining.clone(),
+			tcp_nodelay: self.tcp_nodelay,
+			tcp_user_timeout: self.tcp_user_timeout,
+			accept_timeout: self.accept_timeout,
+			connection_ids: self.connection_ids.clone(),
+			server_header: self.server_header.clone(),
+			stats: self.stats.clone(),
+			socket_buffer_sizes: self.socket_buffer_sizes,
+			max_pending_handshakes: self.max_pending_handshakes.clone(),
+			switching_protocols_reason: self.switching_protocols_reason.clone(),
+			allowed_paths: self.allowed_paths.clone(),
+			required_protocols: self.required_protocols.clone(),
+			rewrite_request: self.rewrite_request.clone(),
+			overload_response: self.overload_response,
+			on_accept: self.on_accept.clone(),
+			socket_config: self.socket_config.clone(),
 		})
 	}
-}
-
-impl Iterator for WsServer<NoTlsAcceptor, TcpListener> {
-	type Item = AcceptResult<TcpStream>;
 
-	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-		Some(self.accept())
+	/// An iterator for use with `set_nonblocking(true)`: like iterating the
+	/// server directly, but a `WouldBlock` (no connection ready yet) ends
+	/// the iteration instead of being yielded as an error. Real errors
+	/// (a failed handshake, a dropped connection, ...) still come through.
+	///
+	/// This drains every connection that is already waiting and then stops,
+	/// so call it again on your next poll rather than expecting it to wait
+	/// for more connections to arrive.
+	///
+	/// ```no_run
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:0").unwrap();
+	/// server.set_nonblocking(true).unwrap();
+	///
+	/// for connection in server.incoming_nonblocking() {
+	///     match connection {
+	///         Ok(upgrade) => drop(upgrade), // handle the connection
+	///         Err(e) => eprintln!("accept failed: {:?}", e.error),
+	///     }
+	/// }
+	/// ```
+	pub fn incoming_nonblocking(&mut self) -> NonBlockingIncoming {
+		NonBlockingIncoming { server: self }
 	}
-}
 
-mod tests {
-	#[test]
-	// test the set_nonblocking() method for Server<NoSslAcceptor>.
-	// Some of this is copied from
-	// https://doc.rust-lang.org/src/std/net/tcp.rs.html#1413
-	fn set_nonblocking() {
-		use super::*;
-
-		// Test unsecure server
+	/// A batteries-included accept loop: runs `pool_size` worker threads,
+	/// each repeatedly accepting a connection and passing it to `handler`.
+	/// Errors returned by `accept` (other than those caused by `drain`
+	/// shutting the loop down) are passed to `on_error` instead of stopping
+	/// the loop. A panic inside `handler` is caught so it takes down neither
+	/// the worker thread nor the rest of the pool.
+	///
+	/// This call blocks, using the calling thread as one of the workers,
+	/// until every worker has stopped (which currently only happens once
+	/// the server starts `drain`-ing).
+	///
+	/// If cloning the server for an extra worker fails (e.g. the process is
+	/// out of file descriptors), that worker is simply not spawned and the
+	/// failure is reported to `on_error` as an `Io` error with no stream,
+	/// rather than panicking the thread setting up the pool - the same
+	/// `on_error` used for any other accept failure.
+	///
+	/// ```no_run
+	/// use websocket::sync::Server;
+	///
+	/// let server = Server::bind("127.0.0.1:0").unwrap();
+	///
+	/// server.serve(
+	///     4,
+	///     |connection| {
+	///         let mut client = match connection.accept() {
+	///             Ok(client) => client,
+	///             Err(_) => return,
+	///         };
+	///         let _ = client.send_message(&websocket::Message::text("hello"));
+	///     },
+	///     |err| eprintln!("accept failed: {:?}", err.error),
+	/// );
+	/// ```
+	pub fn serve<H, E>(mut self, pool_size: usize, handler: H, on_error: E)
+	where
+		H: Fn(Upgrade<TcpStream>) + Send + Sync + 'static,
+		E: Fn(InvalidConnection<TcpStream, Buffer>) + Send + Sync + 'static,
+	{
+		let handler = Arc::new(handler);
+		let on_error = Arc::new(on_error);
 
-		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let workers: Vec<_> = (1..pool_size)
+			.filter_map(|_| {
+				let mut server = match self.try_clone() {
+					Ok(server) => server,
+					Err(err) => {
+						on_error(InvalidConnection {
+							stream: None,
+							parsed: None,
+							buffer: None,
+							error: err.into(),
+							peer_addr: None,
+						});
+						return None;
+					}
+				};
+				let handler = handler.clone();
+				let on_error = on_error.clone();
+				Some(thread::spawn(move || accept_loop(&mut server, &*handler, &*on_error)))
+			})
+			.collect();
 
-		// Note that if set_nonblocking() doesn't work, but the following
-		// fails to panic for some reason, then the .accept() method below
-		// will block indefinitely.
-		server.set_nonblocking(true).unwrap();
+		accept_loop(&mut self, &*handler, &*on_error);
 
-		let result = server.accept();
-		match result {
-			// nobody tried to establish a connection, so we expect an error
-			Ok(_) => panic!("expected error"),
-			Err(e) => match e.error {
-				HyperIntoWsError::Io(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-				_ => panic!("unexpected error {}"),
-			},
+		for worker in workers {
+			let _ = worker.join();
 		}
 	}
+
+	/// `serve`, minus the blocking: runs the same pool of worker threads in
+	/// the background and returns immediately with a `RunHandle`, tracking
+	/// each connection `handler` is given through the `RunHandle`'s
+	/// `ConnectionGuard`.
+	///
+	/// `handler` is given the already-accepted `Client` rather than
+	/// `serve`'s `Upgrade` - `run` calls `WsUpgrade::accept` on its behalf,
+	/// silently dropping any connection whose handshake fails to finish
+	/// rather than passing it to `on_error` (which, as with `serve`, only
+	/// sees failures from `accept`-ing off the listener itself).
+	///
+	/// Stop the returned loop with `WsServer::drain` or
+	/// `WsServer::shutdown_graceful`, called on `self` - `run` leaves `self`
+	/// untouched precisely so you still have it for that.
+	///
+	/// ```no_run
+	/// use std::time::Duration;
+	/// use websocket::sync::Server;
+	///
+	/// let server = Server::bind("127.0.0.1:0").unwrap();
+	/// let handle = server.run(
+	///     4,
+	///     |mut client| {
+	///         let _ = client.send_message(&websocket::Message::text("hello"));
+	///     },
+	///     |err| eprintln!("accept failed: {:?}", err.error),
+	/// ).unwrap();
+	///
+	/// // ... later, once it's time to shut down ...
+	/// server.shutdown_graceful(&handle.connections(), Duration::from_secs(30));
+	/// handle.join();
+	/// ```
+	pub fn run<H, E>(&self, pool_size: usize, handler: H, on_error: E) -> io::Result<RunHandle>
+	where
+		H: Fn(Client<TcpStream>) + Send + Sync + 'static,
+		E: Fn(InvalidConnection<TcpStream, Buffer>) + Send + Sync + 'static,
+	{
+		let server = self.try_clone()?;
+		let connections = ConnectionGuard::new();
+		let tracked = connections.clone();
+
+		let workers = thread::spawn(move || {
+			server.serve(
+				pool_size,
+				move |upgrade| {
+					let token = tracked.track();
+					if let Ok(client) = upgrade.accept() {
+						handler(client);
+					}
+					drop(token);
+				},
+				on_error,
+			);
+		});
+
+		Ok(RunHandle { connections, workers })
+	}
+}
+
+/// Applies `SO_SNDBUF`/`SO_RCVBUF` to `stream`, ignoring any error - the OS
+/// is free to clamp, double, or reject whatever is asked for, and none of
+/// that should fail the connection. Works through a duplicated handle so
+/// `stream` keeps ownership of the original; `SO_SNDBUF`/`SO_RCVBUF` are
+/// properties of the underlying socket, shared across the duplicate. See
+/// `WsServer::socket_buffer_sizes`.
+fn set_socket_buffer_sizes(stream: &TcpStream, send: usize, recv: usize) {
+	if let Ok(dup) = stream.try_clone() {
+		let socket = Socket::from(dup);
+		let _ = socket.set_send_buffer_size(send);
+		let _ = socket.set_recv_buffer_size(recv);
+	}
+}
+
+fn accept_loop<H, E>(server: &mut WsServer<NoTlsAcceptor, TcpListener>, handler: &H, on_error: &E)
+where
+	H: Fn(Upgrade<TcpStream>),
+	E: Fn(InvalidConnection<TcpStream, Buffer>),
+{
+	while !server.is_draining() {
+		match server.accept() {
+			Ok(upgrade) => {
+				let handler = AssertUnwindSafe(handler);
+				let upgrade = AssertUnwindSafe(upgrade);
+				let _ = panic::catch_unwind(move || (handler.0)(upgrade.0));
+			}
+			Err(err) => {
+				if !server.is_draining() {
+					on_error(err);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(feature = "sync-ssl")]
+fn secure_accept_loop<H, E>(server: &mut WsServer<TlsAcceptor, TcpListener>, handler: &H, on_error: &E)
+where
+	H: Fn(Upgrade<TlsStream<TcpStream>>),
+	E: Fn(InvalidConnection<TlsStream<TcpStream>, Buffer>),
+{
+	while !server.is_draining() {
+		match server.accept() {
+			Ok(upgrade) => {
+				let handler = AssertUnwindSafe(handler);
+				let upgrade = AssertUnwindSafe(upgrade);
+				let _ = panic::catch_unwind(move || (handler.0)(upgrade.0));
+			}
+			Err(err) => {
+				if !server.is_draining() {
+					on_error(err);
+				}
+			}
+		}
+	}
+}
+
+/// Iterator returned by `WsServer::incoming_nonblocking`. See that method
+/// for details.
+pub struct NonBlockingIncoming<'s> {
+	server: &'s mut WsServer<NoTlsAcceptor, TcpListener>,
+}
+
+impl<'s> Iterator for NonBlockingIncoming<'s> {
+	type Item = AcceptResult<TcpStream>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self.server.accept() {
+			Err(InvalidConnection {
+				error: HyperIntoWsError::Io(ref e),
+				..
+			}) if e.kind() == io::ErrorKind::WouldBlock =>
+			{
+				None
+			}
+			other => Some(other),
+		}
+	}
+}
+
+mod tests {
+	#[test]
+	// test the set_nonblocking() method for Server<NoSslAcceptor>.
+	// Some of this is copied from
+	// https://doc.rust-lang.org/src/std/net/tcp.rs.html#1413
+	fn set_nonblocking() {
+		use super::*;
+
+		// Test unsecure server
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+
+		// Note that if set_nonblocking() doesn't work, but the following
+		// fails to panic for some reason, then the .accept() method below
+		// will block indefinitely.
+		server.set_nonblocking(true).unwrap();
+
+		let result = server.accept();
+		match result {
+			// nobody tried to establish a connection, so we expect an error
+			Ok(_) => panic!("expected error"),
+			Err(e) => match e.error {
+				HyperIntoWsError::Io(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+				_ => panic!("unexpected error {}"),
+			},
+		}
+	}
+
+	#[test]
+	fn accept_timeout_gives_up_once_the_duration_elapses() {
+		use super::*;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+
+		let started = Instant::now();
+		let result = server.accept_timeout(Duration::from_millis(100));
+		let elapsed = started.elapsed();
+		assert!(elapsed >= Duration::from_millis(100));
+		assert!(elapsed < Duration::from_secs(5));
+
+		match result {
+			Ok(_) => panic!("expected error"),
+			Err(e) => {
+				assert!(e.is_transient(), "expected a transient timeout error");
+			}
+		}
+
+		// The listener should be left blocking again for later, untimed
+		// accepts - prove it by accepting a TCP connection made right after
+		// (no handshake is sent, so only check the TCP half).
+		let addr = server.local_addr().unwrap();
+		let connecting = thread::spawn(move || TcpStream::connect(addr).unwrap());
+		assert!(server.accept_tcp().is_ok());
+		connecting.join().unwrap();
+	}
+
+	#[test]
+	fn accept_timeout_still_accepts_a_connection_made_in_time() {
+		use super::*;
+		use std::io::Write;
+		use std::net::TcpStream;
+		use std::thread;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let connecting = thread::spawn(move || {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			stream.write_all(HANDSHAKE_REQUEST).unwrap();
+			stream
+		});
+
+		let result = server.accept_timeout(Duration::from_secs(5));
+		assert!(result.is_ok());
+		connecting.join().unwrap();
+	}
+
+	#[test]
+	fn accept_tcp_surfaces_would_block_without_running_accept_tls() {
+		use super::*;
+
+		// Mirrors `set_nonblocking` above, but calls `accept_tcp` directly
+		// rather than `accept`, to prove the split doesn't change what a
+		// non-blocking accept loop sees while only doing the TCP half.
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.set_nonblocking(true).unwrap();
+
+		match server.accept_tcp() {
+			Ok(_) => panic!("expected error"),
+			Err(e) => match e.error {
+				HyperIntoWsError::Io(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+				other => panic!("unexpected error {:?}", other),
+			},
+		}
+	}
+
+	#[test]
+	fn accept_tls_can_run_on_a_worker_thread_started_after_accept_tcp() {
+		use super::*;
+		use std::io::Write;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let connecting = thread::spawn(move || {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			stream.write_all(HANDSHAKE_REQUEST).unwrap();
+			stream
+		});
+
+		// The accept loop's half: just grab the TCP connection.
+		let stream = match server.accept_tcp() {
+			Ok(s) => s,
+			Err(_) => panic!("expected accept_tcp to succeed"),
+		};
+		let peer_addr = stream.peer_addr().unwrap();
+		connecting.join().unwrap();
+
+		// Handed off to a separate thread, as if it came from a pool: run
+		// the (here trivial, for `NoTlsAcceptor`) TLS handshake and parse
+		// the websocket upgrade request.
+		let upgrade = match thread::spawn(move || server.accept_tls(stream)).join().unwrap() {
+			Ok(u) => u,
+			Err(_) => panic!("expected accept_tls to parse the handshake"),
+		};
+		assert_eq!(upgrade.request.subject.0, ::hyper::method::Method::Get);
+		assert_eq!(peer_addr.ip(), addr.ip());
+	}
+
+	#[test]
+	fn socket_buffer_sizes_are_applied_to_accepted_streams() {
+		use super::*;
+		use std::net::TcpStream as StdTcpStream;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.socket_buffer_sizes(256 * 1024, 256 * 1024);
+		let addr = server.local_addr().unwrap();
+
+		let client = thread::spawn(move || StdTcpStream::connect(addr).unwrap());
+
+		let stream = match server.accept_tcp() {
+			Ok(s) => s,
+			Err(_) => panic!("expected accept_tcp to succeed"),
+		};
+		let _client = client.join().unwrap();
+
+		let socket = Socket::from(stream.try_clone().unwrap());
+		// The OS is free to clamp/double what was asked for, so just check
+		// it moved off of whatever the (much smaller) default was.
+		assert!(socket.send_buffer_size().unwrap() >= 256 * 1024);
+		assert!(socket.recv_buffer_size().unwrap() >= 256 * 1024);
+	}
+
+	#[test]
+	fn socket_buffer_sizes_default_to_leaving_the_os_defaults_alone() {
+		use super::*;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		assert_eq!(server.socket_buffer_sizes, None);
+	}
+
+	#[test]
+	fn tcp_user_timeout_is_applied_to_accepted_streams() {
+		use super::*;
+		use std::net::TcpStream as StdTcpStream;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.tcp_user_timeout(Some(Duration::from_secs(30)));
+		let addr = server.local_addr().unwrap();
+
+		let client = thread::spawn(move || StdTcpStream::connect(addr).unwrap());
+
+		let stream = match server.accept_tcp() {
+			Ok(s) => s,
+			Err(_) => panic!("expected accept_tcp to succeed"),
+		};
+		let _client = client.join().unwrap();
+
+		// There's no portable getter for TCP_USER_TIMEOUT, so just check
+		// that setting it didn't error out on the accepted stream.
+		assert!(::stream::sync::set_tcp_user_timeout(&stream, Some(Duration::from_secs(30))).is_ok());
+	}
+
+	#[test]
+	fn tcp_user_timeout_defaults_to_leaving_the_os_default_alone() {
+		use super::*;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		assert_eq!(server.tcp_user_timeout, None);
+	}
+
+	#[test]
+	fn socket_config_runs_on_the_raw_stream_before_the_handshake() {
+		use super::*;
+		use std::net::TcpStream as StdTcpStream;
+		use std::sync::atomic::AtomicBool as AtomicFlag;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let ran = Arc::new(AtomicFlag::new(false));
+		let ran_clone = ran.clone();
+		server.set_socket_config(move |stream| {
+			ran_clone.store(true, Ordering::SeqCst);
+			stream.set_nodelay(true)
+		});
+		let addr = server.local_addr().unwrap();
+
+		let client = thread::spawn(move || StdTcpStream::connect(addr).unwrap());
+		let stream = match server.accept_tcp() {
+			Ok(s) => s,
+			Err(_) => panic!("expected accept_tcp to succeed"),
+		};
+		let _client = client.join().unwrap();
+
+		assert!(ran.load(Ordering::SeqCst));
+		assert!(stream.nodelay().unwrap());
+	}
+
+	#[test]
+	fn socket_config_failure_turns_into_an_invalid_connection() {
+		use super::*;
+		use std::net::TcpStream as StdTcpStream;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.set_socket_config(|_stream| {
+			Err(io::Error::new(io::ErrorKind::Other, "nope"))
+		});
+		let addr = server.local_addr().unwrap();
+
+		let client = thread::spawn(move || StdTcpStream::connect(addr).unwrap());
+		match server.accept_tcp() {
+			Ok(_) => panic!("expected socket_config's error to reject the connection"),
+			Err(invalid) => match invalid.error {
+				HyperIntoWsError::Io(ref e) => assert_eq!(e.kind(), io::ErrorKind::Other),
+				other => panic!("expected an Io error, got {:?}", other),
+			},
+		}
+		let _client = client.join().unwrap();
+	}
+
+	#[test]
+	fn socket_config_defaults_to_none() {
+		use super::*;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		assert!(server.socket_config.is_none());
+	}
+
+	#[test]
+	fn set_accept_timeout_fails_a_connection_that_never_sends_the_handshake() {
+		use super::*;
+		use std::net::TcpStream as StdTcpStream;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.set_accept_timeout(Some(Duration::from_millis(100)));
+		let addr = server.local_addr().unwrap();
+
+		// Connects but never writes anything, so `accept_tls`'s handshake
+		// read has nothing to read.
+		let _client = thread::spawn(move || StdTcpStream::connect(addr).unwrap());
+
+		let is_timeout = |kind: io::ErrorKind| kind == io::ErrorKind::TimedOut || kind == io::ErrorKind::WouldBlock;
+		match server.accept() {
+			Err(invalid) => match invalid.error {
+				HyperIntoWsError::Io(ref e) => assert!(is_timeout(e.kind())),
+				HyperIntoWsError::Parsing(::hyper::error::Error::Io(ref e)) => assert!(is_timeout(e.kind())),
+				other => panic!("expected an Io timeout error, got {:?}", other),
+			},
+			Ok(_) => panic!("expected the handshake read to time out"),
+		}
+	}
+
+	#[test]
+	fn invalid_connection_carries_the_peer_addr_even_when_the_handshake_fails() {
+		use super::*;
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		let client_addr = client.local_addr().unwrap();
+		client.write_all(b"not a websocket handshake\r\n\r\n").unwrap();
+
+		match server.accept() {
+			Err(invalid) => assert_eq!(invalid.peer_addr, Some(client_addr)),
+			Ok(_) => panic!("expected the malformed handshake to be rejected"),
+		}
+	}
+
+	#[test]
+	fn set_accept_timeout_is_cleared_once_the_handshake_succeeds() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::OwnedMessage;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.set_accept_timeout(Some(Duration::from_millis(100)));
+		let addr = server.local_addr().unwrap();
+
+		let client = thread::spawn(move || {
+			let mut client = ClientBuilder::new(&format!("ws://{}/", addr))
+				.unwrap()
+				.connect_insecure()
+				.unwrap();
+			// Wait past the accept timeout before sending anything, so a
+			// leftover read timeout on the accepted client would fail the
+			// server's `recv_message` below instead of just this one being
+			// slow.
+			thread::sleep(Duration::from_millis(150));
+			client
+				.send_message(&OwnedMessage::Text("hello".to_owned()))
+				.unwrap();
+		});
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected the handshake to succeed"),
+		};
+		let mut server_client = upgrade.accept().unwrap();
+		let message = server_client.recv_message().unwrap();
+		assert_eq!(message, OwnedMessage::Text("hello".to_owned()));
+
+		client.join().unwrap();
+	}
+
+	#[test]
+	fn max_pending_handshakes_blocks_additional_handshakes_until_one_finishes() {
+		use super::*;
+		use std::sync::mpsc;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		server.max_pending_handshakes.set_limit(Some(1));
+
+		// Hold the only permit, simulating a handshake that's still in flight.
+		let held = server.max_pending_handshakes.acquire();
+
+		let (tx, rx) = mpsc::channel();
+		let limiter = server.max_pending_handshakes.clone();
+		let waiter = thread::spawn(move || {
+			let _permit = limiter.acquire();
+			tx.send(()).unwrap();
+		});
+
+		assert!(
+			rx.recv_timeout(Duration::from_millis(200)).is_err(),
+			"a second handshake should not acquire a permit while the first is in flight"
+		);
+
+		drop(held);
+		rx.recv_timeout(Duration::from_secs(1))
+			.expect("the permit should free up once the first one is dropped");
+		waiter.join().unwrap();
+	}
+
+	#[test]
+	fn stats_count_rejections_by_reason() {
+		use super::*;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		assert_eq!(server.stats().total(), 0);
+
+		server.set_nonblocking(true).unwrap();
+		match server.accept() {
+			Ok(_) => panic!("expected error"),
+			Err(_) => {}
+		}
+
+		assert_eq!(server.stats().io_error(), 1);
+		assert_eq!(server.stats().bad_handshake(), 0);
+		assert_eq!(server.stats().tls_failure(), 0);
+		assert_eq!(server.stats().total(), 1);
+	}
+
+	#[test]
+	fn stats_are_shared_across_clones() {
+		use super::*;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let clone = server.try_clone().unwrap();
+
+		server.draining.store(true, Ordering::SeqCst);
+		match clone.stats().total() {
+			0 => {}
+			_ => panic!("expected no rejections recorded yet"),
+		}
+
+		let mut server = server;
+		let _ = server.accept_tcp();
+		assert_eq!(clone.stats().bad_handshake(), 0);
+		assert_eq!(clone.stats().io_error(), 1);
+	}
+
+	#[test]
+	fn overload_response_defaults_to_503_and_is_applied_to_accepted_handshakes() {
+		use super::*;
+		use std::io::{Read, Write};
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+		upgrade.reject_unavailable().unwrap();
+
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+		assert!(!response.contains("Retry-After"));
+	}
+
+	#[test]
+	fn overload_response_can_be_reconfigured_and_recorded_in_stats() {
+		use super::*;
+		use std::io::{Read, Write};
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.overload_response(StatusCode::TooManyRequests, Some(5));
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+		upgrade.reject_unavailable().unwrap();
+		server.stats().record(RejectReason::Overloaded);
+
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 429"), "{}", response);
+		assert!(response.contains("Retry-After: 5"));
+
+		assert_eq!(server.stats().overloaded(), 1);
+		assert_eq!(server.stats().total(), 1);
+	}
+
+	#[test]
+	fn switching_protocols_reason_is_applied_to_accepted_handshakes() {
+		use super::*;
+		use std::io::{Read, Write};
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.switching_protocols_reason(Some("Upgraded"));
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+		upgrade.accept().unwrap();
+
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 101 Upgraded"), "{}", response);
+	}
+
+	#[test]
+	fn on_accept_fires_with_the_connection_id_and_peer_addr() {
+		use super::*;
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+		use std::sync::Mutex;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let events: Arc<Mutex<Vec<ConnectionEvent>>> = Arc::new(Mutex::new(Vec::new()));
+		let recorded = events.clone();
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.on_accept(move |event| recorded.lock().unwrap().push(event));
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+
+		let events = events.lock().unwrap();
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].connection_id, upgrade.connection_id());
+		assert_eq!(events[0].peer_addr, Some(client.local_addr().unwrap()));
+	}
+
+	#[test]
+	fn on_accept_is_unset_by_default_and_never_called() {
+		use super::*;
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		assert!(server.on_accept.is_none());
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		match server.accept() {
+			Ok(_) => {}
+			Err(_) => panic!("expected a successful handshake"),
+		}
+	}
+
+	#[test]
+	fn allowed_paths_rejects_an_unlisted_path_with_404() {
+		use super::*;
+		use std::io::{Read, Write};
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET /nope HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.allowed_paths(&["/chat"]);
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		match server.accept() {
+			Ok(_) => panic!("expected the handshake to be rejected"),
+			Err(_) => {}
+		}
+
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+		assert_eq!(server.stats().not_found(), 1);
+		assert_eq!(server.stats().bad_handshake(), 0);
+	}
+
+	#[test]
+	fn allowed_paths_accepts_a_listed_path_including_its_query_string() {
+		use super::*;
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET /chat?room=42 HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.allowed_paths(&["/chat"]);
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		match server.accept() {
+			Ok(upgrade) => assert_eq!(upgrade.path(), "/chat"),
+			Err(_) => panic!("expected a successful handshake"),
+		}
+
+		assert_eq!(server.stats().not_found(), 0);
+	}
+
+	#[test]
+	fn require_protocol_rejects_a_handshake_offering_no_matching_protocol() {
+		use super::*;
+		use std::io::{Read, Write};
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Protocol: xmpp\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.require_protocol("json.v1");
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		match server.accept() {
+			Ok(_) => panic!("expected the handshake to be rejected"),
+			Err(_) => {}
+		}
+
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+		assert!(!response.contains("Sec-WebSocket-Protocol"), "{}", response);
+
+		assert_eq!(server.stats().bad_handshake(), 1);
+	}
+
+	#[test]
+	fn require_protocol_accepts_and_echoes_any_matching_protocol() {
+		use super::*;
+		use header::WebSocketProtocol;
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Protocol: xmpp, json.v1\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.require_protocol("soap");
+		server.require_protocol("json.v1");
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+		assert_eq!(
+			upgrade.headers.get::<WebSocketProtocol>(),
+			Some(&WebSocketProtocol(vec!["json.v1".to_owned()]))
+		);
+
+		assert_eq!(server.stats().bad_handshake(), 0);
+	}
+
+	#[test]
+	fn no_required_protocol_accepts_a_handshake_offering_none_and_sends_no_protocol_header() {
+		use super::*;
+		use header::WebSocketProtocol;
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+		assert!(upgrade.protocols().is_empty());
+		assert_eq!(upgrade.headers.get::<WebSocketProtocol>(), None);
+
+		assert_eq!(server.stats().bad_handshake(), 0);
+	}
+
+	#[test]
+	fn require_protocol_rejects_a_handshake_offering_no_protocol_at_all() {
+		use super::*;
+		use std::io::{Read, Write};
+		use std::net::TcpStream as StdTcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.require_protocol("json.v1");
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		match server.accept() {
+			Ok(_) => panic!("expected the handshake to be rejected"),
+			Err(_) => {}
+		}
+
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+		assert!(!response.contains("Sec-WebSocket-Protocol"), "{}", response);
+
+		assert_eq!(server.stats().bad_handshake(), 1);
+	}
+
+	#[test]
+	fn rewrite_request_lets_the_server_accept_a_handshake_missing_connection_upgrade() {
+		use super::*;
+		use header::{Connection, ConnectionOption};
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+		use unicase::UniCase;
+
+		const BROKEN_HANDSHAKE_REQUEST: &'static [u8] = b"GET /chat HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.rewrite_request(|request| {
+			if !request.headers.has::<Connection>() {
+				request
+					.headers
+					.set(Connection(vec![ConnectionOption::ConnectionHeader(UniCase(
+						"Upgrade".to_owned(),
+					))]));
+			}
+		});
+		let addr = server.local_addr().unwrap();
+
+		let mut client = StdTcpStream::connect(addr).unwrap();
+		client.write_all(BROKEN_HANDSHAKE_REQUEST).unwrap();
+
+		match server.accept() {
+			Ok(upgrade) => assert_eq!(upgrade.path(), "/chat"),
+			Err(_) => panic!("expected rewrite_request to fix up the broken handshake"),
+		}
+	}
+
+	#[test]
+	fn connection_ids_increase_and_are_shared_across_clones() {
+		use super::*;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		assert_eq!(server.next_connection_id(), 0);
+		assert_eq!(server.next_connection_id(), 1);
+
+		let clone = server.try_clone().unwrap();
+		assert_eq!(clone.next_connection_id(), 2);
+		assert_eq!(server.next_connection_id(), 3);
+	}
+
+	#[test]
+	fn from_listener_accepts_connections_like_a_bind_created_server() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::OwnedMessage;
+		use std::net::TcpListener as StdTcpListener;
+
+		let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let mut server = Server::from_listener(listener).unwrap();
+		assert_eq!(server.local_addr().unwrap(), addr);
+
+		let client_thread = thread::spawn(move || {
+			let mut client = ClientBuilder::new(&format!("ws://{}/", addr))
+				.unwrap()
+				.connect_insecure()
+				.unwrap();
+			client
+				.send_message(&OwnedMessage::Text("hello".to_owned()))
+				.unwrap();
+		});
+
+		let upgrade = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+		let mut client = upgrade.accept().unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("hello".to_owned())
+		);
+
+		client_thread.join().unwrap();
+	}
+
+	#[test]
+	fn shutdown_graceful_returns_zero_once_every_token_is_dropped() {
+		use super::*;
+		use server::ConnectionGuard;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let guard = ConnectionGuard::new();
+
+		let token = guard.track();
+		assert_eq!(guard.active(), 1);
+		drop(token);
+
+		let remaining = server.shutdown_graceful(&guard, Duration::from_secs(5));
+		assert_eq!(remaining, 0);
+		assert!(server.is_draining());
+	}
+
+	#[test]
+	fn shutdown_graceful_gives_up_once_the_timeout_elapses() {
+		use super::*;
+		use server::ConnectionGuard;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let guard = ConnectionGuard::new();
+		let _token = guard.track();
+
+		let remaining = server.shutdown_graceful(&guard, Duration::from_millis(50));
+		assert_eq!(remaining, 1);
+	}
+
+	#[test]
+	fn incoming_limited_blocks_until_a_token_is_dropped() {
+		use super::*;
+		use std::io::Write;
+		use std::net::TcpStream as StdTcpStream;
+		use std::sync::mpsc;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let (tx, rx) = mpsc::channel();
+		let accepted = thread::spawn(move || {
+			let mut incoming = server.incoming_limited(1);
+			for _ in 0..2 {
+				let (result, token) = incoming.next().unwrap();
+				assert!(result.is_ok());
+				tx.send(token).unwrap();
+			}
+		});
+
+		let mut first = StdTcpStream::connect(addr).unwrap();
+		first.write_all(HANDSHAKE_REQUEST).unwrap();
+		let first_token = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+		// With the only slot held by `first_token`, a second connection
+		// mustn't be handed out yet.
+		let mut second = StdTcpStream::connect(addr).unwrap();
+		second.write_all(HANDSHAKE_REQUEST).unwrap();
+		assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+		// Freeing the slot lets the second connection through.
+		drop(first_token);
+		rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+		accepted.join().unwrap();
+	}
+
+	#[test]
+	fn shutdown_handle_unblocks_a_pending_accept_with_shutdown_error() {
+		use super::*;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let handle = server.shutdown_handle().unwrap();
+
+		let accepted = thread::spawn(move || server.accept());
+
+		// Give the spawned thread time to actually enter the blocking
+		// accept before shutting down, so this exercises the wakeup path
+		// rather than the up-front draining check.
+		thread::sleep(Duration::from_millis(50));
+		handle.shutdown().unwrap();
+
+		match accepted.join().unwrap() {
+			Err(invalid) => match invalid.error {
+				HyperIntoWsError::ShutDown => {}
+				other => panic!("expected ShutDown, got {:?}", other),
+			},
+			Ok(_) => panic!("expected the pending accept to fail with ShutDown"),
+		}
+	}
+
+	#[test]
+	fn weak_handle_shuts_down_a_server_that_is_still_alive() {
+		use super::*;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let handle = server.weak_handle();
+
+		assert!(handle.is_alive());
+		assert!(!server.is_draining());
+
+		handle.shutdown();
+		assert!(server.is_draining());
+	}
+
+	#[test]
+	fn weak_handle_shutdown_is_a_no_op_once_the_server_is_dropped() {
+		use super::*;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let handle = server.weak_handle();
+		drop(server);
+
+		assert!(!handle.is_alive());
+		handle.shutdown();
+	}
+
+	#[test]
+	#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+	fn bind_reuseport_lets_two_servers_share_a_port() {
+		use super::*;
+		use std::io::Write;
+		use std::net::TcpStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		// Grab a free port, then immediately let it go: both servers below
+		// will race to (successfully) bind it thanks to SO_REUSEPORT.
+		let port = {
+			let probe = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+			probe.local_addr().unwrap().port()
+		};
+		let addr = format!("127.0.0.1:{}", port);
+
+		let mut a = Server::bind_reuseport(&addr[..]).unwrap();
+		let mut b = Server::bind_reuseport(&addr[..]).unwrap();
+		a.set_nonblocking(true).unwrap();
+		b.set_nonblocking(true).unwrap();
+
+		// Open enough connections that, with the kernel load-balancing
+		// accepts across both listeners, each one sees at least one. Each
+		// one sends a valid handshake up front so `accept` (which reads the
+		// handshake synchronously) doesn't block waiting for it.
+		let clients: Vec<_> = (0..64)
+			.map(|_| {
+				let mut stream = TcpStream::connect(&addr[..]).unwrap();
+				stream.write_all(HANDSHAKE_REQUEST).unwrap();
+				stream
+			})
+			.collect();
+
+		let mut a_accepted = 0;
+		let mut b_accepted = 0;
+		for _ in 0..clients.len() * 2 {
+			if a.accept().is_ok() {
+				a_accepted += 1;
+			}
+			if b.accept().is_ok() {
+				b_accepted += 1;
+			}
+		}
+
+		assert_eq!(a_accepted + b_accepted, clients.len());
+		assert!(a_accepted > 0, "server a never received a connection");
+		assert!(b_accepted > 0, "server b never received a connection");
+	}
+
+	#[test]
+	fn from_socket_behaves_like_bind() {
+		use super::*;
+		use socket2::{Domain, Socket, Type};
+
+		let socket = Socket::new(Domain::ipv4(), Type::stream(), None).unwrap();
+		socket
+			.bind(&"127.0.0.1:0".parse::<::std::net::SocketAddr>().unwrap().into())
+			.unwrap();
+		let addr = socket.local_addr().unwrap().as_std().unwrap();
+
+		let mut server = Server::from_socket(socket).unwrap();
+		assert_eq!(server.local_addr().unwrap(), addr);
+
+		server.set_nonblocking(true).unwrap();
+		match server.accept() {
+			Ok(_) => panic!("expected error"),
+			Err(e) => match e.error {
+				HyperIntoWsError::Io(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+				_ => panic!("unexpected error {}"),
+			},
+		}
+	}
+
+	#[test]
+	fn incoming_nonblocking_ends_on_would_block_without_an_error() {
+		use super::*;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		server.set_nonblocking(true).unwrap();
+
+		let results: Vec<_> = server.incoming_nonblocking().collect();
+		assert!(results.is_empty(), "expected no connections, got {}", results.len());
+	}
+
+	#[test]
+	fn accept_works_with_a_custom_optional_tls_acceptor() {
+		use super::*;
+		use header::WebSocketVersion;
+		use server::OptionalTlsAcceptor;
+		use std::io::Write;
+
+		// Stands in for a third-party TLS wrapper: wraps the `TcpStream` in
+		// a distinct type without doing any actual encryption, to prove
+		// `WsServer::accept` works generically over `S::Output` rather than
+		// being tied to `native_tls::TlsStream<TcpStream>`.
+		struct PassthroughAcceptor;
+		struct Passthrough(TcpStream);
+
+		impl ::std::io::Read for Passthrough {
+			fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+				self.0.read(buf)
+			}
+		}
+		impl Write for Passthrough {
+			fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+				self.0.write(buf)
+			}
+			fn flush(&mut self) -> io::Result<()> {
+				self.0.flush()
+			}
+		}
+
+		impl OptionalTlsAcceptor for PassthroughAcceptor {
+			type Output = Passthrough;
+
+			fn accept(&self, stream: TcpStream) -> Result<Passthrough, HyperIntoWsError> {
+				Ok(Passthrough(stream))
+			}
+		}
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut server: WsServer<PassthroughAcceptor, TcpListener> = WsServer {
+			listener: TcpListener::bind("127.0.0.1:0").unwrap(),
+			ssl_acceptor: PassthroughAcceptor,
+			accepted_versions: vec![13],
+			draining: Arc::new(AtomicBool::new(false)),
+			tcp_nodelay: false,
+			tcp_user_timeout: None,
+			accept_timeout: None,
+			connection_ids: Arc::new(AtomicU64::new(0)),
+			server_header: None,
+			stats: Arc::new(ServerStats::default()),
+			socket_buffer_sizes: None,
+			max_pending_handshakes: Arc::new(HandshakeLimiter::new()),
+			switching_protocols_reason: None,
+			allowed_paths: None,
+			required_protocols: None,
+			rewrite_request: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+			on_accept: None,
+			socket_config: None,
+		};
+		let addr = server.local_addr().unwrap();
+
+		let mut client = TcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade: Upgrade<Passthrough> = match server.accept() {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected a successful handshake"),
+		};
+		assert_eq!(upgrade.protocol_version(), WebSocketVersion::WebSocket13);
+	}
+
+	#[test]
+	fn run_accepts_a_connection_and_tracks_it_until_shutdown() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::OwnedMessage;
+		use std::sync::mpsc;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let (tx, rx) = mpsc::channel();
+		let handle = server
+			.run(
+				2,
+				move |mut client| {
+					let message = client.recv_message().unwrap();
+					tx.send(message).unwrap();
+				},
+				|_| {},
+			)
+			.unwrap();
+
+		let mut client = ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		client
+			.send_message(&OwnedMessage::Text("hello".to_owned()))
+			.unwrap();
+
+		assert_eq!(
+			rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+			OwnedMessage::Text("hello".to_owned())
+		);
+
+		let remaining = server.shutdown_graceful(&handle.connections(), Duration::from_secs(5));
+		assert_eq!(remaining, 0);
+		assert!(server.is_draining());
+	}
+
+	#[test]
+	fn a_panicking_handler_does_not_take_down_the_rest_of_the_pool() {
+		use super::*;
+		use client::builder::ClientBuilder;
+		use message::OwnedMessage;
+		use std::sync::mpsc;
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let (tx, rx) = mpsc::channel();
+		let handle = server
+			.run(
+				2,
+				move |mut client| {
+					let message = client.recv_message().unwrap();
+					if message == OwnedMessage::Text("panic".to_owned()) {
+						panic!("handler intentionally panicking");
+					}
+					tx.send(message).unwrap();
+				},
+				|_| {},
+			)
+			.unwrap();
+
+		let mut panicking_client = ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		panicking_client
+			.send_message(&OwnedMessage::Text("panic".to_owned()))
+			.unwrap();
+
+		let mut client = ClientBuilder::new(&format!("ws://{}/", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		client
+			.send_message(&OwnedMessage::Text("hello".to_owned()))
+			.unwrap();
+
+		assert_eq!(
+			rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+			OwnedMessage::Text("hello".to_owned())
+		);
+
+		let remaining = server.shutdown_graceful(&handle.connections(), Duration::from_secs(5));
+		assert_eq!(remaining, 0);
+		assert!(server.is_draining());
+	}
 }
@@ -13,7 +13,7 @@ use codec::ws::{Context, MessageCodec};
 use futures::sink::Send as SinkSend;
 use futures::Stream as StreamTrait;
 use futures::{Future, Sink};
-use hyper::header::Headers;
+use hyper::header::{Connection, ConnectionOption, Headers};
 use hyper::http::h1::Incoming;
 use hyper::status::StatusCode;
 use std::io::{self, ErrorKind};
@@ -82,16 +82,32 @@ where
 	/// This will asynchronously send a response accepting the connection
 	/// with custom headers in the response and create a websocket client.
 	pub fn accept_with(self, custom_headers: &Headers) -> ClientNew<S> {
-		self.internal_accept(Some(custom_headers))
+		self.internal_accept(Some(custom_headers.clone()))
 	}
 
-	fn internal_accept(mut self, custom_headers: Option<&Headers>) -> ClientNew<S> {
-		let status = self.prepare_headers(custom_headers);
+	/// Like `accept_with`, but the extra headers are computed from the
+	/// parsed handshake `request` instead of being fixed ahead of time -
+	/// handy when what you send back depends on what the client asked for,
+	/// e.g. picking response headers for whichever subprotocol was
+	/// negotiated. The mandatory websocket headers (`Sec-WebSocket-Accept`,
+	/// `Upgrade`, `Connection`) are still added afterwards and cannot be
+	/// overridden this way.
+	pub fn accept_with_fn<F>(self, headers: F) -> ClientNew<S>
+	where
+		F: FnOnce(&Request) -> Headers,
+	{
+		let headers = headers(&self.request);
+		self.internal_accept(Some(headers))
+	}
+
+	fn internal_accept(mut self, custom_headers: Option<Headers>) -> ClientNew<S> {
+		let status = self.prepare_headers(custom_headers.as_ref());
 		let WsUpgrade {
 			headers,
 			stream,
 			request,
 			buffer,
+			..
 		} = self;
 
 		let mut parts = FramedParts::new(stream, HttpServerCodec);
@@ -135,6 +151,8 @@ where
 		if let Some(custom) = headers {
 			self.headers.extend(custom.iter());
 		}
+		self.headers
+			.set(Connection(vec![ConnectionOption::Close]));
 		let mut parts = FramedParts::new(self.stream, HttpServerCodec);
 		parts.read_buf = self.buffer;
 		let duplex = Framed::from_parts(parts);
@@ -244,6 +262,10 @@ where
 				stream,
 				request: m,
 				buffer,
+				context: None,
+				connection_id: 0,
+				switching_protocols_reason: None,
+				overload_response: (StatusCode::ServiceUnavailable, None),
 			});
 		Box::new(future)
 	}
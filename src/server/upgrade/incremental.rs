@@ -0,0 +1,550 @@
+//! Resumable, non-blocking variants of the handshake read and the
+//! handshake response write, for a reactor-based design that wants to
+//! register the raw socket for readability/writability itself and drive
+//! the handshake forward a chunk at a time, instead of handing a blocking
+//! (or Tokio) read/write off to this crate. See `server::async` for the
+//! Tokio integration these are an alternative to.
+
+use std::io;
+
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::http::h1::parse_request;
+use hyper::status::StatusCode;
+
+use client::sync::Client;
+use header::WebSocketVersion;
+use server::upgrade::sync::Buffer;
+use server::upgrade::{validate_with_versions, HyperIntoWsError, Request, WsUpgrade};
+use stream::Stream;
+
+/// Failure to complete a handshake via `PartialUpgrade::try_upgrade`, in the
+/// same shape `IntoWs::into_ws` and `WsServer::accept` fail with: whatever
+/// could be recovered from the stream, alongside the cause.
+pub type Error<S> = (S, Option<Request>, Option<Buffer>, HyperIntoWsError);
+
+/// The outcome of a `PartialUpgrade::try_upgrade` call.
+pub enum TryUpgrade<S>
+where
+	S: Stream,
+{
+	/// Not enough bytes have arrived yet to finish reading the handshake
+	/// request. Keep this and call `try_upgrade` again once the stream is
+	/// readable.
+	Pending(PartialUpgrade<S>),
+	/// The handshake request was fully read and validated.
+	Upgraded(WsUpgrade<S, Option<Buffer>>),
+}
+
+/// The default `PartialUpgrade::max_header_size`: 16KiB, in line with the
+/// header size limits common HTTP servers default to.
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 16 * 1024;
+
+/// The default `PartialUpgrade::max_header_count`: 100, enough for any
+/// legitimate handshake while still capping how much work a request padded
+/// with thousands of tiny headers can force onto the parser.
+pub const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+
+/// A handshake read that is still in progress.
+///
+/// Build one with `PartialUpgrade::new` over a stream already in
+/// non-blocking mode and registered with your own event loop, then call
+/// `try_upgrade` every time the stream reports itself readable until it
+/// stops returning `Pending`.
+pub struct PartialUpgrade<S>
+where
+	S: Stream,
+{
+	stream: S,
+	buf: Vec<u8>,
+	accepted_versions: Vec<u8>,
+	connection_id: u64,
+	max_header_size: usize,
+	max_header_count: usize,
+}
+
+impl<S> PartialUpgrade<S>
+where
+	S: Stream,
+{
+	/// Starts tracking a fresh handshake read over `stream`. `accepted_versions`
+	/// and `connection_id` carry through to the eventual `WsUpgrade` exactly as
+	/// they would from `WsServer::accept` (see `WsServer::accepted_versions`
+	/// and `WsUpgrade::connection_id`). Buffers up to `DEFAULT_MAX_HEADER_SIZE`
+	/// bytes of handshake before giving up; see `with_max_header_size` to
+	/// change that.
+	pub fn new(stream: S, accepted_versions: Vec<u8>, connection_id: u64) -> Self {
+		PartialUpgrade {
+			stream,
+			buf: Vec::new(),
+			accepted_versions,
+			connection_id,
+			max_header_size: DEFAULT_MAX_HEADER_SIZE,
+			max_header_count: DEFAULT_MAX_HEADER_COUNT,
+		}
+	}
+
+	/// Caps how many bytes of handshake request this will buffer across
+	/// repeated `try_upgrade` calls before giving up with
+	/// `HyperIntoWsError::HandshakeTooLarge`, so a peer trickling bytes in
+	/// forever (deliberately or otherwise) can't grow this buffer without
+	/// bound while the headers never complete.
+	pub fn with_max_header_size(mut self, max_header_size: usize) -> Self {
+		self.max_header_size = max_header_size;
+		self
+	}
+
+	/// Caps how many headers a handshake request may carry before
+	/// `try_upgrade` gives up with `HyperIntoWsError::TooManyHeaders`, once
+	/// the headers finish arriving. Complements `with_max_header_size`: a
+	/// request packed with thousands of tiny headers can be abusive (e.g.
+	/// a hash-collision style parsing attack) well before it crosses a
+	/// byte-size limit. Defaults to `DEFAULT_MAX_HEADER_COUNT`.
+	pub fn with_max_header_count(mut self, max_header_count: usize) -> Self {
+		self.max_header_count = max_header_count;
+		self
+	}
+
+	/// Reads whatever is currently available on the stream without
+	/// blocking. If that completes the handshake request's headers, parses
+	/// and validates it; otherwise returns `Pending` with the bytes read so
+	/// far kept for the next call.
+	///
+	/// A `WouldBlock` from the stream simply means no more bytes have
+	/// arrived yet, and is not an error. Any other I/O error, a malformed/
+	/// unsupported request once the headers are complete, or the buffered
+	/// bytes crossing `max_header_size` before the headers complete, fails
+	/// with whatever could be recovered, same as `WsServer::accept`.
+	pub fn try_upgrade(mut self) -> Result<TryUpgrade<S>, Error<S>> {
+		let mut chunk = [0u8; 4096];
+		loop {
+			match self.stream.read(&mut chunk) {
+				Ok(0) => break,
+				Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+				Err(err) => return Err((self.stream, None, None, err.into())),
+			}
+		}
+
+		if self.buf.len() > self.max_header_size {
+			let error = HyperIntoWsError::HandshakeTooLarge {
+				limit: self.max_header_size,
+				seen: self.buf.len(),
+			};
+			return Err((self.stream, None, None, error));
+		}
+
+		if !headers_complete(&self.buf) {
+			return Ok(TryUpgrade::Pending(self));
+		}
+
+		let mut reader = BufReader::new(io::Cursor::new(self.buf));
+		let request = parse_request(&mut reader);
+		let (_, buf, pos, cap) = reader.into_parts();
+		let buffer = Some(Buffer { buf, pos, cap });
+
+		let request: Request = match request {
+			Ok(r) => r,
+			Err(e) => return Err((self.stream, None, buffer, e.into())),
+		};
+
+		if request.headers.len() > self.max_header_count {
+			let error = HyperIntoWsError::TooManyHeaders {
+				limit: self.max_header_count,
+				seen: request.headers.len(),
+			};
+			return Err((self.stream, Some(request), buffer, error));
+		}
+
+		match validate_with_versions(
+			&request.subject.0,
+			request.version,
+			&request.headers,
+			&self.accepted_versions,
+		) {
+			Ok(_) => Ok(TryUpgrade::Upgraded(WsUpgrade {
+				headers: Headers::new(),
+				stream: self.stream,
+				request,
+				buffer,
+				context: None,
+				connection_id: self.connection_id,
+				switching_protocols_reason: None,
+				overload_response: (StatusCode::ServiceUnavailable, None),
+			})),
+			Err(HyperIntoWsError::UnsupportedWebsocketVersion) => {
+				let versions = self
+					.accepted_versions
+					.iter()
+					.map(|v| v.to_string())
+					.collect::<Vec<_>>()
+					.join(", ");
+				let response = format!(
+					"{} {}\r\nSec-WebSocket-Version: {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+					request.version,
+					StatusCode::UpgradeRequired,
+					versions
+				);
+				let _ = io::Write::write_all(&mut self.stream, response.as_bytes());
+				Err((
+					self.stream,
+					Some(request),
+					buffer,
+					HyperIntoWsError::UnsupportedWebsocketVersion,
+				))
+			}
+			Err(e) => Err((self.stream, Some(request), buffer, e)),
+		}
+	}
+}
+
+/// The outcome of a `PartialAccept::try_write` call.
+pub enum TryAccept<S>
+where
+	S: Stream,
+{
+	/// Not all of the handshake response has been written yet. Keep this
+	/// and call `try_write` again once the stream is writable.
+	Pending(PartialAccept<S>),
+	/// The full response reached the stream; the handshake is complete.
+	Accepted(Client<S>),
+}
+
+/// A handshake response write that is still in progress.
+///
+/// Build one with `WsUpgrade::accept_incremental`/`accept_with_incremental`
+/// over a stream already in non-blocking mode and registered with your own
+/// event loop, then call `try_write` every time the stream reports itself
+/// writable until it stops returning `Pending`. Pairs with `PartialUpgrade`
+/// on the read side.
+pub struct PartialAccept<S>
+where
+	S: Stream,
+{
+	stream: S,
+	response: Vec<u8>,
+	written: usize,
+	connection_id: u64,
+	protocol_version: WebSocketVersion,
+	headers: Headers,
+	buffer: Option<Buffer>,
+}
+
+impl<S> PartialAccept<S>
+where
+	S: Stream,
+{
+	pub(crate) fn new(
+		stream: S,
+		response: Vec<u8>,
+		connection_id: u64,
+		protocol_version: WebSocketVersion,
+		headers: Headers,
+		buffer: Option<Buffer>,
+	) -> Self {
+		PartialAccept {
+			stream,
+			response,
+			written: 0,
+			connection_id,
+			protocol_version,
+			headers,
+			buffer,
+		}
+	}
+
+	/// How many bytes of the handshake response have been written to the
+	/// stream so far.
+	pub fn bytes_written(&self) -> usize {
+		self.written
+	}
+
+	/// The total size of the handshake response being written.
+	pub fn total_len(&self) -> usize {
+		self.response.len()
+	}
+
+	/// Writes whatever the stream will currently accept without blocking.
+	/// If that completes the response, builds the `Client` the same way
+	/// `WsUpgrade::accept` would; otherwise returns `Pending` with the
+	/// bytes written so far kept for the next call.
+	///
+	/// A `WouldBlock` from the stream simply means it has no more room
+	/// right now, and is not an error. Any other I/O error fails with
+	/// whatever could be recovered, same as `WsUpgrade::accept`.
+	pub fn try_write(mut self) -> Result<TryAccept<S>, (S, io::Error)> {
+		loop {
+			if self.written == self.response.len() {
+				let stream = match self.buffer {
+					Some(Buffer { buf, pos, cap }) => BufReader::from_parts(self.stream, buf, pos, cap),
+					None => BufReader::new(self.stream),
+				};
+				let mut client = Client::unchecked(stream, self.headers, false, true);
+				client.connection_id = self.connection_id;
+				client.protocol_version = self.protocol_version;
+				return Ok(TryAccept::Accepted(client));
+			}
+
+			match self.stream.write(&self.response[self.written..]) {
+				Ok(0) => {
+					let error = io::Error::new(io::ErrorKind::WriteZero, "failed to write the whole handshake response");
+					return Err((self.stream, error));
+				}
+				Ok(n) => self.written += n,
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(TryAccept::Pending(self)),
+				Err(err) => return Err((self.stream, err)),
+			}
+		}
+	}
+}
+
+/// Whether `buf` contains a full set of HTTP headers (ending in a blank
+/// line), i.e. enough to hand to `parse_request`.
+fn headers_complete(buf: &[u8]) -> bool {
+	buf.windows(4).any(|w| w == b"\r\n\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Write;
+	use stream::MockStream;
+
+	const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+	#[test]
+	fn stays_pending_until_the_headers_are_fully_read() {
+		let stream = MockStream::new(&HANDSHAKE_REQUEST[..10]);
+		let partial = PartialUpgrade::new(stream, vec![13], 0);
+
+		match partial.try_upgrade() {
+			Ok(TryUpgrade::Pending(_)) => {}
+			_ => panic!("expected Pending with a partial handshake"),
+		}
+	}
+
+	#[test]
+	fn upgrades_once_fed_the_whole_handshake_across_several_calls() {
+		let stream = MockStream::new(&HANDSHAKE_REQUEST[..10]);
+		let mut partial = PartialUpgrade::new(stream, vec![13], 42);
+
+		partial = match partial.try_upgrade() {
+			Ok(TryUpgrade::Pending(partial)) => partial,
+			_ => panic!("expected Pending with a partial handshake"),
+		};
+
+		partial.stream.push_input(&HANDSHAKE_REQUEST[10..]);
+
+		match partial.try_upgrade() {
+			Ok(TryUpgrade::Upgraded(upgrade)) => {
+				assert_eq!(upgrade.connection_id(), 42);
+			}
+			other => panic!("expected Upgraded, got a different result: {}", match other {
+				Ok(TryUpgrade::Pending(_)) => "Pending",
+				Err(_) => "Err",
+				_ => unreachable!(),
+			}),
+		}
+	}
+
+	#[test]
+	fn upgrades_when_the_handshake_arrives_one_byte_at_a_time() {
+		let stream = MockStream::new(&HANDSHAKE_REQUEST[..1]);
+		let mut partial = PartialUpgrade::new(stream, vec![13], 7);
+
+		for &byte in &HANDSHAKE_REQUEST[1..] {
+			partial = match partial.try_upgrade() {
+				Ok(TryUpgrade::Pending(partial)) => partial,
+				other => panic!("expected Pending, got a different result: {}", match other {
+					Ok(TryUpgrade::Upgraded(_)) => "Upgraded",
+					Err(_) => "Err",
+					_ => unreachable!(),
+				}),
+			};
+			partial.stream.push_input(&[byte]);
+		}
+
+		match partial.try_upgrade() {
+			Ok(TryUpgrade::Upgraded(upgrade)) => {
+				assert_eq!(upgrade.connection_id(), 7);
+			}
+			other => panic!("expected Upgraded, got a different result: {}", match other {
+				Ok(TryUpgrade::Pending(_)) => "Pending",
+				Err(_) => "Err",
+				_ => unreachable!(),
+			}),
+		}
+	}
+
+	#[test]
+	fn gives_up_once_the_buffered_handshake_crosses_max_header_size() {
+		let stream = MockStream::new(&HANDSHAKE_REQUEST[..10]);
+		let partial = PartialUpgrade::new(stream, vec![13], 0).with_max_header_size(5);
+
+		match partial.try_upgrade() {
+			Err((_, _, _, HyperIntoWsError::HandshakeTooLarge { limit, seen })) => {
+				assert_eq!(limit, 5);
+				assert_eq!(seen, 10);
+			}
+			other => panic!("expected HandshakeTooLarge, got a different result: {}", match other {
+				Ok(TryUpgrade::Pending(_)) => "Pending",
+				Ok(TryUpgrade::Upgraded(_)) => "Upgraded",
+				Err(_) => "Err",
+			}),
+		}
+	}
+
+	#[test]
+	fn gives_up_once_the_handshake_carries_too_many_headers() {
+		let stream = MockStream::new(HANDSHAKE_REQUEST);
+		let partial = PartialUpgrade::new(stream, vec![13], 0).with_max_header_count(2);
+
+		match partial.try_upgrade() {
+			Err((_, _, _, HyperIntoWsError::TooManyHeaders { limit, seen })) => {
+				assert_eq!(limit, 2);
+				assert_eq!(seen, 4);
+			}
+			other => panic!("expected TooManyHeaders, got a different result: {}", match other {
+				Ok(TryUpgrade::Pending(_)) => "Pending",
+				Ok(TryUpgrade::Upgraded(_)) => "Upgraded",
+				Err(_) => "Err",
+			}),
+		}
+	}
+
+	#[test]
+	fn upgrades_when_the_header_count_is_within_the_limit() {
+		let stream = MockStream::new(HANDSHAKE_REQUEST);
+		let partial = PartialUpgrade::new(stream, vec![13], 0).with_max_header_count(4);
+
+		match partial.try_upgrade() {
+			Ok(TryUpgrade::Upgraded(_)) => {}
+			other => panic!("expected Upgraded, got a different result: {}", match other {
+				Ok(TryUpgrade::Pending(_)) => "Pending",
+				Err(_) => "Err",
+				_ => unreachable!(),
+			}),
+		}
+	}
+
+	/// Runs a full handshake read over a `MockStream`, then swaps in
+	/// `stream` as the `WsUpgrade`'s stream - letting the tests below drive
+	/// `accept_incremental`/`try_write` over whichever stream type they
+	/// need without also having to feed a full handshake request through
+	/// it.
+	fn upgraded<S>(stream: S, connection_id: u64) -> WsUpgrade<S, Option<Buffer>>
+	where
+		S: Stream,
+	{
+		let mock = MockStream::new(HANDSHAKE_REQUEST);
+		let partial = PartialUpgrade::new(mock, vec![13], connection_id);
+		let mock_upgrade = match partial.try_upgrade() {
+			Ok(TryUpgrade::Upgraded(upgrade)) => upgrade,
+			_ => panic!("expected Upgraded"),
+		};
+
+		WsUpgrade {
+			headers: mock_upgrade.headers,
+			stream,
+			request: mock_upgrade.request,
+			buffer: mock_upgrade.buffer,
+			context: None,
+			connection_id: mock_upgrade.connection_id,
+			switching_protocols_reason: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+		}
+	}
+
+	#[test]
+	fn accept_incremental_completes_in_one_call_over_a_stream_with_room_for_it_all() {
+		let upgrade = upgraded(MockStream::new(HANDSHAKE_REQUEST), 5);
+
+		match upgrade.accept_incremental().try_write() {
+			Ok(TryAccept::Accepted(client)) => assert_eq!(client.connection_id(), 5),
+			other => panic!("expected Accepted, got a different result: {}", match other {
+				Ok(TryAccept::Pending(_)) => "Pending",
+				Err(_) => "Err",
+				_ => unreachable!(),
+			}),
+		}
+	}
+
+	/// A stream that only accepts up to `allowance` bytes per `write` call
+	/// before refusing the rest with `WouldBlock`, to exercise
+	/// `PartialAccept` being driven across several `try_write` calls the
+	/// same way a real non-blocking socket with a small send buffer would
+	/// be.
+	struct ChunkedStream {
+		written: Vec<u8>,
+		allowance: usize,
+	}
+
+	impl ChunkedStream {
+		fn new(allowance: usize) -> Self {
+			ChunkedStream {
+				written: Vec::new(),
+				allowance,
+			}
+		}
+
+		fn allow_more(&mut self, n: usize) {
+			self.allowance += n;
+		}
+	}
+
+	impl io::Read for ChunkedStream {
+		fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+			Ok(0)
+		}
+	}
+
+	impl Write for ChunkedStream {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			if self.allowance == 0 {
+				return Err(io::Error::new(io::ErrorKind::WouldBlock, "no room"));
+			}
+			let n = ::std::cmp::min(self.allowance, buf.len());
+			self.written.extend_from_slice(&buf[..n]);
+			self.allowance -= n;
+			Ok(n)
+		}
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn accept_incremental_is_driven_to_completion_across_several_calls() {
+		let upgrade = upgraded(ChunkedStream::new(0), 9);
+		let mut partial = upgrade.accept_incremental();
+		let total_len = partial.total_len();
+		assert!(total_len > 0);
+
+		// No allowance yet: every byte is refused.
+		partial = match partial.try_write() {
+			Ok(TryAccept::Pending(partial)) => partial,
+			_ => panic!("expected Pending with no allowance"),
+		};
+		assert_eq!(partial.bytes_written(), 0);
+
+		// Trickle in just enough allowance to need several more rounds.
+		while partial.bytes_written() < total_len {
+			partial.stream.allow_more(3);
+			partial = match partial.try_write() {
+				Ok(TryAccept::Pending(partial)) => partial,
+				Ok(TryAccept::Accepted(client)) => {
+					assert_eq!(client.connection_id(), 9);
+					return;
+				}
+				Err((_, err)) => panic!("unexpected error: {:?}", err),
+			};
+		}
+		panic!("loop should have returned via TryAccept::Accepted");
+	}
+}
@@ -1,15 +1,18 @@
 //! Allows you to take an existing request or stream of data and convert it into a
 //! WebSocket client.
-use header::extensions::Extension;
+use header::extensions::{Extension, Parameter};
 use header::{
 	Origin, WebSocketAccept, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion,
 };
+use std::any::Any;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use stream::Stream;
 
-use hyper::header::{Connection, ConnectionOption, Headers, Protocol, ProtocolName, Upgrade};
+use hyper::header::{
+	Connection, ConnectionOption, Cookie, Headers, Protocol, ProtocolName, Upgrade,
+};
 use hyper::http::h1::Incoming;
 use hyper::method::Method;
 use hyper::status::StatusCode;
@@ -25,9 +28,133 @@ pub mod async;
 #[cfg(feature = "sync")]
 pub mod sync;
 
+#[cfg(feature = "sync")]
+pub mod incremental;
+
 /// A typical request from hyper
 pub type Request = Incoming<(Method, RequestUri)>;
 
+/// This server's preferences for the RFC 7692 `permessage-deflate`
+/// extension, passed to `WsUpgrade::allow_deflate`. Both fields are choices
+/// this server is free to make unilaterally for the messages *it* sends -
+/// they don't depend on anything the client offered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateParams {
+	/// Reset this server's compression context after every message it
+	/// sends, trading away some compression ratio between messages for
+	/// materially lower memory held per idle connection.
+	pub server_no_context_takeover: bool,
+	/// The largest LZ77 sliding window, as `2^n` bytes, this server will use
+	/// while compressing. Applies whether or not the client's offer included
+	/// `server_max_window_bits`; if it did, that value caps this one
+	/// further. Valid range is `8..=15`; out of range values saturate.
+	pub server_max_window_bits: u8,
+}
+
+impl Default for DeflateParams {
+	/// No context takeover reset, and the full RFC 7692 default window size.
+	fn default() -> Self {
+		DeflateParams {
+			server_no_context_takeover: false,
+			server_max_window_bits: 15,
+		}
+	}
+}
+
+/// What `negotiate_deflate` agreed to, combining this server's
+/// `DeflateParams` with whatever the client's offer allowed.
+struct AgreedDeflateParams {
+	server_no_context_takeover: bool,
+	client_no_context_takeover: bool,
+	server_max_window_bits: u8,
+	client_max_window_bits: Option<u8>,
+}
+
+impl AgreedDeflateParams {
+	/// Builds the `Sec-WebSocket-Extensions` response entry for what was
+	/// agreed - per RFC 7692 ยง7.1, only the parameters that actually narrow
+	/// something down need to be present; `server_max_window_bits` is
+	/// omitted at its default of 15 since that's a no-op either way.
+	fn into_extension(self) -> Extension {
+		let mut params = Vec::new();
+		if self.server_no_context_takeover {
+			params.push(Parameter::new("server_no_context_takeover".to_string(), None));
+		}
+		if self.client_no_context_takeover {
+			params.push(Parameter::new("client_no_context_takeover".to_string(), None));
+		}
+		if self.server_max_window_bits != 15 {
+			params.push(Parameter::new(
+				"server_max_window_bits".to_string(),
+				Some(self.server_max_window_bits.to_string()),
+			));
+		}
+		if let Some(bits) = self.client_max_window_bits {
+			params.push(Parameter::new(
+				"client_max_window_bits".to_string(),
+				Some(bits.to_string()),
+			));
+		}
+
+		Extension {
+			name: "permessage-deflate".to_string(),
+			params,
+		}
+	}
+}
+
+/// Parses `offer` (assumed to already be named `permessage-deflate`)
+/// against this server's `preferred` settings, returning `None` if the
+/// offer uses a parameter this crate doesn't recognise, a flag parameter
+/// that carries a value, a `server_max_window_bits` that doesn't carry one, or
+/// a window size outside `8..=15` - any of which should fall back to no
+/// compression rather than failing the whole handshake.
+fn negotiate_deflate(offer: &Extension, preferred: DeflateParams) -> Option<AgreedDeflateParams> {
+	let mut client_no_context_takeover = false;
+	let mut server_no_context_takeover = preferred.server_no_context_takeover;
+	let mut offered_server_max_window_bits = None;
+	let mut client_max_window_bits = None;
+
+	for param in &offer.params {
+		match (param.name.to_lowercase().as_str(), &param.value) {
+			("server_no_context_takeover", None) => server_no_context_takeover = true,
+			("client_no_context_takeover", None) => client_no_context_takeover = true,
+			("server_max_window_bits", Some(bits)) => {
+				offered_server_max_window_bits = Some(parse_window_bits(bits)?);
+			}
+			("client_max_window_bits", Some(bits)) => {
+				client_max_window_bits = Some(parse_window_bits(bits)?);
+			}
+			("client_max_window_bits", None) => {
+				// The client supports capping its own window but leaves the
+				// cap up to us - we have no need to shrink it, so nothing is
+				// echoed back for this parameter.
+			}
+			_ => return None,
+		}
+	}
+
+	let server_max_window_bits = offered_server_max_window_bits
+		.unwrap_or(15)
+		.min(preferred.server_max_window_bits.max(8).min(15));
+
+	Some(AgreedDeflateParams {
+		server_no_context_takeover,
+		client_no_context_takeover,
+		server_max_window_bits,
+		client_max_window_bits,
+	})
+}
+
+fn parse_window_bits(raw: &str) -> Option<u8> {
+	let bits: u8 = raw.trim().parse().ok()?;
+	if (8..=15).contains(&bits) {
+		Some(bits)
+	} else {
+		None
+	}
+}
+
 /// Intermediate representation of a half created websocket session.
 /// Should be used to examine the client's handshake
 /// accept the protocols requested, route the path, etc.
@@ -50,6 +177,22 @@ where
 	pub request: Request,
 	/// Some buffered data from the stream, if it exists.
 	pub buffer: B,
+	/// Arbitrary user data attached via `set_context`, carried along so it
+	/// is available once `accept` hands back a `Client`.
+	context: Option<Box<Any + Send>>,
+	/// Id assigned by `WsServer::accept` at accept time, for correlating
+	/// log lines across a connection's lifetime. `0` for upgrades that
+	/// didn't go through a `WsServer` (e.g. the blanket `IntoWs` impl, or
+	/// `HyperRequest`). See `WsUpgrade::connection_id`.
+	pub(crate) connection_id: u64,
+	/// Overrides the reason phrase on a `101 Switching Protocols` response,
+	/// leaving the status code itself untouched. `None` uses hyper's
+	/// canonical phrase, as before. See
+	/// `WsServer::switching_protocols_reason`.
+	pub(crate) switching_protocols_reason: Option<String>,
+	/// Status and optional `Retry-After` seconds `WsUpgrade::reject_unavailable`
+	/// sends. See `WsServer::overload_response`.
+	pub(crate) overload_response: (StatusCode, Option<u32>),
 }
 
 impl<S, B> WsUpgrade<S, B>
@@ -68,6 +211,25 @@ where
 		self
 	}
 
+	/// Like `use_protocol`, but only if the client actually offered
+	/// `protocol` in `protocols()` - `use_protocol` will happily echo back
+	/// one the client never asked for, which a spec-compliant client will
+	/// treat as a handshake failure. Returns `Err(self)` unchanged if the
+	/// client didn't offer `protocol` (including the case where it offered
+	/// no protocols at all), so the caller can try a different candidate,
+	/// fall back to no protocol, or `reject` the connection outright.
+	pub fn negotiate_protocol<P>(self, protocol: P) -> Result<Self, Self>
+	where
+		P: AsRef<str>,
+	{
+		if self.protocols().iter().any(|offered| offered == protocol.as_ref()) {
+			let protocol = protocol.as_ref().to_owned();
+			Ok(self.use_protocol(protocol))
+		} else {
+			Err(self)
+		}
+	}
+
 	/// Select an extension to use in the handshake response.
 	pub fn use_extension(mut self, extension: Extension) -> Self {
 		upsert_header!(self.headers; WebSocketExtensions; {
@@ -90,6 +252,40 @@ where
 		self
 	}
 
+	/// Negotiate the RFC 7692 `permessage-deflate` extension if the client
+	/// offered it in `Sec-WebSocket-Extensions`, using `preferred` for the
+	/// two choices this server gets to make unilaterally (whether it resets
+	/// its own compression context every message, and the largest window it
+	/// is willing to use). A no-op - `self` unchanged - if the client didn't
+	/// offer `permessage-deflate`, or offered it with parameters this crate
+	/// can't make sense of (an unknown parameter, a flag param carrying a
+	/// value, or a window size outside `8..=15`): a malformed offer falls
+	/// back to no compression rather than failing the handshake, per RFC
+	/// 7692 ยง5.
+	///
+	/// This only negotiates the extension handshake - check
+	/// `negotiated_extensions()` afterwards to see what was agreed. The
+	/// resulting `Client`'s frames are **not** transparently (de)compressed:
+	/// wiring an inflate/deflate layer into `send_message`/`recv_message`
+	/// touches the `Sender`/`Receiver`/`DataFrame` pipeline for both the
+	/// sync and async clients, which is a substantially larger change than
+	/// this handshake negotiation and is left for a dedicated follow-up.
+	pub fn allow_deflate(self, preferred: DeflateParams) -> Self {
+		let offer = match self
+			.extensions()
+			.iter()
+			.find(|e| e.name.eq_ignore_ascii_case("permessage-deflate"))
+		{
+			Some(offer) => offer,
+			None => return self,
+		};
+
+		match negotiate_deflate(offer, preferred) {
+			Some(agreed) => self.use_extension(agreed.into_extension()),
+			None => self,
+		}
+	}
+
 	/// Drop the connection without saying anything.
 	pub fn drop(self) {
 		::std::mem::drop(self);
@@ -113,36 +309,243 @@ where
 			.unwrap_or(&[])
 	}
 
+	/// The extensions that will actually be sent back once this handshake
+	/// is accepted, i.e. whatever was set via `use_extension`/
+	/// `use_extensions` - as opposed to `extensions`, which is what the
+	/// client merely asked for. Each `Extension`'s `params` carries
+	/// whatever was agreed, such as a `permessage-deflate` extension's
+	/// window bits or context takeover flags. Empty if nothing was set,
+	/// which also means nothing will be negotiated.
+	pub fn negotiated_extensions(&self) -> &[Extension] {
+		self.headers
+			.get::<WebSocketExtensions>()
+			.map(|e| e.0.as_slice())
+			.unwrap_or(&[])
+	}
+
 	/// The client's websocket accept key.
 	pub fn key(&self) -> Option<&[u8; 16]> {
 		self.request.headers.get::<WebSocketKey>().map(|k| &k.0)
 	}
 
+	/// The raw `Sec-WebSocket-Key` nonce, base64 encoded exactly as the
+	/// client sent it. Useful for a custom handshake responder built on top
+	/// of `IntoWs` that computes its own `Sec-WebSocket-Accept` (or just
+	/// wants to log the nonce) rather than going through `accept`/`send`.
+	/// `None` only if the header is somehow absent, which would already
+	/// have failed `validate`.
+	pub fn websocket_key(&self) -> Option<String> {
+		self.request
+			.headers
+			.get::<WebSocketKey>()
+			.map(|k| k.serialize())
+	}
+
 	/// The client's websocket version.
 	pub fn version(&self) -> Option<&WebSocketVersion> {
 		self.request.headers.get::<WebSocketVersion>()
 	}
 
+	/// The `Sec-WebSocket-Version` agreed for this handshake, e.g.
+	/// `WebSocketVersion::WebSocket13`. Unlike `version`, this always
+	/// returns a value: `validate`/`validate_with_versions` already reject
+	/// a request missing the header before a `WsUpgrade` can exist, so this
+	/// falls back to `WebSocketVersion::WebSocket13` only for upgrades
+	/// built without going through validation.
+	pub fn protocol_version(&self) -> WebSocketVersion {
+		self.version().cloned().unwrap_or(WebSocketVersion::WebSocket13)
+	}
+
 	/// The original request URI.
 	pub fn uri(&self) -> String {
 		format!("{}", self.request.subject.1)
 	}
 
+	/// Just the path component of `uri`, with any query string stripped -
+	/// e.g. `/chat` for a request to `/chat?room=42`. Handy for routing
+	/// decisions (see `WsServer::allowed_paths`) that shouldn't have to care
+	/// whether the client tacked on a query string.
+	///
+	/// Allocates a fresh `String`; `path_str` does the same thing as a
+	/// zero-copy borrow if that allocation matters on your accept path.
+	pub fn path(&self) -> String {
+		let uri = self.uri();
+		match uri.find('?') {
+			Some(pos) => uri[..pos].to_owned(),
+			None => uri,
+		}
+	}
+
+	/// Like `path`, but borrows straight out of the already-parsed request
+	/// instead of allocating a `String` - useful for a high-accept-rate
+	/// server that routes on the path (and maybe a header or two via
+	/// `header`) before deciding whether to `accept`/`reject`, and would
+	/// rather not pay a `String` allocation on every handshake just to make
+	/// that call. Falls back to `""` for the `CONNECT`/`OPTIONS *`
+	/// request-target forms, which don't carry a path at all.
+	pub fn path_str(&self) -> &str {
+		match self.request.subject.1 {
+			RequestUri::AbsolutePath(ref path) => match path.find('?') {
+				Some(pos) => &path[..pos],
+				None => path,
+			},
+			RequestUri::AbsoluteUri(ref url) => url.path(),
+			RequestUri::Authority(_) | RequestUri::Star => "",
+		}
+	}
+
 	/// Origin of the client
 	pub fn origin(&self) -> Option<&str> {
 		self.request.headers.get::<Origin>().map(|o| &o.0 as &str)
 	}
 
+	/// Id assigned by `WsServer::accept` when this connection came in,
+	/// monotonically increasing (wrapping on `u64` overflow) and unique
+	/// among connections accepted by the same server, so logs for one
+	/// connection can be correlated across threads. `0` for upgrades that
+	/// didn't go through a `WsServer`.
+	pub fn connection_id(&self) -> u64 {
+		self.connection_id
+	}
+
+	/// Look up a request header by name, case-insensitively, and return its
+	/// first value as a string. Handy for pulling out arbitrary `X-` headers
+	/// (auth tokens, routing hints, ...) without going through hyper's typed
+	/// `Header` trait.
+	///
+	/// If the header was sent more than once, only the first value is
+	/// returned; use `headers_raw` to see every value.
+	pub fn header(&self, name: &str) -> Option<&str> {
+		self.request
+			.headers
+			.get_raw(name)
+			.and_then(|values| values.first())
+			.and_then(|value| ::std::str::from_utf8(value).ok())
+	}
+
+	/// All of the client's request headers, for looking one up through
+	/// hyper's typed `Header` trait (`upgrade.headers().get::<UserAgent>()`)
+	/// rather than the string-based `header`/`headers_raw` above. Mirrors
+	/// `Client::headers` on the other side of the handshake.
+	pub fn headers(&self) -> &Headers {
+		&self.request.headers
+	}
+
+	/// Attach an arbitrary piece of user data to this handshake-in-progress,
+	/// such as a connection id picked before routing. Replaces whatever was
+	/// attached before. This slot is `None` until set, so it costs nothing
+	/// if you never use it. Note that it does not carry over to the
+	/// `Client` produced by `accept`; call `Client::set_context` again there
+	/// if you need it afterwards.
+	pub fn set_context<T: Any + Send>(&mut self, value: T) {
+		self.context = Some(Box::new(value));
+	}
+
+	/// Borrow the attached user data, if any was set via `set_context` and
+	/// it was set with this same type `T`.
+	pub fn context<T: Any + Send>(&self) -> Option<&T> {
+		self.context.as_ref().and_then(|c| c.downcast_ref())
+	}
+
+	/// Mutably borrow the attached user data, if any was set via
+	/// `set_context` and it was set with this same type `T`.
+	pub fn context_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+		self.context.as_mut().and_then(|c| c.downcast_mut())
+	}
+
+	/// All request headers as case-insensitively-keyed `(name, value)`
+	/// string pairs. A header sent multiple times (such as repeated
+	/// `Cookie` lines) appears once per value, with the same name.
+	pub fn headers_raw(&self) -> Vec<(String, String)> {
+		self.request
+			.headers
+			.iter()
+			.map(|view| (view.name().to_string(), view.value_string()))
+			.collect()
+	}
+
+	/// The request's cookies, parsed from its `Cookie` header(s) into
+	/// name/value pairs. Handles a client that packs several cookies into
+	/// one `Cookie: a=1; b=2` header as well as one that sends several
+	/// `Cookie` header lines. A segment that isn't a `name=value` pair
+	/// (or whose name is empty) is skipped rather than failing the whole
+	/// parse.
+	pub fn cookies(&self) -> Vec<(String, String)> {
+		self.request
+			.headers
+			.get::<Cookie>()
+			.map(|cookie| {
+				cookie
+					.0
+					.iter()
+					.filter_map(|pair| {
+						let mut parts = pair.splitn(2, '=');
+						let name = parts.next()?.trim();
+						let value = parts.next()?.trim();
+						if name.is_empty() {
+							None
+						} else {
+							Some((name.to_owned(), value.to_owned()))
+						}
+					})
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	/// The first address in the request's `X-Forwarded-For` header, if any -
+	/// the client IP as reported by the nearest hop of a reverse proxy
+	/// chain. This is taken from the request as-is and does not check that
+	/// the immediate peer is actually a proxy you trust; a direct client is
+	/// free to set this header to anything. See
+	/// `server::upgrade::sync::WsUpgrade::client_ip` for a version that only
+	/// trusts it from configured proxy addresses.
+	pub fn x_forwarded_for(&self) -> Option<&str> {
+		self.header("X-Forwarded-For")
+			.and_then(|v| v.split(',').next())
+			.map(|v| v.trim())
+	}
+
+	/// The request's `X-Real-IP` header, if any - same caveat as
+	/// `x_forwarded_for`: taken as-is, not verified against a trusted proxy
+	/// list.
+	pub fn x_real_ip(&self) -> Option<&str> {
+		self.header("X-Real-IP")
+	}
+
 	#[cfg(feature = "sync")]
 	fn send(&mut self, status: StatusCode) -> io::Result<()> {
-		let data = format!(
-			"{} {}\r\n{}\r\n",
-			self.request.version, status, self.headers
-		);
-		self.stream.write_all(data.as_bytes())?;
+		let data = self.response_bytes(status);
+		self.stream.write_all(&data)?;
 		Ok(())
 	}
 
+	/// Serializes the exact bytes `accept`/`accept_with`/`reject`/
+	/// `reject_with` would write to the stream for `status`, using
+	/// `self.headers` as they currently stand, without writing or
+	/// consuming anything.
+	///
+	/// Call `prepare_headers` first if you want the headers `accept` would
+	/// add (including the computed `Sec-WebSocket-Accept`) reflected here;
+	/// this is handy for conformance tests or debugging a picky client that
+	/// want to assert on the literal response bytes (header ordering and
+	/// all) rather than just the parsed headers.
+	pub fn response_bytes(&self, status: StatusCode) -> Vec<u8> {
+		if status == StatusCode::SwitchingProtocols {
+			if let Some(ref reason) = self.switching_protocols_reason {
+				return format!(
+					"{} {} {}\r\n{}\r\n",
+					self.request.version,
+					status.to_u16(),
+					reason,
+					self.headers
+				)
+				.into_bytes();
+			}
+		}
+		format!("{} {}\r\n{}\r\n", self.request.version, status, self.headers).into_bytes()
+	}
+
 	#[doc(hidden)]
 	pub fn prepare_headers(&mut self, custom: Option<&Headers>) -> StatusCode {
 		if let Some(headers) = custom {
@@ -175,6 +578,9 @@ pub enum HyperIntoWsError {
 	UnsupportedWebsocketVersion,
 	/// A websocket upgrade request must contain a key
 	NoSecWsKeyHeader,
+	/// A websocket upgrade request's `Sec-WebSocket-Key` must be valid
+	/// base64 encoding exactly 16 bytes, per RFC6455 section 4.1
+	InvalidSecWsKeyHeader,
 	/// A websocket upgrade request must ask to upgrade to a `websocket`
 	NoWsUpgradeHeader,
 	/// A websocket upgrade request must contain an `Upgrade` header
@@ -187,6 +593,60 @@ pub enum HyperIntoWsError {
 	Io(io::Error),
 	/// Error while parsing an incoming request
 	Parsing(::hyper::error::Error),
+	/// The TLS handshake itself failed (bad certificate, unsupported
+	/// protocol version, ...), as opposed to a plain I/O failure reading
+	/// the socket. Kept distinct so metrics/alerting can tell a
+	/// certificate or protocol problem apart from a network one.
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	Tls(::native_tls::Error),
+	/// The handshake request's headers didn't finish arriving before
+	/// crossing a configured size limit, e.g.
+	/// `server::upgrade::incremental::PartialUpgrade::with_max_header_size`.
+	/// `seen` is the number of bytes buffered when the limit was tripped;
+	/// `limit` is the configured maximum.
+	HandshakeTooLarge {
+		/// the configured maximum number of handshake bytes
+		limit: usize,
+		/// the number of bytes buffered when the limit was tripped
+		seen: usize,
+	},
+	/// The handshake request carried more headers than a configured count
+	/// limit, e.g.
+	/// `server::upgrade::incremental::PartialUpgrade::with_max_header_count`.
+	/// Guards against a request stuffed with thousands of tiny headers,
+	/// which `HandshakeTooLarge`'s byte-size limit alone would only catch
+	/// once they added up to a lot of bytes. `seen` is the number of
+	/// headers the request carried; `limit` is the configured maximum.
+	TooManyHeaders {
+		/// the configured maximum number of headers
+		limit: usize,
+		/// the number of headers the request carried
+		seen: usize,
+	},
+	/// The request's path wasn't in a configured allow-list, e.g.
+	/// `WsServer::allowed_paths`. The handshake itself was otherwise fine;
+	/// the request is rejected with `404 Not Found` rather than
+	/// `400 Bad Request`.
+	PathNotAllowed {
+		/// the path the client asked for, with any query string stripped
+		path: String,
+	},
+	/// The handshake itself was fine, but the server was overloaded and
+	/// rejected it anyway via `WsUpgrade::reject_unavailable`, e.g. from an
+	/// application-level connection cap or rate limiter.
+	Overloaded,
+	/// The client's `Sec-WebSocket-Protocol` offer didn't include any of
+	/// `WsServer::require_protocol`'s configured protocols. The handshake
+	/// itself was otherwise fine; the request is rejected with the usual
+	/// `400 Bad Request` and no `Sec-WebSocket-Protocol` header in the
+	/// response.
+	NoMatchingProtocol,
+	/// `accept` woke up because `WsServer::shutdown_handle` (or the
+	/// `ShutdownHandle` it returns) was used to stop the server, rather
+	/// than because of an actual incoming connection or a network error.
+	/// Distinguishing this from a plain `Io` error lets an accept loop tell
+	/// "stop" apart from "something went wrong".
+	ShutDown,
 }
 
 impl Display for HyperIntoWsError {
@@ -203,19 +663,30 @@ impl Error for HyperIntoWsError {
 			UnsupportedHttpVersion => "Unsupported request HTTP version",
 			UnsupportedWebsocketVersion => "Unsupported WebSocket version",
 			NoSecWsKeyHeader => "Missing Sec-WebSocket-Key header",
+			InvalidSecWsKeyHeader => "Sec-WebSocket-Key must be valid base64 of 16 bytes",
 			NoWsUpgradeHeader => "Invalid Upgrade WebSocket header",
 			NoUpgradeHeader => "Missing Upgrade WebSocket header",
 			NoWsConnectionHeader => "Invalid Connection WebSocket header",
 			NoConnectionHeader => "Missing Connection WebSocket header",
 			Io(ref e) => e.description(),
 			Parsing(ref e) => e.description(),
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			Tls(ref e) => e.description(),
+			HandshakeTooLarge { .. } => "Handshake request headers exceeded the configured size limit",
+			TooManyHeaders { .. } => "Handshake request carried more headers than the configured limit",
+			PathNotAllowed { .. } => "Handshake request's path is not in the server's allowed_paths",
+			Overloaded => "The server is at capacity and rejected this handshake",
+			NoMatchingProtocol => "Handshake request didn't offer any of the server's required_protocol(s)",
+			ShutDown => "The server was shut down via a ShutdownHandle",
 		}
 	}
 
-	fn cause(&self) -> Option<&Error> {
+	fn source(&self) -> Option<&(Error + 'static)> {
 		match *self {
 			HyperIntoWsError::Io(ref e) => Some(e),
 			HyperIntoWsError::Parsing(ref e) => Some(e),
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			HyperIntoWsError::Tls(ref e) => Some(e),
 			_ => None,
 		}
 	}
@@ -227,12 +698,47 @@ impl From<io::Error> for HyperIntoWsError {
 	}
 }
 
+impl HyperIntoWsError {
+	/// Whether this looks like a failure an `accept` loop should pause and
+	/// retry rather than treat as fatal - currently `WouldBlock`/
+	/// `Interrupted`, and on Unix, running out of file descriptors
+	/// (`EMFILE`/`ENFILE`), which a server can often recover from once
+	/// existing connections close or the OS limit is raised.
+	pub fn is_transient(&self) -> bool {
+		match *self {
+			HyperIntoWsError::Io(ref err) => is_transient_io_error(err),
+			_ => false,
+		}
+	}
+}
+
+#[cfg(unix)]
+fn is_transient_io_error(err: &io::Error) -> bool {
+	// EMFILE (24) and ENFILE (23) on Linux, macOS and the BSDs.
+	match err.kind() {
+		io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => true,
+		_ => matches!(err.raw_os_error(), Some(23) | Some(24)),
+	}
+}
+
+#[cfg(not(unix))]
+fn is_transient_io_error(err: &io::Error) -> bool {
+	matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted)
+}
+
 impl From<::hyper::error::Error> for HyperIntoWsError {
 	fn from(err: ::hyper::error::Error) -> Self {
 		HyperIntoWsError::Parsing(err)
 	}
 }
 
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+impl From<::native_tls::Error> for HyperIntoWsError {
+	fn from(err: ::native_tls::Error) -> Self {
+		HyperIntoWsError::Tls(err)
+	}
+}
+
 #[cfg(feature = "async")]
 impl From<::codec::http::HttpCodecError> for HyperIntoWsError {
 	fn from(src: ::codec::http::HttpCodecError) -> Self {
@@ -245,10 +751,27 @@ impl From<::codec::http::HttpCodecError> for HyperIntoWsError {
 
 #[cfg(any(feature = "sync", feature = "async"))]
 /// Check whether an incoming request is a valid WebSocket upgrade attempt.
+///
+/// Only `Sec-WebSocket-Version: 13` (RFC6455) is accepted. To accept a
+/// different (or wider) set of versions, such as for a server configured
+/// with `WsServer::accepted_versions`, use `validate_with_versions` instead.
 pub fn validate(
 	method: &Method,
 	version: HttpVersion,
 	headers: &Headers,
+) -> Result<(), HyperIntoWsError> {
+	validate_with_versions(method, version, headers, &[13])
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+/// Like `validate`, but the set of `Sec-WebSocket-Version` values that
+/// are considered acceptable is given explicitly rather than being fixed
+/// to `13`.
+pub fn validate_with_versions(
+	method: &Method,
+	version: HttpVersion,
+	headers: &Headers,
+	accepted_versions: &[u8],
 ) -> Result<(), HyperIntoWsError> {
 	if *method != Method::Get {
 		return Err(HyperIntoWsError::MethodNotGet);
@@ -259,13 +782,23 @@ pub fn validate(
 	}
 
 	if let Some(version) = headers.get::<WebSocketVersion>() {
-		if version != &WebSocketVersion::WebSocket13 {
+		let ok = match *version {
+			WebSocketVersion::WebSocket13 => accepted_versions.contains(&13),
+			WebSocketVersion::Unknown(ref v) => {
+				v.parse::<u8>().map(|v| accepted_versions.contains(&v)).unwrap_or(false)
+			}
+		};
+		if !ok {
 			return Err(HyperIntoWsError::UnsupportedWebsocketVersion);
 		}
 	}
 
 	if headers.get::<WebSocketKey>().is_none() {
-		return Err(HyperIntoWsError::NoSecWsKeyHeader);
+		return Err(if headers.get_raw("Sec-WebSocket-Key").is_some() {
+			HyperIntoWsError::InvalidSecWsKeyHeader
+		} else {
+			HyperIntoWsError::NoSecWsKeyHeader
+		});
 	}
 
 	match headers.get() {
@@ -299,3 +832,412 @@ pub fn validate(
 
 	Ok(())
 }
+
+#[cfg(all(test, any(feature = "sync", feature = "async")))]
+mod tests {
+	use super::*;
+	use hyper::header::{Connection, ConnectionOption, Protocol, ProtocolName, Upgrade};
+
+	fn handshake_headers() -> Headers {
+		let mut headers = Headers::new();
+		headers.set(Connection(vec![ConnectionOption::ConnectionHeader(
+			UniCase("Upgrade".to_string()),
+		)]));
+		headers.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+		headers.set_raw("Sec-WebSocket-Version", vec![b"13".to_vec()]);
+		headers
+	}
+
+	#[test]
+	fn rejects_key_that_is_not_16_bytes() {
+		let mut headers = handshake_headers();
+		// valid base64, but decodes to 8 bytes rather than the required 16
+		headers.set_raw("Sec-WebSocket-Key", vec![b"dG9vc2hvcnQ=".to_vec()]);
+
+		match validate(&Method::Get, HttpVersion::Http11, &headers) {
+			Err(HyperIntoWsError::InvalidSecWsKeyHeader) => {}
+			other => panic!("expected InvalidSecWsKeyHeader, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn rejects_missing_key() {
+		let headers = handshake_headers();
+
+		match validate(&Method::Get, HttpVersion::Http11, &headers) {
+			Err(HyperIntoWsError::NoSecWsKeyHeader) => {}
+			other => panic!("expected NoSecWsKeyHeader, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn is_transient_reports_would_block_and_interrupted_as_transient() {
+		let would_block = HyperIntoWsError::Io(io::Error::new(io::ErrorKind::WouldBlock, "nope"));
+		assert!(would_block.is_transient());
+
+		let interrupted = HyperIntoWsError::Io(io::Error::new(io::ErrorKind::Interrupted, "nope"));
+		assert!(interrupted.is_transient());
+	}
+
+	#[test]
+	fn is_transient_reports_other_errors_as_fatal() {
+		let other_io = HyperIntoWsError::Io(io::Error::new(io::ErrorKind::Other, "nope"));
+		assert!(!other_io.is_transient());
+
+		assert!(!HyperIntoWsError::MethodNotGet.is_transient());
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn negotiated_extensions_differs_from_requested_extensions() {
+		use self::sync::IntoWs;
+		use header::extensions::Parameter;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n";
+
+		let mut upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.extensions()[0].name, "permessage-deflate");
+		// nothing has been agreed to yet, so nothing is negotiated
+		assert!(upgrade.negotiated_extensions().is_empty());
+
+		let mut agreed = Extension::new("permessage-deflate".to_string());
+		agreed.params.push(Parameter::new("server_max_window_bits".to_string(), Some("10".to_string())));
+		upgrade = upgrade.use_extension(agreed);
+
+		assert_eq!(upgrade.negotiated_extensions()[0].name, "permessage-deflate");
+		assert_eq!(
+			upgrade.negotiated_extensions()[0].params[0].name,
+			"server_max_window_bits"
+		);
+	}
+
+	#[test]
+	fn allow_deflate_is_a_no_op_when_the_client_did_not_offer_it() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let upgrade = upgrade.allow_deflate(DeflateParams::default());
+		assert!(upgrade.negotiated_extensions().is_empty());
+	}
+
+	#[test]
+	fn allow_deflate_echoes_back_a_valid_client_offer() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Extensions: permessage-deflate; client_no_context_takeover; server_max_window_bits=10\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let upgrade = upgrade.allow_deflate(DeflateParams::default());
+
+		let agreed = &upgrade.negotiated_extensions()[0];
+		assert_eq!(agreed.name, "permessage-deflate");
+		assert!(agreed
+			.params
+			.iter()
+			.any(|p| p.name == "client_no_context_takeover" && p.value.is_none()));
+		assert!(agreed
+			.params
+			.iter()
+			.any(|p| p.name == "server_max_window_bits" && p.value == Some("10".to_string())));
+	}
+
+	#[test]
+	fn allow_deflate_caps_the_servers_window_at_its_own_preference() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Extensions: permessage-deflate\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let preferred = DeflateParams {
+			server_no_context_takeover: false,
+			server_max_window_bits: 10,
+		};
+		let upgrade = upgrade.allow_deflate(preferred);
+
+		let agreed = &upgrade.negotiated_extensions()[0];
+		assert!(agreed
+			.params
+			.iter()
+			.any(|p| p.name == "server_max_window_bits" && p.value == Some("10".to_string())));
+	}
+
+	#[test]
+	fn allow_deflate_falls_back_to_no_compression_on_a_malformed_offer() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Extensions: permessage-deflate; server_max_window_bits=99\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let upgrade = upgrade.allow_deflate(DeflateParams::default());
+		assert!(upgrade.negotiated_extensions().is_empty());
+	}
+
+	#[test]
+	fn negotiate_protocol_accepts_a_protocol_the_client_offered() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Protocol: chat, superchat\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let upgrade = match upgrade.negotiate_protocol("superchat") {
+			Ok(upgrade) => upgrade,
+			Err(_) => panic!("expected negotiate_protocol to accept an offered protocol"),
+		};
+		assert_eq!(
+			upgrade.headers.get::<WebSocketProtocol>().unwrap().0,
+			vec!["superchat".to_owned()]
+		);
+	}
+
+	#[test]
+	fn negotiate_protocol_rejects_a_protocol_the_client_never_offered() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Protocol: chat\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let upgrade = match upgrade.negotiate_protocol("superchat") {
+			Err(upgrade) => upgrade,
+			Ok(_) => panic!("expected negotiate_protocol to reject an unoffered protocol"),
+		};
+		assert!(upgrade.headers.get::<WebSocketProtocol>().is_none());
+	}
+
+	#[test]
+	fn negotiate_protocol_rejects_everything_when_the_client_offered_nothing() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert!(upgrade.negotiate_protocol("chat").is_err());
+	}
+
+	#[test]
+	fn cookies_parses_name_value_pairs_and_skips_malformed_segments() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Cookie: session=abc123; lang=en-US\r\n\
+Cookie: malformed; theme=dark\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(
+			upgrade.cookies(),
+			vec![
+				("session".to_owned(), "abc123".to_owned()),
+				("lang".to_owned(), "en-US".to_owned()),
+				("theme".to_owned(), "dark".to_owned()),
+			]
+		);
+	}
+
+	#[test]
+	fn cookies_is_empty_without_a_cookie_header() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert!(upgrade.cookies().is_empty());
+	}
+
+	#[test]
+	fn path_str_strips_the_query_string_without_allocating_a_string() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET /chat?room=42 HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.path_str(), "/chat");
+		assert_eq!(upgrade.path_str(), upgrade.path());
+	}
+
+	#[test]
+	fn headers_allows_a_typed_lookup_alongside_the_string_based_header() {
+		use self::sync::IntoWs;
+		use hyper::header::UserAgent;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+User-Agent: websocket-tests\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(
+			upgrade.headers().get::<UserAgent>().map(|ua| &ua.0 as &str),
+			Some("websocket-tests")
+		);
+		assert_eq!(upgrade.header("User-Agent"), Some("websocket-tests"));
+	}
+
+	#[test]
+	fn websocket_key_returns_the_raw_base64_nonce() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(
+			upgrade.websocket_key(),
+			Some("dGhlIHNhbXBsZSBub25jZQ==".to_owned())
+		);
+	}
+
+	#[test]
+	fn response_bytes_uses_the_custom_switching_protocols_reason() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let mut upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		upgrade.switching_protocols_reason = Some("Upgraded".to_owned());
+
+		let response = upgrade.response_bytes(StatusCode::SwitchingProtocols);
+		let status_line = String::from_utf8(response).unwrap().lines().next().unwrap().to_owned();
+
+		assert_eq!(status_line, "HTTP/1.1 101 Upgraded");
+	}
+
+	#[test]
+	fn response_bytes_uses_the_canonical_reason_by_default() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+
+		let response = upgrade.response_bytes(StatusCode::SwitchingProtocols);
+		let status_line = String::from_utf8(response).unwrap().lines().next().unwrap().to_owned();
+
+		assert_eq!(status_line, "HTTP/1.1 101 Switching Protocols");
+	}
+
+	#[test]
+	fn x_forwarded_for_takes_the_first_hop() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+X-Forwarded-For: 203.0.113.5, 10.0.0.1\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.x_forwarded_for(), Some("203.0.113.5"));
+	}
+
+	#[test]
+	fn x_real_ip_reads_the_header_verbatim() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+X-Real-IP: 203.0.113.5\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.x_real_ip(), Some("203.0.113.5"));
+	}
+
+	#[test]
+	fn x_forwarded_for_is_none_without_the_header() {
+		use self::sync::IntoWs;
+		use stream::MockStream;
+
+		const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.x_forwarded_for(), None);
+	}
+}
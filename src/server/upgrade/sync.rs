@@ -1,13 +1,15 @@
 //! Allows you to take an existing request or stream of data and convert it into a
 //! WebSocket client.
 use client::sync::Client;
-use server::upgrade::{validate, HyperIntoWsError, Request, WsUpgrade};
-use std::io;
-use std::net::TcpStream;
+use header::WebSocketVersion;
+use server::upgrade::incremental::PartialAccept;
+use server::upgrade::{validate, validate_with_versions, HyperIntoWsError, Request, WsUpgrade};
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, TcpStream};
 use stream::sync::{AsTcpStream, Stream};
 
 use hyper::buffer::BufReader;
-use hyper::header::Headers;
+use hyper::header::{Connection, ConnectionOption, ContentLength, Headers, Location};
 use hyper::http::h1::parse_request;
 use hyper::http::h1::Incoming;
 use hyper::net::NetworkStream;
@@ -32,6 +34,32 @@ pub struct Buffer {
 	pub cap: usize,
 }
 
+impl Buffer {
+	/// The number of buffered bytes that have not been read yet.
+	pub fn len(&self) -> usize {
+		self.cap - self.pos
+	}
+
+	/// Whether every buffered byte has already been read.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl Read for Buffer {
+	/// Replay the buffered-but-unparsed bytes, as if they were still
+	/// waiting to be read from the original stream. Once exhausted, this
+	/// reads zero bytes, the same as any other stream at EOF; it does not
+	/// fall through to the original stream.
+	fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+		let available = &self.buf[self.pos..self.cap];
+		let n = ::std::cmp::min(out.len(), available.len());
+		out[..n].copy_from_slice(&available[..n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
 /// If you have your requests separate from your stream you can use this struct
 /// to upgrade the connection based on the request given
 /// (the request should be a handshake).
@@ -58,40 +86,191 @@ where
 	/// adding on a few headers. These headers are added before the required
 	/// headers are, so some might be overwritten.
 	pub fn accept_with(self, custom_headers: &Headers) -> Result<Client<S>, (S, io::Error)> {
-		self.internal_accept(Some(custom_headers))
+		self.internal_accept(Some(custom_headers.clone()))
 	}
 
-	fn internal_accept(mut self, headers: Option<&Headers>) -> Result<Client<S>, (S, io::Error)> {
-		let status = self.prepare_headers(headers);
+	/// Like `accept_with`, but the extra headers are computed from the
+	/// parsed handshake `request` instead of being fixed ahead of time -
+	/// handy when what you send back depends on what the client asked for,
+	/// e.g. picking response headers for whichever subprotocol was
+	/// negotiated. The mandatory websocket headers (`Sec-WebSocket-Accept`,
+	/// `Upgrade`, `Connection`) are still added afterwards and cannot be
+	/// overridden this way.
+	pub fn accept_with_fn<F>(self, headers: F) -> Result<Client<S>, (S, io::Error)>
+	where
+		F: FnOnce(&Request) -> Headers,
+	{
+		let headers = headers(&self.request);
+		self.internal_accept(Some(headers))
+	}
+
+	fn internal_accept(mut self, headers: Option<Headers>) -> Result<Client<S>, (S, io::Error)> {
+		let status = self.prepare_headers(headers.as_ref());
 
 		if let Err(e) = self.send(status) {
 			return Err((self.stream, e));
 		}
 
+		let connection_id = self.connection_id;
+		let protocol_version = self.version().cloned().unwrap_or(WebSocketVersion::WebSocket13);
 		let stream = match self.buffer {
 			Some(Buffer { buf, pos, cap }) => BufReader::from_parts(self.stream, buf, pos, cap),
 			None => BufReader::new(self.stream),
 		};
 
-		Ok(Client::unchecked(stream, self.headers, false, true))
+		let mut client = Client::unchecked(stream, self.headers, false, true);
+		client.connection_id = connection_id;
+		client.protocol_version = protocol_version;
+		Ok(client)
+	}
+
+	/// Like `accept`, but for a stream in non-blocking mode: builds the
+	/// handshake response up front and returns a `PartialAccept` that
+	/// tracks how many bytes of it have been written so far, to be driven
+	/// forward a chunk at a time via `PartialAccept::try_write` instead of
+	/// blocking until the whole response lands. Pairs with the incremental
+	/// handshake read, `server::upgrade::incremental::PartialUpgrade`.
+	pub fn accept_incremental(self) -> PartialAccept<S> {
+		self.internal_accept_incremental(None)
+	}
+
+	/// Like `accept_incremental`, but adding on a few headers first - see
+	/// `accept_with`.
+	pub fn accept_with_incremental(self, custom_headers: &Headers) -> PartialAccept<S> {
+		self.internal_accept_incremental(Some(custom_headers.clone()))
+	}
+
+	fn internal_accept_incremental(mut self, headers: Option<Headers>) -> PartialAccept<S> {
+		let status = self.prepare_headers(headers.as_ref());
+		let response = self.response_bytes(status);
+		let connection_id = self.connection_id;
+		let protocol_version = self.version().cloned().unwrap_or(WebSocketVersion::WebSocket13);
+
+		PartialAccept::new(
+			self.stream,
+			response,
+			connection_id,
+			protocol_version,
+			self.headers,
+			self.buffer,
+		)
 	}
 
 	/// Reject the client's request to make a websocket connection.
 	pub fn reject(self) -> Result<S, (S, io::Error)> {
-		self.internal_reject(None)
+		self.internal_reject(StatusCode::BadRequest, None)
 	}
 
 	/// Reject the client's request to make a websocket connection
 	/// and send extra headers.
 	pub fn reject_with(self, headers: &Headers) -> Result<S, (S, io::Error)> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(StatusCode::BadRequest, Some(headers))
+	}
+
+	/// Like `reject_with`, but also sends `body` as the response payload -
+	/// e.g. a small JSON error explaining why the handshake was rejected,
+	/// readable by a browser or HTTP client instead of just a bare status
+	/// line. Sets `Content-Length` to `body.len()` itself; there's no need
+	/// to (and no way to usefully) set that in `headers` too. `reject`/
+	/// `reject_with` keep their current no-body behavior.
+	pub fn reject_with_body(
+		self,
+		status: StatusCode,
+		headers: &Headers,
+		body: &[u8],
+	) -> Result<S, (S, io::Error)> {
+		self.internal_reject_with_body(status, Some(headers), body)
 	}
 
-	fn internal_reject(mut self, headers: Option<&Headers>) -> Result<S, (S, io::Error)> {
+	fn internal_reject_with_body(
+		mut self,
+		status: StatusCode,
+		headers: Option<&Headers>,
+		body: &[u8],
+	) -> Result<S, (S, io::Error)> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.iter());
 		}
-		match self.send(StatusCode::BadRequest) {
+		self.headers
+			.set(Connection(vec![ConnectionOption::Close]));
+		self.headers.set(ContentLength(body.len() as u64));
+
+		let mut response = self.response_bytes(status);
+		response.extend_from_slice(body);
+
+		match self.stream.write_all(&response).and_then(|_| self.stream.flush()) {
+			Ok(()) => Ok(self.stream),
+			Err(e) => Err((self.stream, e)),
+		}
+	}
+
+	/// Reject the client's request with `404 Not Found` instead of the
+	/// usual `400 Bad Request` - for a server that only serves websockets
+	/// on a known set of paths (see `WsServer::allowed_paths`) and wants to
+	/// tell a client asking for somewhere else apart from a malformed
+	/// handshake.
+	pub fn reject_not_found(self) -> Result<S, (S, io::Error)> {
+		self.internal_reject(StatusCode::NotFound, None)
+	}
+
+	/// Reject the client's request because this server is overloaded,
+	/// sending `self.overload_response`'s status (`503 Service Unavailable`
+	/// by default) instead of the usual `400 Bad Request`, and a
+	/// `Retry-After` header if a number of seconds was configured. For
+	/// applications that run their own connection cap or rate limiter ahead
+	/// of `accept`/`accept_tls` and want to shed load with a clean HTTP
+	/// response rather than dropping the connection outright. See
+	/// `WsServer::overload_response`.
+	pub fn reject_unavailable(self) -> Result<S, (S, io::Error)> {
+		let (status, retry_after_secs) = self.overload_response;
+		let mut headers = Headers::new();
+		if let Some(secs) = retry_after_secs {
+			headers.set_raw("Retry-After", vec![secs.to_string().into_bytes()]);
+		}
+		let headers = if headers.len() == 0 { None } else { Some(headers) };
+		self.internal_reject(status, headers.as_ref())
+	}
+
+	/// Reject the client's request with a redirect to `location` instead
+	/// of the usual `400 Bad Request`: sends `301 Moved Permanently`
+	/// (`permanent`) or `302 Found` with `location` in a `Location`
+	/// header, then closes the connection. This crate's handshake
+	/// acceptance is one-shot, so the client has to open a fresh
+	/// connection to follow it; a client that doesn't follow redirects at
+	/// all just sees the 3xx and errors out, which is expected. Useful for
+	/// routing a client to a specific shard/node at the WS layer instead of
+	/// inventing an application-level redirect of its own.
+	///
+	/// Fails with `io::ErrorKind::InvalidInput` - without writing anything
+	/// to the stream - if `location` isn't a well-formed absolute URI.
+	pub fn redirect(self, location: &str, permanent: bool) -> Result<S, (S, io::Error)> {
+		if ::url::Url::parse(location).is_err() {
+			return Err((
+				self.stream,
+				io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"redirect location is not a well-formed URI",
+				),
+			));
+		}
+
+		let mut headers = Headers::new();
+		headers.set(Location(location.to_owned()));
+		let status = if permanent {
+			StatusCode::MovedPermanently
+		} else {
+			StatusCode::Found
+		};
+		self.internal_reject(status, Some(&headers))
+	}
+
+	fn internal_reject(mut self, status: StatusCode, headers: Option<&Headers>) -> Result<S, (S, io::Error)> {
+		if let Some(custom) = headers {
+			self.headers.extend(custom.iter());
+		}
+		self.headers
+			.set(Connection(vec![ConnectionOption::Close]));
+		match self.send(status) {
 			Ok(()) => Ok(self.stream),
 			Err(e) => Err((self.stream, e)),
 		}
@@ -107,6 +286,30 @@ where
 	pub fn tcp_stream(&self) -> &TcpStream {
 		self.stream.as_tcp()
 	}
+
+	/// The client's address, straight from the TCP connection - `None` if
+	/// the stream no longer has one to give (see `TcpStream::peer_addr`).
+	/// Unlike `client_ip`, this never consults `X-Forwarded-For`/`X-Real-IP`.
+	pub fn peer_addr(&self) -> Option<SocketAddr> {
+		self.tcp_stream().peer_addr().ok()
+	}
+
+	/// The real client IP, honoring the `X-Forwarded-For`/`X-Real-IP`
+	/// headers only when the immediate TCP peer's address is in
+	/// `trusted_proxies` - otherwise, or if neither header holds a valid IP,
+	/// falls back to the TCP peer's own address. Pass the addresses of your
+	/// reverse proxies here; without that check, a direct client could set
+	/// either header to whatever it likes.
+	pub fn client_ip(&self, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+		let peer_ip = self.tcp_stream().peer_addr().ok()?.ip();
+		if !trusted_proxies.contains(&peer_ip) {
+			return Some(peer_ip);
+		}
+		self.x_forwarded_for()
+			.or_else(|| self.x_real_ip())
+			.and_then(|ip| ip.parse().ok())
+			.or(Some(peer_ip))
+	}
 }
 
 /// Trait to take a stream or similar and attempt to recover the start of a
@@ -153,6 +356,69 @@ pub trait IntoWs {
 	/// `WsUpgrade` struct, call `accept` to start a websocket client, and `reject` to
 	/// send a handshake rejection response.
 	fn into_ws(self) -> Result<Upgrade<Self::Stream>, Self::Error>;
+
+	/// Like `into_ws`, but `buffer` holds bytes already read off this
+	/// stream before the handshake - for example while sniffing whether a
+	/// freshly accepted connection is plain HTTP or a websocket upgrade on
+	/// a port shared between the two - so the handshake parser sees them
+	/// first instead of either re-reading them from the stream or silently
+	/// dropping them. `buffer` is typically one an earlier `into_ws`/
+	/// `into_ws_with_buffer` call on a *different* stream type returned
+	/// from its `Error`, now being handed to the real stream once it's
+	/// available.
+	///
+	/// The default implementation just ignores `buffer` and calls
+	/// `into_ws` - only the blanket impl for `S: Stream` actually reads
+	/// from a byte stream, so it's the only one that overrides this.
+	///
+	/// `buffer.buf`'s length is the capacity later reads will be buffered
+	/// into, not just how much of it is valid right now (that's `pos..cap`)
+	/// - pass along a `Buffer` exactly as received from an earlier
+	/// `into_ws`/`into_ws_with_buffer`/`accept` rather than building one
+	/// from scratch, or a `buf` shorter than what's actually pending will
+	/// get silently truncated on the next read.
+	fn into_ws_with_buffer(self, buffer: Buffer) -> Result<Upgrade<Self::Stream>, Self::Error>
+	where
+		Self: Sized,
+	{
+		let _ = buffer;
+		self.into_ws()
+	}
+}
+
+/// Parses a handshake request out of `reader`'s stream, starting from
+/// whatever bytes `reader` was constructed with - shared by `into_ws` (an
+/// empty `BufReader`) and `into_ws_with_buffer` (a `BufReader` pre-filled
+/// with `buffer`), so the two only differ in how the reader is built.
+fn parse_handshake<S>(
+	mut reader: BufReader<S>,
+) -> Result<Upgrade<S>, (S, Option<Request>, Option<Buffer>, HyperIntoWsError)>
+where
+	S: Stream,
+{
+	let request = parse_request(&mut reader);
+
+	let (stream, buf, pos, cap) = reader.into_parts();
+	let buffer = Some(Buffer { buf, cap, pos });
+
+	let request = match request {
+		Ok(r) => r,
+		Err(e) => return Err((stream, None, buffer, e.into())),
+	};
+
+	match validate(&request.subject.0, request.version, &request.headers) {
+		Ok(_) => Ok(WsUpgrade {
+			headers: Headers::new(),
+			stream,
+			request,
+			buffer,
+			context: None,
+			connection_id: 0,
+			switching_protocols_reason: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+		}),
+		Err(e) => Err((stream, Some(request), buffer, e)),
+	}
 }
 
 impl<S> IntoWs for S
@@ -163,26 +429,82 @@ where
 	type Error = (S, Option<Request>, Option<Buffer>, HyperIntoWsError);
 
 	fn into_ws(self) -> Result<Upgrade<Self::Stream>, Self::Error> {
-		let mut reader = BufReader::new(self);
-		let request = parse_request(&mut reader);
+		parse_handshake(BufReader::new(self))
+	}
 
-		let (stream, buf, pos, cap) = reader.into_parts();
-		let buffer = Some(Buffer { buf, cap, pos });
+	fn into_ws_with_buffer(self, buffer: Buffer) -> Result<Upgrade<Self::Stream>, Self::Error> {
+		parse_handshake(BufReader::from_parts(self, buffer.buf, buffer.pos, buffer.cap))
+	}
+}
 
-		let request = match request {
-			Ok(r) => r,
-			Err(e) => return Err((stream, None, buffer, e.into())),
-		};
+/// Like `IntoWs::into_ws`, but the accepted `Sec-WebSocket-Version`s are
+/// given explicitly, and the parsed request is passed through
+/// `rewrite_request` (if any) before this crate validates it. On a version
+/// mismatch, a `426 Upgrade Required` response advertising
+/// `accepted_versions` is written to the stream before the error is
+/// returned, per RFC6455 section 4.4. Used by `WsServer::accepted_versions`/
+/// `WsServer::rewrite_request`.
+pub(crate) fn into_ws_with_versions<S>(
+	stream: S,
+	accepted_versions: &[u8],
+	connection_id: u64,
+	rewrite_request: Option<&(dyn Fn(&mut Request) + Send + Sync)>,
+) -> Result<Upgrade<S>, (S, Option<Request>, Option<Buffer>, HyperIntoWsError)>
+where
+	S: Stream,
+{
+	let mut reader = BufReader::new(stream);
+	let request = parse_request(&mut reader);
 
-		match validate(&request.subject.0, request.version, &request.headers) {
-			Ok(_) => Ok(WsUpgrade {
-				headers: Headers::new(),
+	let (mut stream, buf, pos, cap) = reader.into_parts();
+	let buffer = Some(Buffer { buf, cap, pos });
+
+	let mut request = match request {
+		Ok(r) => r,
+		Err(e) => return Err((stream, None, buffer, e.into())),
+	};
+
+	if let Some(rewrite) = rewrite_request {
+		rewrite(&mut request);
+	}
+
+	match validate_with_versions(
+		&request.subject.0,
+		request.version,
+		&request.headers,
+		accepted_versions,
+	) {
+		Ok(_) => Ok(WsUpgrade {
+			headers: Headers::new(),
+			stream,
+			request,
+			buffer,
+			context: None,
+			connection_id,
+			switching_protocols_reason: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
+		}),
+		Err(HyperIntoWsError::UnsupportedWebsocketVersion) => {
+			let versions = accepted_versions
+				.iter()
+				.map(|v| v.to_string())
+				.collect::<Vec<_>>()
+				.join(", ");
+			let response = format!(
+				"{} {}\r\nSec-WebSocket-Version: {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+				request.version,
+				StatusCode::UpgradeRequired,
+				versions
+			);
+			let _ = stream.write_all(response.as_bytes());
+			Err((
 				stream,
-				request,
+				Some(request),
 				buffer,
-			}),
-			Err(e) => Err((stream, Some(request), buffer, e)),
+				HyperIntoWsError::UnsupportedWebsocketVersion,
+			))
 		}
+		Err(e) => Err((stream, Some(request), buffer, e)),
 	}
 }
 
@@ -200,6 +522,10 @@ where
 				stream: self.0,
 				request: self.1,
 				buffer: None,
+				context: None,
+				connection_id: 0,
+				switching_protocols_reason: None,
+				overload_response: (StatusCode::ServiceUnavailable, None),
 			}),
 			Err(e) => Err((self.0, self.1, e)),
 		}
@@ -272,6 +598,403 @@ impl<'a, 'b> IntoWs for HyperRequest<'a, 'b> {
 				headers,
 				subject: (method, uri),
 			},
+			context: None,
+			connection_id: 0,
+			switching_protocols_reason: None,
+			overload_response: (StatusCode::ServiceUnavailable, None),
 		})
 	}
 }
+
+impl<'a, 'b> Upgrade<&'a mut &'b mut NetworkStream> {
+	/// Try to convert an already-parsed hyper `Request` into an `Upgrade`,
+	/// for handling websocket connections on the same port as an existing
+	/// hyper HTTP server.
+	///
+	/// There is no separate `stream` parameter: hyper's handler hands you
+	/// the request with the underlying connection still attached (it has
+	/// already "hijacked" it for you, since hyper 0.10 never closes the
+	/// connection out from under a handler), so `request.get_mut()` would
+	/// give you the same stream this reads out of `req` internally. This is
+	/// a thin, more discoverable wrapper around `HyperRequest::into_ws` -
+	/// equivalent to `HyperRequest(req).into_ws()`.
+	///
+	/// ```rust,no_run
+	/// # extern crate hyper;
+	/// # extern crate websocket;
+	/// # fn main() {
+	/// use hyper::server::{Server, Request, Response};
+	/// use websocket::Message;
+	/// use websocket::sync::server::upgrade::Upgrade;
+	///
+	/// Server::http("0.0.0.0:80").unwrap().handle(move |req: Request, res: Response| {
+	///     match Upgrade::from_hyper(req) {
+	///         Ok(upgrade) => {
+	///             let mut client = match upgrade.accept() {
+	///                 Ok(c) => c,
+	///                 Err(_) => panic!(),
+	///             };
+	///
+	///             client.send_message(&Message::text("its free real estate")).unwrap();
+	///         },
+	///         Err((_request, _err)) => {
+	///             res.send(b"Try connecting over ws instead.").unwrap();
+	///         },
+	///     };
+	/// })
+	/// .unwrap();
+	/// # }
+	/// ```
+	pub fn from_hyper(
+		req: ::hyper::server::Request<'a, 'b>,
+	) -> Result<Self, (::hyper::server::Request<'a, 'b>, HyperIntoWsError)> {
+		HyperRequest(req).into_ws()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hyper::method::Method;
+	use stream::MockStream;
+	use unicase::UniCase;
+
+	const HANDSHAKE_REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+	#[test]
+	fn buffer_len_and_read() {
+		let mut buffer = Buffer {
+			buf: b"hello world".to_vec(),
+			pos: 6,
+			cap: 11,
+		};
+		assert_eq!(buffer.len(), 5);
+		assert!(!buffer.is_empty());
+
+		let mut out = [0u8; 5];
+		assert_eq!(buffer.read(&mut out).unwrap(), 5);
+		assert_eq!(&out, b"world");
+		assert!(buffer.is_empty());
+		assert_eq!(buffer.read(&mut out).unwrap(), 0);
+	}
+
+	#[test]
+	fn connection_id_comes_from_into_ws_with_versions() {
+		let upgrade = into_ws_with_versions(MockStream::new(HANDSHAKE_REQUEST), &[13], 42, None).unwrap();
+		assert_eq!(upgrade.connection_id(), 42);
+	}
+
+	#[test]
+	fn rewrite_request_runs_before_validation_and_can_fix_up_a_broken_handshake() {
+		const MISSING_CONNECTION_HEADER: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+		// Without a rewrite, the missing `Connection: Upgrade` this
+		// misbehaving client omitted is fatal.
+		match into_ws_with_versions(MockStream::new(MISSING_CONNECTION_HEADER), &[13], 0, None) {
+			Err(_) => {}
+			Ok(_) => panic!("expected the handshake without Connection: Upgrade to fail"),
+		}
+
+		let patched = into_ws_with_versions(
+			MockStream::new(MISSING_CONNECTION_HEADER),
+			&[13],
+			0,
+			Some(&|request: &mut Request| {
+				request.headers.set(Connection(vec![ConnectionOption::ConnectionHeader(
+					UniCase("Upgrade".to_owned()),
+				)]));
+			}),
+		);
+		assert!(patched.is_ok());
+	}
+
+	#[test]
+	fn connection_id_defaults_to_zero_without_a_server() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.connection_id(), 0);
+	}
+
+	#[test]
+	fn protocol_version_is_13_and_carries_over_to_the_accepted_client() {
+		use header::WebSocketVersion;
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.protocol_version(), WebSocketVersion::WebSocket13);
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let client = upgrade.accept().unwrap();
+		assert_eq!(*client.protocol_version(), WebSocketVersion::WebSocket13);
+	}
+
+	#[test]
+	fn protocols_flattens_a_single_comma_separated_header() {
+		const REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Protocol: foo, bar\r\n\r\n";
+
+		let upgrade = MockStream::new(REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.protocols(), ["foo".to_string(), "bar".to_string()]);
+	}
+
+	#[test]
+	fn protocols_flattens_repeated_headers() {
+		const REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Protocol: foo\r\n\
+Sec-WebSocket-Protocol: bar\r\n\r\n";
+
+		let upgrade = MockStream::new(REQUEST).into_ws().unwrap();
+		assert_eq!(upgrade.protocols(), ["foo".to_string(), "bar".to_string()]);
+	}
+
+	#[test]
+	fn response_bytes_matches_what_accept_writes_to_the_stream() {
+		let mut upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let status = upgrade.prepare_headers(None);
+		let expected = upgrade.response_bytes(status);
+		assert!(String::from_utf8(expected.clone())
+			.unwrap()
+			.contains("Sec-WebSocket-Accept:"));
+
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let client = upgrade.accept().unwrap();
+		assert_eq!(client.stream_ref().output, expected);
+	}
+
+	#[test]
+	fn accept_with_fn_computes_headers_from_the_request() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let client = upgrade
+			.accept_with_fn(|req| {
+				let mut headers = Headers::new();
+				headers.set_raw(
+					"X-Requested-Uri",
+					vec![req.subject.1.to_string().into_bytes()],
+				);
+				headers
+			})
+			.unwrap();
+
+		let response = String::from_utf8(client.stream_ref().output.clone()).unwrap();
+		assert!(response.contains("X-Requested-Uri: /"));
+	}
+
+	#[test]
+	fn client_ip_falls_back_to_the_tcp_peer_when_untrusted() {
+		use std::io::Write;
+		use std::net::TcpListener;
+
+		const REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+X-Forwarded-For: 203.0.113.5\r\n\r\n";
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let mut client = TcpStream::connect(addr).unwrap();
+		let (server, _) = listener.accept().unwrap();
+		let peer_ip = server.peer_addr().unwrap().ip();
+
+		client.write_all(REQUEST).unwrap();
+
+		let upgrade = server.into_ws().unwrap();
+		assert_eq!(upgrade.client_ip(&[]), Some(peer_ip));
+	}
+
+	#[test]
+	fn client_ip_trusts_x_forwarded_for_from_a_configured_proxy() {
+		use std::io::Write;
+		use std::net::TcpListener;
+
+		const REQUEST: &'static [u8] = b"GET / HTTP/1.1\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+X-Forwarded-For: 203.0.113.5\r\n\r\n";
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let mut client = TcpStream::connect(addr).unwrap();
+		let (server, _) = listener.accept().unwrap();
+
+		client.write_all(REQUEST).unwrap();
+
+		let upgrade = server.into_ws().unwrap();
+		let trusted = [addr.ip()];
+		assert_eq!(
+			upgrade.client_ip(&trusted),
+			Some("203.0.113.5".parse().unwrap())
+		);
+	}
+
+	#[test]
+	fn peer_addr_matches_the_tcp_connection_that_was_accepted() {
+		use std::io::Write;
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let mut client = TcpStream::connect(addr).unwrap();
+		let (server, _) = listener.accept().unwrap();
+		let peer_addr = server.peer_addr().unwrap();
+
+		client.write_all(HANDSHAKE_REQUEST).unwrap();
+
+		let upgrade = server.into_ws().unwrap();
+		assert_eq!(upgrade.peer_addr(), Some(peer_addr));
+	}
+
+	#[test]
+	fn reject_sends_connection_close() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let stream = upgrade.reject().unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		assert!(response.contains("Connection: close"));
+	}
+
+	#[test]
+	fn reject_with_sends_connection_close() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let mut custom = Headers::new();
+		custom.set_raw("X-Reason", vec![b"unauthorized".to_vec()]);
+		let stream = upgrade.reject_with(&custom).unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		assert!(response.contains("Connection: close"));
+		assert!(response.contains("X-Reason: unauthorized"));
+	}
+
+	#[test]
+	fn reject_with_body_sets_content_length_and_sends_the_body() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let mut custom = Headers::new();
+		custom.set_raw("WWW-Authenticate", vec![b"Bearer".to_vec()]);
+		let body = b"{\"error\":\"unauthorized\"}";
+
+		let stream = upgrade
+			.reject_with_body(StatusCode::Unauthorized, &custom, body)
+			.unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		let status_line = response.lines().next().unwrap();
+		assert_eq!(status_line, "HTTP/1.1 401 Unauthorized");
+		assert!(response.contains("WWW-Authenticate: Bearer"));
+		assert!(response.contains("Content-Length: 24"));
+		assert!(response.contains("Connection: close"));
+		assert!(response.ends_with("{\"error\":\"unauthorized\"}"));
+	}
+
+	#[test]
+	fn reject_unavailable_defaults_to_503_with_no_retry_after() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let stream = upgrade.reject_unavailable().unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		let status_line = response.lines().next().unwrap();
+		assert_eq!(status_line, "HTTP/1.1 503 Service Unavailable");
+		assert!(response.contains("Connection: close"));
+		assert!(!response.contains("Retry-After"));
+	}
+
+	#[test]
+	fn reject_unavailable_sends_the_configured_status_and_retry_after() {
+		let mut upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		upgrade.overload_response = (StatusCode::TooManyRequests, Some(30));
+		let stream = upgrade.reject_unavailable().unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		let status_line = response.lines().next().unwrap();
+		assert_eq!(status_line, "HTTP/1.1 429 Too Many Requests");
+		assert!(response.contains("Connection: close"));
+		assert!(response.contains("Retry-After: 30"));
+	}
+
+	#[test]
+	fn redirect_sends_a_302_with_the_location_header_by_default() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let stream = upgrade
+			.redirect("wss://shard-2.example.com/socket", false)
+			.unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		let status_line = response.lines().next().unwrap();
+		assert_eq!(status_line, "HTTP/1.1 302 Found");
+		assert!(response.contains("Location: wss://shard-2.example.com/socket"));
+		assert!(response.contains("Connection: close"));
+	}
+
+	#[test]
+	fn redirect_sends_a_301_when_permanent() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		let stream = upgrade
+			.redirect("wss://shard-2.example.com/socket", true)
+			.unwrap();
+
+		let response = String::from_utf8(stream.output).unwrap();
+		let status_line = response.lines().next().unwrap();
+		assert_eq!(status_line, "HTTP/1.1 301 Moved Permanently");
+		assert!(response.contains("Location: wss://shard-2.example.com/socket"));
+	}
+
+	#[test]
+	fn redirect_rejects_a_malformed_location_without_writing_to_the_stream() {
+		let upgrade = MockStream::new(HANDSHAKE_REQUEST).into_ws().unwrap();
+		match upgrade.redirect("not a uri", false) {
+			Err((stream, e)) => {
+				assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+				assert!(stream.output.is_empty());
+			}
+			Ok(_) => panic!("expected a malformed location to be rejected"),
+		}
+	}
+
+	#[test]
+	fn into_ws_with_buffer_parses_a_handshake_split_between_buffer_and_stream() {
+		let split = HANDSHAKE_REQUEST.len() / 2;
+		let buffer = Buffer {
+			buf: HANDSHAKE_REQUEST[..split].to_vec(),
+			pos: 0,
+			cap: split,
+		};
+		let stream = MockStream::new(&HANDSHAKE_REQUEST[split..]);
+
+		let upgrade = stream.into_ws_with_buffer(buffer).unwrap();
+
+		assert_eq!(upgrade.request.subject.0, Method::Get);
+	}
+
+	#[test]
+	fn into_ws_with_buffer_with_nothing_pre_read_behaves_like_into_ws() {
+		// Same shape a fresh `BufReader` has before it's read anything:
+		// `cap == pos == 0`, but `buf` still has its full capacity, unlike a
+		// zero-length `Vec` (which would leave the reader unable to buffer
+		// any further reads at all).
+		let buffer = Buffer {
+			buf: vec![0; 4096],
+			pos: 0,
+			cap: 0,
+		};
+		let stream = MockStream::new(HANDSHAKE_REQUEST);
+
+		let upgrade = stream.into_ws_with_buffer(buffer).unwrap();
+
+		assert_eq!(upgrade.request.subject.0, Method::Get);
+	}
+}
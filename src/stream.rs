@@ -64,6 +64,66 @@ where
 	}
 }
 
+/// An in-memory `Stream` for testing handshake code without opening a real
+/// socket. Feed it the bytes a client or server would have sent as `input`,
+/// run your handshake code against it, then inspect `output` to see what
+/// was written back.
+///
+/// ```rust
+/// use websocket::stream::MockStream;
+/// use websocket::sync::server::upgrade::IntoWs;
+///
+/// let request = b"GET / HTTP/1.1\r\n\
+/// Upgrade: websocket\r\n\
+/// Connection: Upgrade\r\n\
+/// Sec-WebSocket-Version: 13\r\n\
+/// Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+///
+/// let stream = MockStream::new(request);
+/// let upgrade = stream.into_ws().unwrap();
+/// let client = upgrade.accept().unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct MockStream {
+	input: io::Cursor<Vec<u8>>,
+	/// Everything that has been written to this stream so far.
+	pub output: Vec<u8>,
+}
+
+impl MockStream {
+	/// Create a mock stream that will yield `input` to readers, and
+	/// remember anything written to it in `output`.
+	pub fn new(input: &[u8]) -> Self {
+		MockStream {
+			input: io::Cursor::new(input.to_vec()),
+			output: Vec::new(),
+		}
+	}
+
+	/// Makes `more` available to future reads, as if it had just arrived on
+	/// the wire - for tests that need to feed a stream in several chunks
+	/// rather than all at once.
+	pub fn push_input(&mut self, more: &[u8]) {
+		self.input.get_mut().extend_from_slice(more);
+	}
+}
+
+impl Read for MockStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.input.read(buf)
+	}
+}
+
+impl Write for MockStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.output.extend_from_slice(buf);
+		Ok(buf.len())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
 /// A collection of traits and implementations for async streams.
 #[cfg(feature = "async")]
 pub mod async {
@@ -108,6 +168,7 @@ pub mod sync {
 	pub use std::net::Shutdown;
 	pub use std::net::TcpStream;
 	use std::ops::Deref;
+	use std::time::Duration;
 
 	pub use super::Stream;
 
@@ -183,4 +244,137 @@ pub mod sync {
 			self.deref().as_tcp()
 		}
 	}
+
+	/// Whether a stream type runs over TLS. Implemented for the stream types
+	/// this crate hands out itself (`TcpStream`, `TlsStream<TcpStream>`), so
+	/// generic code - e.g. `Client::connection_config` - can report whether a
+	/// connection is secure without needing its own flag threaded through
+	/// from `ClientBuilder`/`WsServer`.
+	pub trait IsSecure {
+		/// `true` if this stream type runs over TLS.
+		fn is_secure() -> bool;
+	}
+
+	impl IsSecure for TcpStream {
+		fn is_secure() -> bool {
+			false
+		}
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	impl IsSecure for TlsStream<TcpStream> {
+		fn is_secure() -> bool {
+			true
+		}
+	}
+
+	impl<T> IsSecure for Box<T>
+	where
+		T: IsSecure,
+	{
+		fn is_secure() -> bool {
+			T::is_secure()
+		}
+	}
+
+	/// Sets `TCP_USER_TIMEOUT` on `stream`: the maximum time transmitted
+	/// data may go unacknowledged before the kernel gives up on the
+	/// connection and fails pending/future writes, surfacing a dead peer far
+	/// sooner than waiting on TCP's own (much longer) retransmission
+	/// timeout. `None` clears it, reverting to the kernel default. This
+	/// complements application-level pings for peers that have gone
+	/// unreachable (power loss, a yanked cable, ...) rather than ones that
+	/// are merely slow to respond.
+	///
+	/// Linux-only: `TCP_USER_TIMEOUT` has no equivalent on other platforms,
+	/// so there this is a no-op that always succeeds.
+	#[cfg(target_os = "linux")]
+	pub fn set_tcp_user_timeout(stream: &TcpStream, timeout: Option<Duration>) -> io::Result<()> {
+		use std::os::raw::{c_int, c_void};
+		use std::os::unix::io::AsRawFd;
+
+		const IPPROTO_TCP: c_int = 6;
+		const TCP_USER_TIMEOUT: c_int = 18;
+
+		extern "C" {
+			fn setsockopt(
+				socket: c_int,
+				level: c_int,
+				name: c_int,
+				value: *const c_void,
+				len: u32,
+			) -> c_int;
+		}
+
+		let millis: u32 = timeout
+			.map(|d| {
+				let millis = d.as_secs().saturating_mul(1000) + u64::from(d.subsec_nanos() / 1_000_000);
+				millis.min(u64::from(u32::max_value())) as u32
+			})
+			.unwrap_or(0);
+
+		let result = unsafe {
+			setsockopt(
+				stream.as_raw_fd(),
+				IPPROTO_TCP,
+				TCP_USER_TIMEOUT,
+				&millis as *const u32 as *const c_void,
+				::std::mem::size_of::<u32>() as u32,
+			)
+		};
+
+		if result == 0 {
+			Ok(())
+		} else {
+			Err(io::Error::last_os_error())
+		}
+	}
+
+	/// See the Linux version of this function. No-op on every other
+	/// platform: there is no equivalent socket option to set.
+	#[cfg(not(target_os = "linux"))]
+	pub fn set_tcp_user_timeout(_stream: &TcpStream, _timeout: Option<Duration>) -> io::Result<()> {
+		Ok(())
+	}
+
+	/// Bytes still sitting in `stream`'s kernel send buffer, i.e. written but
+	/// not yet acknowledged by the peer - the lowest-level signal of how
+	/// backed up a connection's outgoing side is, and a way to poll for
+	/// write backpressure before queuing more application-level data for a
+	/// slow peer instead of buffering unboundedly in front of it.
+	///
+	/// Linux-only (`TIOCOUTQ`): there is no portable ioctl/socket option for
+	/// this on other platforms, so this returns an error there rather than
+	/// a silently wrong number.
+	#[cfg(target_os = "linux")]
+	pub fn send_queue_len(stream: &TcpStream) -> io::Result<usize> {
+		use std::os::raw::{c_int, c_ulong};
+		use std::os::unix::io::AsRawFd;
+
+		const TIOCOUTQ: c_ulong = 0x5411;
+
+		extern "C" {
+			fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+		}
+
+		let mut queued: c_int = 0;
+		let result = unsafe { ioctl(stream.as_raw_fd(), TIOCOUTQ, &mut queued as *mut c_int) };
+
+		if result == 0 {
+			Ok(queued as usize)
+		} else {
+			Err(io::Error::last_os_error())
+		}
+	}
+
+	/// See the Linux version of this function. Every other platform has no
+	/// equivalent to `TIOCOUTQ`, so this always fails rather than making up
+	/// a number.
+	#[cfg(not(target_os = "linux"))]
+	pub fn send_queue_len(_stream: &TcpStream) -> io::Result<usize> {
+		Err(io::Error::new(
+			io::ErrorKind::Other,
+			"send_queue_len requires TIOCOUTQ, which is Linux-only",
+		))
+	}
 }
@@ -6,7 +6,6 @@ use result::WebSocketResult;
 use std::io::Write;
 use ws::util::header as dfh;
 use ws::util::mask;
-use ws::util::mask::Masker;
 
 /// A generic DataFrame. Every dataframe should be able to
 /// provide these methods. (If the payload is not known in advance then
@@ -16,7 +15,25 @@ pub trait DataFrame {
 	fn is_last(&self) -> bool;
 	/// What type of data does this dataframe contain?
 	fn opcode(&self) -> u8;
-	/// Reserved bits of this dataframe
+	/// Reserved bits of this dataframe (RFC6455 5.2), which an extension
+	/// negotiated via `Sec-WebSocket-Extensions` (e.g. `permessage-deflate`)
+	/// would use to flag per-frame behaviour such as compression. This
+	/// crate negotiates extensions (see `WebSocketExtensions`) but doesn't
+	/// implement any itself, and `OwnedMessage::from_dataframes` rejects any
+	/// incoming frame whose reserved bits aren't all clear - so there is no
+	/// supported way to send or receive a frame with one set. Implementing
+	/// an extension that needs them means owning both sides of the wire
+	/// format yourself, down to parsing frames before this crate's own
+	/// `from_dataframes` would reject them.
+	///
+	/// One consequence: there is no `permessage-deflate`-style decompression
+	/// step in this crate, so there is no decompression-ratio ("zip bomb")
+	/// attack surface to cap here either - a frame's payload on the wire
+	/// *is* its logical size, nothing inflates it. `Receiver::max_message_size`
+	/// already bounds that size directly; an extension implemented on top of
+	/// this crate (per the previous paragraph) would need its own cap on
+	/// however much it expands a frame before `max_message_size` ever sees
+	/// the result.
 	fn reserved(&self) -> &[bool; 3];
 
 	/// How long (in bytes) is this dataframe's payload
@@ -49,8 +66,25 @@ pub trait DataFrame {
 	/// Takes the payload out into a vec
 	fn take_payload(self) -> Vec<u8>;
 
-	/// Writes a DataFrame to a Writer.
-	fn write_to(&self, writer: &mut Write, mask: bool) -> WebSocketResult<()> {
+	/// Writes a DataFrame's header, masked (if `mask` is set) payload, and
+	/// everything else into `buf`, which is cleared first rather than
+	/// appended to. Reusing the same `buf` across many calls (as
+	/// `sender::Sender` does) avoids allocating a fresh one per frame.
+	///
+	/// Masking is done by XORing the payload in place once it's landed in
+	/// `buf`, rather than through a `Masker` that re-allocates on every
+	/// `write_payload` call - `write_payload` only promises *some* sequence
+	/// of writes, not one contiguous one, so this is the one point that can
+	/// be sure the whole payload is in one place to mask it cheaply.
+	///
+	/// Payloads of 125 bytes or less (the overwhelming majority of frames -
+	/// every control frame, and most text/binary messages) get a further
+	/// shortcut: their header is written directly into `buf` instead of
+	/// through `ws::util::header::write_header`'s `&mut dyn Write`, which
+	/// costs a virtual call per field written.
+	fn write_to_buf(&self, buf: &mut Vec<u8>, mask: bool) -> WebSocketResult<()> {
+		buf.clear();
+
 		let mut flags = dfh::DataFrameFlags::empty();
 		if self.is_last() {
 			flags.insert(dfh::FIN);
@@ -69,25 +103,42 @@ pub trait DataFrame {
 		}
 
 		let masking_key = if mask { Some(mask::gen_mask()) } else { None };
+		let payload_len = self.size();
+		let opcode = self.opcode();
 
-		let header = dfh::DataFrameHeader {
-			flags,
-			opcode: self.opcode() as u8,
-			mask: masking_key,
-			len: self.size() as u64,
-		};
+		if payload_len <= 125 && opcode <= 0xF {
+			buf.push(flags.bits() | opcode);
+			buf.push((if masking_key.is_some() { 0x80 } else { 0x00 }) | payload_len as u8);
+			if let Some(key) = masking_key {
+				buf.extend_from_slice(&key);
+			}
+		} else {
+			let header = dfh::DataFrameHeader {
+				flags,
+				opcode,
+				mask: masking_key,
+				len: payload_len as u64,
+			};
+			dfh::write_header(buf, header)?;
+		}
 
-		let mut data = Vec::<u8>::new();
-		dfh::write_header(&mut data, header)?;
+		let payload_start = buf.len();
+		self.write_payload(buf)?;
 
-		match masking_key {
-			Some(mask) => {
-				let mut masker = Masker::new(mask, &mut data);
-				self.write_payload(&mut masker)?
+		if let Some(key) = masking_key {
+			for (i, byte) in buf[payload_start..].iter_mut().enumerate() {
+				*byte ^= key[i % 4];
 			}
-			None => self.write_payload(&mut data)?,
-		};
-		writer.write_all(data.as_slice())?;
+		}
+
+		Ok(())
+	}
+
+	/// Writes a DataFrame to a Writer.
+	fn write_to(&self, writer: &mut Write, mask: bool) -> WebSocketResult<()> {
+		let mut data = Vec::new();
+		self.write_to_buf(&mut data, mask)?;
+		writer.write_all(&data)?;
 		Ok(())
 	}
 }
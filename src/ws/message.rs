@@ -14,6 +14,17 @@ pub trait Message: Sized {
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize;
 
+	/// Is this a control message (`Close`, `Ping` or `Pong`), as opposed to
+	/// a data message (`Text` or `Binary`)? Per RFC6455 section 5.5,
+	/// control messages keep flowing even after a `Close` has been
+	/// received, since they carry the closing handshake itself.
+	fn is_control(&self) -> bool;
+
+	/// Is this specifically a `Close` message? Used to notice when a
+	/// `send_message` completes this side of the closing handshake, as
+	/// opposed to any other control message.
+	fn is_close(&self) -> bool;
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self>;
 }
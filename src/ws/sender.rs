@@ -15,6 +15,12 @@ pub trait Sender {
 	fn is_masked(&self) -> bool;
 
 	/// Sends a single data frame using this sender.
+	///
+	/// `dataframe` is borrowed rather than consumed, so the same one can be
+	/// sent to many writers in a row. Each call serializes the frame from
+	/// scratch: it measures the payload and builds the header again, and
+	/// when `is_masked` is set it also draws a fresh random key and makes one
+	/// pass over the payload. Nothing is cached between calls.
 	fn send_dataframe<D, W>(&mut self, writer: &mut W, dataframe: &D) -> WebSocketResult<()>
 	where
 		D: DataFrame,
@@ -24,7 +30,9 @@ pub trait Sender {
 		Ok(())
 	}
 
-	/// Sends a single message using this sender.
+	/// Sends a single message using this sender. Like `send_dataframe`,
+	/// `message` is borrowed, so it can be built once and sent to many
+	/// writers for a cheap fan-out broadcast.
 	fn send_message<M, W>(&mut self, writer: &mut W, message: &M) -> WebSocketResult<()>
 	where
 		M: Message,
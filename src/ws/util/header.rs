@@ -192,6 +192,21 @@ mod tests {
 		assert_eq!(&obtained[..], &expected[..]);
 	}
 
+	#[test]
+	fn test_read_header_rejects_non_minimal_length() {
+		// 126 signals a 16-bit extended length, but the value it introduces
+		// (100) fits in the 7 bits already available; RFC6455 section 5.2
+		// requires the minimal encoding be used.
+		let header = [0x81, 0xFE, 0x00, 0x64];
+		assert!(read_header(&mut &header[..]).is_err());
+
+		// 127 signals a 64-bit extended length, but 40000 fits in 16 bits.
+		let header = [
+			0x81, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x9C, 0x40,
+		];
+		assert!(read_header(&mut &header[..]).is_err());
+	}
+
 	#[bench]
 	fn bench_read_header(b: &mut test::Bencher) {
 		let header = vec![0x42u8, 0xFE, 0x02, 0x00, 0x02, 0x04, 0x08, 0x10];